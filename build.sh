@@ -1,4 +1,8 @@
 #!/bin/sh
-VERSION=0.14.0
-docker build . -t torhovland/rust-trunk:$VERSION
-docker push torhovland/rust-trunk:$VERSION
+# Builds every trunk x Rust combination listed in matrix.toml, with every
+# variant in image.toml, checks the images against structure.toml and the
+# smoke test fixtures, then pushes them; the second build comes from the
+# build cache.
+set -e
+cargo run --release -- build --structure-test --smoke "$@"
+exec cargo run --release -- build --push "$@"
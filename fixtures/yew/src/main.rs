@@ -0,0 +1,39 @@
+use yew::prelude::*;
+
+enum Msg {
+    Increment,
+}
+
+struct Counter {
+    value: i64,
+}
+
+impl Component for Counter {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        Self { value: 0 }
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Increment => {
+                self.value += 1;
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <button onclick={ctx.link().callback(|_| Msg::Increment)}>
+                { self.value }
+            </button>
+        }
+    }
+}
+
+fn main() {
+    yew::start_app::<Counter>();
+}
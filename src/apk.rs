@@ -0,0 +1,138 @@
+//! Alpine repository indices: which package versions a release branch
+//! offers. `APKINDEX` and the installed database `/lib/apk/db/installed`
+//! share one format, read by [`records`].
+
+use std::{collections::BTreeMap, io::Read};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::{http, platform::Platform, Error, Result};
+
+/// The default Alpine mirror.
+pub const ALPINE_MIRROR: &str = "https://dl-cdn.alpinelinux.org/alpine";
+
+/// The repositories of a branch that the `rust` Alpine images install from.
+pub const REPOSITORIES: [&str; 2] = ["main", "community"];
+
+/// The release branch of an Alpine `VERSION_ID`, e.g. `v3.19` for
+/// `3.19.1`. Development snapshots like `3.20.0_alpha20240329` are `edge`.
+pub fn branch(version_id: &str) -> Option<String> {
+    if version_id.contains('_') {
+        return Some("edge".into());
+    }
+    let mut parts = version_id.split('.');
+    let (major, minor) = (parts.next()?, parts.next()?);
+    let numeric = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    (numeric(major) && numeric(minor)).then(|| format!("v{major}.{minor}"))
+}
+
+/// URL of the `APKINDEX` of `repository` in `branch` on `platform`.
+pub fn index_url(mirror: &str, branch: &str, repository: &str, platform: Platform) -> String {
+    format!(
+        "{}/{branch}/{repository}/{}/APKINDEX.tar.gz",
+        mirror.trim_end_matches('/'),
+        platform.machine()
+    )
+}
+
+/// The versions of every package apk can install from `branch` on
+/// `platform`: those of its main repository and, if it has one, its
+/// community repository.
+pub fn available(
+    mirror: &str,
+    branch: &str,
+    platform: Platform,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut available = BTreeMap::new();
+    for (i, repository) in REPOSITORIES.iter().enumerate() {
+        let url = index_url(mirror, branch, repository, platform);
+        let Some(index) = fetch_index(&url)? else {
+            if i == 0 {
+                return Err(Error::Http {
+                    url,
+                    message: "the branch has no package index".into(),
+                });
+            }
+            continue;
+        };
+        for (package, versions) in index {
+            let known: &mut Vec<String> = available.entry(package).or_default();
+            for version in versions {
+                if !known.contains(&version) {
+                    known.push(version);
+                }
+            }
+        }
+    }
+    Ok(available)
+}
+
+/// Reads the `APKINDEX` file out of an `APKINDEX.tar.gz`, which is a
+/// signature and the index gzipped one after the other.
+fn fetch_index(url: &str) -> Result<Option<BTreeMap<String, Vec<String>>>> {
+    let Some(compressed) = http::get(url)? else {
+        return Ok(None);
+    };
+    let error = |err: std::io::Error| Error::Archive(format!("{url}: {err}"));
+    let mut archive = tar::Archive::new(MultiGzDecoder::new(compressed.as_slice()));
+    archive.set_ignore_zeros(true);
+    for entry in archive.entries().map_err(error)? {
+        let mut entry = entry.map_err(error)?;
+        if entry.path().map_err(error)?.as_os_str() != "APKINDEX" {
+            continue;
+        }
+        let mut text = String::new();
+        entry.read_to_string(&mut text).map_err(error)?;
+        return Ok(Some(parse_index(&text)));
+    }
+    Err(Error::Archive(format!("{url} has no APKINDEX")))
+}
+
+/// The records of an `APKINDEX` or apk database, by their one-letter
+/// fields: `P` (package), `V` (version), `A` (architecture), `D`
+/// (dependencies), `p` (what the package provides) and so on.
+pub fn records(text: &str) -> Vec<BTreeMap<char, &str>> {
+    text.split("\n\n")
+        .map(|record| {
+            record
+                .lines()
+                .filter_map(|line| {
+                    let (field, value) = line.split_once(':')?;
+                    let mut chars = field.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(field), None) => Some((field, value)),
+                        _ => None,
+                    }
+                })
+                .collect()
+        })
+        .filter(|fields: &BTreeMap<char, &str>| fields.contains_key(&'P'))
+        .collect()
+}
+
+/// Reads the package names and versions of an `APKINDEX`.
+pub fn parse_index(text: &str) -> BTreeMap<String, Vec<String>> {
+    let mut packages: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for fields in records(text) {
+        if let Some(version) = fields.get(&'V') {
+            packages
+                .entry(fields[&'P'].to_string())
+                .or_default()
+                .push(version.to_string());
+        }
+    }
+    packages
+}
+
+/// Fails unless `available`, the packages of `origin`, offers every
+/// version in `pins`.
+pub fn check_pins(
+    available: &BTreeMap<String, Vec<String>>,
+    pins: &BTreeMap<String, String>,
+    origin: &str,
+) -> Result<()> {
+    match crate::apt::missing_pin(available, pins, origin) {
+        Some(message) => Err(Error::Apk(message)),
+        None => Ok(()),
+    }
+}
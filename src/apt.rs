@@ -0,0 +1,336 @@
+//! Debian archive indices: which package versions a suite offers.
+
+use std::{cmp::Ordering, collections::BTreeMap, io::Read};
+
+use flate2::read::GzDecoder;
+
+use crate::{http, platform::Platform, Error, Result};
+
+/// The default Debian mirror.
+pub const DEBIAN_MIRROR: &str = "http://deb.debian.org/debian";
+
+/// The default mirror of the Debian security archive.
+pub const DEBIAN_SECURITY_MIRROR: &str = "http://deb.debian.org/debian-security";
+
+/// Where snapshot.debian.org serves the archives as they were at a time.
+pub const SNAPSHOT_ARCHIVE: &str = "http://snapshot.debian.org/archive";
+
+/// URL of `archive` (`debian` or `debian-security`) at `timestamp` on
+/// snapshot.debian.org.
+pub fn snapshot_url(archive: &str, timestamp: &str) -> String {
+    format!("{SNAPSHOT_ARCHIVE}/{archive}/{timestamp}")
+}
+
+/// URL of the `Packages` index of the main component of `suite` on
+/// `platform`.
+pub fn packages_url(mirror: &str, suite: &str, platform: Platform) -> String {
+    format!(
+        "{}/dists/{suite}/main/binary-{}/Packages.gz",
+        mirror.trim_end_matches('/'),
+        platform.arch()
+    )
+}
+
+/// The Debian archive and the security archive an image installs its
+/// packages from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archives {
+    pub debian: String,
+    pub security: String,
+}
+
+impl Archives {
+    /// The suites apt reads for the release `suite`, with the archive each
+    /// is in: the release itself, its updates and its security updates.
+    pub fn suites(&self, suite: &str) -> [(&str, String); 3] {
+        [
+            (&self.debian, suite.to_string()),
+            (&self.debian, format!("{suite}-updates")),
+            (&self.security, format!("{suite}-security")),
+        ]
+    }
+}
+
+/// The versions of every package in the main component of `suite` on
+/// `platform`, in the order the index lists them.
+pub fn packages(
+    mirror: &str,
+    suite: &str,
+    platform: Platform,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let url = packages_url(mirror, suite, platform);
+    fetch_packages(&url)?.ok_or_else(|| Error::Http {
+        url,
+        message: "the suite has no package index".into(),
+    })
+}
+
+/// The versions of every package apt can install from the release `suite`
+/// on `platform`: those of the release, its updates and its security
+/// updates, oldest first. A release without updates or security updates,
+/// like `sid`, has just its own.
+pub fn available(
+    archives: &Archives,
+    suite: &str,
+    platform: Platform,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut available = packages(&archives.debian, suite, platform)?;
+    for (mirror, suite) in &archives.suites(suite)[1..] {
+        let Some(packages) = fetch_packages(&packages_url(mirror, suite, platform))? else {
+            continue;
+        };
+        for (package, versions) in packages {
+            available.entry(package).or_default().extend(versions);
+        }
+    }
+    for versions in available.values_mut() {
+        versions.sort_by(|a, b| compare_versions(a, b));
+        versions.dedup();
+    }
+    Ok(available)
+}
+
+fn fetch_packages(url: &str) -> Result<Option<BTreeMap<String, Vec<String>>>> {
+    Ok(fetch_index(url)?.map(|text| parse_packages(&text)))
+}
+
+/// Downloads and decompresses a `Packages.gz`.
+fn fetch_index(url: &str) -> Result<Option<String>> {
+    let Some(compressed) = http::get(url)? else {
+        return Ok(None);
+    };
+    let mut text = String::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut text)
+        .map_err(|err| Error::Archive(format!("{url}: {err}")))?;
+    Ok(Some(text))
+}
+
+/// A package in an archive, with what installing it takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Its `Pre-Depends` and `Depends`, as read by [`dependencies`].
+    pub depends: Vec<Vec<String>>,
+    /// The virtual packages it provides.
+    pub provides: Vec<String>,
+    /// Where its `.deb` is downloaded from.
+    pub url: String,
+    pub sha256: String,
+}
+
+/// Every package apt can install from the release `suite` on `platform`,
+/// like [`available`], with the dependencies and the downloads of each.
+pub fn index(archives: &Archives, suite: &str, platform: Platform) -> Result<Vec<Package>> {
+    let mut index = Vec::new();
+    for (i, (mirror, suite)) in archives.suites(suite).iter().enumerate() {
+        let url = packages_url(mirror, suite, platform);
+        let Some(text) = fetch_index(&url)? else {
+            if i == 0 {
+                return Err(Error::Http {
+                    url,
+                    message: "the suite has no package index".into(),
+                });
+            }
+            continue;
+        };
+        index.extend(parse_index(&text, mirror));
+    }
+    Ok(index)
+}
+
+/// Reads the packages of a `Packages` index of the archive at `mirror`.
+pub fn parse_index(text: &str, mirror: &str) -> Vec<Package> {
+    stanzas(text)
+        .into_iter()
+        .filter_map(|fields| {
+            let field = |name: &str| fields.get(name).map_or("", String::as_str);
+            let depends = ["Pre-Depends", "Depends"]
+                .iter()
+                .flat_map(|name| dependencies(field(name)))
+                .collect();
+            Some(Package {
+                name: fields.get("Package")?.clone(),
+                version: fields.get("Version")?.clone(),
+                depends,
+                provides: dependencies(field("Provides")).concat(),
+                url: format!(
+                    "{}/{}",
+                    mirror.trim_end_matches('/'),
+                    fields.get("Filename")?
+                ),
+                sha256: fields.get("SHA256")?.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The package names of a relationship field like `libc6 (>= 2.28), perl |
+/// perl5:any`, one list of alternatives per dependency, without version
+/// constraints or architecture qualifiers.
+pub fn dependencies(field: &str) -> Vec<Vec<String>> {
+    field
+        .split(',')
+        .map(|dependency| {
+            dependency
+                .split('|')
+                .filter_map(|alternative| alternative.split_whitespace().next())
+                .filter_map(|name| name.split(':').next())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .filter(|alternatives| !alternatives.is_empty())
+        .collect()
+}
+
+/// The fields of each stanza of a Debian control file, like a `Packages`
+/// index or the dpkg status file. Continuation lines are appended to their
+/// field on lines of their own.
+pub fn stanzas(text: &str) -> Vec<BTreeMap<&str, String>> {
+    text.split("\n\n")
+        .map(|stanza| {
+            let mut fields = BTreeMap::new();
+            let mut current: Option<&str> = None;
+            for line in stanza.lines() {
+                if line.starts_with([' ', '\t']) {
+                    if let Some(field) = current {
+                        let value: &mut String = fields.entry(field).or_default();
+                        value.push('\n');
+                        value.push_str(line.trim());
+                    }
+                } else if let Some((field, value)) = line.split_once(':') {
+                    fields.insert(field, value.trim().to_string());
+                    current = Some(field);
+                }
+            }
+            fields
+        })
+        .filter(|fields| !fields.is_empty())
+        .collect()
+}
+
+/// Reads the `Package` and `Version` fields of a `Packages` index.
+pub fn parse_packages(text: &str) -> BTreeMap<String, Vec<String>> {
+    let mut packages: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for stanza in text.split("\n\n") {
+        let field = |name: &str| {
+            stanza.lines().find_map(|line| {
+                line.strip_prefix(name)?
+                    .strip_prefix(':')
+                    .map(|value| value.trim().to_string())
+            })
+        };
+        if let (Some(package), Some(version)) = (field("Package"), field("Version")) {
+            packages.entry(package).or_default().push(version);
+        }
+    }
+    packages
+}
+
+/// Fails unless `available`, the packages of `origin`, offers every
+/// version in `pins`.
+pub fn check_pins(
+    available: &BTreeMap<String, Vec<String>>,
+    pins: &BTreeMap<String, String>,
+    origin: &str,
+) -> Result<()> {
+    match missing_pin(available, pins, origin) {
+        Some(message) => Err(Error::Apt(message)),
+        None => Ok(()),
+    }
+}
+
+/// Says which of `pins` `available` does not offer, if any.
+pub(crate) fn missing_pin(
+    available: &BTreeMap<String, Vec<String>>,
+    pins: &BTreeMap<String, String>,
+    origin: &str,
+) -> Option<String> {
+    pins.iter().find_map(|(package, version)| {
+        let versions = available.get(package).map_or(&[][..], Vec::as_slice);
+        if versions.contains(version) {
+            return None;
+        }
+        let offered = match versions {
+            [] => "no such package".to_string(),
+            versions => format!("only {}", versions.join(", ")),
+        };
+        Some(format!(
+            "{package}={version} is not available from {origin}, which has {offered}"
+        ))
+    })
+}
+
+/// Orders Debian versions like dpkg: by epoch, then upstream version, then
+/// Debian revision, where `~` sorts before anything, even the end.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_upstream, a_revision) = split_version(a);
+    let (b_epoch, b_upstream, b_revision) = split_version(b);
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_fragment(a_upstream, b_upstream))
+        .then_with(|| compare_fragment(a_revision, b_revision))
+}
+
+fn split_version(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+        None => (0, version),
+    };
+    let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+    (epoch, upstream, revision)
+}
+
+/// Compares alternating runs of non-digits, lexically, and of digits,
+/// numerically.
+fn compare_fragment(mut a: &str, mut b: &str) -> Ordering {
+    while !a.is_empty() || !b.is_empty() {
+        let ((a_text, a_rest), (b_text, b_rest)) = (split_run(a, false), split_run(b, false));
+        let ((a_number, a_rest), (b_number, b_rest)) =
+            (split_run(a_rest, true), split_run(b_rest, true));
+        let (a_number, b_number) = (
+            a_number.trim_start_matches('0'),
+            b_number.trim_start_matches('0'),
+        );
+        let order = compare_text(a_text, b_text).then_with(|| {
+            a_number
+                .len()
+                .cmp(&b_number.len())
+                .then_with(|| a_number.cmp(b_number))
+        });
+        if order.is_ne() {
+            return order;
+        }
+        (a, b) = (a_rest, b_rest);
+    }
+    Ordering::Equal
+}
+
+/// Splits the leading run of digits, or of non-digits, off `s`.
+fn split_run(s: &str, digits: bool) -> (&str, &str) {
+    s.split_at(
+        s.find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(s.len()),
+    )
+}
+
+fn compare_text(a: &str, b: &str) -> Ordering {
+    let weight = |c: Option<char>| match c {
+        Some('~') => -1,
+        None => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    };
+    let (mut a, mut b) = (a.chars(), b.chars());
+    loop {
+        let (x, y) = (a.next(), b.next());
+        if x.is_none() && y.is_none() {
+            return Ordering::Equal;
+        }
+        let order = weight(x).cmp(&weight(y));
+        if order.is_ne() {
+            return order;
+        }
+    }
+}
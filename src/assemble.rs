@@ -0,0 +1,667 @@
+//! Daemonless image assembly: the base image plus layers built from the
+//! verified downloads the Dockerfile would otherwise fetch, written to an
+//! OCI image layout without docker.
+//!
+//! Apt packages are unpacked from their `.deb` archives when `image.lock`
+//! knows the base image's Debian suite. Steps that have to run commands
+//! inside the image (apk and `cargo install`) can't be assembled this way.
+
+use std::{collections::BTreeMap, fs, io::Read, path::PathBuf};
+
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+
+use crate::{
+    apt::{self, Archives},
+    config, deb,
+    dockerfile::TrunkSource,
+    http,
+    layer::{self, Layer, LayerBuilder},
+    lock::ImageLock,
+    manifest::{Binaryen, Manifest},
+    matrix::Entry,
+    oci::{self, Descriptor, ImageManifest, Index, Layout},
+    platform::Platform,
+    provenance::{self, BaseImage, Download, Source},
+    registry::{PlatformImage, Registry},
+    sbom::{self, Inventory, Tool},
+    target::Target,
+    trunk::{self, sha256_hex, LockedRelease, TrunkLock},
+    wasm_bindgen, wasm_opt, Error, Result,
+};
+
+pub const RUST_DIST: &str = "https://static.rust-lang.org";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Layout directory, or a tarball if it ends in `.tar`.
+    pub output: PathBuf,
+    /// The reference the image is described as in its SBOMs.
+    pub name: String,
+    /// Server the rust-std components of `[rust] targets` come from.
+    pub rust_dist: String,
+    /// Where crate sources come from, for the dependency trees in SBOMs.
+    pub crates_dl: String,
+    /// The trunk-docker executable to install as the entrypoint.
+    pub entrypoint_binary: Option<PathBuf>,
+    /// Leave out the steps that need to run commands instead of failing.
+    pub partial: bool,
+    /// Debian archives the apt packages are downloaded from; by default the
+    /// manifest's snapshot.
+    pub apt_archives: Option<Archives>,
+    /// Checkout of this repository, whose revision the provenance records.
+    pub source: PathBuf,
+}
+
+/// The result of an assembly.
+#[derive(Debug)]
+pub struct Assembled {
+    pub manifest: Descriptor,
+    /// Digests of the image's layers, base layers first.
+    pub layers: Vec<String>,
+    /// Dockerfile steps left out with [`Options::partial`].
+    pub skipped: Vec<String>,
+    /// SBOMs written next to the output; they are also attached to the
+    /// image in the layout.
+    pub sboms: BTreeMap<sbom::Format, PathBuf>,
+    /// Provenance statement written next to the output, and attached like
+    /// the SBOMs.
+    pub provenance: PathBuf,
+}
+
+/// The Dockerfile steps of an entry that run commands in the image.
+pub fn run_steps(
+    manifest: &Manifest,
+    lock: &TrunkLock,
+    image_lock: &ImageLock,
+    entry: &Entry,
+    platform: Platform,
+) -> Vec<String> {
+    let mut steps = Vec::new();
+    if !manifest.apt.packages.is_empty() && apt_suite(manifest, image_lock, entry).is_none() {
+        steps.push(format!(
+            "apt-get install {}",
+            manifest.apt.packages.join(" ")
+        ));
+    }
+    if !manifest.apk.packages.is_empty() {
+        steps.push(format!("apk add {}", manifest.apk.packages.join(" ")));
+    }
+    if prebuilt_trunk(manifest, lock, entry, platform).is_none() {
+        steps.push(format!(
+            "cargo install --locked trunk --version {}",
+            entry.trunk
+        ));
+    }
+    for version in &manifest.wasm_bindgen.versions {
+        if image_lock
+            .wasm_bindgen_release(manifest, entry, platform, version)
+            .is_none()
+        {
+            steps.push(format!(
+                "cargo install --locked wasm-bindgen-cli --version {version}"
+            ));
+        }
+    }
+    for tool in &manifest.cargo {
+        steps.push(format!(
+            "cargo install --locked {} --version {}",
+            tool.name, tool.version
+        ));
+    }
+    steps
+}
+
+/// The Debian suite the apt packages of `entry` are unpacked from, if
+/// `image.lock` knows the base image's.
+fn apt_suite<'a>(manifest: &Manifest, image_lock: &'a ImageLock, entry: &Entry) -> Option<&'a str> {
+    image_lock.suite(&manifest.base, &entry.rust)
+}
+
+/// The locked trunk release the image of `entry` gets, if it uses one.
+fn prebuilt_trunk<'a>(
+    manifest: &Manifest,
+    lock: &'a TrunkLock,
+    entry: &Entry,
+    platform: Platform,
+) -> Option<&'a LockedRelease> {
+    match TrunkSource::for_entry(manifest, lock, entry, platform) {
+        TrunkSource::Prebuilt => lock.get(&entry.trunk, platform),
+        TrunkSource::Cargo => None,
+    }
+}
+
+/// Assembles the image of `entry` on `platform` into `options.output`,
+/// from the base image `image_lock` pins if it pins one.
+pub fn assemble(
+    manifest: &Manifest,
+    lock: &TrunkLock,
+    image_lock: &ImageLock,
+    entry: &Entry,
+    platform: Platform,
+    options: &Options,
+) -> Result<Assembled> {
+    let skipped = run_steps(manifest, lock, image_lock, entry, platform);
+    if !skipped.is_empty() && !options.partial {
+        return Err(Error::Assemble(format!(
+            "these steps run commands in the image and need docker: {}; \
+             pass --partial to leave them out",
+            skipped.join(", ")
+        )));
+    }
+    if let Some(binaryen) = &manifest.binaryen {
+        wasm_opt::check(binaryen.version, &entry.trunk)?;
+    }
+    let entrypoint = match (&manifest.entrypoint, &options.entrypoint_binary) {
+        (None, _) => None,
+        (Some(_), Some(path)) => Some(fs::read(path).map_err(|source| Error::Io {
+            path: path.clone(),
+            source,
+        })?),
+        (Some(_), None) => {
+            return Err(Error::Assemble(
+                "`[entrypoint]` needs a trunk-docker executable to install".into(),
+            ))
+        }
+    };
+
+    let source = Source::of_checkout(&options.source)?;
+
+    let tarball = options.output.extension().is_some_and(|ext| ext == "tar");
+    let dir = if tarball {
+        options.output.with_extension("layout")
+    } else {
+        options.output.clone()
+    };
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|source| Error::Write {
+            path: dir.clone(),
+            source,
+        })?;
+    }
+    let mut layout = Layout::create(&dir)?;
+
+    let base_reference = image_lock.base_reference(&manifest.base, &entry.rust);
+    let base = pull(&base_reference, platform)?;
+    let base_image = BaseImage::new(&base_reference, &base.image, &base.config);
+    let mut config = base.config;
+    let mut layers = Vec::new();
+    for blob in &base.layers {
+        layers.push(layout.add_blob(oci::LAYER_MEDIA_TYPE, blob)?);
+    }
+
+    let mut tools = Vec::new();
+    let mut downloads = Vec::new();
+    if let Some(suite) = apt_suite(manifest, image_lock, entry) {
+        if !manifest.apt.packages.is_empty() {
+            let archives = options
+                .apt_archives
+                .clone()
+                .unwrap_or_else(|| manifest.apt.archives());
+            let base_layers: Vec<&[u8]> = base.layers.iter().map(Vec::as_slice).collect();
+            let (builder, debs) = apt_layer(
+                manifest,
+                image_lock,
+                entry,
+                platform,
+                &archives,
+                suite,
+                &base_layers,
+            )?;
+            tools.push((
+                format!("apt packages {}", manifest.apt.packages.join(", ")),
+                builder,
+            ));
+            downloads.extend(debs);
+        }
+    }
+    if let Some(release) = prebuilt_trunk(manifest, lock, entry, platform) {
+        let mut builder = LayerBuilder::new();
+        builder.file(
+            "/usr/local/cargo/bin/trunk",
+            0o755,
+            trunk::binary(&release.fetch()?)?,
+        );
+        tools.push((
+            format!("trunk {} from {}", entry.trunk, release.url),
+            builder,
+        ));
+        downloads.push(Download {
+            uri: release.url.clone(),
+            sha256: release.sha256.clone(),
+        });
+    }
+    if let Some(binaryen) = &manifest.binaryen {
+        let (builder, download) = binaryen_layer(binaryen, platform)?;
+        tools.push((format!("binaryen version_{}", binaryen.version), builder));
+        downloads.push(download);
+    }
+    for version in &manifest.wasm_bindgen.versions {
+        let Some(release) = image_lock.wasm_bindgen_release(manifest, entry, platform, version)
+        else {
+            continue;
+        };
+        tools.push((
+            format!("wasm-bindgen-cli {version} from {}", release.url),
+            wasm_bindgen_layer(version, &release.fetch()?)?,
+        ));
+        downloads.push(Download {
+            uri: release.url.clone(),
+            sha256: release.sha256.clone(),
+        });
+    }
+    if !manifest.rust.targets.is_empty() {
+        let base_layers: Vec<&[u8]> = base.layers.iter().map(Vec::as_slice).collect();
+        tools.push((
+            format!("rust-std for {}", manifest.rust.targets.join(", ")),
+            rust_std_layer(
+                &options.rust_dist,
+                &config,
+                platform,
+                &manifest.rust.targets,
+                &base_layers,
+            )?,
+        ));
+    }
+    if let Some(binary) = entrypoint {
+        let mut builder = LayerBuilder::new();
+        builder.file("/usr/local/bin/trunk-docker", 0o755, binary);
+        tools.push(("trunk-docker entrypoint".to_string(), builder));
+        config["config"]["Entrypoint"] = json!(["trunk-docker", "entrypoint"]);
+        config["config"]["Cmd"] = json!(["bash"]);
+    }
+    for (description, builder) in tools {
+        if builder.is_empty() {
+            continue;
+        }
+        let Layer { diff_id, blob } = builder.finish()?;
+        layers.push(layout.add_blob(oci::LAYER_MEDIA_TYPE, &blob)?);
+        append(&mut config, "/rootfs/diff_ids", json!(diff_id))?;
+        append(
+            &mut config,
+            "/history",
+            json!({ "created_by": format!("trunk-docker assemble: {description}") }),
+        )?;
+    }
+
+    let config = layout.add_blob(oci::CONFIG_MEDIA_TYPE, &oci::to_canonical_json(&config))?;
+    let mut image = ImageManifest {
+        schema_version: 2,
+        media_type: Some(oci::MANIFEST_MEDIA_TYPE.to_string()),
+        artifact_type: None,
+        config,
+        layers,
+        subject: None,
+        annotations: Default::default(),
+    };
+    image.annotations.extend([
+        (
+            "org.opencontainers.image.base.name".to_string(),
+            base_reference,
+        ),
+        (
+            "org.opencontainers.image.base.digest".to_string(),
+            base_image.digest.clone(),
+        ),
+    ]);
+    let mut descriptor =
+        layout.add_blob(oci::MANIFEST_MEDIA_TYPE, &oci::to_canonical_json(&image))?;
+    descriptor.platform = Some(platform.into());
+    layout.index = Index::new(vec![descriptor.clone()]);
+    layout.save()?;
+
+    // The only crates assembly installs are prebuilt releases.
+    let installed: Vec<Tool> = sbom::tools(manifest, entry)
+        .into_iter()
+        .filter(|tool| match tool {
+            Tool::Crate { name, .. } if name == "trunk" => {
+                prebuilt_trunk(manifest, lock, entry, platform).is_some()
+            }
+            Tool::Crate { name, version } if name == "wasm-bindgen-cli" => image_lock
+                .wasm_bindgen_release(manifest, entry, platform, version)
+                .is_some(),
+            Tool::Crate { .. } => false,
+            Tool::Release { .. } => true,
+        })
+        .collect();
+    let inventory = Inventory::of_image(&options.name, &layout, &installed, &options.crates_dl)?;
+    let sboms = inventory.write(&options.output.with_extension(""))?;
+    for format in sbom::Format::ALL {
+        let document = inventory.render(format);
+        layout.add_artifact(&sbom::artifact(format, document.as_bytes(), &descriptor))?;
+    }
+    let build = provenance::Build {
+        repository: Target::parse_reference(&options.name)?.0.to_string(),
+        digest: descriptor.digest.clone(),
+        builder: "assemble",
+        trunk: entry.trunk.clone(),
+        rust: entry.rust.clone(),
+        platform,
+        dockerfile: None,
+        source,
+        base: base_image,
+        downloads,
+        host: provenance::host(),
+    };
+    let statement = build.render();
+    let provenance = options.output.with_extension("intoto.json");
+    config::write(&provenance, &statement)?;
+    layout.add_artifact(&provenance::artifact(statement.as_bytes(), &descriptor))?;
+    layout.save()?;
+
+    if tarball {
+        layout.write_tar(&options.output)?;
+        fs::remove_dir_all(&dir).map_err(|source| Error::Write {
+            path: dir.clone(),
+            source,
+        })?;
+    }
+    descriptor.platform = None;
+    Ok(Assembled {
+        manifest: descriptor,
+        layers: image.layers.into_iter().map(|l| l.digest).collect(),
+        skipped,
+        sboms,
+        provenance,
+    })
+}
+
+/// A base image pulled for one platform.
+struct Base {
+    image: PlatformImage,
+    config: Value,
+    layers: Vec<Vec<u8>>,
+}
+
+fn pull(reference: &str, platform: Platform) -> Result<Base> {
+    let (target, tag) = Target::parse_reference(reference)?;
+    let name = target.name();
+    let mut registry = Registry::for_target(&target);
+    let image = registry
+        .platform_image(&name, &tag, platform)?
+        .ok_or_else(|| Error::Assemble(format!("base image {reference} does not exist")))?;
+    let config = parse_json(
+        reference,
+        &registry.blob(&name, &image.manifest.config.digest)?,
+    )?;
+    let layers = image
+        .manifest
+        .layers
+        .iter()
+        .map(|layer| registry.blob(&name, &layer.digest))
+        .collect::<Result<_>>()?;
+    Ok(Base {
+        image,
+        config,
+        layers,
+    })
+}
+
+/// Installs the apt packages at their locked versions, with the
+/// dependencies the base image lacks, from their `.deb` archives in
+/// `archives`, and records them in the dpkg database.
+fn apt_layer(
+    manifest: &Manifest,
+    image_lock: &ImageLock,
+    entry: &Entry,
+    platform: Platform,
+    archives: &Archives,
+    suite: &str,
+    base_layers: &[&[u8]],
+) -> Result<(LayerBuilder, Vec<Download>)> {
+    let mut status =
+        String::from_utf8_lossy(&layer::read_file(base_layers, deb::STATUS)?.unwrap_or_default())
+            .trim_end()
+            .to_string();
+    let mut packages = manifest.apt.packages.clone();
+    packages.sort();
+    packages.dedup();
+    let requested: Vec<(&str, Option<&str>)> = packages
+        .iter()
+        .map(|package| {
+            let version = image_lock.apt_pin(manifest, entry, platform, package);
+            (package.as_str(), version)
+        })
+        .collect();
+    let index = apt::index(archives, suite, platform)?;
+    let installs = deb::resolve(&index, &deb::installed(&status), &requested)?;
+
+    // Debian's merged /usr turns /bin, /lib and /sbin into links to their
+    // /usr counterparts; files packaged under them go there instead.
+    let mut merged: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut builder = LayerBuilder::new();
+    let mut downloads = Vec::new();
+    for package in installs {
+        let archive = http::get(&package.url)?.ok_or_else(|| Error::Http {
+            url: package.url.clone(),
+            message: "package does not exist".into(),
+        })?;
+        let actual = sha256_hex(&archive);
+        if actual != package.sha256 {
+            return Err(Error::Checksum(format!(
+                "{} has sha256 {actual}, but the archive index expects {}",
+                package.url, package.sha256
+            )));
+        }
+        let unpacked = deb::unpack(&archive)?;
+        let info = format!("{}/{}", deb::INFO_DIR, unpacked.info_name());
+        let stanza = unpacked.status();
+        let mut list = String::new();
+        for (path, entry) in unpacked.entries {
+            let (top, rest) = path[1..].split_once('/').unwrap_or((&path[1..], ""));
+            let link = match merged.get(top) {
+                Some(link) => link.clone(),
+                None => {
+                    let link = layer::read_link(base_layers, top)?;
+                    merged.insert(top.to_string(), link.clone());
+                    link
+                }
+            };
+            let path = match link {
+                Some(_) if rest.is_empty() => continue,
+                Some(target) => format!("/{}/{rest}", target.trim_matches('/')),
+                None => path,
+            };
+            match entry {
+                deb::Entry::Dir { mode } => builder.dir(&path, mode),
+                deb::Entry::File { mode, contents } => builder.file(&path, mode, contents),
+                deb::Entry::Symlink { target } => builder.symlink(&path, &target),
+            };
+            list.push_str(&path);
+            list.push('\n');
+        }
+        builder.file(&format!("{info}.list"), 0o644, list.into_bytes());
+        if let Some(md5sums) = unpacked.md5sums {
+            builder.file(&format!("{info}.md5sums"), 0o644, md5sums);
+        }
+        status.push_str("\n\n");
+        status.push_str(stanza.trim_end());
+        downloads.push(Download {
+            uri: package.url.clone(),
+            sha256: actual,
+        });
+    }
+    status.push('\n');
+    builder.file(deb::STATUS, 0o644, status.trim_start().as_bytes().to_vec());
+    Ok((builder, downloads))
+}
+
+fn binaryen_layer(binaryen: &Binaryen, platform: Platform) -> Result<(LayerBuilder, Download)> {
+    let url = binaryen.url(platform);
+    let missing = |url: &str| Error::Http {
+        url: url.to_string(),
+        message: "release asset does not exist".into(),
+    };
+    let tarball = http::get(&url)?.ok_or_else(|| missing(&url))?;
+    let expected = match binaryen.sha256.get(platform.machine()) {
+        Some(sha256) => sha256.clone(),
+        None => {
+            let checksum_url = format!("{url}.sha256");
+            let text = http::get(&checksum_url)?.ok_or_else(|| missing(&checksum_url))?;
+            String::from_utf8_lossy(&text)
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        }
+    };
+    let actual = sha256_hex(&tarball);
+    if actual != expected {
+        return Err(Error::Checksum(format!(
+            "{url} has sha256 {actual}, expected {expected}"
+        )));
+    }
+    let prefix = format!("binaryen-version_{}/bin/", binaryen.version);
+    let mut builder = LayerBuilder::new();
+    for_each_file(&tarball, |path, mode, contents| {
+        if let Some(name) = path.strip_prefix(&prefix) {
+            builder.file(&format!("/usr/local/bin/{name}"), mode, contents);
+        }
+    })?;
+    Ok((
+        builder,
+        Download {
+            uri: url,
+            sha256: actual,
+        },
+    ))
+}
+
+/// Unpacks a wasm-bindgen-cli release into the bundle directory of
+/// `version`, as the Dockerfile does with `tar --strip-components=1`.
+fn wasm_bindgen_layer(version: &str, tarball: &[u8]) -> Result<LayerBuilder> {
+    let bin = format!("{}/{version}/bin", wasm_bindgen::BUNDLE_DIR);
+    let mut builder = LayerBuilder::new();
+    for_each_file(tarball, |path, mode, contents| {
+        if let Some((_, name)) = path.split_once('/') {
+            builder.file(&format!("{bin}/{name}"), mode, contents);
+        }
+    })?;
+    Ok(builder)
+}
+
+/// Installs the rust-std components of `targets` into the base image's
+/// toolchain, as `rustup target add` would.
+fn rust_std_layer(
+    dist: &str,
+    config: &Value,
+    platform: Platform,
+    targets: &[String],
+    base_layers: &[&[u8]],
+) -> Result<LayerBuilder> {
+    let env = |key: &str| oci::config_env(config, key);
+    let version = env("RUST_VERSION")
+        .ok_or_else(|| Error::Assemble("the base image does not set RUST_VERSION".into()))?;
+    let rustup_home = env("RUSTUP_HOME").unwrap_or_else(|| "/usr/local/rustup".into());
+    let toolchain = format!(
+        "{rustup_home}/toolchains/{version}-{}",
+        platform.rust_target()
+    );
+
+    let channel_url = format!("{dist}/dist/channel-rust-{version}.toml");
+    let channel = http::get(&channel_url)?.ok_or_else(|| Error::Http {
+        url: channel_url.clone(),
+        message: "no such Rust release".into(),
+    })?;
+    let channel: toml::Value = toml::from_str(&String::from_utf8_lossy(&channel))?;
+
+    let components_path = format!("{toolchain}/lib/rustlib/components");
+    let mut components = String::from_utf8_lossy(
+        &layer::read_file(base_layers, &components_path)?.unwrap_or_default(),
+    )
+    .into_owned();
+    let mut builder = LayerBuilder::new();
+    for target in targets {
+        let component = format!("rust-std-{target}");
+        if components.lines().any(|line| line == component) {
+            continue;
+        }
+        let package = &channel["pkg"]["rust-std"]["target"][target.as_str()];
+        let (Some(url), Some(hash)) = (package["url"].as_str(), package["hash"].as_str()) else {
+            return Err(Error::Assemble(format!(
+                "Rust {version} has no rust-std for {target}"
+            )));
+        };
+        let tarball = http::get(url)?.ok_or_else(|| Error::Http {
+            url: url.to_string(),
+            message: "component does not exist".into(),
+        })?;
+        let actual = sha256_hex(&tarball);
+        if actual != hash {
+            return Err(Error::Checksum(format!(
+                "{url} has sha256 {actual}, but the channel manifest expects {hash}"
+            )));
+        }
+        let mut installed = Vec::new();
+        for_each_file(&tarball, |path, mode, contents| {
+            // <package>/<component>/<path in the toolchain>
+            let mut parts = path.splitn(3, '/');
+            let (Some(_), Some(dir), Some(rest)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return;
+            };
+            if dir != component || rest == "manifest.in" {
+                return;
+            }
+            installed.push(format!("file:{rest}\n"));
+            builder.file(&format!("{toolchain}/{rest}"), mode, contents);
+        })?;
+        builder.file(
+            &format!("{toolchain}/lib/rustlib/manifest-{component}"),
+            0o644,
+            installed.concat().into_bytes(),
+        );
+        components.push_str(&component);
+        components.push('\n');
+    }
+    if !builder.is_empty() {
+        builder.file(&components_path, 0o644, components.into_bytes());
+    }
+    Ok(builder)
+}
+
+/// Calls `f` with the path, mode and contents of every regular file in a
+/// gzip-compressed tarball.
+fn for_each_file(tarball: &[u8], mut f: impl FnMut(&str, u32, Vec<u8>)) -> Result<()> {
+    let error = |err: std::io::Error| Error::Archive(err.to_string());
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries().map_err(error)? {
+        let mut entry = entry.map_err(error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(error)?.to_string_lossy().into_owned();
+        let mode = entry.header().mode().map_err(error)?;
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).map_err(error)?;
+        f(path.trim_start_matches("./"), mode, contents);
+    }
+    Ok(())
+}
+
+fn append(config: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    let (parent, key) = pointer.rsplit_once('/').expect("pointer has a key");
+    let parent = if parent.is_empty() {
+        Some(&mut *config)
+    } else {
+        config.pointer_mut(parent)
+    };
+    let Some(Value::Object(parent)) = parent else {
+        return Err(Error::Assemble(format!(
+            "the base image config has no {parent:?} object"
+        )));
+    };
+    match parent.entry(key).or_insert_with(|| json!([])) {
+        Value::Array(array) => {
+            array.push(value);
+            Ok(())
+        }
+        _ => Err(Error::Assemble(format!(
+            "{pointer} in the base image config is not a list"
+        ))),
+    }
+}
+
+fn parse_json<T: for<'de> serde::Deserialize<'de>>(reference: &str, bytes: &[u8]) -> Result<T> {
+    serde_json::from_slice(bytes).map_err(|err| Error::Oci(format!("{reference}: {err}")))
+}
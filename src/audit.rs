@@ -0,0 +1,383 @@
+//! RustSec audit of the crates compiled into the cargo-installed tools.
+//!
+//! Advisories are read from a local checkout of the RustSec advisory
+//! database (<https://github.com/rustsec/advisory-db>). The crates a tool
+//! is built from come from the `Cargo.lock` it was published with, which
+//! the image installs it with (`cargo install --locked`).
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+};
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::{config, crates, sbom::Tool, Error, Result};
+
+/// What an advisory is about, as far as the policy is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// A security vulnerability.
+    Vulnerability,
+    /// The crate is no longer maintained.
+    Unmaintained,
+    /// The crate's safe API can cause undefined behaviour.
+    Unsound,
+    /// Anything else worth knowing.
+    Notice,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Vulnerability => "vulnerability",
+            Kind::Unmaintained => "unmaintained",
+            Kind::Unsound => "unsound",
+            Kind::Notice => "notice",
+        })
+    }
+}
+
+/// When an audit fails the release, from `[audit]` in `image.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Advisory kinds that fail the release; the others are reported only.
+    #[serde(default = "default_deny")]
+    pub deny: BTreeSet<Kind>,
+    /// Advisory IDs that were reviewed and accepted.
+    #[serde(default)]
+    pub ignore: BTreeSet<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            deny: default_deny(),
+            ignore: BTreeSet::new(),
+        }
+    }
+}
+
+fn default_deny() -> BTreeSet<Kind> {
+    BTreeSet::from([Kind::Vulnerability])
+}
+
+/// One advisory of the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advisory {
+    /// E.g. `RUSTSEC-2020-0071`.
+    pub id: String,
+    pub package: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub kind: Kind,
+    pub withdrawn: bool,
+    pub patched: Vec<VersionReq>,
+    pub unaffected: Vec<VersionReq>,
+}
+
+#[derive(Deserialize)]
+struct FrontMatter {
+    advisory: AdvisoryMetadata,
+    #[serde(default)]
+    versions: Versions,
+}
+
+#[derive(Deserialize)]
+struct AdvisoryMetadata {
+    id: String,
+    package: String,
+    url: Option<String>,
+    informational: Option<String>,
+    withdrawn: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct Versions {
+    #[serde(default)]
+    patched: Vec<VersionReq>,
+    #[serde(default)]
+    unaffected: Vec<VersionReq>,
+}
+
+impl FromStr for Advisory {
+    type Err = Error;
+
+    /// Parses an advisory file: TOML front matter in a ```` ```toml ````
+    /// block, followed by the Markdown description.
+    fn from_str(text: &str) -> Result<Self> {
+        let missing = || Error::Audit("the advisory has no TOML front matter".into());
+        let rest = text.strip_prefix("```toml").ok_or_else(missing)?;
+        let (front, description) = rest.split_once("\n```").ok_or_else(missing)?;
+        let front: FrontMatter = toml::from_str(front)?;
+        let title = description
+            .lines()
+            .find_map(|line| line.strip_prefix("# "))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let kind = match front.advisory.informational.as_deref() {
+            None => Kind::Vulnerability,
+            Some("unmaintained") => Kind::Unmaintained,
+            Some("unsound") => Kind::Unsound,
+            Some(_) => Kind::Notice,
+        };
+        Ok(Advisory {
+            id: front.advisory.id,
+            package: front.advisory.package,
+            title,
+            url: front.advisory.url,
+            kind,
+            withdrawn: front.advisory.withdrawn.is_some(),
+            patched: front.versions.patched,
+            unaffected: front.versions.unaffected,
+        })
+    }
+}
+
+impl Advisory {
+    /// Whether `version` of the package is affected.
+    pub fn affects(&self, version: &Version) -> bool {
+        !self.withdrawn
+            && !self
+                .patched
+                .iter()
+                .chain(&self.unaffected)
+                .any(|req| req.matches(version))
+    }
+}
+
+/// The advisories of a local advisory-db checkout, by package.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    advisories: BTreeMap<String, Vec<Advisory>>,
+}
+
+impl Database {
+    /// Reads the advisories under `crates/<package>/` of the checkout.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let read_dir = |path: &Path| {
+            fs::read_dir(path)
+                .and_then(|entries| entries.map(|e| e.map(|e| e.path())).collect())
+                .map_err(|source| Error::Io {
+                    path: path.to_owned(),
+                    source,
+                })
+        };
+        let mut database = Database::default();
+        let packages: Vec<_> = read_dir(&dir.join("crates"))?;
+        for package in packages.iter().filter(|p| p.is_dir()) {
+            let files: Vec<_> = read_dir(package)?;
+            for path in files
+                .iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "md"))
+            {
+                database.add(config::load(path)?);
+            }
+        }
+        for advisories in database.advisories.values_mut() {
+            advisories.sort_by(|a, b| a.id.cmp(&b.id));
+        }
+        Ok(database)
+    }
+
+    pub fn add(&mut self, advisory: Advisory) {
+        self.advisories
+            .entry(advisory.package.clone())
+            .or_default()
+            .push(advisory);
+    }
+
+    /// The advisories affecting `version` of `package`.
+    pub fn query(&self, package: &str, version: &Version) -> Vec<&Advisory> {
+        self.advisories
+            .get(package)
+            .into_iter()
+            .flatten()
+            .filter(|advisory| advisory.affects(version))
+            .collect()
+    }
+}
+
+/// An advisory affecting a crate compiled into the image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub advisory: String,
+    pub kind: Kind,
+    pub package: String,
+    pub version: String,
+    pub title: String,
+    pub url: Option<String>,
+    /// Versions with the fix, e.g. `>=0.2.23`.
+    pub patched: Vec<String>,
+    /// The tools the crate is compiled into, e.g. `trunk 0.14.0`.
+    pub tools: Vec<String>,
+    /// Whether the policy fails the release over it.
+    pub denied: bool,
+    /// Whether the policy lists it as accepted.
+    pub ignored: bool,
+}
+
+/// The result of auditing the tools of one or more images.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    /// The audited tools, e.g. `trunk 0.14.0`.
+    pub tools: Vec<String>,
+    /// Tools published without a `Cargo.lock`, whose dependencies can't be
+    /// audited.
+    pub unlocked: Vec<String>,
+    /// Number of distinct crates checked.
+    pub crates: usize,
+    /// Sorted by advisory and crate.
+    pub findings: Vec<Finding>,
+}
+
+/// Audits the crates compiled into the cargo-installed `tools` against
+/// `database`, downloading the tools' lockfiles from `crates_dl`.
+pub fn audit(
+    database: &Database,
+    tools: &[Tool],
+    crates_dl: &str,
+    policy: &Policy,
+) -> Result<Report> {
+    let mut report = Report {
+        tools: Vec::new(),
+        unlocked: Vec::new(),
+        crates: 0,
+        findings: Vec::new(),
+    };
+    // Crate and version to the tools compiled with it.
+    let mut packages: BTreeMap<(String, String), BTreeSet<String>> = BTreeMap::new();
+    for tool in tools {
+        let Tool::Crate { name, version } = tool else {
+            continue;
+        };
+        let label = format!("{name} {version}");
+        if report.tools.contains(&label) {
+            continue;
+        }
+        report.tools.push(label.clone());
+        let Some(lockfile) = crates::lockfile(crates_dl, name, version)? else {
+            report.unlocked.push(label.clone());
+            packages
+                .entry((name.clone(), version.clone()))
+                .or_default()
+                .insert(label);
+            continue;
+        };
+        for package in lockfile.closure(name, version) {
+            if package.source.is_some() && !package.from_crates_io() {
+                continue;
+            }
+            packages
+                .entry((package.name.clone(), package.version.clone()))
+                .or_default()
+                .insert(label.clone());
+        }
+    }
+    report.crates = packages.len();
+    for ((package, version), tools) in packages {
+        let Ok(parsed) = Version::parse(&version) else {
+            continue;
+        };
+        for advisory in database.query(&package, &parsed) {
+            let ignored = policy.ignore.contains(&advisory.id);
+            report.findings.push(Finding {
+                advisory: advisory.id.clone(),
+                kind: advisory.kind,
+                package: package.clone(),
+                version: version.clone(),
+                title: advisory.title.clone(),
+                url: advisory.url.clone(),
+                patched: advisory.patched.iter().map(ToString::to_string).collect(),
+                tools: tools.iter().cloned().collect(),
+                denied: !ignored && policy.deny.contains(&advisory.kind),
+                ignored,
+            });
+        }
+    }
+    report.findings.sort_by(|a, b| {
+        (&a.advisory, &a.package, &a.version).cmp(&(&b.advisory, &b.package, &b.version))
+    });
+    Ok(report)
+}
+
+impl Report {
+    pub fn denied(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| finding.denied)
+    }
+
+    /// Fails if the policy denies any finding.
+    pub fn check(&self) -> Result<()> {
+        let denied: Vec<String> = self
+            .denied()
+            .map(|f| format!("{} ({} {})", f.advisory, f.package, f.version))
+            .collect();
+        if denied.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Audit(format!(
+            "the image would ship crates with denied advisories: {}",
+            denied.join(", ")
+        )))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report serializes")
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        config::write(path, &(self.to_json() + "\n"))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "audited {} crates in {}",
+            self.crates,
+            self.tools.join(", ")
+        )?;
+        for tool in &self.unlocked {
+            writeln!(
+                f,
+                "warning: {tool} was published without a Cargo.lock; its dependencies are not audited"
+            )?;
+        }
+        for finding in &self.findings {
+            let status = if finding.denied {
+                "denied"
+            } else if finding.ignored {
+                "ignored"
+            } else {
+                "allowed"
+            };
+            writeln!(
+                f,
+                "{} {} {} ({}, {status}): {}",
+                finding.advisory, finding.package, finding.version, finding.kind, finding.title
+            )?;
+            writeln!(f, "  in {}", finding.tools.join(", "))?;
+            if finding.patched.is_empty() {
+                writeln!(f, "  no patched version")?;
+            } else {
+                writeln!(f, "  patched: {}", finding.patched.join(", "))?;
+            }
+            if let Some(url) = &finding.url {
+                writeln!(f, "  {url}")?;
+            }
+        }
+        let denied = self.denied().count();
+        writeln!(
+            f,
+            "{} advisories found, {denied} denied by the policy",
+            self.findings.len()
+        )
+    }
+}
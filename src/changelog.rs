@@ -0,0 +1,284 @@
+//! Release notes: for every published image, what changed since the image
+//! the push replaces, from the SBOMs of both, as Markdown for the README
+//! or a GitHub release.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::{
+    config,
+    diff::{self, Change},
+    oci::ImageManifest,
+    platform::Platform,
+    registry::Registry,
+    sbom::Format,
+    target::Target,
+    Error, Result,
+};
+
+/// Where a component is listed in the changelog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Section {
+    /// Debian or Alpine packages.
+    Packages,
+    /// Tools installed on top of the base image, and the Rust release.
+    Tools,
+    /// Crates compiled into the cargo-installed tools.
+    Crates,
+}
+
+impl Section {
+    const ALL: [Section; 3] = [Section::Packages, Section::Tools, Section::Crates];
+
+    fn title(self) -> &'static str {
+        match self {
+            Section::Packages => "OS packages",
+            Section::Tools => "Tools",
+            Section::Crates => "Crates compiled into the tools",
+        }
+    }
+}
+
+/// A component of an SBOM, with the versions found of it joined by `, `.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub section: Section,
+    pub name: String,
+    pub version: String,
+}
+
+/// The components of a CycloneDX SBOM, by package URL without the
+/// version, e.g. `pkg:deb/debian/curl`.
+pub fn components(document: &[u8], origin: &str) -> Result<BTreeMap<String, Component>> {
+    let bom: Value = serde_json::from_slice(document).map_err(|err| Error::Json {
+        path: origin.into(),
+        message: err.to_string(),
+    })?;
+    let mut components: BTreeMap<String, Component> = BTreeMap::new();
+    for component in bom["components"].as_array().into_iter().flatten() {
+        let (Some(purl), Some(name)) = (component["purl"].as_str(), component["name"].as_str())
+        else {
+            continue;
+        };
+        let key = purl.split('@').next().unwrap_or(purl).to_string();
+        let version = component["version"].as_str().unwrap_or_default();
+        let section = if purl.starts_with("pkg:deb/") || purl.starts_with("pkg:apk/") {
+            Section::Packages
+        } else if component["type"] == "application" || !purl.starts_with("pkg:cargo/") {
+            Section::Tools
+        } else {
+            Section::Crates
+        };
+        components
+            .entry(key)
+            .and_modify(|existing| {
+                let mut versions: Vec<&str> = existing.version.split(", ").collect();
+                versions.push(version);
+                versions.sort();
+                versions.dedup();
+                existing.version = versions.join(", ");
+            })
+            .or_insert(Component {
+                section,
+                name: name.to_string(),
+                version: version.to_string(),
+            });
+    }
+    Ok(components)
+}
+
+/// The image a push replaces.
+#[derive(Debug, Clone)]
+pub struct Previous {
+    pub image: String,
+    /// `None` if no CycloneDX SBOM is attached to it.
+    pub components: Option<BTreeMap<String, Component>>,
+}
+
+/// The changelog entry of one published image.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// The image's most specific tag, which names the entry.
+    pub tag: String,
+    /// Every reference the image is published under.
+    pub references: Vec<String>,
+    /// The platform whose images are compared.
+    pub platform: Platform,
+    pub components: BTreeMap<String, Component>,
+    /// `None` when nothing was published under the image's tags yet.
+    pub previous: Option<Previous>,
+}
+
+impl Entry {
+    /// Compares the image whose CycloneDX SBOM is at `sbom` with the one
+    /// published under the first of `tags` that exists in `target`.
+    pub fn against_published(
+        tag: String,
+        references: Vec<String>,
+        sbom: &Path,
+        target: &Target,
+        tags: &[String],
+        platform: Platform,
+    ) -> Result<Self> {
+        let document = fs::read(sbom).map_err(|source| Error::Io {
+            path: sbom.into(),
+            source,
+        })?;
+        let components = components(&document, &sbom.display().to_string())?;
+        let mut registry = Registry::for_target(target);
+        let name = target.name();
+        let mut previous = None;
+        for tag in tags {
+            let Some(image) = registry.try_platform_image(&name, tag, platform)? else {
+                continue;
+            };
+            let image_name = target.reference(tag);
+            let sbom = registry
+                .referrers(&name, &image.digest)?
+                .into_iter()
+                .find(|d| d.artifact_type.as_deref() == Some(Format::CycloneDx.media_type()));
+            let components = match sbom {
+                Some(descriptor) => {
+                    let Some((_, bytes)) = registry.manifest(&name, &descriptor.digest)? else {
+                        return Err(Error::Changelog(format!(
+                            "the SBOM of {image_name} is listed but missing"
+                        )));
+                    };
+                    let manifest: ImageManifest =
+                        serde_json::from_slice(&bytes).map_err(|err| {
+                            Error::Changelog(format!("the SBOM of {image_name}: {err}"))
+                        })?;
+                    let Some(layer) = manifest.layers.first() else {
+                        return Err(Error::Changelog(format!(
+                            "the SBOM of {image_name} has no document"
+                        )));
+                    };
+                    let document = registry.blob(&name, &layer.digest)?;
+                    Some(self::components(
+                        &document,
+                        &format!("the SBOM of {image_name}"),
+                    )?)
+                }
+                None => None,
+            };
+            previous = Some(Previous {
+                image: image_name,
+                components,
+            });
+            break;
+        }
+        Ok(Entry {
+            tag,
+            references,
+            platform,
+            components,
+            previous,
+        })
+    }
+
+    /// The version of the component `key` in the new image and, if known,
+    /// in the previous one.
+    fn versions(&self, key: &str) -> (Option<&str>, Option<&str>) {
+        let previous = self
+            .previous
+            .as_ref()
+            .and_then(|previous| previous.components.as_ref())
+            .and_then(|components| components.get(key));
+        (
+            previous.map(|c| c.version.as_str()),
+            self.components.get(key).map(|c| c.version.as_str()),
+        )
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "## {}\n", self.tag)?;
+        let references: Vec<String> = self.references.iter().map(|r| format!("`{r}`")).collect();
+        writeln!(f, "Published as {}.\n", references.join(", "))?;
+        for (name, key) in [("trunk", "pkg:cargo/trunk"), ("Rust", "pkg:generic/rust")] {
+            match self.versions(key) {
+                (Some(old), Some(new)) if old != new => writeln!(f, "- {name} {old} → {new}")?,
+                (_, Some(new)) => writeln!(f, "- {name} {new}")?,
+                (_, None) => {}
+            }
+        }
+        let Some(previous) = &self.previous else {
+            return writeln!(
+                f,
+                "\nFirst release: nothing was published under these tags for {} before.",
+                self.platform
+            );
+        };
+        let Some(old) = &previous.components else {
+            return writeln!(
+                f,
+                "\n`{}` has no SBOM attached, so what changed since is unknown.",
+                previous.image
+            );
+        };
+        writeln!(
+            f,
+            "\nChanges since `{}` on {}.",
+            previous.image, self.platform
+        )?;
+        let versions = |components: &BTreeMap<String, Component>, section| {
+            components
+                .iter()
+                .filter(|(_, c)| c.section == section)
+                .map(|(key, c)| (key.clone(), c.version.clone()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        for section in Section::ALL {
+            let changes = diff::changes(
+                &versions(old, section),
+                &versions(&self.components, section),
+            );
+            writeln!(f, "\n### {}\n", section.title())?;
+            if changes.is_empty() {
+                writeln!(f, "No changes.")?;
+                continue;
+            }
+            let count =
+                |kind: fn(&Change<String>) -> bool| changes.values().filter(|c| kind(c)).count();
+            writeln!(
+                f,
+                "{} changed, {} added, {} removed.\n",
+                count(|c| matches!(c, Change::Changed { .. })),
+                count(|c| matches!(c, Change::Added { .. })),
+                count(|c| matches!(c, Change::Removed { .. })),
+            )?;
+            writeln!(f, "| Name | Before | After |\n| --- | --- | --- |")?;
+            for (key, change) in &changes {
+                let name = self
+                    .components
+                    .get(key)
+                    .or_else(|| old.get(key))
+                    .map_or(key.as_str(), |c| c.name.as_str());
+                let (before, after) = match change {
+                    Change::Added { new } => ("", new.as_str()),
+                    Change::Removed { old } => (old.as_str(), ""),
+                    Change::Changed { old, new } => (old.as_str(), new.as_str()),
+                };
+                writeln!(f, "| {name} | {before} | {after} |")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes every entry to `<dir>/<tag>.md`, and returns the paths.
+pub fn write(dir: &Path, entries: &[Entry]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = dir.join(format!("{}.md", entry.tag));
+        config::write(&path, &entry.to_string())?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
@@ -0,0 +1,29 @@
+use std::{fs, path::Path, str::FromStr};
+
+use crate::{Error, Result};
+
+/// Reads and parses a config file, attributing parse errors to its path.
+pub(crate) fn load<T: FromStr<Err = Error>>(path: &Path) -> Result<T> {
+    let text = fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    text.parse().map_err(|source| Error::Config {
+        path: path.to_owned(),
+        source: Box::new(source),
+    })
+}
+
+/// Writes a generated file, creating its parent directories.
+pub(crate) fn write(path: &Path, contents: &str) -> Result<()> {
+    let write = || {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)
+    };
+    write().map_err(|source| Error::Write {
+        path: path.to_owned(),
+        source,
+    })
+}
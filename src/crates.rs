@@ -0,0 +1,107 @@
+//! Published crate sources, for the dependency trees of the cargo-installed
+//! tools.
+
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+use crate::{http, Error, Result};
+
+/// Where crates.io serves `.crate` files from.
+pub const CRATES_DL: &str = "https://static.crates.io/crates";
+
+/// A `Cargo.lock`, as far as it describes the dependency graph.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+    /// `name`, `name version` or `name version (source)`.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+impl Lockfile {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// The locked package a `dependencies` entry refers to.
+    pub fn resolve(&self, dependency: &str) -> Option<&LockedPackage> {
+        let mut parts = dependency.split_whitespace();
+        let name = parts.next()?;
+        let version = parts.next();
+        self.package
+            .iter()
+            .find(|p| p.name == name && version.is_none_or(|v| p.version == v))
+    }
+
+    /// The packages `name` (at `version`) depends on, directly or not,
+    /// including itself.
+    pub fn closure(&self, name: &str, version: &str) -> Vec<&LockedPackage> {
+        let mut found: Vec<&LockedPackage> = Vec::new();
+        let mut pending: Vec<&LockedPackage> = self
+            .package
+            .iter()
+            .filter(|p| p.name == name && p.version == version)
+            .collect();
+        while let Some(package) = pending.pop() {
+            if found.contains(&package) {
+                continue;
+            }
+            found.push(package);
+            pending.extend(package.dependencies.iter().filter_map(|d| self.resolve(d)));
+        }
+        found.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        found
+    }
+}
+
+impl LockedPackage {
+    /// The package URL, e.g. `pkg:cargo/trunk@0.14.0`.
+    pub fn purl(&self) -> String {
+        purl(&self.name, &self.version)
+    }
+
+    pub fn from_crates_io(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| source.starts_with("registry+"))
+    }
+}
+
+pub fn purl(name: &str, version: &str) -> String {
+    format!("pkg:cargo/{name}@{version}")
+}
+
+/// The `Cargo.lock` that `name` was published with, if it has one.
+/// Binary crates are published with theirs, which is what `cargo install
+/// --locked` builds with.
+pub fn lockfile(dl: &str, name: &str, version: &str) -> Result<Option<Lockfile>> {
+    let url = format!("{dl}/{name}/{name}-{version}.crate");
+    let archive = http::get(&url)?.ok_or_else(|| Error::Http {
+        url: url.clone(),
+        message: "crate does not exist".into(),
+    })?;
+    let error = |err: std::io::Error| Error::Archive(format!("{url}: {err}"));
+    let wanted = format!("{name}-{version}/Cargo.lock");
+    let mut archive = tar::Archive::new(GzDecoder::new(archive.as_slice()));
+    for entry in archive.entries().map_err(error)? {
+        let mut entry = entry.map_err(error)?;
+        if entry.path().map_err(error)?.to_string_lossy() != wanted {
+            continue;
+        }
+        let mut text = String::new();
+        entry.read_to_string(&mut text).map_err(error)?;
+        return Lockfile::parse(&text).map(Some);
+    }
+    Ok(None)
+}
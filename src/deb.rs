@@ -0,0 +1,299 @@
+//! Installing Debian packages without dpkg, for daemonless assembly:
+//! picking the packages an install takes, unpacking `.deb` archives and
+//! recording them in the dpkg database. Maintainer scripts are not run.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io::Read,
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    apt::{self, Package},
+    Error, Result,
+};
+
+/// The dpkg status file, which lists the installed packages.
+pub const STATUS: &str = "/var/lib/dpkg/status";
+
+/// Where dpkg keeps the file lists and checksums of the installed packages.
+pub const INFO_DIR: &str = "/var/lib/dpkg/info";
+
+/// What the packages installed according to a dpkg status file satisfy:
+/// their names and the virtual packages they provide.
+pub fn installed(status: &str) -> BTreeSet<String> {
+    apt::stanzas(status)
+        .into_iter()
+        .filter(|fields| {
+            fields
+                .get("Status")
+                .is_some_and(|status| status.ends_with(" installed"))
+        })
+        .flat_map(|fields| {
+            let provides = fields.get("Provides").map_or("", String::as_str);
+            let provides = apt::dependencies(provides).concat();
+            fields.get("Package").cloned().into_iter().chain(provides)
+        })
+        .collect()
+}
+
+/// The packages installing `requested` takes, sorted by name: each
+/// requested package at its version, or else its newest one, and the
+/// dependencies that nothing `installed` or picked before satisfies yet.
+/// A dependency takes its first alternative `index` has, at its newest
+/// version, or else the first package providing it. Version constraints
+/// are not checked: the packages of one snapshot are installable together.
+pub fn resolve<'a>(
+    index: &'a [Package],
+    installed: &BTreeSet<String>,
+    requested: &[(&str, Option<&str>)],
+) -> Result<Vec<&'a Package>> {
+    let mut by_name: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+    let mut providers: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+    for package in index {
+        by_name.entry(&package.name).or_default().push(package);
+        for virtual_package in &package.provides {
+            providers.entry(virtual_package).or_default().push(package);
+        }
+    }
+    let newest = |name: &str| {
+        by_name
+            .get(name)?
+            .iter()
+            .max_by(|a, b| apt::compare_versions(&a.version, &b.version))
+            .copied()
+    };
+
+    let mut picked = Picked {
+        satisfied: installed.clone(),
+        packages: BTreeMap::new(),
+        queue: VecDeque::new(),
+    };
+    for &(name, version) in requested {
+        let package = match version {
+            Some(version) => by_name
+                .get(name)
+                .and_then(|packages| packages.iter().find(|p| p.version == version))
+                .copied(),
+            None => newest(name),
+        };
+        let package = package.ok_or_else(|| {
+            let wanted = version.map_or(name.to_string(), |v| format!("{name}={v}"));
+            Error::Apt(format!("{wanted} is not in the archive"))
+        })?;
+        picked.pick(package);
+    }
+    while let Some(package) = picked.queue.pop_front() {
+        for alternatives in &package.depends {
+            if alternatives
+                .iter()
+                .any(|name| picked.satisfied.contains(name))
+            {
+                continue;
+            }
+            let dependency = alternatives
+                .iter()
+                .find_map(|name| newest(name))
+                .or_else(|| {
+                    alternatives
+                        .iter()
+                        .find_map(|name| providers.get(name.as_str())?.first().copied())
+                })
+                .ok_or_else(|| {
+                    Error::Apt(format!(
+                        "{} depends on {}, which nothing in the archive provides",
+                        package.name,
+                        alternatives.join(" | ")
+                    ))
+                })?;
+            picked.pick(dependency);
+        }
+    }
+    Ok(picked.packages.into_values().collect())
+}
+
+/// The packages [`resolve`] picked so far, and the ones whose dependencies
+/// it has yet to look at.
+struct Picked<'a> {
+    satisfied: BTreeSet<String>,
+    packages: BTreeMap<&'a str, &'a Package>,
+    queue: VecDeque<&'a Package>,
+}
+
+impl<'a> Picked<'a> {
+    fn pick(&mut self, package: &'a Package) {
+        if self.packages.insert(&package.name, package).is_none() {
+            self.satisfied.insert(package.name.clone());
+            self.satisfied.extend(package.provides.iter().cloned());
+            self.queue.push_back(package);
+        }
+    }
+}
+
+/// What a `.deb` installs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deb {
+    /// The `control` file, the package's stanza in the status file.
+    pub control: String,
+    /// The `md5sums` control file, if the package has one.
+    pub md5sums: Option<Vec<u8>>,
+    /// Its files, directories and links, by absolute path, in the order of
+    /// the archive.
+    pub entries: Vec<(String, Entry)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Dir { mode: u32 },
+    File { mode: u32, contents: Vec<u8> },
+    Symlink { target: String },
+}
+
+impl Deb {
+    /// The package's stanza in the dpkg status file: its control file,
+    /// marked as installed.
+    pub fn status(&self) -> String {
+        let mut stanza = String::new();
+        for line in self.control.trim_end().lines() {
+            stanza.push_str(line);
+            stanza.push('\n');
+            if line.starts_with("Package:") {
+                stanza.push_str("Status: install ok installed\n");
+            }
+        }
+        stanza
+    }
+
+    /// The name dpkg gives the package's files in [`INFO_DIR`]: the package
+    /// name, qualified by its architecture for `Multi-Arch: same` packages.
+    pub fn info_name(&self) -> String {
+        let fields = apt::stanzas(&self.control)
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let name = fields.get("Package").cloned().unwrap_or_default();
+        match (fields.get("Multi-Arch"), fields.get("Architecture")) {
+            (Some(multi_arch), Some(arch)) if multi_arch == "same" => format!("{name}:{arch}"),
+            _ => name,
+        }
+    }
+}
+
+/// Reads a `.deb`: an ar archive of `debian-binary`, the control tarball
+/// and the data tarball, each compressed with gzip, xz or not at all.
+pub fn unpack(deb: &[u8]) -> Result<Deb> {
+    let members = ar_members(deb)?;
+    let member = |prefix: &str| {
+        members
+            .iter()
+            .find(|(name, _)| name.starts_with(prefix))
+            .ok_or_else(|| Error::Archive(format!(".deb has no {prefix} member")))
+    };
+    let (name, control_tar) = member("control.tar")?;
+    let control_tar = decompress(name, control_tar)?;
+    let mut control = None;
+    let mut md5sums = None;
+    for (path, entry) in tar_entries(&control_tar)? {
+        if let Entry::File { contents, .. } = entry {
+            match path.as_str() {
+                "/control" => control = Some(String::from_utf8_lossy(&contents).into_owned()),
+                "/md5sums" => md5sums = Some(contents),
+                _ => {}
+            }
+        }
+    }
+    let control = control.ok_or_else(|| Error::Archive(".deb has no control file".into()))?;
+    let (name, data_tar) = member("data.tar")?;
+    let entries = tar_entries(&decompress(name, data_tar)?)?;
+    Ok(Deb {
+        control,
+        md5sums,
+        entries,
+    })
+}
+
+fn ar_members(archive: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    let malformed = || Error::Archive("malformed .deb archive".into());
+    let mut rest = archive.strip_prefix(b"!<arch>\n").ok_or_else(malformed)?;
+    let mut members = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 60 {
+            return Err(malformed());
+        }
+        let (header, body) = rest.split_at(60);
+        let field = |range: std::ops::Range<usize>| String::from_utf8_lossy(&header[range]);
+        let name = field(0..16).trim_end().trim_end_matches('/').to_string();
+        let size: usize = field(48..58).trim().parse().map_err(|_| malformed())?;
+        if body.len() < size {
+            return Err(malformed());
+        }
+        members.push((name, &body[..size]));
+        // Members are aligned to two bytes.
+        rest = &body[(size + size % 2).min(body.len())..];
+    }
+    Ok(members)
+}
+
+fn decompress(name: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let error = |err: String| Error::Archive(format!("{name}: {err}"));
+    let mut out = Vec::new();
+    if name.ends_with(".gz") {
+        GzDecoder::new(data)
+            .read_to_end(&mut out)
+            .map_err(|err| error(err.to_string()))?;
+    } else if name.ends_with(".xz") {
+        lzma_rs::xz_decompress(&mut &data[..], &mut out).map_err(|err| error(err.to_string()))?;
+    } else if name.ends_with(".tar") {
+        out = data.to_vec();
+    } else {
+        return Err(error("unsupported compression".into()));
+    }
+    Ok(out)
+}
+
+/// The entries of an uncompressed tarball by absolute path. Hard links are
+/// copies of the file they link to.
+fn tar_entries(tarball: &[u8]) -> Result<Vec<(String, Entry)>> {
+    let error = |err: std::io::Error| Error::Archive(err.to_string());
+    let mut entries = Vec::new();
+    let mut files: BTreeMap<String, (u32, Vec<u8>)> = BTreeMap::new();
+    let mut archive = tar::Archive::new(tarball);
+    for entry in archive.entries().map_err(error)? {
+        let mut entry = entry.map_err(error)?;
+        let path = entry.path().map_err(error)?.to_string_lossy().into_owned();
+        let path = path.trim_start_matches('.').trim_matches('/');
+        if path.is_empty() {
+            continue;
+        }
+        let path = format!("/{path}");
+        let mode = entry.header().mode().map_err(error)?;
+        let link = || -> Result<String> {
+            Ok(entry
+                .link_name()
+                .map_err(error)?
+                .map(|target| target.to_string_lossy().into_owned())
+                .unwrap_or_default())
+        };
+        let node = match entry.header().entry_type() {
+            tar::EntryType::Directory => Entry::Dir { mode },
+            tar::EntryType::Symlink => Entry::Symlink { target: link()? },
+            tar::EntryType::Link => {
+                let target = format!("/{}", link()?.trim_start_matches('.').trim_matches('/'));
+                let (mode, contents) = files.get(&target).cloned().ok_or_else(|| {
+                    Error::Archive(format!("{path} links to {target}, which is not a file"))
+                })?;
+                Entry::File { mode, contents }
+            }
+            kind if kind.is_file() => {
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents).map_err(error)?;
+                files.insert(path.clone(), (mode, contents.clone()));
+                Entry::File { mode, contents }
+            }
+            _ => continue,
+        };
+        entries.push((path, node));
+    }
+    Ok(entries)
+}
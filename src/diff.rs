@@ -0,0 +1,262 @@
+//! What changed between two images: Debian or Alpine packages,
+//! cargo-installed crates, environment variables and the binaries in
+//! `/usr/local/cargo/bin`.
+
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    layer, oci::Layout, platform::Platform, registry::Registry, sbom, target::Target, Error, Result,
+};
+
+/// Where `cargo install` puts binaries in the image.
+pub const CARGO_BIN: &str = "/usr/local/cargo/bin";
+
+/// The parts of an image that are compared.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    /// The layout directory or image reference.
+    pub image: String,
+    /// Installed Debian or Alpine packages and their versions.
+    pub packages: BTreeMap<String, String>,
+    /// Crates installed with `cargo install`, with their versions joined
+    /// by `, ` when several are installed side by side.
+    pub crates: BTreeMap<String, String>,
+    pub env: BTreeMap<String, String>,
+    /// Files in [`CARGO_BIN`] and their sizes.
+    pub cargo_bin: BTreeMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Reads the image in the OCI layout at `dir`.
+    pub fn of_layout(dir: &Path) -> Result<Self> {
+        let layout = Layout::open(dir)?;
+        let (_, manifest) = layout.image()?;
+        let config: Value = layout.json(&manifest.config.digest)?;
+        let blobs = manifest
+            .layers
+            .iter()
+            .map(|layer| layout.blob(&layer.digest))
+            .collect::<Result<Vec<_>>>()?;
+        Self::of_layers(dir.display().to_string(), &config, &blobs)
+    }
+
+    /// Downloads the image `reference` points to for `platform`.
+    pub fn of_published(reference: &str, platform: Platform) -> Result<Self> {
+        let (target, tag) = Target::parse_reference(reference)?;
+        let name = target.name();
+        let mut registry = Registry::for_target(&target);
+        let image = registry
+            .platform_image(&name, &tag, platform)?
+            .ok_or_else(|| Error::Registry(format!("{reference} does not exist")))?;
+        let config = registry.blob(&name, &image.manifest.config.digest)?;
+        let config: Value = serde_json::from_slice(&config).map_err(|err| Error::Json {
+            path: format!("{reference} config").into(),
+            message: err.to_string(),
+        })?;
+        let blobs = image
+            .manifest
+            .layers
+            .iter()
+            .map(|layer| registry.blob(&name, &layer.digest))
+            .collect::<Result<Vec<_>>>()?;
+        Self::of_layers(reference.to_string(), &config, &blobs)
+    }
+
+    /// Reads a layout directory if `image` is one, and the published image
+    /// otherwise.
+    pub fn load(image: &str, platform: Platform) -> Result<Self> {
+        if Path::new(image).is_dir() {
+            Self::of_layout(Path::new(image))
+        } else {
+            Self::of_published(image, platform)
+        }
+    }
+
+    fn of_layers(image: String, config: &Value, blobs: &[Vec<u8>]) -> Result<Self> {
+        let layers: Vec<&[u8]> = blobs.iter().map(Vec::as_slice).collect();
+        let packages = sbom::os_packages(&layers, &BTreeMap::new())?
+            .into_iter()
+            .map(|package| (package.name, package.version))
+            .collect();
+
+        // Every `cargo install` root records what it installed, e.g.
+        // wasm-bindgen-cli under /usr/local/wasm-bindgen/<version>.
+        let mut installed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for path in layer::list_files(&layers, "/usr/local")?.keys() {
+            if !path.ends_with("/.crates.toml") {
+                continue;
+            }
+            let text = layer::read_file(&layers, path)?.unwrap_or_default();
+            for (name, version) in installed_crates(&String::from_utf8_lossy(&text)) {
+                installed.entry(name).or_default().push(version);
+            }
+        }
+        let crates = installed
+            .into_iter()
+            .map(|(name, mut versions)| {
+                versions.sort();
+                versions.dedup();
+                (name, versions.join(", "))
+            })
+            .collect();
+
+        let env = config
+            .pointer("/config/Env")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|var| var.as_str()?.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let cargo_bin = layer::list_files(&layers, CARGO_BIN)?
+            .into_iter()
+            .map(|(path, size)| (path[CARGO_BIN.len() + 1..].to_string(), size))
+            .collect();
+        Ok(Snapshot {
+            image,
+            packages,
+            crates,
+            env,
+            cargo_bin,
+        })
+    }
+}
+
+/// The crates listed in a `.crates.toml`, whose keys look like
+/// `"trunk 0.14.0 (registry+https://github.com/rust-lang/crates.io-index)"`.
+fn installed_crates(text: &str) -> Vec<(String, String)> {
+    let Ok(value) = text.parse::<toml::Table>() else {
+        return Vec::new();
+    };
+    value
+        .get("v1")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|table| table.keys())
+        .filter_map(|key| {
+            let mut parts = key.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// How one entry differs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "change")]
+pub enum Change<T> {
+    Added { new: T },
+    Removed { old: T },
+    Changed { old: T, new: T },
+}
+
+/// The changes from one image to another, by section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    pub old: String,
+    pub new: String,
+    pub packages: BTreeMap<String, Change<String>>,
+    pub crates: BTreeMap<String, Change<String>>,
+    pub env: BTreeMap<String, Change<String>>,
+    pub cargo_bin: BTreeMap<String, Change<u64>>,
+}
+
+impl Diff {
+    pub fn new(old: &Snapshot, new: &Snapshot) -> Self {
+        Diff {
+            old: old.image.clone(),
+            new: new.image.clone(),
+            packages: changes(&old.packages, &new.packages),
+            crates: changes(&old.crates, &new.crates),
+            env: changes(&old.env, &new.env),
+            cargo_bin: changes(&old.cargo_bin, &new.cargo_bin),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+            && self.crates.is_empty()
+            && self.env.is_empty()
+            && self.cargo_bin.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("diff serializes")
+    }
+}
+
+pub(crate) fn changes<T: Clone + PartialEq>(
+    old: &BTreeMap<String, T>,
+    new: &BTreeMap<String, T>,
+) -> BTreeMap<String, Change<T>> {
+    let mut changes = BTreeMap::new();
+    for (key, old_value) in old {
+        match new.get(key) {
+            None => {
+                changes.insert(
+                    key.clone(),
+                    Change::Removed {
+                        old: old_value.clone(),
+                    },
+                );
+            }
+            Some(new_value) if new_value != old_value => {
+                changes.insert(
+                    key.clone(),
+                    Change::Changed {
+                        old: old_value.clone(),
+                        new: new_value.clone(),
+                    },
+                );
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, new_value) in new {
+        if !old.contains_key(key) {
+            changes.insert(
+                key.clone(),
+                Change::Added {
+                    new: new_value.clone(),
+                },
+            );
+        }
+    }
+    changes
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} -> {}", self.old, self.new)?;
+        section(f, "OS packages", &self.packages, String::clone)?;
+        section(f, "cargo-installed crates", &self.crates, String::clone)?;
+        section(f, "environment", &self.env, String::clone)?;
+        section(f, CARGO_BIN, &self.cargo_bin, |size| {
+            format!("{size} bytes")
+        })
+    }
+}
+
+fn section<T>(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    changes: &BTreeMap<String, Change<T>>,
+    show: impl Fn(&T) -> String,
+) -> fmt::Result {
+    if changes.is_empty() {
+        return writeln!(f, "\n{title}: unchanged");
+    }
+    writeln!(f, "\n{title}:")?;
+    for (key, change) in changes {
+        match change {
+            Change::Added { new } => writeln!(f, "  + {key} {}", show(new))?,
+            Change::Removed { old } => writeln!(f, "  - {key} {}", show(old))?,
+            Change::Changed { old, new } => {
+                writeln!(f, "  ~ {key} {} -> {}", show(old), show(new))?
+            }
+        }
+    }
+    Ok(())
+}
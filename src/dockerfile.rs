@@ -0,0 +1,248 @@
+use std::{fmt::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    apt, config,
+    crates::CRATES_DL,
+    lock::ImageLock,
+    manifest::{Binaryen, Manifest},
+    matrix::Entry,
+    platform::Platform,
+    trunk::{LockedRelease, TrunkLock},
+    wasm_bindgen, wasm_opt, Result,
+};
+
+/// How trunk gets into the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrunkSource {
+    /// Copied from the verified release binary in the `trunk` build context.
+    Prebuilt,
+    /// Compiled with `cargo install`.
+    Cargo,
+}
+
+impl TrunkSource {
+    /// Prebuilt if `trunk.lock` pins a binary for the entry's version on
+    /// the platform, unless the manifest builds trunk from source.
+    pub fn for_entry(
+        manifest: &Manifest,
+        lock: &TrunkLock,
+        entry: &Entry,
+        platform: Platform,
+    ) -> Self {
+        match lock.get(&entry.trunk, platform) {
+            Some(_) if !manifest.trunk.from_source => TrunkSource::Prebuilt,
+            _ => TrunkSource::Cargo,
+        }
+    }
+}
+
+/// Renders the Dockerfile for one matrix entry on one platform.
+///
+/// The output only depends on its inputs: apt and apk packages are sorted
+/// and deduplicated, cargo tools keep the order of the manifest. What `lock`
+/// pins is installed at its locked version: the base image by digest, apt
+/// and apk packages as `pkg=version`, trunk from its checksummed crate and
+/// wasm-bindgen-cli from its checksummed releases.
+/// Versions pinned in the manifest win over the lock's.
+pub fn render(
+    manifest: &Manifest,
+    lock: &ImageLock,
+    entry: &Entry,
+    platform: Platform,
+    trunk: TrunkSource,
+) -> String {
+    let mut steps = Vec::new();
+    let mut packages = manifest.apt.packages.clone();
+    packages.sort();
+    packages.dedup();
+    if !packages.is_empty() {
+        if let Some(timestamp) = &manifest.apt.snapshot {
+            steps.extend(snapshot_steps(timestamp));
+        }
+        steps.push("apt-get -y update".to_string());
+        let mut install = "apt-get -y install".to_string();
+        for package in &packages {
+            match lock.apt_pin(manifest, entry, platform, package) {
+                Some(version) => write!(install, " \\\n    {package}={version}").unwrap(),
+                None => write!(install, " \\\n    {package}").unwrap(),
+            }
+        }
+        steps.push(install);
+        steps.push("rm -rf /var/lib/apt/lists/*".to_string());
+    }
+    let mut packages = manifest.apk.packages.clone();
+    packages.sort();
+    packages.dedup();
+    if !packages.is_empty() {
+        let mut install = "apk add --no-cache".to_string();
+        for package in &packages {
+            match lock.apk_pin(manifest, entry, platform, package) {
+                Some(version) => write!(install, " \\\n    {package}={version}").unwrap(),
+                None => write!(install, " \\\n    {package}").unwrap(),
+            }
+        }
+        steps.push(install);
+    }
+    if let Some(binaryen) = &manifest.binaryen {
+        steps.extend(binaryen_steps(binaryen, platform));
+    }
+    if !manifest.rust.targets.is_empty() {
+        steps.push(format!(
+            "rustup target add {}",
+            manifest.rust.targets.join(" ")
+        ));
+    }
+    if trunk == TrunkSource::Cargo {
+        match lock.trunk_sha256(&entry.trunk) {
+            Some(sha256) => steps.extend(trunk_crate_steps(&entry.trunk, sha256)),
+            None => steps.push(format!(
+                "cargo install --locked trunk --version {}",
+                entry.trunk
+            )),
+        }
+    }
+    for version in &manifest.wasm_bindgen.versions {
+        match lock.wasm_bindgen_release(manifest, entry, platform, version) {
+            Some(release) => steps.extend(wasm_bindgen_steps(version, release)),
+            None => steps.push(format!(
+                "cargo install --locked wasm-bindgen-cli --version {version} --root {}/{version}",
+                wasm_bindgen::BUNDLE_DIR
+            )),
+        }
+    }
+    for tool in &manifest.cargo {
+        steps.push(format!(
+            "cargo install --locked {} --version {}",
+            tool.name, tool.version
+        ));
+    }
+    steps.push("rm -rf /usr/local/cargo/registry".to_string());
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated from image.toml by `trunk-docker generate`."
+    )
+    .unwrap();
+    if let Some(entrypoint) = &manifest.entrypoint {
+        writeln!(out, "FROM {} AS entrypoint", entrypoint.builder).unwrap();
+        writeln!(out, "COPY . /src").unwrap();
+        writeln!(
+            out,
+            "RUN cargo install --locked --path /src --root /usr/local"
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+    }
+    writeln!(
+        out,
+        "FROM {}",
+        lock.base_reference(&manifest.base, &entry.rust)
+    )
+    .unwrap();
+    if trunk == TrunkSource::Prebuilt {
+        writeln!(out, "COPY --from=trunk trunk /usr/local/cargo/bin/trunk").unwrap();
+    }
+    writeln!(out, "RUN {}", steps.join(" && \\\n    ")).unwrap();
+    if manifest.entrypoint.is_some() {
+        writeln!(
+            out,
+            "COPY --from=entrypoint /usr/local/bin/trunk-docker /usr/local/bin/trunk-docker"
+        )
+        .unwrap();
+        if manifest.binaryen.is_some() {
+            writeln!(
+                out,
+                "RUN trunk-docker check-wasm-opt --trunk {}",
+                entry.trunk
+            )
+            .unwrap();
+        }
+        writeln!(out, r#"ENTRYPOINT ["trunk-docker", "entrypoint"]"#).unwrap();
+        writeln!(out, r#"CMD ["bash"]"#).unwrap();
+    }
+    out
+}
+
+/// Downloads, verifies and unpacks the binaryen release into `/usr/local`.
+fn binaryen_steps(binaryen: &Binaryen, platform: Platform) -> Vec<String> {
+    let version = binaryen.version;
+    let url = binaryen.url(platform);
+    let tarball = "/tmp/binaryen.tar.gz";
+    let mut steps = vec![format!("curl -fsSL -o {tarball} {url}")];
+    match binaryen.sha256.get(platform.machine()) {
+        Some(sha256) => steps.push(format!("echo '{sha256}  {tarball}' | sha256sum -c -")),
+        None => steps.push(format!(
+            "echo \"$(curl -fsSL {url}.sha256 | cut -d ' ' -f 1)  {tarball}\" | sha256sum -c -"
+        )),
+    }
+    steps.push(format!(
+        "tar -xzf {tarball} -C /usr/local --strip-components=1 binaryen-version_{version}/bin"
+    ));
+    steps.push(format!("rm {tarball}"));
+    steps
+}
+
+/// Downloads, verifies and unpacks a wasm-bindgen-cli release into the
+/// bundle directory of its version.
+fn wasm_bindgen_steps(version: &str, release: &LockedRelease) -> Vec<String> {
+    let tarball = "/tmp/wasm-bindgen.tar.gz";
+    let bin = format!("{}/{version}/bin", wasm_bindgen::BUNDLE_DIR);
+    vec![
+        format!("curl -fsSL -o {tarball} {}", release.url),
+        format!("echo '{}  {tarball}' | sha256sum -c -", release.sha256),
+        format!("mkdir -p {bin}"),
+        format!("tar -xzf {tarball} -C {bin} --strip-components=1"),
+        format!("rm {tarball}"),
+    ]
+}
+
+/// Points apt at the Debian and security archives as snapshot.debian.org
+/// has them at `timestamp`, for the base image's suite.
+fn snapshot_steps(timestamp: &str) -> Vec<String> {
+    let debian = apt::snapshot_url("debian", timestamp);
+    let security = apt::snapshot_url("debian-security", timestamp);
+    vec![
+        format!(
+            ". /etc/os-release && printf 'deb [check-valid-until=no] %s %s main\\n' \\\n        \
+             {debian} \"$VERSION_CODENAME\" \\\n        \
+             {debian} \"$VERSION_CODENAME-updates\" \\\n        \
+             {security} \"$VERSION_CODENAME-security\" \\\n        \
+             > /etc/apt/sources.list"
+        ),
+        "rm -f /etc/apt/sources.list.d/debian.sources".to_string(),
+    ]
+}
+
+/// Downloads the trunk crate, checks it against the locked checksum and
+/// installs it from the unpacked sources.
+fn trunk_crate_steps(version: &str, sha256: &str) -> Vec<String> {
+    let archive = "/tmp/trunk.crate";
+    let sources = format!("/tmp/trunk-{version}");
+    vec![
+        format!("curl -fsSL -o {archive} {CRATES_DL}/trunk/trunk-{version}.crate"),
+        format!("echo '{sha256}  {archive}' | sha256sum -c -"),
+        format!("tar -xzf {archive} -C /tmp"),
+        format!("cargo install --locked --path {sources}"),
+        format!("rm -rf {archive} {sources}"),
+    ]
+}
+
+/// Renders the Dockerfile for an entry and writes it to `path`, after
+/// checking that the pinned binaryen is new enough for its trunk version.
+pub fn write(
+    path: &Path,
+    manifest: &Manifest,
+    lock: &ImageLock,
+    entry: &Entry,
+    platform: Platform,
+    trunk: TrunkSource,
+) -> Result<()> {
+    if let Some(binaryen) = &manifest.binaryen {
+        wasm_opt::check(binaryen.version, &entry.trunk)?;
+    }
+    config::write(path, &render(manifest, lock, entry, platform, trunk))
+}
@@ -0,0 +1,77 @@
+//! The image entrypoint: checks the project against what the image provides
+//! before handing over to the actual command, so that nothing is fetched at
+//! build time behind the user's back.
+
+use std::{env, fs, path::Path};
+
+use crate::{
+    process::{self, Invocation},
+    toolchain::{self, Installed, ToolchainFile},
+    wasm_bindgen, Error, Result,
+};
+
+/// Checks the project in `dir` and returns `command` with the environment
+/// it has to run in.
+pub fn prepare(dir: &Path, command: Invocation) -> Result<Invocation> {
+    let command = pin_toolchain(dir, command)?;
+    select_wasm_bindgen(dir, command)
+}
+
+fn pin_toolchain(dir: &Path, command: Invocation) -> Result<Invocation> {
+    let Some((path, file)) = ToolchainFile::find(dir)? else {
+        return Ok(command);
+    };
+    let installed = installed_toolchains()?;
+    let toolchain = toolchain::check(&file, &installed)
+        .map_err(|mismatch| Error::Toolchain(format!("{}: {mismatch}", path.display())))?;
+    // rustup would otherwise try to install the channel exactly as spelled in
+    // the file (`1.56` is a different toolchain name than `1.56.1`).
+    Ok(command.env("RUSTUP_TOOLCHAIN", &toolchain.name))
+}
+
+/// Puts the bundled wasm-bindgen matching the project's `Cargo.lock` first
+/// on `PATH`, where trunk finds it instead of downloading one.
+fn select_wasm_bindgen(dir: &Path, command: Invocation) -> Result<Invocation> {
+    let Some(path) = wasm_bindgen::find_lockfile(dir) else {
+        return Ok(command);
+    };
+    let text = fs::read_to_string(&path).map_err(|source| Error::Io {
+        path: path.clone(),
+        source,
+    })?;
+    let locked = wasm_bindgen::locked_version(&text).map_err(|source| Error::Config {
+        path: path.clone(),
+        source: Box::new(source),
+    })?;
+    let Some(locked) = locked else {
+        return Ok(command);
+    };
+    let bin = wasm_bindgen::select(Path::new(wasm_bindgen::BUNDLE_DIR), &locked)?;
+    let mut paths = vec![bin];
+    paths.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+    let joined = env::join_paths(paths).expect("bundle path contains no separator");
+    Ok(command.env("PATH", joined.to_string_lossy()))
+}
+
+/// Asks rustup which toolchains, targets and components are installed.
+pub fn installed_toolchains() -> Result<Vec<Installed>> {
+    let list = process::output(&Invocation::new("rustup").args(["toolchain", "list"]))?;
+    toolchain::parse_toolchain_list(&list)
+        .into_iter()
+        .map(|name| {
+            let query = |kind: &str| -> Result<Vec<String>> {
+                let output = process::output(
+                    &Invocation::new("rustup")
+                        .args([kind, "list", "--installed", "--toolchain"])
+                        .arg(&name),
+                )?;
+                Ok(output.lines().map(|line| line.trim().to_string()).collect())
+            };
+            Ok(Installed {
+                targets: query("target")?,
+                components: query("component")?,
+                name: name.clone(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,67 @@
+use std::{io, path::PathBuf, process::ExitStatus};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}: {source}", path.display())]
+    Config { path: PathBuf, source: Box<Error> },
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error("invalid JSON in {}: {message}", path.display())]
+    Json { path: PathBuf, message: String },
+    #[error("failed to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+    #[error("invalid matrix: {0}")]
+    Matrix(String),
+    #[error("invalid image manifest: {0}")]
+    Manifest(String),
+    #[error("{0}")]
+    Toolchain(String),
+    #[error("{0}")]
+    WasmBindgen(String),
+    #[error("{0}")]
+    WasmOpt(String),
+    #[error("{url}: {message}")]
+    Http { url: String, message: String },
+    #[error("checksum mismatch: {0}")]
+    Checksum(String),
+    #[error("invalid archive: {0}")]
+    Archive(String),
+    #[error("invalid target: {0}")]
+    Target(String),
+    #[error("registry: {0}")]
+    Registry(String),
+    #[error("OCI: {0}")]
+    Oci(String),
+    #[error("cannot assemble the image: {0}")]
+    Assemble(String),
+    #[error("audit failed: {0}")]
+    Audit(String),
+    #[error("apt: {0}")]
+    Apt(String),
+    #[error("apk: {0}")]
+    Apk(String),
+    #[error("lock: {0}")]
+    Lock(String),
+    #[error("changelog: {0}")]
+    Changelog(String),
+    #[error("reproducibility: {0}")]
+    Repro(String),
+    #[error("provenance: {0}")]
+    Provenance(String),
+    #[error("image size: {0}")]
+    Size(String),
+    #[error("signature: {0}")]
+    Signature(String),
+    #[error("smoke test: {0}")]
+    Smoke(String),
+    #[error("structure test: {0}")]
+    Structure(String),
+    #[error("failed to run `{command}`: {source}")]
+    Spawn { command: String, source: io::Error },
+    #[error("`{command}` exited with {status}")]
+    CommandFailed { command: String, status: ExitStatus },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use crate::{Error, Result};
+
+/// Release assets can be large; anything above this is treated as an error.
+pub(crate) const BODY_LIMIT: u64 = 512 * 1024 * 1024;
+
+pub(crate) fn agent() -> ureq::Agent {
+    ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(Duration::from_secs(600)))
+        .build()
+        .into()
+}
+
+/// Downloads `url`, returning `None` if the server says it doesn't exist.
+pub fn get(url: &str) -> Result<Option<Vec<u8>>> {
+    let error = |message: String| Error::Http {
+        url: url.to_string(),
+        message,
+    };
+    let mut response = agent()
+        .get(url)
+        .call()
+        .map_err(|err| error(err.to_string()))?;
+    match response.status().as_u16() {
+        200..=299 => response
+            .body_mut()
+            .with_config()
+            .limit(BODY_LIMIT)
+            .read_to_vec()
+            .map(Some)
+            .map_err(|err| error(err.to_string())),
+        404 => Ok(None),
+        status => Err(error(format!("server responded with {status}"))),
+    }
+}
@@ -0,0 +1,222 @@
+//! Image layers: building them from files, and reading files back out.
+
+use std::{collections::BTreeMap, io::Read};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{oci, Error, Result};
+
+/// A gzip-compressed layer tarball.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    /// Digest of the uncompressed tarball, as listed in the image
+    /// config's `rootfs.diff_ids`.
+    pub diff_id: String,
+    pub blob: Vec<u8>,
+}
+
+/// Collects files for a layer. Entries are written sorted by path, with
+/// no timestamps or owners, so the same files always give the same layer.
+#[derive(Debug, Default)]
+pub struct LayerBuilder {
+    entries: BTreeMap<String, Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Dir { mode: u32 },
+    File { mode: u32, contents: Vec<u8> },
+    Symlink { target: String },
+}
+
+impl LayerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file at the absolute `path`, along with its parent
+    /// directories.
+    pub fn file(&mut self, path: &str, mode: u32, contents: Vec<u8>) -> &mut Self {
+        self.insert(path, Node::File { mode, contents })
+    }
+
+    /// Adds a directory at the absolute `path`, along with its parents.
+    pub fn dir(&mut self, path: &str, mode: u32) -> &mut Self {
+        let path = format!("{}/", path.trim_end_matches('/'));
+        self.insert(&path, Node::Dir { mode })
+    }
+
+    /// Adds a symbolic link at the absolute `path` pointing to `target`,
+    /// along with its parent directories.
+    pub fn symlink(&mut self, path: &str, target: &str) -> &mut Self {
+        let target = target.to_string();
+        self.insert(path, Node::Symlink { target })
+    }
+
+    fn insert(&mut self, path: &str, node: Node) -> &mut Self {
+        let path = path.trim_start_matches('/');
+        let mut parent = String::new();
+        let parents = path.trim_end_matches('/').split('/');
+        for component in parents.clone().take(parents.count() - 1) {
+            parent.push_str(component);
+            parent.push('/');
+            self.entries
+                .entry(parent.clone())
+                .or_insert(Node::Dir { mode: 0o755 });
+        }
+        self.entries.insert(path.to_string(), node);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn finish(&self) -> Result<Layer> {
+        let error = |err: std::io::Error| Error::Archive(format!("layer: {err}"));
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, node) in &self.entries {
+            let mut header = tar::Header::new_ustar();
+            header.set_mtime(0);
+            header.set_uid(0);
+            header.set_gid(0);
+            match node {
+                Node::Dir { mode } => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(*mode);
+                    header.set_size(0);
+                    builder
+                        .append_data(&mut header, path, std::io::empty())
+                        .map_err(error)?;
+                }
+                Node::File { mode, contents } => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(*mode);
+                    header.set_size(contents.len() as u64);
+                    builder
+                        .append_data(&mut header, path, contents.as_slice())
+                        .map_err(error)?;
+                }
+                Node::Symlink { target } => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_mode(0o777);
+                    header.set_size(0);
+                    builder
+                        .append_link(&mut header, path, target)
+                        .map_err(error)?;
+                }
+            }
+        }
+        let tarball = builder.into_inner().map_err(error)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut encoder, &tarball).map_err(error)?;
+        Ok(Layer {
+            diff_id: oci::digest(&tarball),
+            blob: encoder.finish().map_err(error)?,
+        })
+    }
+}
+
+/// Reads the file at the absolute `path` from a stack of gzip-compressed
+/// layers, the way the container would see it: the topmost layer that
+/// mentions the path wins, and whiteouts delete it.
+pub fn read_file(layers: &[&[u8]], path: &str) -> Result<Option<Vec<u8>>> {
+    let path = path.trim_start_matches('/');
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let whiteout = match dir {
+        "" => format!(".wh.{name}"),
+        dir => format!("{dir}/.wh.{name}"),
+    };
+    let error = |err: std::io::Error| Error::Archive(format!("layer: {err}"));
+    for layer in layers.iter().rev() {
+        let mut archive = tar::Archive::new(GzDecoder::new(*layer));
+        for entry in archive.entries().map_err(error)? {
+            let mut entry = entry.map_err(error)?;
+            let entry_path = entry.path().map_err(error)?;
+            let entry_path = entry_path.to_string_lossy();
+            let entry_path = entry_path.trim_start_matches("./");
+            if entry_path == whiteout {
+                return Ok(None);
+            }
+            if entry_path == path && entry.header().entry_type().is_file() {
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents).map_err(error)?;
+                return Ok(Some(contents));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads where the symbolic link at the absolute `path` points in a stack
+/// of gzip-compressed layers; `None` if the topmost layer that mentions the
+/// path has something else there, or deletes it.
+pub fn read_link(layers: &[&[u8]], path: &str) -> Result<Option<String>> {
+    let path = path.trim_matches('/');
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let whiteout = match dir {
+        "" => format!(".wh.{name}"),
+        dir => format!("{dir}/.wh.{name}"),
+    };
+    let error = |err: std::io::Error| Error::Archive(format!("layer: {err}"));
+    for layer in layers.iter().rev() {
+        let mut archive = tar::Archive::new(GzDecoder::new(*layer));
+        for entry in archive.entries().map_err(error)? {
+            let entry = entry.map_err(error)?;
+            let entry_path = entry.path().map_err(error)?;
+            let entry_path = entry_path.to_string_lossy();
+            let entry_path = entry_path.trim_start_matches("./").trim_end_matches('/');
+            if entry_path == whiteout {
+                return Ok(None);
+            }
+            if entry_path == path {
+                if !entry.header().entry_type().is_symlink() {
+                    return Ok(None);
+                }
+                let target = entry.link_name().map_err(error)?;
+                return Ok(target.map(|target| target.to_string_lossy().into_owned()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Lists the files under the absolute directory `dir` in a stack of
+/// gzip-compressed layers, with their sizes, the way the container would
+/// see them: upper layers replace files and whiteouts delete them.
+pub fn list_files(layers: &[&[u8]], dir: &str) -> Result<BTreeMap<String, u64>> {
+    let prefix = format!("{}/", dir.trim_matches('/'));
+    let error = |err: std::io::Error| Error::Archive(format!("layer: {err}"));
+    let mut files = BTreeMap::new();
+    for layer in layers {
+        let mut added = Vec::new();
+        let mut deleted = Vec::new();
+        let mut archive = tar::Archive::new(GzDecoder::new(*layer));
+        for entry in archive.entries().map_err(error)? {
+            let entry = entry.map_err(error)?;
+            let path = entry.path().map_err(error)?;
+            let path = path.to_string_lossy();
+            let path = path.trim_start_matches("./");
+            let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+            if name == ".wh..wh..opq" {
+                // An opaque directory hides everything below it.
+                deleted.push(format!("/{parent}/"));
+            } else if let Some(name) = name.strip_prefix(".wh.") {
+                deleted.push(format!("/{parent}/{name}"));
+                deleted.push(format!("/{parent}/{name}/"));
+            } else if path.starts_with(&prefix) && !entry.header().entry_type().is_dir() {
+                added.push((format!("/{path}"), entry.header().size().map_err(error)?));
+            }
+        }
+        for deleted in deleted {
+            let deleted = deleted.replace("//", "/");
+            if deleted.ends_with('/') {
+                files.retain(|path: &String, _| !path.starts_with(&deleted));
+            } else {
+                files.remove(&deleted);
+            }
+        }
+        files.extend(added);
+    }
+    Ok(files)
+}
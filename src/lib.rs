@@ -0,0 +1,40 @@
+//! Tooling for building and publishing the `rust-trunk` docker images.
+
+pub mod apk;
+pub mod apt;
+pub mod assemble;
+pub mod audit;
+pub mod changelog;
+mod config;
+pub mod crates;
+pub mod deb;
+pub mod diff;
+pub mod dockerfile;
+pub mod entrypoint;
+pub mod error;
+pub mod http;
+pub mod layer;
+pub mod lock;
+pub mod manifest;
+pub mod matrix;
+pub mod oci;
+pub mod pipeline;
+pub mod platform;
+pub mod process;
+pub mod provenance;
+pub mod registry;
+pub mod repro;
+pub mod sbom;
+pub mod sign;
+pub mod size;
+pub mod smoke;
+pub mod structure;
+pub mod tags;
+pub mod target;
+pub mod toolchain;
+pub mod trunk;
+pub mod updates;
+pub mod wasm_bindgen;
+pub mod wasm_opt;
+
+pub use error::{Error, Result};
@@ -0,0 +1,495 @@
+//! The resolved inputs of the images, pinned in `image.lock`: the digests of
+//! the base images, the versions of the Debian and Alpine packages and the
+//! checksums of the trunk crates and the wasm-bindgen-cli releases. Builds
+//! only read it; `trunk-docker update-lock` writes it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apk,
+    apt::{self, Archives},
+    config, http, layer,
+    manifest::{is_sha256, Base, Manifest},
+    matrix::{Entry, Matrix},
+    platform::Platform,
+    registry::Registry,
+    sbom,
+    target::Target,
+    trunk::{sha256_hex, LockedRelease},
+    updates, wasm_bindgen, Error, Result,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageLock {
+    /// Keyed by the base image reference, e.g. `rust:1.56-slim`.
+    #[serde(default)]
+    pub base: BTreeMap<String, LockedBase>,
+    /// The newest package versions in each apt snapshot, keyed by the
+    /// snapshot, then by Debian suite, then by architecture. Images sharing
+    /// a snapshot share its versions; pins stay in the manifest.
+    #[serde(default)]
+    pub apt: BTreeMap<String, BTreeMap<String, BTreeMap<String, Versions>>>,
+    /// The newest package versions in each Alpine branch, keyed by the
+    /// branch, then by architecture.
+    #[serde(default)]
+    pub apk: BTreeMap<String, BTreeMap<String, Versions>>,
+    /// Checksums of the trunk crate, keyed by version.
+    #[serde(default)]
+    pub trunk: BTreeMap<String, LockedCrate>,
+    /// Checksums of the prebuilt wasm-bindgen-cli releases, keyed by
+    /// version and then by target triple. A version without an entry for a
+    /// platform has no release for it and is compiled instead.
+    #[serde(default, rename = "wasm-bindgen")]
+    pub wasm_bindgen: BTreeMap<String, BTreeMap<String, LockedRelease>>,
+}
+
+/// Package versions, keyed by package name.
+pub type Versions = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedBase {
+    /// What the tag pointed to: an image index for multi-platform bases.
+    pub digest: String,
+    /// The Debian suite the image is based on, e.g. `bullseye`; none for
+    /// other distributions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suite: Option<String>,
+    /// The Alpine release branch the image is based on, e.g. `v3.19`; none
+    /// for other distributions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedCrate {
+    pub sha256: String,
+}
+
+impl ImageLock {
+    pub fn load(path: &Path) -> Result<Self> {
+        config::load(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let body = toml::to_string(self).expect("lockfile serializes");
+        config::write(
+            path,
+            &format!("# Resolved inputs of the images; update with `trunk-docker update-lock`.\n\n{body}"),
+        )
+    }
+
+    /// The base image for a Rust version, by digest if it is locked.
+    pub fn base_reference(&self, base: &Base, rust: &str) -> String {
+        let reference = base.reference(rust);
+        match self.base.get(&reference) {
+            Some(locked) => format!("{reference}@{}", locked.digest),
+            None => reference,
+        }
+    }
+
+    /// The Debian suite of the base image for a Rust version, if it is
+    /// locked.
+    pub fn suite(&self, base: &Base, rust: &str) -> Option<&str> {
+        self.base.get(&base.reference(rust))?.suite.as_deref()
+    }
+
+    /// The Alpine branch of the base image for a Rust version, if it is
+    /// locked.
+    pub fn branch(&self, base: &Base, rust: &str) -> Option<&str> {
+        self.base.get(&base.reference(rust))?.branch.as_deref()
+    }
+
+    /// The locked version of an apt package in `snapshot`, for the suite of
+    /// the base image of `entry`.
+    pub fn apt_version(
+        &self,
+        snapshot: &str,
+        base: &Base,
+        entry: &Entry,
+        platform: Platform,
+        package: &str,
+    ) -> Option<&str> {
+        let suite = self.suite(base, &entry.rust)?;
+        self.apt
+            .get(snapshot)?
+            .get(suite)?
+            .get(platform.arch())?
+            .get(package)
+            .map(String::as_str)
+    }
+
+    /// The version an apt package is installed at in the image of `entry`:
+    /// the manifest's pin, or else the locked version. Locked versions are
+    /// only installable from the manifest's snapshot, so without one the
+    /// package is not pinned.
+    pub fn apt_pin<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        entry: &Entry,
+        platform: Platform,
+        package: &str,
+    ) -> Option<&'a str> {
+        match (manifest.apt.versions.get(package), &manifest.apt.snapshot) {
+            (Some(version), _) => Some(version),
+            (None, Some(snapshot)) => {
+                self.apt_version(snapshot, &manifest.base, entry, platform, package)
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// The version an apk package is installed at in the image of `entry`:
+    /// the manifest's pin, or else the version locked for the Alpine branch
+    /// of its base image.
+    pub fn apk_pin<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        entry: &Entry,
+        platform: Platform,
+        package: &str,
+    ) -> Option<&'a str> {
+        if let Some(version) = manifest.apk.versions.get(package) {
+            return Some(version);
+        }
+        let branch = self.branch(&manifest.base, &entry.rust)?;
+        self.apk
+            .get(branch)?
+            .get(platform.arch())?
+            .get(package)
+            .map(String::as_str)
+    }
+
+    /// The SHA-256 of the `.crate` file of a trunk version.
+    pub fn trunk_sha256(&self, version: &str) -> Option<&str> {
+        self.trunk.get(version).map(|locked| locked.sha256.as_str())
+    }
+
+    /// The locked wasm-bindgen-cli release of `version` that the image of
+    /// `entry` installs on `platform`, if the manifest downloads releases.
+    /// Alpine images only take the static musl releases, since the glibc
+    /// ones don't run there.
+    pub fn wasm_bindgen_release(
+        &self,
+        manifest: &Manifest,
+        entry: &Entry,
+        platform: Platform,
+        version: &str,
+    ) -> Option<&LockedRelease> {
+        manifest.wasm_bindgen.release_url.as_ref()?;
+        let target = wasm_bindgen::release_target(platform);
+        let alpine =
+            !manifest.apk.packages.is_empty() || self.branch(&manifest.base, &entry.rust).is_some();
+        if alpine && !target.ends_with("-musl") {
+            return None;
+        }
+        self.wasm_bindgen.get(version)?.get(target)
+    }
+
+    /// Fails unless `archives` have every apt version the manifest pins, in
+    /// the suite of the base image of `entry` or its updates. Without a
+    /// locked suite there is nothing to look the versions up in, and
+    /// apt-get checks them.
+    pub fn check_apt_pins(
+        &self,
+        manifest: &Manifest,
+        entry: &Entry,
+        platform: Platform,
+        archives: &Archives,
+    ) -> Result<()> {
+        let Some(suite) = self.suite(&manifest.base, &entry.rust) else {
+            return Ok(());
+        };
+        if manifest.apt.versions.is_empty() {
+            return Ok(());
+        }
+        apt::check_pins(
+            &apt::available(archives, suite, platform)?,
+            &manifest.apt.versions,
+            &origin(archives, suite, platform),
+        )
+    }
+
+    /// What the build of `entry` on `platform` takes from upstream as it is
+    /// today, because the lock does not pin it.
+    pub fn unlocked(&self, manifest: &Manifest, entry: &Entry, platform: Platform) -> Vec<String> {
+        let reference = manifest.base.reference(&entry.rust);
+        let mut unlocked = Vec::new();
+        if !self.base.contains_key(&reference) {
+            unlocked.push(reference);
+        }
+        for package in &manifest.apt.packages {
+            if self.apt_pin(manifest, entry, platform, package).is_none() {
+                unlocked.push(format!("apt package {package}"));
+            }
+        }
+        for package in &manifest.apk.packages {
+            if self.apk_pin(manifest, entry, platform, package).is_none() {
+                unlocked.push(format!("apk package {package}"));
+            }
+        }
+        if self.trunk_sha256(&entry.trunk).is_none() {
+            unlocked.push(format!("trunk crate {}", entry.trunk));
+        }
+        unlocked
+    }
+
+    /// Resolves every input of the matrix's images, and of their variants,
+    /// as upstream has it now: the base images in their registries, the apt
+    /// packages in the bases' suites and their updates in `apt_archives`
+    /// (by default, the archives each image installs from), the apk
+    /// packages in the bases' branches on `apk_mirror` (by default
+    /// [`apk::ALPINE_MIRROR`]), and the trunk crates in the crates.io index
+    /// at `index`, and the wasm-bindgen-cli releases at the manifest's
+    /// `release-url`. Apt versions are locked under the image's snapshot, so
+    /// `apt_archives` must serve it. Packages the manifest pins must be in
+    /// the archives.
+    pub fn resolve(
+        matrix: &Matrix,
+        manifest: &Manifest,
+        index: &str,
+        apt_archives: Option<&Archives>,
+        apk_mirror: Option<&str>,
+    ) -> Result<Self> {
+        let mut lock = ImageLock::default();
+        for (variant, image) in manifest.images() {
+            if !image.apt.packages.is_empty() && image.apt.snapshot.is_none() {
+                let image = variant.map_or("the default image".into(), |v| format!("variant {v}"));
+                return Err(Error::Lock(format!(
+                    "{image} installs apt packages without `apt.snapshot`; Debian mirrors \
+                     drop superseded versions, so only a snapshot keeps locked ones installable"
+                )));
+            }
+            let snapshot = image.apt.snapshot.clone().unwrap_or_default();
+            let archives = apt_archives
+                .cloned()
+                .unwrap_or_else(|| image.apt.archives());
+            let mut suites = BTreeSet::new();
+            let mut branches = BTreeSet::new();
+            for rust in &matrix.rust {
+                let reference = image.base.reference(rust);
+                if !lock.base.contains_key(&reference) {
+                    let locked = resolve_base(&reference, &matrix.platforms)?;
+                    lock.base.insert(reference.clone(), locked);
+                }
+                if !image.apt.packages.is_empty() {
+                    let suite = lock.base[&reference].suite.clone().ok_or_else(|| {
+                        Error::Lock(format!(
+                            "{reference} does not name its Debian suite in os-release, \
+                             so its apt packages cannot be locked"
+                        ))
+                    })?;
+                    suites.insert(suite);
+                }
+                if !image.apk.packages.is_empty() {
+                    let branch = lock.base[&reference].branch.clone().ok_or_else(|| {
+                        Error::Lock(format!(
+                            "{reference} does not name its Alpine release in os-release, \
+                             so its apk packages cannot be locked"
+                        ))
+                    })?;
+                    branches.insert(branch);
+                }
+            }
+            let mirror = apk_mirror.unwrap_or(apk::ALPINE_MIRROR);
+            for branch in branches {
+                for &platform in &matrix.platforms {
+                    let available = apk::available(mirror, &branch, platform)?;
+                    apk::check_pins(
+                        &available,
+                        &image.apk.versions,
+                        &format!("{mirror} {branch} on {platform}"),
+                    )?;
+                    let versions = lock
+                        .apk
+                        .entry(branch.clone())
+                        .or_default()
+                        .entry(platform.arch().to_string())
+                        .or_default();
+                    for package in &image.apk.packages {
+                        let version =
+                            available
+                                .get(package)
+                                .and_then(|v| v.last())
+                                .ok_or_else(|| {
+                                    Error::Lock(format!(
+                                        "{branch} has no package {package} for {platform}"
+                                    ))
+                                })?;
+                        versions.insert(package.clone(), version.clone());
+                    }
+                }
+            }
+            for suite in suites {
+                for &platform in &matrix.platforms {
+                    let available = apt::available(&archives, &suite, platform)?;
+                    apt::check_pins(
+                        &available,
+                        &image.apt.versions,
+                        &origin(&archives, &suite, platform),
+                    )?;
+                    let versions = lock
+                        .apt
+                        .entry(snapshot.clone())
+                        .or_default()
+                        .entry(suite.clone())
+                        .or_default()
+                        .entry(platform.arch().to_string())
+                        .or_default();
+                    for package in &image.apt.packages {
+                        let version =
+                            available
+                                .get(package)
+                                .and_then(|v| v.last())
+                                .ok_or_else(|| {
+                                    Error::Lock(format!(
+                                        "{suite} has no package {package} for {platform}"
+                                    ))
+                                })?;
+                        versions.insert(package.clone(), version.clone());
+                    }
+                }
+            }
+            if let Some(template) = &image.wasm_bindgen.release_url {
+                for version in &image.wasm_bindgen.versions {
+                    for &platform in &matrix.platforms {
+                        lock.lock_wasm_bindgen(template, version, platform)?;
+                    }
+                }
+            }
+        }
+        for version in &matrix.trunk {
+            let Some(sha256) = updates::crate_checksum(index, "trunk", version)? else {
+                return Err(Error::Lock(format!(
+                    "trunk {version} is not released in the index at {index}"
+                )));
+            };
+            lock.trunk.insert(version.clone(), LockedCrate { sha256 });
+        }
+        lock.validate()?;
+        Ok(lock)
+    }
+
+    /// Records the checksum of the wasm-bindgen-cli release of `version`
+    /// for `platform`, unless wasm-bindgen published none.
+    fn lock_wasm_bindgen(
+        &mut self,
+        template: &str,
+        version: &str,
+        platform: Platform,
+    ) -> Result<()> {
+        let target = wasm_bindgen::release_target(platform);
+        if self
+            .wasm_bindgen
+            .get(version)
+            .is_some_and(|targets| targets.contains_key(target))
+        {
+            return Ok(());
+        }
+        let url = wasm_bindgen::asset_url(template, version, platform);
+        if let Some(asset) = http::get(&url)? {
+            let sha256 = sha256_hex(&asset);
+            self.wasm_bindgen
+                .entry(version.to_string())
+                .or_default()
+                .insert(target.to_string(), LockedRelease { url, sha256 });
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (reference, locked) in &self.base {
+            let hex = locked.digest.strip_prefix("sha256:").unwrap_or_default();
+            if !is_sha256(hex) {
+                return Err(Error::Checksum(format!(
+                    "{reference} has a malformed digest"
+                )));
+            }
+        }
+        for (version, locked) in &self.trunk {
+            if !is_sha256(&locked.sha256) {
+                return Err(Error::Checksum(format!(
+                    "trunk crate {version} has a malformed sha256"
+                )));
+            }
+        }
+        for (version, targets) in &self.wasm_bindgen {
+            if targets.values().any(|release| !is_sha256(&release.sha256)) {
+                return Err(Error::Checksum(format!(
+                    "wasm-bindgen-cli {version} has a malformed sha256"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ImageLock {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let lock: ImageLock = toml::from_str(s)?;
+        lock.validate()?;
+        Ok(lock)
+    }
+}
+
+/// Names the suites of `archives` that apt reads for `suite`.
+fn origin(archives: &Archives, suite: &str, platform: Platform) -> String {
+    let suites: Vec<String> = archives
+        .suites(suite)
+        .iter()
+        .map(|(mirror, suite)| format!("{mirror} {suite}"))
+        .collect();
+    format!("{} on {platform}", suites.join(", "))
+}
+
+/// The digest `reference` points to, which must have an image for every
+/// one of `platforms`, and the Debian suite or Alpine branch of its first
+/// one, if any.
+fn resolve_base(reference: &str, platforms: &[Platform]) -> Result<LockedBase> {
+    let (target, tag) = Target::parse_reference(reference)?;
+    let name = target.name();
+    let mut registry = Registry::for_target(&target);
+    let digest =
+        updates::digest_for_platforms(&mut registry, &name, &tag, platforms)?.ok_or_else(|| {
+            Error::Lock(format!(
+                "{reference} has no image for every platform of the matrix"
+            ))
+        })?;
+    let image = registry
+        .platform_image(&name, &digest, platforms[0])?
+        .ok_or_else(|| Error::Lock(format!("{reference} disappeared while locking it")))?;
+    let blobs = image
+        .manifest
+        .layers
+        .iter()
+        .map(|layer| registry.blob(&name, &layer.digest))
+        .collect::<Result<Vec<_>>>()?;
+    let layers: Vec<&[u8]> = blobs.iter().map(Vec::as_slice).collect();
+    let os_release = match layer::read_file(&layers, "/etc/os-release")? {
+        Some(text) => Some(text),
+        None => layer::read_file(&layers, "/usr/lib/os-release")?,
+    };
+    let mut os_release =
+        sbom::parse_os_release(&String::from_utf8_lossy(&os_release.unwrap_or_default()));
+    let branch = match os_release.get("ID").map(String::as_str) {
+        Some("alpine") => os_release
+            .get("VERSION_ID")
+            .and_then(|version| apk::branch(version)),
+        _ => None,
+    };
+    Ok(LockedBase {
+        digest,
+        suite: os_release.remove("VERSION_CODENAME"),
+        branch,
+    })
+}
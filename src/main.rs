@@ -0,0 +1,949 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand};
+use trunk_docker::{
+    apk,
+    apt::Archives,
+    assemble::{self, RUST_DIST},
+    audit::{self, Database},
+    changelog,
+    crates::CRATES_DL,
+    diff::{Diff, Snapshot},
+    dockerfile::{self, TrunkSource},
+    entrypoint,
+    lock::ImageLock,
+    manifest::Manifest,
+    matrix::{Entry, Matrix},
+    pipeline::{self, Options},
+    platform::Platform,
+    process::{self, Invocation, SystemRunner},
+    registry::Registry,
+    repro, sbom, sign,
+    size::{self, Comparison, ImageSize},
+    smoke,
+    structure::{self, Spec},
+    tags,
+    target::Target,
+    trunk::TrunkLock,
+    updates::{self, CRATES_INDEX},
+    wasm_opt, Error, Result,
+};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build (and optionally push) the images of the version matrix.
+    Build(BuildArgs),
+    /// Show what `build` would run, without touching docker or a registry.
+    Plan {
+        #[command(flatten)]
+        args: BuildArgs,
+        /// Print the plan as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Generate a Dockerfile from the image manifest.
+    Generate {
+        #[command(flatten)]
+        inputs: Inputs,
+        #[command(flatten)]
+        image: ImageArgs,
+        /// Where to write the Dockerfile.
+        #[arg(short, long, default_value = "Dockerfile")]
+        output: PathBuf,
+    },
+    /// Assemble an image into an OCI layout without a docker daemon, from
+    /// the base image and the verified tool downloads.
+    Assemble {
+        #[command(flatten)]
+        inputs: Inputs,
+        #[command(flatten)]
+        image: ImageArgs,
+        /// Layout directory, or a tarball if it ends in `.tar`.
+        #[arg(short, long, default_value = "target/images/image.tar")]
+        output: PathBuf,
+        /// trunk-docker executable for the entrypoint; defaults to this
+        /// one, which only suits images for the platform it was built for.
+        #[arg(long, value_name = "PATH")]
+        entrypoint_binary: Option<PathBuf>,
+        /// Server the rust-std components are downloaded from.
+        #[arg(long, default_value = RUST_DIST)]
+        rust_dist: String,
+        /// Where crate sources are downloaded from, for the SBOMs.
+        #[arg(long, default_value = CRATES_DL)]
+        crates_dl: String,
+        /// Leave out the steps that run commands in the image (apk and
+        /// cargo install) instead of failing. Such an image is not the
+        /// published one, so it needs a `--tag` of its own.
+        #[arg(long, requires = "tag")]
+        partial: bool,
+        /// Debian mirror the apt packages are downloaded from; by default
+        /// the image's snapshot, or else deb.debian.org.
+        #[arg(long, requires = "apt_security_mirror")]
+        apt_mirror: Option<String>,
+        /// Mirror of the Debian security archive, to go with `--apt-mirror`.
+        #[arg(long, requires = "apt_mirror")]
+        apt_security_mirror: Option<String>,
+        /// Reference the image and its SBOMs and provenance are described
+        /// as, instead of the first tag of its matrix entry.
+        #[arg(long, value_name = "REFERENCE")]
+        tag: Option<String>,
+    },
+    /// Check the crates compiled into the cargo-installed tools against the
+    /// RustSec advisory database; fails if `[audit]` in the manifest denies
+    /// a finding.
+    Audit {
+        #[command(flatten)]
+        inputs: Inputs,
+        /// Only audit these trunk versions.
+        #[arg(long = "trunk", value_name = "VERSION")]
+        trunk: Vec<String>,
+        /// Local checkout of the RustSec advisory database.
+        #[arg(long, value_name = "DIR")]
+        advisory_db: PathBuf,
+        /// Where crate sources are downloaded from.
+        #[arg(long, default_value = CRATES_DL)]
+        crates_dl: String,
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Generate a key pair for signing images. The public key can be
+    /// given to `cosign verify --key` as well.
+    GenerateKeyPair {
+        /// Where to write the private key.
+        #[arg(long, default_value = "signing.key")]
+        private_key: PathBuf,
+        /// Where to write the public key.
+        #[arg(long, default_value = "signing.pub")]
+        public_key: PathBuf,
+    },
+    /// Sign pushed images, the way `cosign sign --key` does.
+    Sign {
+        /// Private key, as written by `generate-key-pair`.
+        #[arg(long, value_name = "PATH")]
+        key: PathBuf,
+        /// Image references, e.g. `torhovland/rust-trunk:0.14.0`.
+        #[arg(required = true)]
+        images: Vec<String>,
+    },
+    /// Check that an image is signed by a key before using it.
+    Verify {
+        /// Public key, e.g. `signing.pub` or a `cosign.pub`.
+        #[arg(long, value_name = "PATH")]
+        key: PathBuf,
+        /// Image reference, e.g. `torhovland/rust-trunk:0.14.0`.
+        image: String,
+    },
+    /// Build the fixture apps with `trunk build --release` in an image and
+    /// check what trunk writes to `dist/`.
+    Smoke {
+        /// Image to test, e.g. `torhovland/rust-trunk:0.14.0`.
+        image: String,
+        /// Only build these fixtures.
+        #[arg(long = "fixture", value_name = "NAME")]
+        fixtures: Vec<String>,
+        /// Directory holding the fixture apps.
+        #[arg(long, default_value = smoke::FIXTURES)]
+        fixtures_dir: PathBuf,
+        /// Where the fixtures are copied to and built.
+        #[arg(long, default_value = "target/smoke")]
+        work_dir: PathBuf,
+    },
+    /// Show what changed between two images: Debian or Alpine packages,
+    /// cargo-installed crates, environment variables and the files in
+    /// /usr/local/cargo/bin.
+    Diff {
+        /// The older image, as a reference or an OCI layout directory.
+        old: String,
+        /// The newer image, as a reference or an OCI layout directory.
+        new: String,
+        /// Platform of the images to compare from image indexes.
+        #[arg(long, default_value_t = Platform::Amd64)]
+        platform: Platform,
+        /// Print the changes as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Report the layer sizes of an OCI layout, compared with a published
+    /// image; fails if it grew past `[size]` in the manifest.
+    Size {
+        /// The layout, e.g. as written by `assemble`.
+        layout: PathBuf,
+        /// Published image to compare with, e.g. `torhovland/rust-trunk:0.14.0`.
+        #[arg(long, value_name = "IMAGE")]
+        against: Option<String>,
+        #[arg(long, default_value_t = Platform::Amd64)]
+        platform: Platform,
+        /// Path to the image manifest.
+        #[arg(long, default_value = "image.toml")]
+        manifest: PathBuf,
+    },
+    /// Check what an image contains against the structure test spec.
+    StructureTest {
+        /// Image to test, e.g. `torhovland/rust-trunk:0.14.0`.
+        image: String,
+        /// The trunk version the image holds.
+        #[arg(long)]
+        trunk: String,
+        /// The Rust version the image is built on.
+        #[arg(long)]
+        rust: String,
+        #[arg(long, default_value_t = Platform::Amd64)]
+        platform: Platform,
+        /// Path to the spec.
+        #[arg(long, default_value = structure::SPEC)]
+        spec: PathBuf,
+    },
+    /// Build an image twice from scratch under the same SOURCE_DATE_EPOCH
+    /// and compare the layers; fails if any differ.
+    VerifyRepro {
+        #[command(flatten)]
+        inputs: Inputs,
+        #[command(flatten)]
+        image: ImageArgs,
+        /// Timestamp the builds set in the image; defaults to
+        /// $SOURCE_DATE_EPOCH, then to the commit time of the build context.
+        #[arg(long, value_name = "SECONDS")]
+        source_date_epoch: Option<u64>,
+        /// Docker build context.
+        #[arg(long, default_value = ".")]
+        context: PathBuf,
+        /// Directory for the generated Dockerfile and both builds.
+        #[arg(long, default_value = "target/repro")]
+        out_dir: PathBuf,
+    },
+    /// Look for trunk releases and Rust base images newer than the
+    /// matrix's; add the trunk release to the matrix, move it to the base
+    /// image, and raise the manifest's binaryen if the new trunk needs it.
+    CheckUpdates {
+        #[command(flatten)]
+        inputs: Inputs,
+        /// The crates.io index: a sparse index URL or a local mirror.
+        #[arg(long, default_value = CRATES_INDEX)]
+        index: String,
+        /// Only report the updates, leaving the matrix as it is.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Resolve the base image digests, apt and apk package versions and
+    /// trunk crate checksums of the matrix as upstream has them now, and record them
+    /// in the image lockfile that builds use.
+    UpdateLock {
+        #[command(flatten)]
+        inputs: Inputs,
+        /// The crates.io index: a sparse index URL or a local mirror.
+        #[arg(long, default_value = CRATES_INDEX)]
+        index: String,
+        /// Debian mirror the apt package versions are looked up in; by
+        /// default each image's snapshot, or else deb.debian.org.
+        #[arg(long, requires = "apt_security_mirror")]
+        apt_mirror: Option<String>,
+        /// Mirror of the Debian security archive, to go with `--apt-mirror`.
+        #[arg(long, requires = "apt_mirror")]
+        apt_security_mirror: Option<String>,
+        /// Alpine mirror the apk package versions are looked up in.
+        #[arg(long, default_value = apk::ALPINE_MIRROR)]
+        apk_mirror: String,
+    },
+    /// Record the checksums of prebuilt trunk releases in the lockfile.
+    LockTrunk {
+        #[command(flatten)]
+        inputs: Inputs,
+        /// Versions to lock; defaults to every trunk version in the matrix.
+        #[arg(long = "trunk", value_name = "VERSION")]
+        trunk: Vec<String>,
+    },
+    /// Fail unless the `wasm-opt` on PATH is new enough for a trunk version.
+    CheckWasmOpt {
+        #[arg(long)]
+        trunk: String,
+    },
+    /// Check the project in the working directory against the image, then
+    /// run COMMAND.
+    Entrypoint {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    #[command(flatten)]
+    inputs: Inputs,
+    /// Only build these trunk versions.
+    #[arg(long = "trunk", value_name = "VERSION")]
+    trunk: Vec<String>,
+    /// Only build on these Rust versions.
+    #[arg(long = "rust", value_name = "VERSION")]
+    rust: Vec<String>,
+    /// Push the tags after building.
+    #[arg(long)]
+    push: bool,
+    /// Push to this repository instead of the matrix's targets, e.g.
+    /// `ghcr.io/our-org/rust-trunk`; repeat for several.
+    #[arg(long = "target", value_name = "REPOSITORY")]
+    targets: Vec<Target>,
+    /// Docker build context.
+    #[arg(long, default_value = ".")]
+    context: PathBuf,
+    /// Directory for the generated Dockerfiles.
+    #[arg(long, default_value = "target/images")]
+    out_dir: PathBuf,
+    /// Where crate sources are downloaded from, for the SBOMs and the
+    /// audit.
+    #[arg(long, default_value = CRATES_DL)]
+    crates_dl: String,
+    /// Sign the pushed images with this private key.
+    #[arg(long, value_name = "PATH", requires = "push")]
+    signing_key: Option<PathBuf>,
+    /// Audit the cargo-installed tools against this checkout of the
+    /// RustSec advisory database before building.
+    #[arg(long, value_name = "DIR")]
+    advisory_db: Option<PathBuf>,
+    /// Run the structure tests of this spec against the built images; only
+    /// for local builds, which load the images into docker.
+    #[arg(
+        long,
+        value_name = "SPEC",
+        num_args = 0..=1,
+        default_missing_value = structure::SPEC,
+        conflicts_with = "push"
+    )]
+    structure_test: Option<PathBuf>,
+    /// Build the fixture apps in this directory with every built image and
+    /// its variants, like `smoke` does; only for local builds.
+    #[arg(
+        long,
+        value_name = "FIXTURES",
+        num_args = 0..=1,
+        default_missing_value = smoke::FIXTURES,
+        conflicts_with = "push"
+    )]
+    smoke: Option<PathBuf>,
+}
+
+impl BuildArgs {
+    /// Loads the inputs, with `--target` applied to the matrix.
+    fn load(&self) -> Result<(Matrix, Manifest, TrunkLock)> {
+        let (mut matrix, manifest, lock) = self.inputs.load()?;
+        if !self.targets.is_empty() {
+            matrix.retarget(self.targets.clone());
+        }
+        Ok((matrix, manifest, lock))
+    }
+
+    fn options(&self) -> Options {
+        Options {
+            context: self.context.clone(),
+            out_dir: self.out_dir.clone(),
+            push: self.push,
+            crates_dl: self.crates_dl.clone(),
+        }
+    }
+}
+
+/// Selects a single image of the matrix.
+#[derive(Args)]
+struct ImageArgs {
+    /// Trunk version; defaults to the newest one in the matrix.
+    #[arg(long)]
+    trunk: Option<String>,
+    /// Rust version; defaults to the newest one in the matrix.
+    #[arg(long)]
+    rust: Option<String>,
+    /// Platform; defaults to the first one in the matrix.
+    #[arg(long)]
+    platform: Option<Platform>,
+    /// Variant of the manifest; defaults to the default image.
+    #[arg(long, value_name = "NAME")]
+    variant: Option<String>,
+}
+
+impl ImageArgs {
+    fn select(self, matrix: &Matrix) -> Result<(Entry, Platform)> {
+        let default = matrix.default_entry();
+        let trunk = self.trunk.unwrap_or(default.trunk);
+        let rust = self.rust.unwrap_or(default.rust);
+        let entry = matrix
+            .select(std::slice::from_ref(&trunk), std::slice::from_ref(&rust))?
+            .pop()
+            .ok_or_else(|| Error::Matrix(format!("trunk {trunk} is not built on Rust {rust}")))?;
+        let platform = self.platform.unwrap_or(matrix.platforms[0]);
+        if !matrix.platforms.contains(&platform) {
+            return Err(Error::Matrix(format!("{platform} is not in the matrix")));
+        }
+        Ok((entry, platform))
+    }
+}
+
+#[derive(Args)]
+struct Inputs {
+    /// Path to the version matrix.
+    #[arg(long, default_value = "matrix.toml")]
+    matrix: PathBuf,
+    /// Path to the image manifest.
+    #[arg(long, default_value = "image.toml")]
+    manifest: PathBuf,
+    /// Path to the trunk release checksums.
+    #[arg(long, default_value = "trunk.lock")]
+    trunk_lock: PathBuf,
+    /// Path to the locked base images, apt packages and trunk crates.
+    #[arg(long, default_value = "image.lock")]
+    image_lock: PathBuf,
+}
+
+impl Inputs {
+    fn load(&self) -> Result<(Matrix, Manifest, TrunkLock)> {
+        Ok((
+            Matrix::load(&self.matrix)?,
+            Manifest::load(&self.manifest)?,
+            TrunkLock::load(&self.trunk_lock)?,
+        ))
+    }
+
+    fn image_lock(&self) -> Result<ImageLock> {
+        ImageLock::load(&self.image_lock)
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Build(args) => {
+            let (matrix, manifest, lock) = args.load()?;
+            let image_lock = args.inputs.image_lock()?;
+            let entries = matrix.select(&args.trunk, &args.rust)?;
+            let options = args.options();
+            let spec = args.structure_test.as_deref().map(Spec::load).transpose()?;
+            let fixtures = args.smoke.as_deref().map(smoke::fixtures).transpose()?;
+            let signing_key = args
+                .signing_key
+                .as_deref()
+                .map(sign::load_signing_key)
+                .transpose()?;
+            if let Some(advisory_db) = &args.advisory_db {
+                let report = run_audit(&manifest, &entries, advisory_db, &options.crates_dl)?;
+                print!("{report}");
+                let path = options.out_dir.join("audit.json");
+                report.write_json(&path)?;
+                println!("audit report written to {}", path.display());
+                report.check()?;
+            }
+            let mut plan = pipeline::plan(&matrix, &manifest, &lock, &entries, &options)?;
+            plan.prepare(&manifest, &lock, &image_lock)?;
+            pipeline::run(&mut SystemRunner, &plan.invocations(&options))?;
+            plan.record_digests()?;
+            if options.push {
+                let sizes = plan.measure()?;
+                for comparison in &sizes {
+                    print!("{comparison}");
+                }
+                let path = options.out_dir.join("sizes.json");
+                size::write_json(&path, &sizes)?;
+                println!("size report written to {}", path.display());
+                for comparison in &sizes {
+                    comparison.check(&manifest.size)?;
+                }
+            }
+            if let Some(spec) = &spec {
+                let reports = plan.structure_test(&mut SystemRunner, spec);
+                for report in &reports {
+                    print!("{report}");
+                }
+                for report in &reports {
+                    report.check()?;
+                }
+            }
+            if let Some(fixtures) = &fixtures {
+                let work_dir = options.out_dir.join("smoke");
+                let reports = plan.smoke_test(&mut SystemRunner, fixtures, &work_dir)?;
+                for report in &reports {
+                    print!("{report}");
+                }
+                for report in &reports {
+                    report.check()?;
+                }
+            }
+            if options.push {
+                plan.write_sboms(&manifest, &options)?;
+                plan.write_provenance(&manifest, &lock, &image_lock, &options)?;
+                let entries = plan.changelog()?;
+                for path in changelog::write(&options.out_dir.join("changelog"), &entries)? {
+                    println!("changelog written to {}", path.display());
+                }
+                plan.push()?;
+                if let Some(key) = &signing_key {
+                    plan.sign(key)?;
+                }
+                plan.tag()?;
+            }
+            let path = options.out_dir.join("plan.json");
+            plan.write_json(&path)?;
+            println!("build plan with digests written to {}", path.display());
+            Ok(())
+        }
+        Command::Plan { args, json } => {
+            let (matrix, manifest, lock) = args.load()?;
+            let entries = matrix.select(&args.trunk, &args.rust)?;
+            let options = args.options();
+            let plan = pipeline::plan(&matrix, &manifest, &lock, &entries, &options)?;
+            let preview = plan.preview(&lock, &options);
+            if json {
+                println!("{}", preview.to_json());
+            } else {
+                print!("{preview}");
+            }
+            Ok(())
+        }
+        Command::Generate {
+            inputs,
+            image,
+            output,
+        } => {
+            let (matrix, manifest, lock) = inputs.load()?;
+            let image_lock = inputs.image_lock()?;
+            let manifest = manifest.variant(image.variant.as_deref())?;
+            let (entry, platform) = image.select(&matrix)?;
+            let source = TrunkSource::for_entry(manifest, &lock, &entry, platform);
+            image_lock.check_apt_pins(manifest, &entry, platform, &manifest.apt.archives())?;
+            dockerfile::write(&output, manifest, &image_lock, &entry, platform, source)
+        }
+        Command::Assemble {
+            inputs,
+            image,
+            output,
+            entrypoint_binary,
+            rust_dist,
+            crates_dl,
+            partial,
+            tag: reference,
+            apt_mirror,
+            apt_security_mirror,
+        } => {
+            let (matrix, manifest, lock) = inputs.load()?;
+            let image_lock = inputs.image_lock()?;
+            let flavor = manifest.base.suffix.as_deref();
+            let variant = image.variant.clone();
+            let (entry, platform) = image.select(&matrix)?;
+            let tag = &tags::for_variant(&matrix, flavor, variant.as_deref(), &entry)?[0];
+            let manifest = manifest.variant(variant.as_deref())?;
+            let entrypoint_binary = match entrypoint_binary {
+                Some(path) => path,
+                None => env::current_exe().map_err(|source| Error::Io {
+                    path: "trunk-docker".into(),
+                    source,
+                })?,
+            };
+            let options = assemble::Options {
+                output,
+                name: reference.unwrap_or_else(|| matrix.targets_for(&entry)[0].reference(tag)),
+                rust_dist,
+                crates_dl,
+                entrypoint_binary: Some(entrypoint_binary),
+                partial,
+                apt_archives: apt_mirror
+                    .zip(apt_security_mirror)
+                    .map(|(debian, security)| Archives { debian, security }),
+                source: ".".into(),
+            };
+            let assembled =
+                assemble::assemble(manifest, &lock, &image_lock, &entry, platform, &options)?;
+            for step in &assembled.skipped {
+                println!("left out: {step}");
+            }
+            for path in assembled.sboms.values() {
+                println!("SBOM written to {}", path.display());
+            }
+            println!("provenance written to {}", assembled.provenance.display());
+            println!(
+                "assembled {} ({} layers) into {}",
+                assembled.manifest.digest,
+                assembled.layers.len(),
+                options.output.display()
+            );
+            Ok(())
+        }
+        Command::Audit {
+            inputs,
+            trunk,
+            advisory_db,
+            crates_dl,
+            json,
+        } => {
+            let (matrix, manifest, _) = inputs.load()?;
+            let entries = matrix.select(&trunk, &[])?;
+            let report = run_audit(&manifest, &entries, &advisory_db, &crates_dl)?;
+            if json {
+                println!("{}", report.to_json());
+            } else {
+                print!("{report}");
+            }
+            report.check()
+        }
+        Command::GenerateKeyPair {
+            private_key,
+            public_key,
+        } => {
+            if private_key.exists() {
+                return Err(Error::Signature(format!(
+                    "{} already exists; not overwriting it",
+                    private_key.display()
+                )));
+            }
+            sign::generate_key_pair(&private_key, &public_key)?;
+            println!(
+                "private key written to {}, public key to {}",
+                private_key.display(),
+                public_key.display()
+            );
+            Ok(())
+        }
+        Command::Sign { key, images } => {
+            let key = sign::load_signing_key(&key)?;
+            for image in images {
+                let (target, reference) = Target::parse_reference(&image)?;
+                let mut registry = Registry::for_target(&target);
+                let digest = sign::resolve(&mut registry, &target, &reference)?;
+                sign::sign(&mut registry, &target, &digest, &key)?;
+                println!("signed {target}@{digest}");
+            }
+            Ok(())
+        }
+        Command::Smoke {
+            image,
+            fixtures,
+            fixtures_dir,
+            work_dir,
+        } => {
+            let mut available = smoke::fixtures(&fixtures_dir)?;
+            if let Some(unknown) = fixtures
+                .iter()
+                .find(|name| !available.iter().any(|f| &f.name == *name))
+            {
+                return Err(Error::Smoke(format!(
+                    "no fixture named {unknown} in {}",
+                    fixtures_dir.display()
+                )));
+            }
+            if !fixtures.is_empty() {
+                available.retain(|f| fixtures.contains(&f.name));
+            }
+            let report = smoke::run(&mut SystemRunner, &image, &available, &work_dir)?;
+            print!("{report}");
+            report.check()
+        }
+        Command::Diff {
+            old,
+            new,
+            platform,
+            json,
+        } => {
+            let diff = Diff::new(
+                &Snapshot::load(&old, platform)?,
+                &Snapshot::load(&new, platform)?,
+            );
+            if json {
+                println!("{}", diff.to_json());
+            } else {
+                print!("{diff}");
+            }
+            Ok(())
+        }
+        Command::Size {
+            layout,
+            against,
+            platform,
+            manifest,
+        } => {
+            let manifest = Manifest::load(&manifest)?;
+            let current = ImageSize::of_layout(&layout)?;
+            let previous = match against {
+                Some(image) => {
+                    let (target, tag) = Target::parse_reference(&image)?;
+                    let mut registry = Registry::for_target(&target);
+                    let previous = ImageSize::of_published(
+                        &mut registry,
+                        &target,
+                        &tag,
+                        platform,
+                        &current.layers,
+                    )?;
+                    if previous.is_none() {
+                        return Err(Error::Size(format!("{image} has no {platform} image")));
+                    }
+                    previous
+                }
+                None => None,
+            };
+            let comparison = Comparison { current, previous };
+            print!("{comparison}");
+            comparison.check(&manifest.size)
+        }
+        Command::StructureTest {
+            image,
+            trunk,
+            rust,
+            platform,
+            spec,
+        } => {
+            let spec = Spec::load(&spec)?;
+            let entry = Entry { trunk, rust };
+            let report = structure::run(&mut SystemRunner, &spec, &image, &entry, platform);
+            print!("{report}");
+            report.check()
+        }
+        Command::Verify { key, image } => {
+            let key = sign::load_verifying_key(&key)?;
+            let (target, reference) = Target::parse_reference(&image)?;
+            let mut registry = Registry::for_target(&target);
+            let verified = sign::verify(&mut registry, &target, &reference, &key)?;
+            println!(
+                "{image} ({}) has {} valid signature(s)",
+                verified.digest,
+                verified.payloads.len()
+            );
+            for payload in &verified.payloads {
+                println!("  signed as {}", payload.critical.identity.docker_reference);
+            }
+            Ok(())
+        }
+        Command::CheckUpdates {
+            inputs,
+            index,
+            dry_run,
+        } => {
+            let matrix = Matrix::load(&inputs.matrix)?;
+            let manifest = Manifest::load(&inputs.manifest)?;
+            let check = updates::check(&matrix, &manifest.base, &index)?;
+            print!("{check}");
+            let binaryen = if check.trunk.is_newer() {
+                updates::binaryen_bump(&manifest, &check.trunk.latest)?
+            } else {
+                None
+            };
+            if let (Some(version), Some(current)) = (binaryen, &manifest.binaryen) {
+                println!(
+                    "binaryen: {} -> {version} (needed by trunk {})",
+                    current.version, check.trunk.latest
+                );
+            }
+            if dry_run {
+                return Ok(());
+            }
+            let read = |path: &PathBuf| {
+                fs::read_to_string(path).map_err(|source| Error::Io {
+                    path: path.clone(),
+                    source,
+                })
+            };
+            let mut text = read(&inputs.matrix)?;
+            if check.trunk.is_newer() {
+                text = updates::add(&text, "trunk", &check.trunk.current, &check.trunk.latest)?;
+            }
+            if check.rust.is_newer() {
+                text = updates::bump(&text, "rust", &check.rust.current, &check.rust.latest)?;
+            }
+            let mut bumped = Vec::new();
+            if let Some(version) = binaryen {
+                let manifest_text = updates::bump_binaryen(&read(&inputs.manifest)?, version)?;
+                bumped.push((&inputs.manifest, manifest_text));
+            }
+            if check.trunk.is_newer() || check.rust.is_newer() {
+                bumped.push((&inputs.matrix, text));
+            }
+            for (path, text) in bumped {
+                fs::write(path, text).map_err(|source| Error::Write {
+                    path: path.clone(),
+                    source,
+                })?;
+                println!("bumped {}", path.display());
+            }
+            Ok(())
+        }
+        Command::VerifyRepro {
+            inputs,
+            image,
+            source_date_epoch,
+            context,
+            out_dir,
+        } => {
+            let (matrix, manifest, lock) = inputs.load()?;
+            let image_lock = inputs.image_lock()?;
+            let variant = image.variant.clone();
+            manifest.variant(variant.as_deref())?;
+            let (entry, platform) = image.select(&matrix)?;
+            let epoch = match (source_date_epoch, env::var("SOURCE_DATE_EPOCH")) {
+                (Some(epoch), _) => epoch,
+                (None, Ok(value)) => value.parse().map_err(|_| {
+                    Error::Repro(format!("SOURCE_DATE_EPOCH={value} is not a timestamp"))
+                })?,
+                (None, Err(_)) => repro::commit_epoch(&context)?,
+            };
+            let options = Options {
+                context: context.clone(),
+                out_dir,
+                push: false,
+                crates_dl: CRATES_DL.to_string(),
+            };
+            let mut plan = pipeline::plan(
+                &matrix,
+                &manifest,
+                &lock,
+                std::slice::from_ref(&entry),
+                &options,
+            )?;
+            plan.images.retain(|image| image.variant == variant);
+            plan.images[0]
+                .platforms
+                .retain(|build| build.platform == platform);
+            plan.prepare(&manifest, &lock, &image_lock)?;
+            let dir =
+                pipeline::build_dir(&options, &entry, variant.as_deref(), platform).join("repro");
+            let report = repro::verify(
+                &mut SystemRunner,
+                &plan.images[0].platforms[0],
+                &context,
+                epoch,
+                &dir,
+            )?;
+            print!("{report}");
+            report.check()
+        }
+        Command::UpdateLock {
+            inputs,
+            index,
+            apt_mirror,
+            apt_security_mirror,
+            apk_mirror,
+        } => {
+            let (matrix, manifest, _) = inputs.load()?;
+            let archives = apt_mirror
+                .zip(apt_security_mirror)
+                .map(|(debian, security)| Archives { debian, security });
+            let lock = ImageLock::resolve(
+                &matrix,
+                &manifest,
+                &index,
+                archives.as_ref(),
+                Some(&apk_mirror),
+            )?;
+            for (reference, base) in &lock.base {
+                match &base.suite {
+                    Some(suite) => println!("locked {reference}@{} ({suite})", base.digest),
+                    None => println!("locked {reference}@{}", base.digest),
+                }
+            }
+            for (snapshot, suites) in &lock.apt {
+                for (suite, arches) in suites {
+                    for (arch, packages) in arches {
+                        for (package, version) in packages {
+                            println!(
+                                "locked {package}={version} in {suite} on {arch} at snapshot {snapshot}"
+                            );
+                        }
+                    }
+                }
+            }
+            for (branch, arches) in &lock.apk {
+                for (arch, packages) in arches {
+                    for (package, version) in packages {
+                        println!("locked {package}={version} in Alpine {branch} on {arch}");
+                    }
+                }
+            }
+            for (version, locked) in &lock.trunk {
+                println!("locked trunk crate {version} with sha256 {}", locked.sha256);
+            }
+            for (version, targets) in &lock.wasm_bindgen {
+                for (target, release) in targets {
+                    println!(
+                        "locked wasm-bindgen-cli {version} for {target} with sha256 {}",
+                        release.sha256
+                    );
+                }
+            }
+            lock.save(&inputs.image_lock)
+        }
+        Command::LockTrunk { inputs, trunk } => {
+            let (matrix, manifest, mut lock) = inputs.load()?;
+            let Some(template) = manifest.trunk.release_url else {
+                return Err(Error::Manifest(
+                    "`trunk.release-url` is not set, so there is nothing to lock".into(),
+                ));
+            };
+            let versions = if trunk.is_empty() {
+                matrix.trunk
+            } else {
+                trunk
+            };
+            for version in &versions {
+                for &platform in &matrix.platforms {
+                    if lock.lock(&template, version, platform)? {
+                        println!("locked trunk {version} for {platform}");
+                    } else {
+                        println!(
+                            "trunk {version} has no prebuilt binary for {platform}; \
+                             it will be built with cargo install"
+                        );
+                    }
+                }
+            }
+            lock.save(&inputs.trunk_lock)
+        }
+        Command::CheckWasmOpt { trunk } => {
+            let version = wasm_opt::installed_version()?;
+            wasm_opt::check(version, &trunk)?;
+            println!("wasm-opt version {version} is new enough for trunk {trunk}");
+            Ok(())
+        }
+        Command::Entrypoint { command } => {
+            let dir = env::current_dir().map_err(|source| Error::Io {
+                path: ".".into(),
+                source,
+            })?;
+            let invocation = Invocation::new(&command[0]).args(&command[1..]);
+            Err(process::exec(&entrypoint::prepare(&dir, invocation)?))
+        }
+    }
+}
+
+/// Audits the cargo-installed tools of every entry.
+fn run_audit(
+    manifest: &Manifest,
+    entries: &[Entry],
+    advisory_db: &Path,
+    crates_dl: &str,
+) -> Result<audit::Report> {
+    let database = Database::open(advisory_db)?;
+    let mut tools = Vec::new();
+    for entry in entries {
+        for (_, image) in manifest.images() {
+            for tool in sbom::tools(image, entry) {
+                if !tools.contains(&tool) {
+                    tools.push(tool);
+                }
+            }
+        }
+    }
+    audit::audit(&database, &tools, crates_dl, &manifest.audit)
+}
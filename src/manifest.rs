@@ -0,0 +1,554 @@
+use std::{collections::BTreeMap, iter, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{apt, audit, config, platform::Platform, size, wasm_opt, Error, Result};
+
+/// Packages that provide the C toolchain `cargo install` links with.
+const C_TOOLCHAINS: &[&str] = &["build-essential", "gcc", "build-base", "musl-dev"];
+
+/// The declarative description of the image, read from `image.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub base: Base,
+    #[serde(default)]
+    pub rust: Rust,
+    #[serde(default)]
+    pub trunk: Trunk,
+    #[serde(default)]
+    pub apt: Apt,
+    /// Alpine packages, for bases such as `rust:1.56-alpine`.
+    #[serde(default)]
+    pub apk: Apk,
+    /// Pinned binaryen release `wasm-opt` is installed from.
+    pub binaryen: Option<Binaryen>,
+    #[serde(default, rename = "wasm-bindgen")]
+    pub wasm_bindgen: WasmBindgen,
+    /// Crates installed with `cargo install` after trunk.
+    #[serde(default)]
+    pub cargo: Vec<CargoTool>,
+    /// Installs `trunk-docker` as the image entrypoint when present.
+    pub entrypoint: Option<EntrypointStage>,
+    /// Which RustSec advisories against the cargo-installed tools fail the
+    /// release.
+    #[serde(default)]
+    pub audit: audit::Policy,
+    /// How much an image may grow over the one it replaces.
+    #[serde(default)]
+    pub size: size::Budget,
+    /// Named variants of the image, from `[variants.<name>]`. Each table a
+    /// variant sets replaces the one of the same name above, e.g.
+    /// `[variants.minimal.apt]` replaces `[apt]`.
+    #[serde(skip)]
+    pub variants: BTreeMap<String, Manifest>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Base {
+    /// The official Rust image, e.g. `rust`.
+    pub image: String,
+    /// Appended to the Rust version to form the base tag, e.g. `slim`.
+    pub suffix: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rust {
+    /// Targets added with `rustup target add`, so builds never fetch them.
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Trunk {
+    /// Where prebuilt releases are downloaded from, with `{version}` and
+    /// `{target}` placeholders. Without it trunk is always built from source.
+    pub release_url: Option<String>,
+    /// Builds trunk with `cargo install` even where `trunk.lock` pins a
+    /// prebuilt release, e.g. on musl bases the glibc releases do not run on.
+    #[serde(default)]
+    pub from_source: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Apt {
+    pub packages: Vec<String>,
+    /// Exact Debian versions of some of the packages, e.g.
+    /// `git = "1:2.30.2-1"`; the others are taken from `image.lock`. Both
+    /// need a `snapshot` to install from.
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+    /// A snapshot.debian.org timestamp, e.g. `20220301T000000Z`. The image
+    /// installs the packages from the archive as it was then.
+    pub snapshot: Option<String>,
+}
+
+impl Apt {
+    /// The Debian archives the packages are installed from.
+    pub fn archives(&self) -> apt::Archives {
+        match &self.snapshot {
+            Some(timestamp) => apt::Archives {
+                debian: apt::snapshot_url("debian", timestamp),
+                security: apt::snapshot_url("debian-security", timestamp),
+            },
+            None => apt::Archives {
+                debian: apt::DEBIAN_MIRROR.to_string(),
+                security: apt::DEBIAN_SECURITY_MIRROR.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Apk {
+    pub packages: Vec<String>,
+    /// Exact Alpine versions of some of the packages, e.g.
+    /// `curl = "8.5.0-r0"`; the others are taken from `image.lock`.
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Binaryen {
+    /// Release number, e.g. `105` for `version_105`.
+    pub version: u32,
+    /// SHA-256 of the release tarballs, keyed by machine (`x86_64`,
+    /// `aarch64`). Without a pin the image build checks the tarball against
+    /// the `.sha256` file published next to it.
+    #[serde(default)]
+    pub sha256: BTreeMap<String, String>,
+    /// Where the release tarballs are downloaded from instead of GitHub,
+    /// with `{version}` and `{machine}` placeholders.
+    pub release_url: Option<String>,
+}
+
+impl Binaryen {
+    /// Download URL of the release tarball for `platform`.
+    pub fn url(&self, platform: Platform) -> String {
+        match &self.release_url {
+            Some(template) => template
+                .replace("{version}", &self.version.to_string())
+                .replace("{machine}", platform.machine()),
+            None => wasm_opt::release_url(self.version, platform.machine()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WasmBindgen {
+    /// wasm-bindgen-cli versions bundled in the image; the entrypoint picks
+    /// the one matching the project's `Cargo.lock`.
+    #[serde(default)]
+    pub versions: Vec<String>,
+    /// Where prebuilt releases are downloaded from, with `{version}` and
+    /// `{target}` placeholders. Versions `image.lock` has no checksum for,
+    /// or that are built without one, are compiled with `cargo install`.
+    pub release_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CargoTool {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntrypointStage {
+    /// Image `trunk-docker` is compiled in. It needs a current Rust and the
+    /// same C library as the base image, e.g. `rust:1.82-slim-bullseye`.
+    pub builder: String,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// The default image, then every variant by name.
+    pub fn images(&self) -> impl Iterator<Item = (Option<&str>, &Manifest)> {
+        iter::once((None, self)).chain(
+            self.variants
+                .iter()
+                .map(|(name, variant)| (Some(name.as_str()), variant)),
+        )
+    }
+
+    /// The manifest of a variant, or of the default image for `None`.
+    pub fn variant(&self, name: Option<&str>) -> Result<&Manifest> {
+        match name {
+            None => Ok(self),
+            Some(name) => self
+                .variants
+                .get(name)
+                .ok_or_else(|| Error::Manifest(format!("there is no variant named {name}"))),
+        }
+    }
+
+    /// Whether the packages give the image a C compiler and C library to
+    /// link build scripts and proc macros with: `build-essential` or `gcc`
+    /// on Debian, `build-base` or `musl-dev` on Alpine, whose Rust images
+    /// ship gcc without the C library.
+    pub fn has_c_toolchain(&self) -> bool {
+        self.apt
+            .packages
+            .iter()
+            .chain(&self.apk.packages)
+            .any(|p| C_TOOLCHAINS.contains(&p.as_str()))
+    }
+
+    /// Fails if a variant dropped the C toolchain with the packages it
+    /// replaced, but still compiles trunk, wasm-bindgen-cli or cargo tools.
+    /// Whether trunk is compiled without `trunk.from-source` depends on
+    /// `trunk.lock`, so the build plan checks that.
+    fn check_cargo_installs(&self) -> Result<()> {
+        if self.trunk.from_source {
+            self.check_cargo_install("trunk")?;
+        }
+        if let Some(version) = self.wasm_bindgen.versions.first() {
+            self.check_cargo_install(&format!("wasm-bindgen-cli {version}"))?;
+        }
+        if let Some(tool) = self.cargo.first() {
+            self.check_cargo_install(&tool.name)?;
+        }
+        Ok(())
+    }
+
+    /// Fails unless the image can `cargo install` the crate `what`.
+    pub fn check_cargo_install(&self, what: &str) -> Result<()> {
+        if self.has_c_toolchain() {
+            return Ok(());
+        }
+        Err(Error::Manifest(format!(
+            "`cargo install` of {what} needs a C toolchain; add one of {} to the packages",
+            C_TOOLCHAINS.join(", ")
+        )))
+    }
+
+    /// Reads the variants from the `[variants]` table of the manifest's
+    /// `table`, which must not have it anymore.
+    fn add_variants(&mut self, table: &toml::Table, variants: toml::Value) -> Result<()> {
+        let toml::Value::Table(variants) = variants else {
+            return Err(Error::Manifest(
+                "`variants` must be a table of named variants".into(),
+            ));
+        };
+        for (name, overrides) in variants {
+            if !is_variant_name(&name) || name == "latest" {
+                return Err(Error::Manifest(format!(
+                    "{name:?} is not a valid variant name; it is part of the tags"
+                )));
+            }
+            if self.base.suffix.as_deref() == Some(name.as_str()) {
+                return Err(Error::Manifest(format!(
+                    "variant {name} would share its tags with the default image's -{name} twins"
+                )));
+            }
+            let in_variant = |err| in_variant(Some(&name), err);
+            let toml::Value::Table(overrides) = overrides else {
+                return Err(in_variant(Error::Manifest(
+                    "must be a table of the tables it replaces".into(),
+                )));
+            };
+            if let Some(key) = ["audit", "size", "variants"]
+                .into_iter()
+                .find(|key| overrides.contains_key(*key))
+            {
+                return Err(in_variant(Error::Manifest(format!(
+                    "`[{key}]` applies to every image and cannot be set per variant"
+                ))));
+            }
+            let mut merged = table.clone();
+            merged.extend(overrides);
+            let variant: Manifest = merged
+                .try_into()
+                .map_err(|err| in_variant(Error::Toml(err)))?;
+            variant.validate().map_err(in_variant)?;
+            variant.check_cargo_installs().map_err(in_variant)?;
+            self.variants.insert(name, variant);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.base.image.is_empty() {
+            return Err(Error::Manifest("`base.image` must not be empty".into()));
+        }
+        let base = [Some(&self.base.image), self.base.suffix.as_ref()];
+        if base
+            .into_iter()
+            .flatten()
+            .any(|part| part.contains("distroless"))
+        {
+            return Err(Error::Manifest(
+                "distroless base images are not supported: the build steps need a shell and a \
+                 package manager, and building apps needs a C toolchain"
+                    .into(),
+            ));
+        }
+        if let Some(max) = self.size.max_growth_percent {
+            if !(max >= 0.0 && max.is_finite()) {
+                return Err(Error::Manifest(format!(
+                    "`size.max-growth-percent` must be a positive number, not {max}"
+                )));
+            }
+        }
+        if let Some(package) = self.apt.packages.iter().find(|p| !is_apt_name(p)) {
+            return Err(Error::Manifest(format!(
+                "{package:?} is not a valid apt package name"
+            )));
+        }
+        for (package, version) in &self.apt.versions {
+            if !self.apt.packages.contains(package) {
+                return Err(Error::Manifest(format!(
+                    "`apt.versions` pins {package}, which is not in `apt.packages`"
+                )));
+            }
+            if !is_debian_version(version) {
+                return Err(Error::Manifest(format!(
+                    "{version:?} is not a valid Debian version for {package}"
+                )));
+            }
+        }
+        if !self.apt.versions.is_empty() && self.apt.snapshot.is_none() {
+            return Err(Error::Manifest(
+                "`apt.versions` needs `apt.snapshot`; Debian mirrors drop superseded versions"
+                    .into(),
+            ));
+        }
+        if let Some(snapshot) = self.apt.snapshot.as_deref().filter(|s| !is_snapshot(s)) {
+            return Err(Error::Manifest(format!(
+                "{snapshot:?} is not a snapshot.debian.org timestamp like 20220301T000000Z"
+            )));
+        }
+        if let Some(package) = self.apk.packages.iter().find(|p| !is_apt_name(p)) {
+            return Err(Error::Manifest(format!(
+                "{package:?} is not a valid apk package name"
+            )));
+        }
+        for (package, version) in &self.apk.versions {
+            if !self.apk.packages.contains(package) {
+                return Err(Error::Manifest(format!(
+                    "`apk.versions` pins {package}, which is not in `apk.packages`"
+                )));
+            }
+            if !is_alpine_version(version) {
+                return Err(Error::Manifest(format!(
+                    "{version:?} is not a valid Alpine version for {package}"
+                )));
+            }
+        }
+        if !self.apt.packages.is_empty() && !self.apk.packages.is_empty() {
+            return Err(Error::Manifest(
+                "a base image has either apt or apk; list packages in one of them".into(),
+            ));
+        }
+        if let Some(target) = self.rust.targets.iter().find(|t| !is_target_name(t)) {
+            return Err(Error::Manifest(format!(
+                "{target:?} is not a valid rustup target"
+            )));
+        }
+        if let Some(binaryen) = &self.binaryen {
+            if self.apt.packages.iter().any(|p| p == "binaryen") {
+                return Err(Error::Manifest(
+                    "install binaryen either from apt or from `[binaryen]`, not both".into(),
+                ));
+            }
+            if !self
+                .apt
+                .packages
+                .iter()
+                .chain(&self.apk.packages)
+                .any(|p| p == "curl")
+            {
+                return Err(Error::Manifest(
+                    "downloading binaryen needs `curl` in `apt.packages` or `apk.packages`".into(),
+                ));
+            }
+            if let Some(sha256) = binaryen.sha256.values().find(|s| !is_sha256(s)) {
+                return Err(Error::Manifest(format!(
+                    "{sha256:?} is not a hex SHA-256 digest"
+                )));
+            }
+        }
+        if let Some(version) = self
+            .wasm_bindgen
+            .versions
+            .iter()
+            .find(|v| semver::Version::parse(v).is_err())
+        {
+            return Err(Error::Manifest(format!(
+                "{version:?} is not a valid wasm-bindgen version"
+            )));
+        }
+        if self.wasm_bindgen.release_url.is_some()
+            && !self.wasm_bindgen.versions.is_empty()
+            && !self
+                .apt
+                .packages
+                .iter()
+                .chain(&self.apk.packages)
+                .any(|p| p == "curl")
+        {
+            return Err(Error::Manifest(
+                "downloading wasm-bindgen-cli releases needs `curl` in `apt.packages` or \
+                 `apk.packages`"
+                    .into(),
+            ));
+        }
+        for tool in &self.cargo {
+            if !is_crate_name(&tool.name) {
+                return Err(Error::Manifest(format!(
+                    "{:?} is not a valid crate name",
+                    tool.name
+                )));
+            }
+            if tool.name == "trunk" {
+                return Err(Error::Manifest(
+                    "trunk is always installed; its version comes from the matrix".into(),
+                ));
+            }
+            if tool.version.is_empty() || !tool.version.chars().all(is_version_char) {
+                return Err(Error::Manifest(format!(
+                    "{:?} is not a valid version for {}",
+                    tool.version, tool.name
+                )));
+            }
+        }
+        if let Some(id) = self.audit.ignore.iter().find(|id| !is_advisory_id(id)) {
+            return Err(Error::Manifest(format!(
+                "{id:?} is not a RustSec advisory ID"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Base {
+    /// The base image reference for a Rust version, e.g. `rust:1.56-slim`.
+    pub fn reference(&self, rust: &str) -> String {
+        match &self.suffix {
+            Some(suffix) => format!("{}:{rust}-{suffix}", self.image),
+            None => format!("{}:{rust}", self.image),
+        }
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(s)?;
+        let variants = table.remove("variants");
+        let mut manifest: Manifest = table.clone().try_into()?;
+        manifest.validate()?;
+        if let Some(variants) = variants {
+            manifest.add_variants(&table, variants)?;
+        }
+        Ok(manifest)
+    }
+}
+
+fn is_apt_name(name: &str) -> bool {
+    name.len() > 1
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c))
+}
+
+/// E.g. `1:2.30.2-1+deb11u2`.
+fn is_debian_version(version: &str) -> bool {
+    version.starts_with(|c: char| c.is_ascii_digit())
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".+~:-".contains(c))
+}
+
+/// E.g. `8.5.0-r0`.
+fn is_alpine_version(version: &str) -> bool {
+    version.starts_with(|c: char| c.is_ascii_digit())
+        && version.contains("-r")
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+/// E.g. `20220301T000000Z`.
+fn is_snapshot(timestamp: &str) -> bool {
+    let bytes = timestamp.as_bytes();
+    bytes.len() == 16
+        && bytes[8] == b'T'
+        && bytes[15] == b'Z'
+        && bytes[..8]
+            .iter()
+            .chain(&bytes[9..15])
+            .all(u8::is_ascii_digit)
+}
+
+/// E.g. `alpine` or `full`.
+fn is_variant_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn is_crate_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn is_target_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+pub(crate) fn is_sha256(digest: &str) -> bool {
+    digest.len() == 64
+        && digest
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// E.g. `RUSTSEC-2020-0071`.
+fn is_advisory_id(id: &str) -> bool {
+    id.strip_prefix("RUSTSEC-")
+        .and_then(|rest| rest.split_once('-'))
+        .is_some_and(|(year, number)| {
+            year.len() == 4
+                && number.len() == 4
+                && year
+                    .chars()
+                    .chain(number.chars())
+                    .all(|c| c.is_ascii_digit())
+        })
+}
+
+fn is_version_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".-+".contains(c)
+}
+
+/// Names the variant an error of its manifest is about; `None` is the
+/// default image.
+pub(crate) fn in_variant(name: Option<&str>, err: Error) -> Error {
+    match (name, err) {
+        (Some(name), Error::Manifest(message)) => {
+            Error::Manifest(format!("variant {name}: {message}"))
+        }
+        (Some(name), Error::Toml(err)) => Error::Manifest(format!("variant {name}: {err}")),
+        (_, err) => err,
+    }
+}
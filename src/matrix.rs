@@ -0,0 +1,207 @@
+use std::{cmp::Ordering, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{config, platform::Platform, target::Target, Error, Result};
+
+/// The set of images to build: every trunk version on every Rust base version.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+    /// Repositories every image is pushed to, e.g. `torhovland/rust-trunk`
+    /// or `ghcr.io/our-org/rust-trunk`.
+    pub targets: Vec<Target>,
+    pub trunk: Vec<String>,
+    pub rust: Vec<String>,
+    /// Platforms every image is built for, combined into one image index.
+    #[serde(default = "default_platforms")]
+    pub platforms: Vec<Platform>,
+    /// Entries pushed somewhere other than `targets`.
+    #[serde(default)]
+    pub publish: Vec<Publish>,
+}
+
+/// Push targets for some of the entries. The first `[[publish]]` table
+/// matching an entry replaces `targets` for it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Publish {
+    /// Trunk versions this applies to; empty for all of them.
+    #[serde(default)]
+    pub trunk: Vec<String>,
+    /// Rust versions this applies to; empty for all of them.
+    #[serde(default)]
+    pub rust: Vec<String>,
+    pub targets: Vec<Target>,
+}
+
+impl Publish {
+    fn matches(&self, entry: &Entry) -> bool {
+        (self.trunk.is_empty() || self.trunk.contains(&entry.trunk))
+            && (self.rust.is_empty() || self.rust.contains(&entry.rust))
+    }
+}
+
+fn default_platforms() -> Vec<Platform> {
+    vec![Platform::Amd64]
+}
+
+/// One trunk × Rust combination of the matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub trunk: String,
+    pub rust: String,
+}
+
+impl Matrix {
+    pub fn load(path: &Path) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// All combinations, in the order the versions are listed.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.trunk
+            .iter()
+            .flat_map(|trunk| {
+                self.rust.iter().map(move |rust| Entry {
+                    trunk: trunk.clone(),
+                    rust: rust.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The combinations restricted to the given versions; an empty filter
+    /// keeps every version on that axis.
+    pub fn select(&self, trunk: &[String], rust: &[String]) -> Result<Vec<Entry>> {
+        check_known("trunk", trunk, &self.trunk)?;
+        check_known("rust", rust, &self.rust)?;
+        Ok(self
+            .entries()
+            .into_iter()
+            .filter(|e| trunk.is_empty() || trunk.contains(&e.trunk))
+            .filter(|e| rust.is_empty() || rust.contains(&e.rust))
+            .collect())
+    }
+
+    /// Where the images of `entry` are pushed.
+    pub fn targets_for(&self, entry: &Entry) -> &[Target] {
+        self.publish
+            .iter()
+            .find(|publish| publish.matches(entry))
+            .map_or(&self.targets, |publish| &publish.targets)
+    }
+
+    /// Pushes every entry to `targets` instead of the configured ones.
+    pub fn retarget(&mut self, targets: Vec<Target>) {
+        self.targets = targets;
+        self.publish.clear();
+    }
+
+    /// The newest Rust version in the matrix.
+    pub fn newest_rust(&self) -> &str {
+        newest(&self.rust)
+    }
+
+    /// The newest trunk on the newest Rust version, which is what the
+    /// checked-in `Dockerfile` is generated for.
+    pub fn default_entry(&self) -> Entry {
+        Entry {
+            trunk: newest(&self.trunk).to_owned(),
+            rust: self.newest_rust().to_owned(),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_targets("`targets`", &self.targets)?;
+        for publish in &self.publish {
+            check_known("trunk", &publish.trunk, &self.trunk)?;
+            check_known("rust", &publish.rust, &self.rust)?;
+            check_targets("`publish.targets`", &publish.targets)?;
+        }
+        if self.platforms.is_empty() {
+            return Err(Error::Matrix("`platforms` lists no platforms".into()));
+        }
+        if let Some(i) =
+            (1..self.platforms.len()).find(|&i| self.platforms[..i].contains(&self.platforms[i]))
+        {
+            return Err(Error::Matrix(format!(
+                "`platforms` lists {} more than once",
+                self.platforms[i]
+            )));
+        }
+        for (axis, versions) in [("trunk", &self.trunk), ("rust", &self.rust)] {
+            if versions.is_empty() {
+                return Err(Error::Matrix(format!("`{axis}` lists no versions")));
+            }
+            for (i, version) in versions.iter().enumerate() {
+                // Trunk versions may be pre-releases like `0.15.0-alpha.1`.
+                if axis == "trunk" {
+                    if semver::Version::parse(version).is_err() {
+                        return Err(Error::Matrix(format!(
+                            "trunk version {version:?} is not a full `major.minor.patch` version"
+                        )));
+                    }
+                } else if parse_version(version).is_none() {
+                    return Err(Error::Matrix(format!(
+                        "`{axis}` version {version:?} is not a dotted version number"
+                    )));
+                }
+                if versions[..i].contains(version) {
+                    return Err(Error::Matrix(format!(
+                        "`{axis}` lists {version} more than once"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Matrix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let matrix: Matrix = toml::from_str(s)?;
+        matrix.validate()?;
+        Ok(matrix)
+    }
+}
+
+fn check_known(axis: &str, wanted: &[String], known: &[String]) -> Result<()> {
+    match wanted.iter().find(|v| !known.contains(v)) {
+        Some(version) => Err(Error::Matrix(format!(
+            "{axis} version {version} is not in the matrix"
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn check_targets(field: &str, targets: &[Target]) -> Result<()> {
+    if targets.is_empty() {
+        return Err(Error::Matrix(format!("{field} lists no repositories")));
+    }
+    if let Some(i) = (1..targets.len()).find(|&i| targets[..i].contains(&targets[i])) {
+        return Err(Error::Matrix(format!(
+            "{field} lists {} more than once",
+            targets[i]
+        )));
+    }
+    Ok(())
+}
+
+fn newest(versions: &[String]) -> &str {
+    versions
+        .iter()
+        .max_by(|a, b| compare_versions(a, b))
+        .expect("validated matrix has versions on every axis")
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Orders dotted version numbers numerically, so that `1.9 < 1.10`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    parse_version(a).cmp(&parse_version(b))
+}
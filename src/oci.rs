@@ -0,0 +1,367 @@
+//! OCI image manifests, indexes and on-disk image layouts.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{platform::Platform, trunk::sha256_hex, Error, Result};
+
+pub const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const DOCKER_MANIFEST_LIST_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+pub const DOCKER_MANIFEST_MEDIA_TYPE: &str = "application/vnd.docker.distribution.manifest.v2+json";
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+/// The config of artifacts, which have none.
+pub const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<PlatformSpec>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlatformSpec {
+    pub architecture: String,
+    pub os: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub manifests: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageManifest {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl Descriptor {
+    /// Describes `bytes` as a blob of the given media type.
+    pub fn of(media_type: &str, bytes: &[u8]) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest: digest(bytes),
+            size: bytes.len() as u64,
+            artifact_type: None,
+            platform: None,
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    /// Just the media type, digest and size, as artifacts refer to their
+    /// subject.
+    pub fn bare(&self) -> Self {
+        Self {
+            media_type: self.media_type.clone(),
+            digest: self.digest.clone(),
+            size: self.size,
+            artifact_type: None,
+            platform: None,
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+/// An artifact manifest holding one blob about another manifest, its
+/// subject, e.g. an SBOM of an image.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    /// Describes the manifest, with its artifact type.
+    pub descriptor: Descriptor,
+    pub manifest: Vec<u8>,
+    /// The config and the blob.
+    pub blobs: Vec<Vec<u8>>,
+}
+
+impl Artifact {
+    pub fn new(artifact_type: &str, media_type: &str, blob: &[u8], subject: &Descriptor) -> Self {
+        let config = b"{}".to_vec();
+        let manifest = to_canonical_json(&ImageManifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+            artifact_type: Some(artifact_type.to_string()),
+            config: Descriptor::of(EMPTY_MEDIA_TYPE, &config),
+            layers: vec![Descriptor::of(media_type, blob)],
+            subject: Some(subject.bare()),
+            annotations: BTreeMap::new(),
+        });
+        let mut descriptor = Descriptor::of(MANIFEST_MEDIA_TYPE, &manifest);
+        descriptor.artifact_type = Some(artifact_type.to_string());
+        Self {
+            descriptor,
+            manifest,
+            blobs: vec![config, blob.to_vec()],
+        }
+    }
+}
+
+impl From<Platform> for PlatformSpec {
+    fn from(platform: Platform) -> Self {
+        Self {
+            architecture: platform.arch().to_string(),
+            os: "linux".to_string(),
+            variant: (platform == Platform::Arm64).then(|| "v8".to_string()),
+        }
+    }
+}
+
+impl Index {
+    pub fn new(manifests: Vec<Descriptor>) -> Self {
+        Self {
+            schema_version: 2,
+            media_type: Some(INDEX_MEDIA_TYPE.to_string()),
+            manifests,
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+/// The value of `key` in the environment of an image config.
+pub fn config_env(config: &serde_json::Value, key: &str) -> Option<String> {
+    config["config"]["Env"]
+        .as_array()?
+        .iter()
+        .filter_map(serde_json::Value::as_str)
+        .find_map(|var| var.strip_prefix(key)?.strip_prefix('='))
+        .map(str::to_string)
+}
+
+/// The `sha256:<hex>` digest of `bytes`.
+pub fn digest(bytes: &[u8]) -> String {
+    format!("sha256:{}", sha256_hex(bytes))
+}
+
+/// Serializes a manifest or index the way it is stored and digested.
+pub fn to_canonical_json<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("OCI types serialize")
+}
+
+pub fn is_index(media_type: &str) -> bool {
+    media_type == INDEX_MEDIA_TYPE || media_type == DOCKER_MANIFEST_LIST_MEDIA_TYPE
+}
+
+/// The OCI equivalent of a Docker image media type, whose content is the
+/// same; other media types are returned unchanged.
+pub fn to_oci_media_type(media_type: &str) -> &str {
+    match media_type {
+        DOCKER_MANIFEST_LIST_MEDIA_TYPE => INDEX_MEDIA_TYPE,
+        DOCKER_MANIFEST_MEDIA_TYPE => MANIFEST_MEDIA_TYPE,
+        "application/vnd.docker.container.image.v1+json" => CONFIG_MEDIA_TYPE,
+        "application/vnd.docker.image.rootfs.diff.tar.gzip" => LAYER_MEDIA_TYPE,
+        other => other,
+    }
+}
+
+/// An OCI image layout directory, as written by
+/// `docker buildx build --output type=oci,tar=false`.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub dir: PathBuf,
+    pub index: Index,
+}
+
+impl Layout {
+    /// Starts an empty layout in `dir`; blobs are added with
+    /// [`Layout::add_blob`] and the index is written by [`Layout::save`].
+    pub fn create(dir: &Path) -> Result<Self> {
+        write_file(
+            &dir.join("oci-layout"),
+            br#"{"imageLayoutVersion":"1.0.0"}"#,
+        )?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            index: Index::new(Vec::new()),
+        })
+    }
+
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join("index.json");
+        let index = read_json(
+            &path,
+            &fs::read(&path).map_err(|source| Error::Io {
+                path: path.clone(),
+                source,
+            })?,
+        )?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            index,
+        })
+    }
+
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        let (algorithm, hex) = digest.split_once(':').unwrap_or(("sha256", digest));
+        self.dir.join("blobs").join(algorithm).join(hex)
+    }
+
+    /// Reads a blob, checking it against its digest.
+    pub fn blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let path = self.blob_path(digest);
+        let bytes = fs::read(&path).map_err(|source| Error::Io {
+            path: path.clone(),
+            source,
+        })?;
+        if self::digest(&bytes) != digest {
+            return Err(Error::Checksum(format!(
+                "{} does not match its digest",
+                path.display()
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// Stores `bytes` as a blob and describes it.
+    pub fn add_blob(&self, media_type: &str, bytes: &[u8]) -> Result<Descriptor> {
+        let descriptor = Descriptor::of(media_type, bytes);
+        write_file(&self.blob_path(&descriptor.digest), bytes)?;
+        Ok(descriptor)
+    }
+
+    /// Stores an artifact and lists it in the index next to the image.
+    pub fn add_artifact(&mut self, artifact: &Artifact) -> Result<()> {
+        for blob in artifact.blobs.iter().chain([&artifact.manifest]) {
+            write_file(&self.blob_path(&digest(blob)), blob)?;
+        }
+        self.index.manifests.push(artifact.descriptor.clone());
+        Ok(())
+    }
+
+    /// Writes `index.json`.
+    pub fn save(&self) -> Result<()> {
+        write_file(
+            &self.dir.join("index.json"),
+            &to_canonical_json(&self.index),
+        )
+    }
+
+    /// Packs the layout into a tarball like the one
+    /// `docker buildx build --output type=oci` writes. Entries are sorted
+    /// and carry no timestamps or owners, so equal layouts give equal
+    /// tarballs.
+    pub fn write_tar(&self, path: &Path) -> Result<()> {
+        let mut files = vec![PathBuf::from("oci-layout"), PathBuf::from("index.json")];
+        let blobs = self.dir.join("blobs");
+        let read_dir = |dir: &Path| {
+            fs::read_dir(dir)
+                .and_then(|entries| entries.map(|e| e.map(|e| e.file_name())).collect())
+                .map_err(|source| Error::Io {
+                    path: dir.to_owned(),
+                    source,
+                })
+        };
+        let mut algorithms: Vec<_> = read_dir(&blobs)?;
+        algorithms.sort();
+        for algorithm in algorithms {
+            let mut names: Vec<_> = read_dir(&blobs.join(&algorithm))?;
+            names.sort();
+            files.extend(
+                names
+                    .into_iter()
+                    .map(|name| Path::new("blobs").join(&algorithm).join(name)),
+            );
+        }
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for file in files {
+            let source = self.dir.join(&file);
+            let bytes = fs::read(&source).map_err(|source_err| Error::Io {
+                path: source.clone(),
+                source: source_err,
+            })?;
+            let mut header = tar::Header::new_ustar();
+            header.set_size(bytes.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(0);
+            header.set_entry_type(tar::EntryType::Regular);
+            builder
+                .append_data(&mut header, &file, bytes.as_slice())
+                .map_err(|err| Error::Archive(format!("{}: {err}", path.display())))?;
+        }
+        let tarball = builder
+            .into_inner()
+            .map_err(|err| Error::Archive(format!("{}: {err}", path.display())))?;
+        write_file(path, &tarball)
+    }
+
+    pub fn json<T: for<'de> Deserialize<'de>>(&self, digest: &str) -> Result<T> {
+        read_json(&self.blob_path(digest), &self.blob(digest)?)
+    }
+
+    /// The single image manifest in the layout, descending into a nested
+    /// index if the exporter wrote one. Artifacts next to it are ignored.
+    pub fn image(&self) -> Result<(Descriptor, ImageManifest)> {
+        let mut manifests: Vec<_> = self
+            .index
+            .manifests
+            .iter()
+            .filter(|d| d.artifact_type.is_none())
+            .cloned()
+            .collect();
+        while let [descriptor] = manifests.as_slice() {
+            if !is_index(&descriptor.media_type) {
+                let manifest = self.json(&descriptor.digest)?;
+                return Ok((descriptor.clone(), manifest));
+            }
+            manifests = self.json::<Index>(&descriptor.digest)?.manifests;
+        }
+        Err(Error::Oci(format!(
+            "{} should hold exactly one image, found {} manifests",
+            self.dir.display(),
+            manifests.len()
+        )))
+    }
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let write = || {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, bytes)
+    };
+    write().map_err(|source| Error::Write {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path, bytes: &[u8]) -> Result<T> {
+    serde_json::from_slice(bytes).map_err(|err| Error::Json {
+        path: path.to_owned(),
+        message: err.to_string(),
+    })
+}
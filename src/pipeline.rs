@@ -0,0 +1,734 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, iter,
+    path::{Path, PathBuf},
+    slice,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    changelog, config,
+    dockerfile::{self, TrunkSource},
+    lock::ImageLock,
+    manifest::{self, Manifest},
+    matrix::{Entry, Matrix},
+    oci::{self, Artifact, Descriptor, Index, Layout, INDEX_MEDIA_TYPE},
+    platform::Platform,
+    process::{Invocation, Runner},
+    provenance::{self, BaseImage, Download, Source},
+    registry::Registry,
+    sbom::{self, Inventory},
+    sign::{self, SigningKey},
+    size, smoke, structure, tags,
+    target::Target,
+    trunk::{self, TrunkLock},
+    Error, Result,
+};
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Docker build context.
+    pub context: PathBuf,
+    /// Directory the generated per-entry Dockerfiles are written to.
+    pub out_dir: PathBuf,
+    pub push: bool,
+    /// Where crate sources come from, for the dependency trees in SBOMs.
+    pub crates_dl: String,
+}
+
+/// Everything a build run produces: per matrix entry and variant, one image
+/// per platform, combined into an image index under the image's tags.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildPlan {
+    pub images: Vec<ImagePlan>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImagePlan {
+    pub trunk: String,
+    pub rust: String,
+    /// The manifest's variant the image is built from, if not the default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Repositories the image is pushed to.
+    pub targets: Vec<Target>,
+    /// Tags the image index is published under in every target.
+    pub tags: Vec<String>,
+    pub platforms: Vec<PlatformBuild>,
+    /// Digest of the image index, known once it has been pushed.
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlatformBuild {
+    pub platform: Platform,
+    /// Tag of the per-platform image the index points to.
+    pub tag: String,
+    pub dockerfile: PathBuf,
+    pub trunk: TrunkSource,
+    /// Where buildx writes the build result, including the image digest.
+    pub metadata_file: PathBuf,
+    /// OCI image layout the image is exported to when pushing.
+    pub layout: PathBuf,
+    /// SBOMs of the exported image, once written.
+    pub sboms: BTreeMap<sbom::Format, PathBuf>,
+    /// Provenance statement of the exported image, once written.
+    pub provenance: Option<PathBuf>,
+    /// Known once the image has been built.
+    pub digest: Option<String>,
+}
+
+impl PlatformBuild {
+    /// The SBOMs and provenance written for the image, as artifacts about
+    /// `subject`.
+    fn artifacts(&self, subject: &Descriptor) -> Result<Vec<Artifact>> {
+        let read = |path: &PathBuf| {
+            fs::read(path).map_err(|source| Error::Io {
+                path: path.clone(),
+                source,
+            })
+        };
+        let mut artifacts = Vec::new();
+        for (&format, path) in &self.sboms {
+            artifacts.push(sbom::artifact(format, &read(path)?, subject));
+        }
+        if let Some(path) = &self.provenance {
+            artifacts.push(provenance::artifact(&read(path)?, subject));
+        }
+        Ok(artifacts)
+    }
+}
+
+/// Metadata buildx writes with `--metadata-file`.
+#[derive(Deserialize)]
+struct BuildMetadata {
+    #[serde(rename = "containerimage.digest")]
+    digest: String,
+}
+
+/// The output directory of the generated files of an entry, or of one of
+/// its variants, on a platform.
+pub fn build_dir(
+    options: &Options,
+    entry: &Entry,
+    variant: Option<&str>,
+    platform: Platform,
+) -> PathBuf {
+    let name = format!("{}-rust{}", entry.trunk, entry.rust);
+    let name = match variant {
+        Some(variant) => format!("{name}-{variant}"),
+        None => name,
+    };
+    options.out_dir.join(name).join(platform.arch())
+}
+
+/// The build context holding the prebuilt trunk binary of a build.
+pub fn trunk_context(build: &PlatformBuild) -> PathBuf {
+    build
+        .dockerfile
+        .parent()
+        .expect("dockerfile lives in the build directory")
+        .join("trunk")
+}
+
+/// Lays out the builds of the given entries, each in the default image and
+/// in every variant of the manifest.
+pub fn plan(
+    matrix: &Matrix,
+    manifest: &Manifest,
+    lock: &TrunkLock,
+    entries: &[Entry],
+    options: &Options,
+) -> Result<BuildPlan> {
+    let flavor = manifest.base.suffix.as_deref();
+    let images = entries
+        .iter()
+        .flat_map(|entry| manifest.images().map(move |image| (entry, image)))
+        .map(|(entry, (variant, image))| {
+            let tags = tags::for_variant(matrix, flavor, variant, entry)?;
+            let platforms = matrix
+                .platforms
+                .iter()
+                .map(|&platform| {
+                    let dir = build_dir(options, entry, variant, platform);
+                    let trunk = TrunkSource::for_entry(image, lock, entry, platform);
+                    if variant.is_some() && trunk == TrunkSource::Cargo {
+                        let what = format!(
+                            "trunk {} without a prebuilt release for {platform} in trunk.lock",
+                            entry.trunk
+                        );
+                        image
+                            .check_cargo_install(&what)
+                            .map_err(|err| manifest::in_variant(variant, err))?;
+                    }
+                    Ok(PlatformBuild {
+                        platform,
+                        tag: format!("{}-{}", tags[0], platform.arch()),
+                        dockerfile: dir.join("Dockerfile"),
+                        trunk,
+                        metadata_file: dir.join("metadata.json"),
+                        layout: dir.join("oci"),
+                        sboms: BTreeMap::new(),
+                        provenance: None,
+                        digest: None,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            Ok(ImagePlan {
+                trunk: entry.trunk.clone(),
+                rust: entry.rust.clone(),
+                variant: variant.map(str::to_string),
+                targets: matrix.targets_for(entry).to_vec(),
+                tags,
+                platforms,
+                digest: None,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(BuildPlan { images })
+}
+
+impl ImagePlan {
+    pub fn entry(&self) -> Entry {
+        Entry {
+            trunk: self.trunk.clone(),
+            rust: self.rust.clone(),
+        }
+    }
+
+    /// What the image is built from: `manifest`, or its variant.
+    pub fn manifest<'a>(&self, manifest: &'a Manifest) -> &'a Manifest {
+        match &self.variant {
+            Some(variant) => &manifest.variants[variant],
+            None => manifest,
+        }
+    }
+
+    /// The references written to the registries when pushing.
+    pub fn pushes(&self, options: &Options) -> Vec<String> {
+        if !options.push {
+            return Vec::new();
+        }
+        self.targets
+            .iter()
+            .flat_map(|target| {
+                self.platforms
+                    .iter()
+                    .map(|build| &build.tag)
+                    .chain(&self.tags)
+                    .map(|tag| target.reference(tag))
+            })
+            .collect()
+    }
+
+    /// The docker invocations that build this image.
+    ///
+    /// Each platform is built on its own. When pushing, the images are
+    /// exported to OCI layouts, which [`BuildPlan::push`] uploads;
+    /// otherwise they are loaded into docker, the first platform's image
+    /// under every tag.
+    pub fn invocations(&self, options: &Options) -> Vec<Invocation> {
+        let mut invocations = Vec::new();
+        for (i, build) in self.platforms.iter().enumerate() {
+            let mut invocation = Invocation::new("docker")
+                .args(["buildx", "build", "--platform"])
+                .arg(build.platform.to_string())
+                .arg("-f")
+                .arg(build.dockerfile.display().to_string());
+            if build.trunk == TrunkSource::Prebuilt {
+                invocation = invocation
+                    .arg("--build-context")
+                    .arg(format!("trunk={}", trunk_context(build).display()));
+            }
+            invocation = invocation
+                .arg("--metadata-file")
+                .arg(build.metadata_file.display().to_string());
+            if options.push {
+                invocation = invocation
+                    .arg("--provenance=false")
+                    .arg("--output")
+                    .arg(format!(
+                        "type=oci,dest={},tar=false",
+                        build.layout.display()
+                    ));
+            } else {
+                for target in &self.targets {
+                    invocation = invocation.arg("-t").arg(target.reference(&build.tag));
+                    if i == 0 {
+                        for tag in &self.tags {
+                            invocation = invocation.arg("-t").arg(target.reference(tag));
+                        }
+                    }
+                }
+                invocation = invocation.arg("--load");
+            }
+            invocations.push(invocation.arg(options.context.display().to_string()));
+        }
+        invocations
+    }
+}
+
+/// What a build run would do, as shown by `trunk-docker plan`.
+#[derive(Debug, Serialize)]
+pub struct Preview<'a> {
+    pub push: bool,
+    pub images: Vec<ImagePreview<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImagePreview<'a> {
+    #[serde(flatten)]
+    pub image: &'a ImagePlan,
+    /// Prebuilt trunk releases that would be downloaded.
+    pub downloads: Vec<String>,
+    /// References that would be written to the registry.
+    pub pushes: Vec<String>,
+    pub commands: Vec<String>,
+}
+
+impl Preview<'_> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("preview serializes")
+    }
+}
+
+impl fmt::Display for Preview<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, preview) in self.images.iter().enumerate() {
+            let image = preview.image;
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match &image.variant {
+                Some(variant) => writeln!(
+                    f,
+                    "trunk {} on Rust {}, variant {variant}",
+                    image.trunk, image.rust
+                )?,
+                None => writeln!(f, "trunk {} on Rust {}", image.trunk, image.rust)?,
+            }
+            writeln!(f, "  targets:")?;
+            for target in &image.targets {
+                writeln!(f, "    {target}")?;
+            }
+            writeln!(f, "  tags:")?;
+            for tag in &image.tags {
+                writeln!(f, "    {tag}")?;
+            }
+            writeln!(f, "  platforms:")?;
+            for build in &image.platforms {
+                writeln!(
+                    f,
+                    "    {} -> {} (trunk: {})",
+                    build.platform,
+                    build.tag,
+                    match build.trunk {
+                        TrunkSource::Prebuilt => "prebuilt",
+                        TrunkSource::Cargo => "cargo install",
+                    }
+                )?;
+            }
+            for url in &preview.downloads {
+                writeln!(f, "  downloads: {url}")?;
+            }
+            if preview.pushes.is_empty() {
+                writeln!(f, "  pushes: nothing (run with --push to publish)")?;
+            } else {
+                writeln!(f, "  pushes:")?;
+                for reference in &preview.pushes {
+                    writeln!(f, "    {reference}")?;
+                }
+            }
+            writeln!(f, "  commands:")?;
+            for command in &preview.commands {
+                writeln!(f, "    {command}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BuildPlan {
+    /// Writes the Dockerfile of every build, and downloads and verifies the
+    /// trunk binaries that `trunk.lock` pins. Fails if the Debian archive
+    /// lacks an apt version the manifest pins.
+    pub fn prepare(
+        &self,
+        manifest: &Manifest,
+        lock: &TrunkLock,
+        image_lock: &ImageLock,
+    ) -> Result<()> {
+        let mut checked = BTreeSet::new();
+        for image in &self.images {
+            let entry = image.entry();
+            let manifest = image.manifest(manifest);
+            let archives = manifest.apt.archives();
+            for build in &image.platforms {
+                if checked.insert((&image.variant, &image.rust, build.platform)) {
+                    image_lock.check_apt_pins(manifest, &entry, build.platform, &archives)?;
+                }
+                let unlocked = image_lock.unlocked(manifest, &entry, build.platform);
+                if !unlocked.is_empty() {
+                    println!(
+                        "image.lock does not pin {} for {} on {}; \
+                         building from what upstream has now",
+                        unlocked.join(", "),
+                        build.tag,
+                        build.platform
+                    );
+                }
+                match (build.trunk, lock.get(&entry.trunk, build.platform)) {
+                    (TrunkSource::Prebuilt, Some(release)) => {
+                        println!("fetching trunk {} from {}", entry.trunk, release.url);
+                        trunk::unpack(&release.fetch()?, &trunk_context(build))?;
+                    }
+                    (TrunkSource::Cargo, Some(_)) => println!(
+                        "{} builds trunk {} from source, as `trunk.from-source` asks",
+                        build.tag, entry.trunk
+                    ),
+                    _ => println!(
+                        "trunk {} has no locked prebuilt binary for {}; \
+                         it will be built with cargo install",
+                        entry.trunk, build.platform
+                    ),
+                }
+                dockerfile::write(
+                    &build.dockerfile,
+                    manifest,
+                    image_lock,
+                    &entry,
+                    build.platform,
+                    build.trunk,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The docker invocations that build the images, in order.
+    pub fn invocations(&self, options: &Options) -> Vec<Invocation> {
+        self.images
+            .iter()
+            .flat_map(|image| image.invocations(options))
+            .collect()
+    }
+
+    /// Describes the builds without running anything.
+    pub fn preview<'a>(&'a self, lock: &TrunkLock, options: &Options) -> Preview<'a> {
+        let images = self
+            .images
+            .iter()
+            .map(|image| ImagePreview {
+                image,
+                downloads: image
+                    .platforms
+                    .iter()
+                    .filter(|build| build.trunk == TrunkSource::Prebuilt)
+                    .filter_map(|build| lock.get(&image.trunk, build.platform))
+                    .map(|release| release.url.clone())
+                    .collect(),
+                pushes: image.pushes(options),
+                commands: image
+                    .invocations(options)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            })
+            .collect();
+        Preview {
+            push: options.push,
+            images,
+        }
+    }
+
+    /// Fills in the image digests from the metadata buildx wrote.
+    pub fn record_digests(&mut self) -> Result<()> {
+        for build in self.images.iter_mut().flat_map(|i| &mut i.platforms) {
+            let text = fs::read_to_string(&build.metadata_file).map_err(|source| Error::Io {
+                path: build.metadata_file.clone(),
+                source,
+            })?;
+            let metadata: BuildMetadata =
+                serde_json::from_str(&text).map_err(|err| Error::Json {
+                    path: build.metadata_file.clone(),
+                    message: err.to_string(),
+                })?;
+            build.digest = Some(metadata.digest);
+        }
+        Ok(())
+    }
+
+    /// Writes CycloneDX and SPDX SBOMs of every exported image next to
+    /// its layout.
+    pub fn write_sboms(&mut self, manifest: &Manifest, options: &Options) -> Result<()> {
+        for image in &mut self.images {
+            let entry = image.entry();
+            let tools = sbom::tools(image.manifest(manifest), &entry);
+            for build in &mut image.platforms {
+                let layout = Layout::open(&build.layout)?;
+                let name = image.targets[0].reference(&build.tag);
+                let inventory = Inventory::of_image(&name, &layout, &tools, &options.crates_dl)?;
+                build.sboms = inventory.write(&build.layout.with_file_name("sbom"))?;
+                println!(
+                    "{name}: {} components in the SBOM",
+                    inventory.components.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Measures every exported image against the one published under the
+    /// first of its tags that exists in its first target, which is the
+    /// image pushing it replaces.
+    pub fn measure(&self) -> Result<Vec<size::Comparison>> {
+        let mut comparisons = Vec::new();
+        for image in &self.images {
+            let target = &image.targets[0];
+            for build in &image.platforms {
+                let tags: Vec<String> =
+                    iter::once(&build.tag).chain(&image.tags).cloned().collect();
+                comparisons.push(size::Comparison::against_published(
+                    &build.layout,
+                    target.reference(&build.tag),
+                    target,
+                    &tags,
+                    build.platform,
+                )?);
+            }
+        }
+        Ok(comparisons)
+    }
+
+    /// Compares the SBOM of every image's first platform with the one
+    /// attached to the image pushing it replaces, found like
+    /// [`BuildPlan::measure`] finds it. Call before [`BuildPlan::push`]
+    /// moves the tags.
+    pub fn changelog(&self) -> Result<Vec<changelog::Entry>> {
+        let mut entries = Vec::new();
+        for image in &self.images {
+            let target = &image.targets[0];
+            let build = &image.platforms[0];
+            let Some(sbom) = build.sboms.get(&sbom::Format::CycloneDx) else {
+                return Err(Error::Changelog(format!(
+                    "no SBOM written for {}",
+                    target.reference(&build.tag)
+                )));
+            };
+            let references = image
+                .targets
+                .iter()
+                .flat_map(|target| image.tags.iter().map(|tag| target.reference(tag)))
+                .collect();
+            let tags: Vec<String> = iter::once(&build.tag).chain(&image.tags).cloned().collect();
+            entries.push(changelog::Entry::against_published(
+                image.tags[0].clone(),
+                references,
+                sbom,
+                target,
+                &tags,
+                build.platform,
+            )?);
+        }
+        Ok(entries)
+    }
+
+    /// Runs the structure tests against every platform's image, as loaded
+    /// into docker by a local build.
+    pub fn structure_test(
+        &self,
+        runner: &mut dyn Runner,
+        spec: &structure::Spec,
+    ) -> Vec<structure::Report> {
+        let mut reports = Vec::new();
+        for image in &self.images {
+            for build in &image.platforms {
+                let reference = image.targets[0].reference(&build.tag);
+                reports.push(structure::run(
+                    runner,
+                    spec,
+                    &reference,
+                    &image.entry(),
+                    build.platform,
+                ));
+            }
+        }
+        reports
+    }
+
+    /// Builds the smoke test fixtures in every image, as loaded into docker
+    /// by a local build for its first platform, each in its own directory
+    /// under `work_dir`.
+    pub fn smoke_test(
+        &self,
+        runner: &mut dyn Runner,
+        fixtures: &[smoke::Fixture],
+        work_dir: &Path,
+    ) -> Result<Vec<smoke::Report>> {
+        let mut reports = Vec::new();
+        for image in &self.images {
+            let reference = image.targets[0].reference(&image.tags[0]);
+            reports.push(smoke::run(
+                runner,
+                &reference,
+                fixtures,
+                &work_dir.join(&image.tags[0]),
+            )?);
+        }
+        Ok(reports)
+    }
+
+    /// Writes the provenance statement of every exported image next to its
+    /// layout, after checking the image was built on its locked base image,
+    /// or on what its base tag points to now if it is not locked.
+    pub fn write_provenance(
+        &mut self,
+        manifest: &Manifest,
+        lock: &TrunkLock,
+        image_lock: &ImageLock,
+        options: &Options,
+    ) -> Result<()> {
+        let source = Source::of_checkout(&options.context)?;
+        let host = provenance::host();
+        for image in &mut self.images {
+            let base = &image.manifest(manifest).base;
+            let base_reference = image_lock.base_reference(base, &image.rust);
+            for build in &mut image.platforms {
+                let layout = Layout::open(&build.layout)?;
+                let (descriptor, exported) = layout.image()?;
+                let base = BaseImage::resolve(&base_reference, build.platform)?;
+                base.check_is_base_of(&layout.json(&exported.config.digest)?)?;
+                let dockerfile = fs::read(&build.dockerfile).map_err(|source| Error::Io {
+                    path: build.dockerfile.clone(),
+                    source,
+                })?;
+                let downloads = lock
+                    .get(&image.trunk, build.platform)
+                    .filter(|_| build.trunk == TrunkSource::Prebuilt)
+                    .map(|release| Download {
+                        uri: release.url.clone(),
+                        sha256: release.sha256.clone(),
+                    })
+                    .into_iter()
+                    .collect();
+                let statement = provenance::Build {
+                    repository: image.targets[0].to_string(),
+                    digest: descriptor.digest,
+                    builder: "buildx",
+                    trunk: image.trunk.clone(),
+                    rust: image.rust.clone(),
+                    platform: build.platform,
+                    dockerfile: Some(dockerfile),
+                    source: source.clone(),
+                    base,
+                    downloads,
+                    host: host.clone(),
+                }
+                .render();
+                let path = build.layout.with_file_name("provenance.intoto.json");
+                config::write(&path, &statement)?;
+                build.provenance = Some(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads the exported images to every target over the distribution
+    /// API by digest: each platform's image with its SBOMs and provenance
+    /// attached, and an image index of all platforms. No tag moves until
+    /// [`BuildPlan::tag`].
+    pub fn push(&mut self) -> Result<()> {
+        for image in &mut self.images {
+            let layouts = image
+                .platforms
+                .iter()
+                .map(|build| Layout::open(&build.layout))
+                .collect::<Result<Vec<_>>>()?;
+            for target in &image.targets {
+                let mut registry = Registry::for_target(target);
+                let name = target.name();
+                let mut manifests = Vec::new();
+                for (build, layout) in image.platforms.iter_mut().zip(&layouts) {
+                    let digest = layout.image()?.0.digest;
+                    println!("pushing {target}@{digest} ({})", build.tag);
+                    let mut descriptor = registry.push_layout(&name, &digest, layout)?;
+                    build.digest = Some(digest);
+                    for artifact in build.artifacts(&descriptor)? {
+                        registry.attach(&name, &descriptor, &artifact)?;
+                    }
+                    descriptor.platform = Some(build.platform.into());
+                    descriptor.annotations.clear();
+                    manifests.push(descriptor);
+                }
+                let index = oci::to_canonical_json(&Index::new(manifests));
+                let digest = oci::digest(&index);
+                println!("pushing {target}@{digest}");
+                registry.put_manifest(&name, &digest, INDEX_MEDIA_TYPE, &index)?;
+                image.digest = Some(digest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Points the tags at the pushed images in every target: each
+    /// platform's tag at its image, and the image's tags at the index.
+    /// Run after [`BuildPlan::sign`], so no tag ever names an image that
+    /// isn't signed yet.
+    pub fn tag(&self) -> Result<()> {
+        for image in &self.images {
+            let platforms = image
+                .platforms
+                .iter()
+                .filter_map(|build| Some((build.digest.as_ref()?, slice::from_ref(&build.tag))));
+            let tagged = platforms.chain(image.digest.iter().map(|d| (d, image.tags.as_slice())));
+            for target in &image.targets {
+                let mut registry = Registry::for_target(target);
+                let name = target.name();
+                for (digest, tags) in tagged.clone() {
+                    for tag in tags {
+                        println!("tagging {}", target.reference(tag));
+                        registry.tag(&name, digest, tag)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Signs every pushed image index and per-platform image in every
+    /// target, the way `cosign sign --key` does.
+    pub fn sign(&self, key: &SigningKey) -> Result<()> {
+        for image in &self.images {
+            let digests = image
+                .digest
+                .iter()
+                .chain(image.platforms.iter().filter_map(|b| b.digest.as_ref()));
+            for target in &image.targets {
+                let mut registry = Registry::for_target(target);
+                for digest in digests.clone() {
+                    println!("signing {target}@{digest}");
+                    sign::sign(&mut registry, target, digest, key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("plan serializes")
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        config::write(path, &(self.to_json() + "\n"))
+    }
+}
+
+/// Runs the invocations in order, stopping at the first failure.
+pub fn run(runner: &mut dyn Runner, invocations: &[Invocation]) -> Result<()> {
+    for invocation in invocations {
+        println!("+ {invocation}");
+        runner.run(invocation)?;
+    }
+    Ok(())
+}
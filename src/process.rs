@@ -0,0 +1,177 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use crate::{Error, Result};
+
+/// A single external command, recorded so it can be shown before it is run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Variables set on top of the inherited environment.
+    pub env: Vec<(String, String)>,
+}
+
+impl Invocation {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            env: Vec::new(),
+        }
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{key}={} ", quote(value))?;
+        }
+        f.write_str(&quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes `word` for a POSIX shell if it contains anything but safe characters.
+pub(crate) fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+impl Invocation {
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .envs(self.env.iter().map(|(k, v)| (k, v)));
+        command
+    }
+
+    fn spawn_error(&self, source: io::Error) -> Error {
+        Error::Spawn {
+            command: self.to_string(),
+            source,
+        }
+    }
+}
+
+/// Runs `invocation` and returns its standard output.
+pub fn output(invocation: &Invocation) -> Result<String> {
+    let output = invocation
+        .command()
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|source| invocation.spawn_error(source))?;
+    if !output.status.success() {
+        return Err(Error::CommandFailed {
+            command: invocation.to_string(),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs `invocation` with `input` on its standard input, and returns how
+/// it exited and its standard output, whether it succeeded or not.
+pub fn communicate(invocation: &Invocation, input: &[u8]) -> Result<(ExitStatus, String)> {
+    let mut child = invocation
+        .command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|source| invocation.spawn_error(source))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input)
+            .map_err(|source| invocation.spawn_error(source))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|source| invocation.spawn_error(source))?;
+    Ok((
+        output.status,
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
+
+/// Replaces the current process with `invocation`; only returns on failure.
+#[cfg(unix)]
+pub fn exec(invocation: &Invocation) -> Error {
+    use std::os::unix::process::CommandExt;
+
+    invocation.spawn_error(invocation.command().exec())
+}
+
+#[cfg(not(unix))]
+pub fn exec(invocation: &Invocation) -> Error {
+    match SystemRunner.run(invocation) {
+        Ok(()) => std::process::exit(0),
+        Err(err) => err,
+    }
+}
+
+/// Executes invocations; swapped out in tests to record them instead.
+pub trait Runner {
+    fn run(&mut self, invocation: &Invocation) -> Result<()>;
+
+    /// Runs `invocation` and returns its standard output. Runners that
+    /// only record invocations return nothing.
+    fn output(&mut self, invocation: &Invocation) -> Result<String> {
+        self.run(invocation).map(|()| String::new())
+    }
+}
+
+/// Runs invocations as child processes, inheriting stdio.
+#[derive(Debug, Default)]
+pub struct SystemRunner;
+
+impl Runner for SystemRunner {
+    fn run(&mut self, invocation: &Invocation) -> Result<()> {
+        let status = invocation
+            .command()
+            .status()
+            .map_err(|source| invocation.spawn_error(source))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::CommandFailed {
+                command: invocation.to_string(),
+                status,
+            })
+        }
+    }
+
+    fn output(&mut self, invocation: &Invocation) -> Result<String> {
+        output(invocation)
+    }
+}
@@ -0,0 +1,637 @@
+//! A client for the OCI distribution API, used to push images to any
+//! registry (Docker Hub, GHCR, self-hosted) without going through docker.
+
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Deserialize;
+
+use crate::{
+    http,
+    oci::{self, Artifact, Descriptor, ImageManifest, Index, Layout, PlatformSpec},
+    platform::Platform,
+    process::{self, Invocation},
+    target::{Target, DOCKER_HUB},
+    Error, Result,
+};
+
+/// Manifest media types accepted when reading from a registry.
+const ACCEPT_MANIFESTS: &str = "application/vnd.oci.image.index.v1+json, \
+     application/vnd.oci.image.manifest.v1+json, \
+     application/vnd.docker.distribution.manifest.list.v2+json, \
+     application/vnd.docker.distribution.manifest.v2+json";
+
+/// The image a tag or digest points to for one platform.
+#[derive(Debug, Clone)]
+pub struct PlatformImage {
+    /// Digest of the image index the reference points to, if it is one.
+    pub index_digest: Option<String>,
+    /// Digest of the platform's image manifest.
+    pub digest: String,
+    pub manifest: ImageManifest,
+}
+
+/// `user` and `password` for a registry, if the docker config has any, or
+/// why looking them up failed.
+type Login = std::result::Result<Option<(String, String)>, String>;
+
+/// A connection to one registry.
+pub struct Registry {
+    base: String,
+    agent: ureq::Agent,
+    credentials: Login,
+    /// Bearer tokens by the scope they were issued for.
+    tokens: HashMap<String, String>,
+}
+
+struct Response {
+    status: u16,
+    headers: ureq::http::HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+}
+
+impl Registry {
+    /// Connects to the registry of `target`, with credentials from the
+    /// docker config (`$DOCKER_CONFIG/config.json`) or the credential
+    /// helper it names, if it has any.
+    pub fn for_target(target: &Target) -> Self {
+        Self {
+            base: target.api_base(),
+            agent: http::agent(),
+            credentials: docker_credentials(&target.registry),
+            tokens: HashMap::new(),
+        }
+    }
+
+    pub fn has_blob(&mut self, name: &str, digest: &str) -> Result<bool> {
+        let url = format!("{}/v2/{name}/blobs/{digest}", self.base);
+        let response = self.send("HEAD", &url, name, &[], None)?;
+        match response.status {
+            200..=299 => Ok(true),
+            404 => Ok(false),
+            _ => Err(status_error(&url, &response)),
+        }
+    }
+
+    /// Uploads a blob in a single request, unless the registry has it.
+    pub fn push_blob(&mut self, name: &str, digest: &str, bytes: &[u8]) -> Result<()> {
+        if self.has_blob(name, digest)? {
+            return Ok(());
+        }
+        let url = format!("{}/v2/{name}/blobs/uploads/", self.base);
+        let response = self.send("POST", &url, name, &[], Some(&[]))?;
+        if response.status != 202 {
+            return Err(status_error(&url, &response));
+        }
+        let location = response.header("location").ok_or_else(|| Error::Http {
+            url: url.clone(),
+            message: "upload response has no Location header".into(),
+        })?;
+        let location = if location.starts_with('/') {
+            format!("{}{location}", self.base)
+        } else {
+            location.to_string()
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!("{location}{separator}digest={digest}");
+        let headers = [("content-type", "application/octet-stream")];
+        let response = self.send("PUT", &url, name, &headers, Some(bytes))?;
+        if response.status != 201 {
+            return Err(status_error(&url, &response));
+        }
+        Ok(())
+    }
+
+    pub fn blob(&mut self, name: &str, digest: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/v2/{name}/blobs/{digest}", self.base);
+        let response = self.send("GET", &url, name, &[], None)?;
+        if response.status != 200 {
+            return Err(status_error(&url, &response));
+        }
+        if oci::digest(&response.body) != digest {
+            return Err(Error::Checksum(format!("{url} does not match its digest")));
+        }
+        Ok(response.body)
+    }
+
+    pub fn put_manifest(
+        &mut self,
+        name: &str,
+        reference: &str,
+        media_type: &str,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let url = format!("{}/v2/{name}/manifests/{reference}", self.base);
+        let headers = [("content-type", media_type)];
+        let response = self.send("PUT", &url, name, &headers, Some(bytes))?;
+        if response.status != 201 {
+            return Err(status_error(&url, &response));
+        }
+        Ok(())
+    }
+
+    /// Points `tag` at the manifest or index `digest` already pushed to
+    /// `name`.
+    pub fn tag(&mut self, name: &str, digest: &str, tag: &str) -> Result<()> {
+        let (media_type, bytes) = self
+            .manifest(name, digest)?
+            .ok_or_else(|| Error::Registry(format!("{name}@{digest} has not been pushed")))?;
+        self.put_manifest(name, tag, &media_type, &bytes)
+    }
+
+    /// Fetches a manifest or index by tag or digest, with its media type.
+    pub fn manifest(&mut self, name: &str, reference: &str) -> Result<Option<(String, Vec<u8>)>> {
+        let url = format!("{}/v2/{name}/manifests/{reference}", self.base);
+        let response = self.send("GET", &url, name, &[("accept", ACCEPT_MANIFESTS)], None)?;
+        match response.status {
+            200 => {
+                let media_type = response
+                    .header("content-type")
+                    .unwrap_or(oci::MANIFEST_MEDIA_TYPE)
+                    .to_string();
+                Ok(Some((media_type, response.body)))
+            }
+            404 => Ok(None),
+            _ => Err(status_error(&url, &response)),
+        }
+    }
+
+    /// Lists every tag of `name`, following the pages the registry splits
+    /// the list into.
+    pub fn tags(&mut self, name: &str) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct TagList {
+            #[serde(default)]
+            tags: Option<Vec<String>>,
+        }
+
+        let mut tags = Vec::new();
+        let mut url = format!("{}/v2/{name}/tags/list?n=1000", self.base);
+        loop {
+            let response = self.send("GET", &url, name, &[], None)?;
+            match response.status {
+                200 => {}
+                404 => return Ok(tags),
+                _ => return Err(status_error(&url, &response)),
+            }
+            let list: TagList = parse_json(name, "tags/list", &response.body)?;
+            tags.extend(list.tags.unwrap_or_default());
+            // `Link: </v2/<name>/tags/list?n=1000&last=x>; rel="next"`
+            let next = response
+                .header("link")
+                .filter(|link| link.contains("rel=\"next\""))
+                .and_then(|link| Some(link.split_once('<')?.1.split_once('>')?.0.to_string()));
+            match next {
+                Some(next) if next.starts_with('/') => url = format!("{}{next}", self.base),
+                Some(next) => url = next,
+                None => return Ok(tags),
+            }
+        }
+    }
+
+    /// Fetches the image `reference` points to for `platform`, picking it
+    /// from the image index if the reference is one.
+    pub fn platform_image(
+        &mut self,
+        name: &str,
+        reference: &str,
+        platform: Platform,
+    ) -> Result<Option<PlatformImage>> {
+        match self.find_platform_image(name, reference, platform)? {
+            Some(Some(image)) => Ok(Some(image)),
+            Some(None) => Err(Error::Registry(format!(
+                "{name}:{reference} has no {platform} image"
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`Registry::platform_image`], but an image index without
+    /// `platform` counts as missing too.
+    pub fn try_platform_image(
+        &mut self,
+        name: &str,
+        reference: &str,
+        platform: Platform,
+    ) -> Result<Option<PlatformImage>> {
+        Ok(self
+            .find_platform_image(name, reference, platform)?
+            .flatten())
+    }
+
+    /// `None` if `reference` doesn't exist, `Some(None)` if it is an image
+    /// index without `platform`.
+    fn find_platform_image(
+        &mut self,
+        name: &str,
+        reference: &str,
+        platform: Platform,
+    ) -> Result<Option<Option<PlatformImage>>> {
+        let Some((mut media_type, mut bytes)) = self.manifest(name, reference)? else {
+            return Ok(None);
+        };
+        let mut index_digest = None;
+        if oci::is_index(&media_type) {
+            let index: Index = parse_json(name, reference, &bytes)?;
+            let wanted = PlatformSpec::from(platform);
+            let Some(descriptor) = index.manifests.iter().find(|d| {
+                d.platform.as_ref().is_some_and(|p| {
+                    p.os == wanted.os
+                        && p.architecture == wanted.architecture
+                        && (p.variant.is_none() || p.variant == wanted.variant)
+                })
+            }) else {
+                return Ok(Some(None));
+            };
+            index_digest = Some(oci::digest(&bytes));
+            let Some(manifest) = self.manifest(name, &descriptor.digest)? else {
+                return Ok(None);
+            };
+            (media_type, bytes) = manifest;
+        }
+        if oci::to_oci_media_type(&media_type) != oci::MANIFEST_MEDIA_TYPE {
+            return Err(Error::Registry(format!(
+                "{name}:{reference} is a {media_type}, not an image manifest"
+            )));
+        }
+        Ok(Some(Some(PlatformImage {
+            index_digest,
+            digest: oci::digest(&bytes),
+            manifest: parse_json(name, reference, &bytes)?,
+        })))
+    }
+
+    /// Pushes the image in `layout` under `reference`, and returns the
+    /// descriptor of its manifest.
+    pub fn push_layout(
+        &mut self,
+        name: &str,
+        reference: &str,
+        layout: &Layout,
+    ) -> Result<Descriptor> {
+        let (descriptor, manifest) = layout.image()?;
+        for blob in std::iter::once(&manifest.config).chain(&manifest.layers) {
+            self.push_blob(name, &blob.digest, &layout.blob(&blob.digest)?)?;
+        }
+        let bytes = layout.blob(&descriptor.digest)?;
+        self.put_manifest(name, reference, &descriptor.media_type, &bytes)?;
+        Ok(descriptor)
+    }
+
+    /// Pushes `artifact` and makes it discoverable from its subject. On
+    /// registries without the OCI 1.1 referrers API, it is listed in the
+    /// `sha256-<hex>` index the spec falls back to.
+    pub fn attach(&mut self, name: &str, subject: &Descriptor, artifact: &Artifact) -> Result<()> {
+        for blob in &artifact.blobs {
+            self.push_blob(name, &oci::digest(blob), blob)?;
+        }
+        let digest = &artifact.descriptor.digest;
+        self.put_manifest(name, digest, oci::MANIFEST_MEDIA_TYPE, &artifact.manifest)?;
+
+        let url = format!("{}/v2/{name}/referrers/{}", self.base, subject.digest);
+        let response = self.send("GET", &url, name, &[], None)?;
+        match response.status {
+            200 => return Ok(()),
+            404 => {}
+            _ => return Err(status_error(&url, &response)),
+        }
+        let tag = subject.digest.replacen(':', "-", 1);
+        let mut referrers = match self.manifest(name, &tag)? {
+            Some((_, bytes)) => serde_json::from_slice(&bytes)
+                .map_err(|err| Error::Registry(format!("referrers index {name}:{tag}: {err}")))?,
+            None => Index::new(Vec::new()),
+        };
+        if referrers.manifests.iter().all(|d| &d.digest != digest) {
+            referrers.manifests.push(artifact.descriptor.clone());
+            let bytes = oci::to_canonical_json(&referrers);
+            self.put_manifest(name, &tag, oci::INDEX_MEDIA_TYPE, &bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Lists the artifacts attached to the manifest `digest`, from the
+    /// referrers API or the `sha256-<hex>` index it falls back to.
+    pub fn referrers(&mut self, name: &str, digest: &str) -> Result<Vec<Descriptor>> {
+        let url = format!("{}/v2/{name}/referrers/{digest}", self.base);
+        let response = self.send("GET", &url, name, &[], None)?;
+        let (reference, bytes) = match response.status {
+            200 => (digest.to_string(), response.body),
+            404 => {
+                let tag = digest.replacen(':', "-", 1);
+                match self.manifest(name, &tag)? {
+                    Some((_, bytes)) => (tag, bytes),
+                    None => return Ok(Vec::new()),
+                }
+            }
+            _ => return Err(status_error(&url, &response)),
+        };
+        let index: Index = parse_json(name, &reference, &bytes)?;
+        Ok(index.manifests)
+    }
+
+    /// Sends a request, authenticating and retrying once if challenged.
+    fn send(
+        &mut self,
+        method: &str,
+        url: &str,
+        name: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<Response> {
+        let scope = format!("repository:{name}:pull,push");
+        let response = self.send_once(method, url, headers, body, self.authorization(&scope))?;
+        if response.status != 401 {
+            return Ok(response);
+        }
+        let challenge = response
+            .header("www-authenticate")
+            .unwrap_or_default()
+            .to_string();
+        let authorization = self.authenticate(&challenge, &scope)?;
+        self.send_once(method, url, headers, body, Some(authorization))
+    }
+
+    fn send_once(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        authorization: Option<String>,
+    ) -> Result<Response> {
+        let error = |err: ureq::Error| Error::Http {
+            url: url.to_string(),
+            message: err.to_string(),
+        };
+        let mut all_headers: Vec<(&str, String)> =
+            headers.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        if let Some(authorization) = authorization {
+            all_headers.push(("authorization", authorization));
+        }
+        let response = match (method, body) {
+            ("GET" | "HEAD", _) => {
+                let mut request = if method == "GET" {
+                    self.agent.get(url)
+                } else {
+                    self.agent.head(url)
+                };
+                for (key, value) in &all_headers {
+                    request = request.header(*key, value);
+                }
+                request.call().map_err(error)?
+            }
+            (_, body) => {
+                let mut request = if method == "POST" {
+                    self.agent.post(url)
+                } else {
+                    self.agent.put(url)
+                };
+                for (key, value) in &all_headers {
+                    request = request.header(*key, value);
+                }
+                request.send(body.unwrap_or_default()).map_err(error)?
+            }
+        };
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = if method == "HEAD" {
+            Vec::new()
+        } else {
+            response
+                .into_body()
+                .with_config()
+                .limit(http::BODY_LIMIT)
+                .read_to_vec()
+                .map_err(error)?
+        };
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn authorization(&self, scope: &str) -> Option<String> {
+        self.tokens
+            .get(scope)
+            .map(|token| format!("Bearer {token}"))
+    }
+
+    /// Answers a `WWW-Authenticate` challenge: basic auth directly, bearer
+    /// auth by fetching a token from the challenge's realm.
+    fn authenticate(&mut self, challenge: &str, scope: &str) -> Result<String> {
+        let credentials = self.credentials.clone().map_err(Error::Registry)?;
+        let basic = credentials.map(|(user, password)| {
+            format!("Basic {}", BASE64.encode(format!("{user}:{password}")))
+        });
+        let (scheme, params) = challenge.split_once(' ').unwrap_or((challenge, ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            return basic.ok_or_else(|| {
+                Error::Registry(format!(
+                    "{} requires credentials; log in with `docker login`",
+                    self.base
+                ))
+            });
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return Err(Error::Registry(format!(
+                "{} sent an unsupported challenge {challenge:?}",
+                self.base
+            )));
+        }
+        let params = parse_challenge(params);
+        let realm = params.get("realm").ok_or_else(|| {
+            Error::Registry(format!(
+                "{} sent a bearer challenge without realm",
+                self.base
+            ))
+        })?;
+        let mut url = format!(
+            "{realm}?scope={}",
+            params.get("scope").map_or(scope, String::as_str)
+        );
+        if let Some(service) = params.get("service") {
+            url.push_str(&format!("&service={service}"));
+        }
+        let mut request = self.agent.get(&url);
+        if let Some(basic) = &basic {
+            request = request.header("authorization", basic);
+        }
+        let mut response = request.call().map_err(|err| Error::Http {
+            url: url.clone(),
+            message: err.to_string(),
+        })?;
+        if response.status() != 200 {
+            return Err(Error::Registry(format!(
+                "{realm} refused a token for {scope} ({})",
+                response.status()
+            )));
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let body = response
+            .body_mut()
+            .read_to_vec()
+            .map_err(|err| Error::Http {
+                url: url.clone(),
+                message: err.to_string(),
+            })?;
+        let token: TokenResponse = serde_json::from_slice(&body).map_err(|err| Error::Http {
+            url: url.clone(),
+            message: err.to_string(),
+        })?;
+        let token = token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| Error::Registry(format!("{realm} answered without a token")))?;
+        self.tokens.insert(scope.to_string(), token.clone());
+        Ok(format!("Bearer {token}"))
+    }
+}
+
+fn status_error(url: &str, response: &Response) -> Error {
+    let body = String::from_utf8_lossy(&response.body);
+    Error::Http {
+        url: url.to_string(),
+        message: format!(
+            "registry responded with {}: {}",
+            response.status,
+            body.trim()
+        ),
+    }
+}
+
+/// Parses `key="value",key2="value2"` challenge parameters.
+fn parse_challenge(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, after)) => (value, after),
+                None => (quoted, ""),
+            },
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        parsed.insert(key, value.to_string());
+        rest = after.trim_start_matches(',').trim();
+    }
+    parsed
+}
+
+/// Looks up `user:password` for `registry` like docker does: from the
+/// credential helper the docker config names for it in `credHelpers`, or
+/// else from its `credsStore`, or else from `auths`, where `docker login`
+/// stores them without a helper.
+fn docker_credentials(registry: &str) -> Login {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Config {
+        #[serde(default)]
+        auths: HashMap<String, Auth>,
+        creds_store: Option<String>,
+        #[serde(default)]
+        cred_helpers: HashMap<String, String>,
+    }
+    #[derive(Deserialize)]
+    struct Auth {
+        auth: Option<String>,
+    }
+
+    let Some(dir) = env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))
+    else {
+        return Ok(None);
+    };
+    let path = dir.join("config.json");
+    let Ok(bytes) = fs::read(&path) else {
+        return Ok(None);
+    };
+    let config: Config = serde_json::from_slice(&bytes)
+        .map_err(|err| format!("invalid docker config {}: {err}", path.display()))?;
+    let key = if registry == DOCKER_HUB {
+        "https://index.docker.io/v1/"
+    } else {
+        registry
+    };
+    let helper = config
+        .cred_helpers
+        .get(registry)
+        .or(config.cred_helpers.get(key))
+        .or(config.creds_store.as_ref());
+    if let Some(helper) = helper.filter(|helper| !helper.is_empty()) {
+        return credential_helper(helper, key);
+    }
+    let Some(encoded) = config.auths.get(key).and_then(|auth| auth.auth.as_ref()) else {
+        return Ok(None);
+    };
+    let decoded = BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    match decoded
+        .as_deref()
+        .and_then(|decoded| decoded.split_once(':'))
+    {
+        Some((user, password)) => Ok(Some((user.to_string(), password.to_string()))),
+        None => Err(format!("invalid auth for {key} in {}", path.display())),
+    }
+}
+
+/// Asks `docker-credential-<helper>` for the credentials of `server`,
+/// as docker does: `get` with the server on standard input, answered with
+/// its `Username` and `Secret` as JSON.
+fn credential_helper(helper: &str, server: &str) -> Login {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Credentials {
+        username: String,
+        secret: String,
+    }
+
+    let invocation = Invocation::new(format!("docker-credential-{helper}")).arg("get");
+    let (status, output) =
+        process::communicate(&invocation, server.as_bytes()).map_err(|err| err.to_string())?;
+    if !status.success() {
+        // What helpers answer for servers they hold nothing for.
+        if output.contains("credentials not found") {
+            return Ok(None);
+        }
+        return Err(format!(
+            "`{invocation}` failed ({status}): {}",
+            output.trim()
+        ));
+    }
+    let credentials: Credentials = serde_json::from_str(&output)
+        .map_err(|err| format!("`{invocation}` answered with invalid credentials: {err}"))?;
+    if credentials.username == "<token>" {
+        return Err(format!(
+            "`{invocation}` answered with an identity token for {server}, which isn't supported"
+        ));
+    }
+    Ok(Some((credentials.username, credentials.secret)))
+}
+
+fn parse_json<T: for<'de> Deserialize<'de>>(
+    name: &str,
+    reference: &str,
+    bytes: &[u8],
+) -> Result<T> {
+    serde_json::from_slice(bytes)
+        .map_err(|err| Error::Registry(format!("invalid manifest {name}:{reference}: {err}")))
+}
@@ -0,0 +1,342 @@
+//! Reproducibility checks: an image built twice from the same Dockerfile,
+//! under the same `SOURCE_DATE_EPOCH` and without the build cache, must
+//! have the same layers.
+
+use std::{collections::BTreeMap, fmt, fs, io::Read, path::Path};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    diff::{self, Change},
+    dockerfile::TrunkSource,
+    oci::{self, Layout},
+    pipeline::{trunk_context, PlatformBuild},
+    platform::Platform,
+    process::{self, Invocation, Runner},
+    Error, Result,
+};
+
+/// The commit time of the checkout at `dir`, the usual `SOURCE_DATE_EPOCH`.
+pub fn commit_epoch(dir: &Path) -> Result<u64> {
+    let output = process::output(
+        &Invocation::new("git")
+            .arg("-C")
+            .arg(dir.display().to_string())
+            .args(["log", "-1", "--format=%ct"]),
+    )?;
+    output.trim().parse().map_err(|_| {
+        Error::Repro(format!(
+            "cannot read the commit time of {}: {output:?}",
+            dir.display()
+        ))
+    })
+}
+
+/// The docker invocation that builds `build` from scratch into the OCI
+/// layout `layout`, with the timestamps in the image set from `epoch`.
+pub fn invocation(build: &PlatformBuild, context: &Path, epoch: u64, layout: &Path) -> Invocation {
+    let mut invocation = Invocation::new("docker")
+        .args(["buildx", "build", "--platform"])
+        .arg(build.platform.to_string())
+        .arg("-f")
+        .arg(build.dockerfile.display().to_string());
+    if build.trunk == TrunkSource::Prebuilt {
+        invocation = invocation
+            .arg("--build-context")
+            .arg(format!("trunk={}", trunk_context(build).display()));
+    }
+    invocation
+        .args(["--no-cache", "--provenance=false", "--build-arg"])
+        .arg(format!("SOURCE_DATE_EPOCH={epoch}"))
+        .arg("--output")
+        .arg(format!(
+            "type=oci,dest={},tar=false,rewrite-timestamp=true",
+            layout.display()
+        ))
+        .arg(context.display().to_string())
+}
+
+/// Builds `build` twice into `dir/first` and `dir/second` and compares the
+/// two images.
+pub fn verify(
+    runner: &mut dyn Runner,
+    build: &PlatformBuild,
+    context: &Path,
+    epoch: u64,
+    dir: &Path,
+) -> Result<Report> {
+    let layouts = [dir.join("first"), dir.join("second")];
+    for layout in &layouts {
+        if layout.exists() {
+            fs::remove_dir_all(layout).map_err(|source| Error::Write {
+                path: layout.clone(),
+                source,
+            })?;
+        }
+        runner.run(&invocation(build, context, epoch, layout))?;
+    }
+    compare(
+        &build.tag,
+        build.platform,
+        epoch,
+        &Layout::open(&layouts[0])?,
+        &Layout::open(&layouts[1])?,
+    )
+}
+
+/// What a layer entry is, as far as the image is concerned. Regular files
+/// are told apart by their contents' digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    kind: &'static str,
+    mode: u32,
+    owner: (u64, u64),
+    mtime: u64,
+    link: Option<String>,
+    contents: Option<String>,
+}
+
+/// The entries of a gzip-compressed layer in the order of the tarball,
+/// with modification times after `epoch` clamped to it, like buildkit's
+/// `rewrite-timestamp` does.
+fn entries(blob: &[u8], epoch: u64) -> Result<Vec<(String, Entry)>> {
+    let error = |err: std::io::Error| Error::Archive(format!("layer: {err}"));
+    let mut entries = Vec::new();
+    let mut archive = tar::Archive::new(GzDecoder::new(blob));
+    for entry in archive.entries().map_err(error)? {
+        let mut entry = entry.map_err(error)?;
+        let path = entry.path().map_err(error)?;
+        let path = format!("/{}", path.to_string_lossy().trim_start_matches("./"));
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => "file",
+            tar::EntryType::Directory => "directory",
+            tar::EntryType::Symlink => "symlink",
+            tar::EntryType::Link => "hard link",
+            _ => "special file",
+        };
+        let link = entry
+            .link_name()
+            .map_err(error)?
+            .map(|link| link.to_string_lossy().into_owned());
+        let mut described = Entry {
+            kind,
+            mode: header.mode().map_err(error)?,
+            owner: (header.uid().map_err(error)?, header.gid().map_err(error)?),
+            mtime: header.mtime().map_err(error)?.min(epoch),
+            link,
+            contents: None,
+        };
+        if kind == "file" {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).map_err(error)?;
+            described.contents = Some(oci::digest(&contents));
+        }
+        entries.push((path, described));
+    }
+    Ok(entries)
+}
+
+/// How two builds of a file differ, e.g. `mode 644 vs 755`.
+fn differences(first: &Entry, second: &Entry) -> Vec<String> {
+    let mut how = Vec::new();
+    if first.kind != second.kind {
+        how.push(format!("{} vs {}", first.kind, second.kind));
+    }
+    if first.contents != second.contents {
+        how.push(format!(
+            "contents {} vs {}",
+            first.contents.as_deref().unwrap_or("-"),
+            second.contents.as_deref().unwrap_or("-")
+        ));
+    }
+    if first.link != second.link {
+        how.push(format!(
+            "link to {} vs {}",
+            first.link.as_deref().unwrap_or("-"),
+            second.link.as_deref().unwrap_or("-")
+        ));
+    }
+    if first.mode != second.mode {
+        how.push(format!("mode {:o} vs {:o}", first.mode, second.mode));
+    }
+    if first.owner != second.owner {
+        how.push(format!(
+            "owner {}:{} vs {}:{}",
+            first.owner.0, first.owner.1, second.owner.0, second.owner.1
+        ));
+    }
+    if first.mtime != second.mtime {
+        how.push(format!("modified at {} vs {}", first.mtime, second.mtime));
+    }
+    how
+}
+
+/// How the same layer of two builds compares.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Identical,
+    /// Different digests, but equal once the timestamps after
+    /// `SOURCE_DATE_EPOCH` are clamped. Still not reproducible: something
+    /// escaped buildx's timestamp rewriting.
+    IdenticalOnceNormalized,
+    /// The files that differ, and how.
+    Differs(BTreeMap<String, Vec<String>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct LayerReport {
+    /// The layer's digest in each build, `None` if the build has fewer
+    /// layers.
+    pub first: Option<String>,
+    pub second: Option<String>,
+    pub outcome: Outcome,
+}
+
+/// The comparison of two builds of one image.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub image: String,
+    pub platform: Platform,
+    pub epoch: u64,
+    pub layers: Vec<LayerReport>,
+}
+
+/// Compares the images in two layouts layer by layer.
+pub fn compare(
+    image: &str,
+    platform: Platform,
+    epoch: u64,
+    first: &Layout,
+    second: &Layout,
+) -> Result<Report> {
+    let (_, first_manifest) = first.image()?;
+    let (_, second_manifest) = second.image()?;
+    let count = first_manifest
+        .layers
+        .len()
+        .max(second_manifest.layers.len());
+    let mut layers = Vec::new();
+    for i in 0..count {
+        let first_digest = first_manifest.layers.get(i).map(|d| d.digest.clone());
+        let second_digest = second_manifest.layers.get(i).map(|d| d.digest.clone());
+        let read = |layout: &Layout, digest: &Option<String>| match digest {
+            Some(digest) => entries(&layout.blob(digest)?, epoch),
+            None => Ok(Vec::new()),
+        };
+        let outcome = if first_digest == second_digest {
+            Outcome::Identical
+        } else {
+            let first_entries = read(first, &first_digest)?;
+            let second_entries = read(second, &second_digest)?;
+            if first_entries == second_entries {
+                Outcome::IdenticalOnceNormalized
+            } else {
+                let old: BTreeMap<_, _> = first_entries.iter().cloned().collect();
+                let new: BTreeMap<_, _> = second_entries.iter().cloned().collect();
+                let mut files = BTreeMap::new();
+                for (path, change) in diff::changes(&old, &new) {
+                    let how = match change {
+                        Change::Added { .. } => vec!["only in the second build".to_string()],
+                        Change::Removed { .. } => vec!["only in the first build".to_string()],
+                        Change::Changed { old, new } => differences(&old, &new),
+                    };
+                    files.insert(path, how);
+                }
+                if files.is_empty() {
+                    files.insert(
+                        "/".to_string(),
+                        vec!["same files, in a different order".to_string()],
+                    );
+                }
+                Outcome::Differs(files)
+            }
+        };
+        layers.push(LayerReport {
+            first: first_digest,
+            second: second_digest,
+            outcome,
+        });
+    }
+    Ok(Report {
+        image: image.to_string(),
+        platform,
+        epoch,
+        layers,
+    })
+}
+
+impl Report {
+    /// The numbers, from 1, of the layers whose digests differ.
+    fn differing(&self) -> Vec<usize> {
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.outcome != Outcome::Identical)
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Fails if the digest of any layer differs between the builds, even
+    /// if only in timestamps.
+    pub fn check(&self) -> Result<()> {
+        let differing = self.differing();
+        if differing.is_empty() {
+            return Ok(());
+        }
+        let layers: Vec<String> = differing.iter().map(ToString::to_string).collect();
+        let layers = match layers.as_slice() {
+            [layer] => format!("layer {layer} differs"),
+            layers => format!("layers {} differ", layers.join(", ")),
+        };
+        Err(Error::Repro(format!(
+            "{} on {} is not reproducible: {layers} between builds",
+            self.image, self.platform
+        )))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} on {}, built twice with SOURCE_DATE_EPOCH={}:",
+            self.image, self.platform, self.epoch
+        )?;
+        let digest = |digest: &Option<String>| digest.clone().unwrap_or_else(|| "none".into());
+        for (i, layer) in self.layers.iter().enumerate() {
+            let number = i + 1;
+            match &layer.outcome {
+                Outcome::Identical => {
+                    writeln!(f, "  layer {number}: identical {}", digest(&layer.first))?
+                }
+                Outcome::IdenticalOnceNormalized => writeln!(
+                    f,
+                    "  layer {number}: DIFFERS, {} vs {}\n    \
+                     only in timestamps after the epoch, which buildx did not rewrite",
+                    digest(&layer.first),
+                    digest(&layer.second)
+                )?,
+                Outcome::Differs(files) => {
+                    writeln!(
+                        f,
+                        "  layer {number}: DIFFERS, {} vs {}",
+                        digest(&layer.first),
+                        digest(&layer.second)
+                    )?;
+                    for (path, how) in files {
+                        writeln!(f, "    {path}: {}", how.join(", "))?;
+                    }
+                }
+            }
+        }
+        match self.differing().len() {
+            0 => writeln!(f, "reproducible"),
+            n => writeln!(
+                f,
+                "not reproducible: {n} of {} layers differ",
+                self.layers.len()
+            ),
+        }
+    }
+}
@@ -0,0 +1,560 @@
+//! Software bills of materials: what is installed in an image, as
+//! CycloneDX and SPDX documents.
+//!
+//! The inventory is read from the image itself where the image records it
+//! (Debian packages in the dpkg status file, Alpine packages in the apk
+//! database, the Rust toolchain in the config), and from the published `Cargo.lock` of every cargo-installed
+//! tool for the crates compiled into it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    apk, apt, config, crates, layer,
+    manifest::Manifest,
+    matrix::Entry,
+    oci::{self, Artifact, Descriptor, Layout},
+    Result,
+};
+
+/// A supported SBOM format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    CycloneDx,
+    Spdx,
+}
+
+impl Format {
+    pub const ALL: [Format; 2] = [Format::CycloneDx, Format::Spdx];
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::CycloneDx => "application/vnd.cyclonedx+json",
+            Format::Spdx => "application/spdx+json",
+        }
+    }
+
+    /// The file extension, e.g. `cdx.json`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::CycloneDx => "cdx.json",
+            Format::Spdx => "spdx.json",
+        }
+    }
+}
+
+/// A tool the Dockerfile installs on top of the base image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tool {
+    /// A cargo-installed crate, along with the crates compiled into it.
+    Crate { name: String, version: String },
+    /// Anything else installed from a release.
+    Release {
+        name: String,
+        version: String,
+        purl: String,
+    },
+}
+
+/// The tools the image of `entry` gets.
+pub fn tools(manifest: &Manifest, entry: &Entry) -> Vec<Tool> {
+    let mut tools = vec![Tool::Crate {
+        name: "trunk".into(),
+        version: entry.trunk.clone(),
+    }];
+    if let Some(binaryen) = &manifest.binaryen {
+        let version = format!("version_{}", binaryen.version);
+        tools.push(Tool::Release {
+            name: "binaryen".into(),
+            purl: format!("pkg:github/WebAssembly/binaryen@{version}"),
+            version,
+        });
+    }
+    tools.extend(
+        manifest
+            .wasm_bindgen
+            .versions
+            .iter()
+            .map(|version| Tool::Crate {
+                name: "wasm-bindgen-cli".into(),
+                version: version.clone(),
+            }),
+    );
+    tools.extend(manifest.cargo.iter().map(|tool| Tool::Crate {
+        name: tool.name.clone(),
+        version: tool.version.clone(),
+    }));
+    tools
+}
+
+/// Everything found in one image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inventory {
+    /// The reference the image is published under.
+    pub image: String,
+    /// Digest of the image manifest.
+    pub digest: String,
+    /// When the image was created, from its config.
+    pub created: String,
+    /// Sorted by package URL.
+    pub components: Vec<Component>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub kind: Kind,
+    pub name: String,
+    pub version: String,
+    /// Package URL, which also identifies the component in the documents.
+    pub purl: String,
+    pub sha256: Option<String>,
+    /// Package URLs of the components this one depends on.
+    pub depends_on: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A Debian package.
+    Debian,
+    /// An Alpine package.
+    Alpine,
+    /// A crate compiled into a cargo-installed tool.
+    Crate,
+    /// A cargo-installed tool.
+    Tool,
+    /// Anything else installed from a release, e.g. binaryen.
+    Release,
+}
+
+impl Inventory {
+    /// Takes stock of the image in `layout`, with `tools` installed on top
+    /// of its base. Crate lockfiles are downloaded from `crates_dl`.
+    pub fn of_image(image: &str, layout: &Layout, tools: &[Tool], crates_dl: &str) -> Result<Self> {
+        let (descriptor, image_manifest) = layout.image()?;
+        let config: Value = layout.json(&image_manifest.config.digest)?;
+        let blobs = image_manifest
+            .layers
+            .iter()
+            .map(|layer| layout.blob(&layer.digest))
+            .collect::<Result<Vec<_>>>()?;
+        let layers: Vec<&[u8]> = blobs.iter().map(Vec::as_slice).collect();
+
+        let mut components = BTreeMap::new();
+        let mut add = |component: Component| {
+            components
+                .entry(component.purl.clone())
+                .and_modify(|existing: &mut Component| {
+                    existing.depends_on.extend(component.depends_on.clone())
+                })
+                .or_insert(component);
+        };
+
+        let os_release = match layer::read_file(&layers, "/etc/os-release")? {
+            Some(text) => Some(text),
+            None => layer::read_file(&layers, "/usr/lib/os-release")?,
+        };
+        let os_release =
+            parse_os_release(&String::from_utf8_lossy(&os_release.unwrap_or_default()));
+        for package in os_packages(&layers, &os_release)? {
+            add(package);
+        }
+
+        if let Some(version) = oci::config_env(&config, "RUST_VERSION") {
+            add(Component {
+                kind: Kind::Release,
+                purl: format!("pkg:generic/rust@{version}"),
+                name: "rust".into(),
+                version,
+                sha256: None,
+                depends_on: BTreeSet::new(),
+            });
+        }
+        for tool in tools {
+            let Tool::Release {
+                name,
+                version,
+                purl,
+            } = tool
+            else {
+                continue;
+            };
+            add(Component {
+                kind: Kind::Release,
+                name: name.clone(),
+                version: version.clone(),
+                purl: purl.clone(),
+                sha256: None,
+                depends_on: BTreeSet::new(),
+            });
+        }
+        for tool in tools {
+            let Tool::Crate { name, version } = tool else {
+                continue;
+            };
+            let lockfile = crates::lockfile(crates_dl, name, version)?.unwrap_or_default();
+            let closure = lockfile.closure(name, version);
+            let mut tool = Component {
+                kind: Kind::Tool,
+                purl: crates::purl(name, version),
+                name: name.clone(),
+                version: version.clone(),
+                sha256: None,
+                depends_on: BTreeSet::new(),
+            };
+            for package in closure {
+                let depends_on = package
+                    .dependencies
+                    .iter()
+                    .filter_map(|d| lockfile.resolve(d))
+                    .map(|d| d.purl())
+                    .collect();
+                if package.purl() == tool.purl {
+                    tool.depends_on = depends_on;
+                    continue;
+                }
+                add(Component {
+                    kind: Kind::Crate,
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    purl: package.purl(),
+                    sha256: package.checksum.clone(),
+                    depends_on,
+                });
+            }
+            add(tool);
+        }
+
+        Ok(Self {
+            image: image.to_string(),
+            digest: descriptor.digest,
+            created: config["created"]
+                .as_str()
+                .unwrap_or("1970-01-01T00:00:00Z")
+                .to_string(),
+            components: components.into_values().collect(),
+        })
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        let document = match format {
+            Format::CycloneDx => self.cyclonedx(),
+            Format::Spdx => self.spdx(),
+        };
+        serde_json::to_string_pretty(&document).expect("SBOM serializes") + "\n"
+    }
+
+    /// Writes the documents in every format to `<stem>.<extension>`.
+    pub fn write(&self, stem: &Path) -> Result<BTreeMap<Format, PathBuf>> {
+        let mut written = BTreeMap::new();
+        for format in Format::ALL {
+            let mut path = stem.as_os_str().to_owned();
+            path.push(".");
+            path.push(format.extension());
+            let path = PathBuf::from(path);
+            config::write(&path, &self.render(format))?;
+            written.insert(format, path);
+        }
+        Ok(written)
+    }
+
+    fn cyclonedx(&self) -> Value {
+        let components: Vec<Value> = self
+            .components
+            .iter()
+            .map(|component| {
+                let mut value = json!({
+                    "type": match component.kind {
+                        Kind::Tool => "application",
+                        _ => "library",
+                    },
+                    "bom-ref": component.purl,
+                    "name": component.name,
+                    "version": component.version,
+                    "purl": component.purl,
+                });
+                if let Some(sha256) = &component.sha256 {
+                    value["hashes"] = json!([{ "alg": "SHA-256", "content": sha256 }]);
+                }
+                value
+            })
+            .collect();
+        let mut dependencies = vec![json!({
+            "ref": self.digest,
+            "dependsOn": self.components.iter().map(|c| &c.purl).collect::<Vec<_>>(),
+        })];
+        dependencies.extend(
+            self.components.iter().map(
+                |component| json!({ "ref": component.purl, "dependsOn": component.depends_on }),
+            ),
+        );
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", uuid_from_digest(&self.digest)),
+            "version": 1,
+            "metadata": {
+                "timestamp": self.created,
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": "container",
+                    "bom-ref": self.digest,
+                    "name": self.image,
+                    "version": self.digest,
+                },
+            },
+            "components": components,
+            "dependencies": dependencies,
+        })
+    }
+
+    fn spdx(&self) -> Value {
+        let image_id = "SPDXRef-Image";
+        let mut packages = vec![json!({
+            "name": self.image,
+            "SPDXID": image_id,
+            "versionInfo": self.digest,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "primaryPackagePurpose": "CONTAINER",
+        })];
+        let mut relationships = vec![json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": image_id,
+        })];
+        for component in &self.components {
+            let mut package = json!({
+                "name": component.name,
+                "SPDXID": spdx_id(&component.purl),
+                "versionInfo": component.version,
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": component.purl,
+                }],
+            });
+            if let Some(sha256) = &component.sha256 {
+                package["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": sha256 }]);
+            }
+            packages.push(package);
+            relationships.push(json!({
+                "spdxElementId": image_id,
+                "relationshipType": "CONTAINS",
+                "relatedSpdxElement": spdx_id(&component.purl),
+            }));
+            relationships.extend(component.depends_on.iter().map(|dependency| {
+                json!({
+                    "spdxElementId": spdx_id(&component.purl),
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": spdx_id(dependency),
+                })
+            }));
+        }
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.image,
+            "documentNamespace": format!(
+                "https://github.com/Follpvosten/trunk-docker/spdx/{}",
+                self.digest
+            ),
+            "creationInfo": {
+                "created": self.created,
+                "creators": [format!(
+                    "Tool: {}-{}",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                )],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+}
+
+/// The packages the image's package manager installed: from the dpkg
+/// status file on Debian, and from the apk database on Alpine.
+pub(crate) fn os_packages(
+    layers: &[&[u8]],
+    os_release: &BTreeMap<String, String>,
+) -> Result<Vec<Component>> {
+    if let Some(status) = layer::read_file(layers, "/var/lib/dpkg/status")? {
+        return Ok(debian_packages(
+            &String::from_utf8_lossy(&status),
+            os_release,
+        ));
+    }
+    if let Some(installed) = layer::read_file(layers, "/lib/apk/db/installed")? {
+        return Ok(alpine_packages(&String::from_utf8_lossy(&installed)));
+    }
+    Ok(Vec::new())
+}
+
+/// The installed packages of a dpkg status file.
+pub(crate) fn debian_packages(
+    status: &str,
+    os_release: &BTreeMap<String, String>,
+) -> Vec<Component> {
+    let stanzas: Vec<BTreeMap<&str, String>> = apt::stanzas(status)
+        .into_iter()
+        .filter(|fields| {
+            fields
+                .get("Status")
+                .is_some_and(|status| status.ends_with(" installed"))
+        })
+        .collect();
+    let distro = os_release.get("ID").map_or("debian", String::as_str);
+    let codename = os_release.get("VERSION_CODENAME");
+    let purl = |fields: &BTreeMap<&str, String>| {
+        let mut purl = format!(
+            "pkg:deb/{distro}/{}@{}?arch={}",
+            fields["Package"],
+            fields.get("Version").map_or("", String::as_str),
+            fields.get("Architecture").map_or("all", String::as_str),
+        );
+        if let Some(codename) = codename {
+            purl.push_str(&format!("&distro={codename}"));
+        }
+        purl
+    };
+    let by_name: BTreeMap<&str, String> = stanzas
+        .iter()
+        .filter_map(|fields| Some((fields.get("Package")?.as_str(), purl(fields))))
+        .collect();
+    stanzas
+        .iter()
+        .filter(|fields| fields.contains_key("Package"))
+        .map(|fields| {
+            let depends_on = ["Pre-Depends", "Depends"]
+                .iter()
+                .filter_map(|field| fields.get(field))
+                .flat_map(|depends| depends.split(','))
+                .filter_map(|dependency| {
+                    // The first installed alternative, without version
+                    // constraints or architecture qualifiers.
+                    dependency.split('|').find_map(|alternative| {
+                        let name = alternative.split_whitespace().next()?;
+                        by_name.get(name.split(':').next()?).cloned()
+                    })
+                })
+                .collect();
+            Component {
+                kind: Kind::Debian,
+                name: fields["Package"].clone(),
+                version: fields.get("Version").cloned().unwrap_or_default(),
+                purl: purl(fields),
+                sha256: None,
+                depends_on,
+            }
+        })
+        .collect()
+}
+
+/// The installed packages of an apk database, whose records are lines like
+/// `P:curl` (package), `V:8.5.0-r0` (version), `A:x86_64` (architecture),
+/// `D:so:libc.musl-x86_64.so.1 libcurl=8.5.0-r0` (dependencies) and
+/// `p:cmd:curl=8.5.0-r0` (what the package provides).
+pub(crate) fn alpine_packages(installed: &str) -> Vec<Component> {
+    let records = apk::records(installed);
+    let purl = |fields: &BTreeMap<char, &str>| {
+        format!(
+            "pkg:apk/alpine/{}@{}?arch={}",
+            fields[&'P'],
+            fields.get(&'V').unwrap_or(&""),
+            fields.get(&'A').unwrap_or(&"noarch"),
+        )
+    };
+    // Dependencies name a package or something one provides, e.g. a
+    // shared library as `so:libz.so.1`.
+    let mut providers: BTreeMap<&str, String> = BTreeMap::new();
+    for fields in &records {
+        let provided = fields.get(&'p').map_or("", |p| *p).split_whitespace();
+        for name in provided.chain([fields[&'P']]) {
+            providers.insert(apk_name(name), purl(fields));
+        }
+    }
+    records
+        .iter()
+        .map(|fields| {
+            let depends_on = fields
+                .get(&'D')
+                .map_or("", |d| *d)
+                .split_whitespace()
+                .filter(|dependency| !dependency.starts_with('!'))
+                .filter_map(|dependency| providers.get(apk_name(dependency)).cloned())
+                .filter(|dependency| *dependency != purl(fields))
+                .collect();
+            Component {
+                kind: Kind::Alpine,
+                name: fields[&'P'].to_string(),
+                version: fields.get(&'V').unwrap_or(&"").to_string(),
+                purl: purl(fields),
+                sha256: None,
+                depends_on,
+            }
+        })
+        .collect()
+}
+
+/// An apk dependency or provided name without its version constraint, e.g.
+/// `libcurl` for `libcurl=8.5.0-r0`.
+fn apk_name(name: &str) -> &str {
+    name.split(['=', '<', '>', '~']).next().unwrap_or(name)
+}
+
+pub(crate) fn parse_os_release(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.trim_matches('"').to_string()))
+        .collect()
+}
+
+/// SPDX identifiers only allow letters, digits, `.` and `-`.
+fn spdx_id(purl: &str) -> String {
+    let id: String = purl
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{id}")
+}
+
+/// A UUID derived from the image digest, so the same image always gets
+/// the same serial number.
+fn uuid_from_digest(digest: &str) -> String {
+    let hex = digest.trim_start_matches("sha256:");
+    let hex = format!("{hex:0<32}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Describes an SBOM attached to the image it was generated for.
+pub fn artifact(format: Format, document: &[u8], subject: &Descriptor) -> Artifact {
+    Artifact::new(format.media_type(), format.media_type(), document, subject)
+}
@@ -0,0 +1,281 @@
+//! cosign-compatible image signatures.
+//!
+//! Signatures are stored the way `cosign sign --key` stores them: an image
+//! manifest tagged `sha256-<hex>.sig` next to the signed manifest, with one
+//! layer per signature. Each layer is a "simple signing" payload naming the
+//! signed digest, annotated with the base64 ECDSA P-256 signature of the
+//! payload. `cosign verify --key` accepts signatures made here, and
+//! `verify` accepts signatures made by cosign with a key pair.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+pub use p256::ecdsa::{SigningKey, VerifyingKey};
+use p256::{
+    ecdsa::{
+        signature::{Signer, Verifier},
+        Signature,
+    },
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    SecretKey,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    config,
+    oci::{self, Descriptor, ImageManifest},
+    registry::Registry,
+    target::{Target, DOCKER_HUB},
+    Error, Result,
+};
+
+pub const SIMPLE_SIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+/// The layer annotation holding the signature.
+pub const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+const SIGNATURE_TYPE: &str = "cosign container image signature";
+
+/// Generates a key pair and writes it as PEM: the private key as PKCS#8,
+/// the public key in the format of `cosign.pub`.
+pub fn generate_key_pair(private: &Path, public: &Path) -> Result<()> {
+    let key = SigningKey::random(&mut rand_core::OsRng);
+    let private_pem = key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|err| Error::Signature(format!("cannot encode the private key: {err}")))?;
+    config::write(private, &private_pem)?;
+    config::write(public, &public_key_pem(key.verifying_key())?)
+}
+
+pub fn public_key_pem(key: &VerifyingKey) -> Result<String> {
+    key.to_public_key_pem(LineEnding::LF)
+        .map_err(|err| Error::Signature(format!("cannot encode the public key: {err}")))
+}
+
+/// Reads an unencrypted P-256 private key, as PKCS#8 (`PRIVATE KEY`) or
+/// SEC1 (`EC PRIVATE KEY`) PEM. cosign's own encrypted key files need
+/// converting first.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    let pem = read(path)?;
+    let key = if pem.contains("BEGIN EC PRIVATE KEY") {
+        SecretKey::from_sec1_pem(&pem).map(SigningKey::from)
+    } else {
+        SigningKey::from_pkcs8_pem(&pem).map_err(Into::into)
+    };
+    key.map_err(|err| {
+        Error::Signature(format!(
+            "{} is not an unencrypted P-256 private key: {err}",
+            path.display()
+        ))
+    })
+}
+
+/// Reads a P-256 public key in PEM, like `cosign.pub`.
+pub fn load_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    VerifyingKey::from_public_key_pem(&read(path)?).map_err(|err| {
+        Error::Signature(format!(
+            "{} is not a P-256 public key: {err}",
+            path.display()
+        ))
+    })
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// The claims a signature is made over.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Payload {
+    pub critical: Critical,
+    pub optional: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Critical {
+    pub identity: Identity,
+    pub image: Image,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    #[serde(rename = "docker-reference")]
+    pub docker_reference: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image {
+    #[serde(rename = "docker-manifest-digest")]
+    pub docker_manifest_digest: String,
+}
+
+impl Payload {
+    pub fn new(target: &Target, digest: &str) -> Self {
+        Payload {
+            critical: Critical {
+                identity: Identity {
+                    docker_reference: docker_reference(target),
+                },
+                image: Image {
+                    docker_manifest_digest: digest.to_string(),
+                },
+                kind: SIGNATURE_TYPE.to_string(),
+            },
+            optional: None,
+        }
+    }
+}
+
+/// The repository as cosign names it, e.g. `index.docker.io/torhovland/rust-trunk`.
+pub fn docker_reference(target: &Target) -> String {
+    match target.registry.as_str() {
+        DOCKER_HUB => format!("index.docker.io/{}", target.name()),
+        registry => format!("{registry}/{}", target.name()),
+    }
+}
+
+/// The tag cosign stores the signatures of `digest` under.
+pub fn signature_tag(digest: &str) -> String {
+    format!("{}.sig", digest.replacen(':', "-", 1))
+}
+
+/// Signs the manifest `digest` in `target` with `key`, adding the signature
+/// to the ones already stored for it.
+pub fn sign(
+    registry: &mut Registry,
+    target: &Target,
+    digest: &str,
+    key: &SigningKey,
+) -> Result<()> {
+    let name = target.name();
+    let payload = oci::to_canonical_json(&Payload::new(target, digest));
+    let signature: Signature = key.sign(&payload);
+    let mut layer = Descriptor::of(SIMPLE_SIGNING_MEDIA_TYPE, &payload);
+    layer.annotations.insert(
+        SIGNATURE_ANNOTATION.into(),
+        BASE64.encode(signature.to_der()),
+    );
+
+    let tag = signature_tag(digest);
+    let mut layers = match registry.manifest(&name, &tag)? {
+        Some((_, bytes)) => parse_manifest(&name, &tag, &bytes)?.layers,
+        None => Vec::new(),
+    };
+    if layers.contains(&layer) {
+        return Ok(());
+    }
+    registry.push_blob(&name, &layer.digest, &payload)?;
+    layers.push(layer);
+
+    // cosign gives the signature image a config listing its layers.
+    let config = oci::to_canonical_json(&json!({
+        "architecture": "",
+        "config": {},
+        "created": "0001-01-01T00:00:00Z",
+        "history": [{ "created": "0001-01-01T00:00:00Z" }],
+        "os": "",
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": layers.iter().map(|l| &l.digest).collect::<Vec<_>>(),
+        },
+    }));
+    let config_descriptor = Descriptor::of(oci::CONFIG_MEDIA_TYPE, &config);
+    registry.push_blob(&name, &config_descriptor.digest, &config)?;
+    let manifest = oci::to_canonical_json(&ImageManifest {
+        schema_version: 2,
+        media_type: Some(oci::MANIFEST_MEDIA_TYPE.into()),
+        artifact_type: None,
+        config: config_descriptor,
+        layers,
+        subject: None,
+        annotations: BTreeMap::new(),
+    });
+    registry.put_manifest(&name, &tag, oci::MANIFEST_MEDIA_TYPE, &manifest)
+}
+
+/// A signature that checked out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    /// The digest the reference resolved to.
+    pub digest: String,
+    pub payloads: Vec<Payload>,
+}
+
+/// The digest of the manifest `reference` (a tag or digest) points to in
+/// `target`.
+pub fn resolve(registry: &mut Registry, target: &Target, reference: &str) -> Result<String> {
+    let (_, manifest) = registry
+        .manifest(&target.name(), reference)?
+        .ok_or_else(|| {
+            Error::Signature(format!("{} does not exist", display(target, reference)))
+        })?;
+    Ok(oci::digest(&manifest))
+}
+
+/// Resolves `reference` (a tag or digest) in `target` and checks that it
+/// has at least one signature by `key` over its digest.
+pub fn verify(
+    registry: &mut Registry,
+    target: &Target,
+    reference: &str,
+    key: &VerifyingKey,
+) -> Result<Verified> {
+    let name = target.name();
+    let image = display(target, reference);
+    let digest = resolve(registry, target, reference)?;
+    let tag = signature_tag(&digest);
+    let (_, bytes) = registry
+        .manifest(&name, &tag)?
+        .ok_or_else(|| Error::Signature(format!("{image} ({digest}) is not signed")))?;
+    let mut payloads = Vec::new();
+    for layer in parse_manifest(&name, &tag, &bytes)?.layers {
+        if layer.media_type != SIMPLE_SIGNING_MEDIA_TYPE {
+            continue;
+        }
+        let Some(signature) = layer.annotations.get(SIGNATURE_ANNOTATION) else {
+            continue;
+        };
+        let Some(signature) = BASE64
+            .decode(signature)
+            .ok()
+            .and_then(|der| Signature::from_der(&der).ok())
+        else {
+            continue;
+        };
+        let payload = registry.blob(&name, &layer.digest)?;
+        if key.verify(&payload, &signature).is_err() {
+            continue;
+        }
+        let payload: Payload = serde_json::from_slice(&payload).map_err(|err| {
+            Error::Signature(format!("invalid signature payload {}: {err}", layer.digest))
+        })?;
+        if payload.critical.image.docker_manifest_digest == digest
+            && payload.critical.kind == SIGNATURE_TYPE
+        {
+            payloads.push(payload);
+        }
+    }
+    if payloads.is_empty() {
+        return Err(Error::Signature(format!(
+            "{image} ({digest}) has no valid signature by the given key"
+        )));
+    }
+    Ok(Verified { digest, payloads })
+}
+
+fn display(target: &Target, reference: &str) -> String {
+    if reference.contains(':') {
+        format!("{target}@{reference}")
+    } else {
+        target.reference(reference)
+    }
+}
+
+fn parse_manifest(name: &str, tag: &str, bytes: &[u8]) -> Result<ImageManifest> {
+    serde_json::from_slice(bytes)
+        .map_err(|err| Error::Signature(format!("invalid signature manifest {name}:{tag}: {err}")))
+}
@@ -0,0 +1,261 @@
+//! Image size reports: per-layer and total sizes, compressed as pushed and
+//! uncompressed as unpacked, compared with the image a push replaces.
+
+use std::{collections::BTreeMap, fmt, io, path::Path};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config,
+    oci::{Descriptor, Layout},
+    platform::Platform,
+    registry::Registry,
+    target::Target,
+    Error, Result,
+};
+
+/// How much an image may grow over the one it replaces, from `[size]` in
+/// `image.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Budget {
+    /// Growth of the compressed or uncompressed total, in percent, that
+    /// fails the release. Unset, sizes are reported only.
+    pub max_growth_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayerSize {
+    pub digest: String,
+    pub compressed: u64,
+    pub uncompressed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageSize {
+    /// Where the image was measured, e.g. `rust-trunk:0.14.0` or a layout
+    /// directory.
+    pub image: String,
+    pub layers: Vec<LayerSize>,
+}
+
+impl ImageSize {
+    pub fn compressed(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.compressed).sum()
+    }
+
+    pub fn uncompressed(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.uncompressed).sum()
+    }
+
+    /// Measures the image in the OCI layout at `dir`.
+    pub fn of_layout(dir: &Path) -> Result<Self> {
+        let layout = Layout::open(dir)?;
+        let (_, manifest) = layout.image()?;
+        let layers = manifest
+            .layers
+            .iter()
+            .map(|layer| measure(layer, &layout.blob(&layer.digest)?))
+            .collect::<Result<_>>()?;
+        Ok(ImageSize {
+            image: dir.display().to_string(),
+            layers,
+        })
+    }
+
+    /// Measures the image under `tag` in `target` for `platform`, or
+    /// returns `None` if there is none. Layers in `known` aren't
+    /// downloaded again.
+    pub fn of_published(
+        registry: &mut Registry,
+        target: &Target,
+        tag: &str,
+        platform: Platform,
+        known: &[LayerSize],
+    ) -> Result<Option<Self>> {
+        let name = target.name();
+        let Some(image) = registry.try_platform_image(&name, tag, platform)? else {
+            return Ok(None);
+        };
+        let known: BTreeMap<_, _> = known.iter().map(|l| (&l.digest, l)).collect();
+        let mut layers = Vec::new();
+        for layer in &image.manifest.layers {
+            layers.push(match known.get(&layer.digest) {
+                Some(&known) => known.clone(),
+                None => measure(layer, &registry.blob(&name, &layer.digest)?)?,
+            });
+        }
+        Ok(Some(ImageSize {
+            image: target.reference(tag),
+            layers,
+        }))
+    }
+}
+
+fn measure(layer: &Descriptor, blob: &[u8]) -> Result<LayerSize> {
+    let uncompressed = io::copy(&mut GzDecoder::new(blob), &mut io::sink())
+        .map_err(|err| Error::Archive(format!("layer {}: {err}", layer.digest)))?;
+    Ok(LayerSize {
+        digest: layer.digest.clone(),
+        compressed: blob.len() as u64,
+        uncompressed,
+    })
+}
+
+/// An image's size next to the one it replaces.
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub current: ImageSize,
+    /// `None` when nothing was published under the image's tags yet.
+    pub previous: Option<ImageSize>,
+}
+
+impl Comparison {
+    /// Measures the image in `layout` and the one published under the
+    /// first of `tags` that exists in `target`.
+    pub fn against_published(
+        layout: &Path,
+        image: String,
+        target: &Target,
+        tags: &[String],
+        platform: Platform,
+    ) -> Result<Self> {
+        let current = ImageSize {
+            image,
+            ..ImageSize::of_layout(layout)?
+        };
+        let mut registry = Registry::for_target(target);
+        let mut previous = None;
+        for tag in tags {
+            previous =
+                ImageSize::of_published(&mut registry, target, tag, platform, &current.layers)?;
+            if previous.is_some() {
+                break;
+            }
+        }
+        Ok(Comparison { current, previous })
+    }
+
+    /// Growth of the compressed and uncompressed totals, in percent.
+    pub fn growth(&self) -> Option<(f64, f64)> {
+        let previous = self.previous.as_ref()?;
+        Some((
+            percent(self.current.compressed(), previous.compressed()),
+            percent(self.current.uncompressed(), previous.uncompressed()),
+        ))
+    }
+
+    /// Fails if the image grew past the budget.
+    pub fn check(&self, budget: &Budget) -> Result<()> {
+        let (Some(max), Some(previous), Some((compressed, uncompressed))) =
+            (budget.max_growth_percent, &self.previous, self.growth())
+        else {
+            return Ok(());
+        };
+        for (kind, growth) in [("compressed", compressed), ("uncompressed", uncompressed)] {
+            if growth > max {
+                return Err(Error::Size(format!(
+                    "{} grew by {growth:.1}% {kind} over {}, more than the {max}% budget",
+                    self.current.image, previous.image
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn percent(current: u64, previous: u64) -> f64 {
+    if previous == 0 {
+        return 0.0;
+    }
+    (current as f64 - previous as f64) / previous as f64 * 100.0
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.previous {
+            Some(previous) => writeln!(
+                f,
+                "size of {}, against {}:",
+                self.current.image, previous.image
+            )?,
+            None => writeln!(f, "size of {}, not published before:", self.current.image)?,
+        }
+        let previous: Vec<&str> = self
+            .previous
+            .iter()
+            .flat_map(|image| &image.layers)
+            .map(|layer| layer.digest.as_str())
+            .collect();
+        for (i, layer) in self.current.layers.iter().enumerate() {
+            let status = if self.previous.is_none() {
+                ""
+            } else if previous.contains(&layer.digest.as_str()) {
+                "  unchanged"
+            } else {
+                "  new"
+            };
+            let label = format!(
+                "layer {:<2} {}",
+                i + 1,
+                &layer.digest[..layer.digest.len().min(19)]
+            );
+            row(f, &label, layer.compressed, layer.uncompressed)?;
+            writeln!(f, "{status}")?;
+        }
+        row(
+            f,
+            "total",
+            self.current.compressed(),
+            self.current.uncompressed(),
+        )?;
+        writeln!(f)?;
+        if let (Some(previous), Some((compressed, uncompressed))) = (&self.previous, self.growth())
+        {
+            row(
+                f,
+                "previous",
+                previous.compressed(),
+                previous.uncompressed(),
+            )?;
+            writeln!(
+                f,
+                "\n  {:<28}  {compressed:>+9.1}% compressed  {uncompressed:>+9.1}% uncompressed",
+                "growth"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes one row of the size table, without the line break.
+fn row(f: &mut fmt::Formatter<'_>, label: &str, compressed: u64, uncompressed: u64) -> fmt::Result {
+    write!(
+        f,
+        "  {label:<28}  {:>10} compressed  {:>10} uncompressed",
+        human(compressed),
+        human(uncompressed)
+    )
+}
+
+/// Formats a byte count with a binary unit, e.g. `12.3 MiB`.
+pub fn human(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Writes the comparisons as JSON.
+pub fn write_json(path: &Path, comparisons: &[Comparison]) -> Result<()> {
+    let json = serde_json::to_string_pretty(comparisons).expect("sizes serialize");
+    config::write(path, &(json + "\n"))
+}
@@ -0,0 +1,301 @@
+//! End-to-end smoke tests: builds the fixture apps under `fixtures/` with
+//! `trunk build --release` inside an image, and checks what trunk wrote to
+//! `dist/` and that it used the image's tools instead of downloading its
+//! own.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    process::{Invocation, Runner},
+    Error, Result,
+};
+
+/// Where the fixture apps live in this repository.
+pub const FIXTURES: &str = "fixtures";
+/// Where a fixture is mounted in the container.
+const WORKDIR: &str = "/app";
+/// Where the container lists the tools trunk downloaded to its cache.
+const DOWNLOADS: &str = ".trunk-downloads";
+
+/// A trunk app to build in the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixture {
+    pub name: String,
+    pub dir: PathBuf,
+    /// Whether the app links a stylesheet, which trunk compiles to CSS.
+    pub stylesheet: bool,
+}
+
+/// Lists the fixtures in `dir`: every directory with an `index.html`, in
+/// name order.
+pub fn fixtures(dir: &Path) -> Result<Vec<Fixture>> {
+    let read_error = |source| Error::Io {
+        path: dir.to_owned(),
+        source,
+    };
+    let mut fixtures = Vec::new();
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let index = path.join("index.html");
+        if !index.is_file() {
+            continue;
+        }
+        let html = fs::read_to_string(&index).map_err(|source| Error::Io {
+            path: index.clone(),
+            source,
+        })?;
+        fixtures.push(Fixture {
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            stylesheet: html.contains("rel=\"scss\"")
+                || html.contains("rel=\"sass\"")
+                || html.contains("rel=\"css\""),
+            dir: path,
+        });
+    }
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(fixtures)
+}
+
+/// Runs `trunk build --release` in `image` on a copy of a fixture at
+/// `dir`, then lists trunk's tool cache to [`DOWNLOADS`]. Build output in a
+/// bind mount belongs to root, so it is handed back to the owner of the
+/// directory before the container exits.
+pub fn invocation(image: &str, dir: &Path) -> Invocation {
+    Invocation::new("docker")
+        .args(["run", "--rm", "--volume"])
+        .arg(format!("{}:{WORKDIR}", dir.display()))
+        .args(["--workdir", WORKDIR, image, "sh", "-c"])
+        .arg(format!(
+            "trunk build --release; status=$?; \
+             ls \"${{XDG_CACHE_HOME:-$HOME/.cache}}/trunk\" > {DOWNLOADS} 2>/dev/null; \
+             chown -R \"$(stat -c %u:%g .)\" .; exit $status"
+        ))
+}
+
+/// The assets of a trunk build, as referenced by its `index.html`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dist {
+    pub wasm: Vec<String>,
+    pub js: Vec<String>,
+    pub css: Vec<String>,
+}
+
+/// Checks that `dist` holds an `index.html` referencing a hashed `.wasm`
+/// file and its JS glue, and that every asset it references exists.
+pub fn check_dist(dist: &Path) -> Result<Dist> {
+    let index = dist.join("index.html");
+    let html = fs::read_to_string(&index).map_err(|source| Error::Io {
+        path: index.clone(),
+        source,
+    })?;
+    let mut found = Dist::default();
+    for asset in assets(&html) {
+        let file = asset.trim_start_matches('/');
+        if !dist.join(file).is_file() {
+            return Err(Error::Smoke(format!(
+                "index.html references {asset}, which is not in {}",
+                dist.display()
+            )));
+        }
+        let list = if file.ends_with(".wasm") {
+            &mut found.wasm
+        } else if file.ends_with(".js") {
+            &mut found.js
+        } else {
+            &mut found.css
+        };
+        if !list.iter().any(|known| known == file) {
+            list.push(file.to_string());
+        }
+    }
+    for (kind, files) in [(".wasm file", &found.wasm), ("JS glue", &found.js)] {
+        if files.is_empty() {
+            return Err(Error::Smoke(format!("index.html references no {kind}")));
+        }
+    }
+    if let Some(unhashed) = found
+        .wasm
+        .iter()
+        .chain(&found.js)
+        .find(|file| !is_hashed(file))
+    {
+        return Err(Error::Smoke(format!(
+            "{unhashed} has no content hash in its name"
+        )));
+    }
+    Ok(found)
+}
+
+/// The quoted `.wasm`, `.js` and `.css` paths in `html`.
+fn assets(html: &str) -> impl Iterator<Item = &str> {
+    html.split(['"', '\''])
+        .skip(1)
+        .step_by(2)
+        .filter(|value| !value.contains(char::is_whitespace))
+        .filter(|value| {
+            [".wasm", ".js", ".css"]
+                .iter()
+                .any(|ext| value.ends_with(ext))
+        })
+}
+
+/// Whether `file` is named like trunk names its output,
+/// `<crate>-<hex hash>.js` or `<crate>-<hex hash>_bg.wasm`.
+fn is_hashed(file: &str) -> bool {
+    let stem = file
+        .rsplit_once('.')
+        .map_or(file, |(stem, _)| stem)
+        .trim_end_matches("_bg");
+    stem.rsplit_once('-')
+        .is_some_and(|(_, hash)| !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Fails if trunk downloaded any tool in `dir`, which means the image
+/// doesn't provide the version the app needs.
+pub fn check_downloads(dir: &Path) -> Result<()> {
+    let path = dir.join(DOWNLOADS);
+    let listing = match fs::read_to_string(&path) {
+        Ok(listing) => listing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(source) => return Err(Error::Io { path, source }),
+    };
+    let tools: Vec<_> = listing.split_whitespace().collect();
+    if tools.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Smoke(format!(
+        "trunk downloaded {} instead of using the image's",
+        tools.join(", ")
+    )))
+}
+
+/// The outcome of building one fixture.
+#[derive(Debug)]
+pub struct Outcome {
+    pub fixture: String,
+    pub result: Result<Dist>,
+}
+
+/// The outcome of the smoke tests against one image.
+#[derive(Debug)]
+pub struct Report {
+    pub image: String,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Fails if any fixture failed.
+    pub fn check(&self) -> Result<()> {
+        let failed: Vec<_> = self
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .map(|outcome| outcome.fixture.as_str())
+            .collect();
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Smoke(format!(
+            "{} failed to build {}",
+            self.image,
+            failed.join(", ")
+        )))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "smoke tests of {}:", self.image)?;
+        for outcome in &self.outcomes {
+            match &outcome.result {
+                Ok(dist) => writeln!(
+                    f,
+                    "  ok    {} ({})",
+                    outcome.fixture,
+                    dist.wasm
+                        .iter()
+                        .chain(&dist.js)
+                        .chain(&dist.css)
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?,
+                Err(err) => writeln!(f, "  FAIL  {}: {err}", outcome.fixture)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds each fixture in `image`, on a fresh copy under `work_dir`, and
+/// checks the result. A failing fixture doesn't stop the others.
+pub fn run(
+    runner: &mut dyn Runner,
+    image: &str,
+    fixtures: &[Fixture],
+    work_dir: &Path,
+) -> Result<Report> {
+    let mut outcomes = Vec::new();
+    for fixture in fixtures {
+        let dir = work_dir.join(&fixture.name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).map_err(|source| Error::Write {
+                path: dir.clone(),
+                source,
+            })?;
+        }
+        copy_dir(&fixture.dir, &dir)?;
+        let dir = fs::canonicalize(&dir).map_err(|source| Error::Io {
+            path: dir.clone(),
+            source,
+        })?;
+        let result = runner
+            .run(&invocation(image, &dir))
+            .and_then(|()| check_dist(&dir.join("dist")))
+            .and_then(|dist| {
+                if fixture.stylesheet && dist.css.is_empty() {
+                    return Err(Error::Smoke("index.html references no stylesheet".into()));
+                }
+                check_downloads(&dir)?;
+                Ok(dist)
+            });
+        outcomes.push(Outcome {
+            fixture: fixture.name.clone(),
+            result,
+        });
+    }
+    Ok(Report {
+        image: image.to_string(),
+        outcomes,
+    })
+}
+
+/// Copies the sources of a fixture, leaving out earlier build output.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    let write_error = |source| Error::Write {
+        path: to.to_owned(),
+        source,
+    };
+    fs::create_dir_all(to).map_err(write_error)?;
+    let read_error = |source| Error::Io {
+        path: from.to_owned(),
+        source,
+    };
+    for entry in fs::read_dir(from).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let name = entry.file_name();
+        if name == "target" || name == "dist" {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir(&path, &to.join(&name))?;
+        } else {
+            fs::copy(&path, to.join(&name)).map_err(write_error)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+//! Structure tests: declarative checks of what a built image contains,
+//! read from `structure.toml` and run in a container of the image.
+
+use std::{collections::BTreeSet, fmt, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    config,
+    matrix::Entry,
+    platform::Platform,
+    process::{quote, Invocation, Runner},
+    Error, Result,
+};
+
+/// Where the spec lives in this repository.
+pub const SPEC: &str = "structure.toml";
+
+/// The tests to run against every image. In commands, expected output and
+/// paths, `{trunk}` and `{rust}` stand for the image's versions.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(default)]
+    pub command: Vec<CommandTest>,
+    #[serde(default)]
+    pub file: Vec<FileTest>,
+}
+
+/// Runs a shell command in the image; it must succeed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandTest {
+    pub name: String,
+    pub run: String,
+    /// Text the standard output must contain.
+    #[serde(default)]
+    pub stdout: Vec<String>,
+}
+
+/// Checks a path in the image.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileTest {
+    pub name: String,
+    pub path: String,
+    /// Whether the path must exist, or must not.
+    pub exists: Option<bool>,
+    /// The path must be missing, an empty directory or an empty file.
+    #[serde(default)]
+    pub empty: bool,
+    /// Text the file must contain.
+    pub contains: Option<String>,
+    /// The path must be an executable file.
+    #[serde(default)]
+    pub executable: bool,
+}
+
+impl FileTest {
+    /// The shell condition that holds when the test passes.
+    fn condition(&self) -> String {
+        let path = quote(&self.path);
+        let mut conditions = Vec::new();
+        match self.exists {
+            Some(true) => conditions.push(format!("test -e {path}")),
+            Some(false) => conditions.push(format!("! test -e {path}")),
+            None => {}
+        }
+        if self.empty {
+            conditions.push(format!(
+                "if test -d {path}; then test -z \"$(ls -A {path})\"; else ! test -s {path}; fi"
+            ));
+        }
+        if let Some(text) = &self.contains {
+            conditions.push(format!("grep -qF -e {} {path} 2>/dev/null", quote(text)));
+        }
+        if self.executable {
+            conditions.push(format!("test -f {path} && test -x {path}"));
+        }
+        conditions.join(" && ")
+    }
+
+    /// What the test expects, e.g. `exist and contain "x"`.
+    fn expectation(&self) -> String {
+        let mut expected = Vec::new();
+        match self.exists {
+            Some(true) => expected.push("exist".to_string()),
+            Some(false) => expected.push("be missing".to_string()),
+            None => {}
+        }
+        if self.empty {
+            expected.push("be missing or empty".to_string());
+        }
+        if let Some(text) = &self.contains {
+            expected.push(format!("contain {text:?}"));
+        }
+        if self.executable {
+            expected.push("be an executable file".to_string());
+        }
+        expected.join(" and ")
+    }
+}
+
+impl Spec {
+    pub fn load(path: &Path) -> Result<Self> {
+        config::load(path)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names = BTreeSet::new();
+        let all = self
+            .command
+            .iter()
+            .map(|test| &test.name)
+            .chain(self.file.iter().map(|test| &test.name));
+        for name in all {
+            if !names.insert(name) {
+                return Err(Error::Structure(format!("two tests are named {name:?}")));
+            }
+        }
+        for test in &self.file {
+            let expects = test.exists.is_some() as usize
+                + test.empty as usize
+                + test.contains.is_some() as usize
+                + test.executable as usize;
+            if expects == 0 {
+                return Err(Error::Structure(format!(
+                    "{:?} checks nothing; set `exists`, `empty`, `contains` or `executable`",
+                    test.name
+                )));
+            }
+            if test.exists == Some(false) && expects > 1 {
+                return Err(Error::Structure(format!(
+                    "{:?} expects {} to be missing, so it can't check anything else",
+                    test.name, test.path
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Spec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let spec: Spec = toml::from_str(s)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+/// Replaces `{trunk}` and `{rust}` with the versions of `entry`.
+fn expand(text: &str, entry: &Entry) -> String {
+    text.replace("{trunk}", &entry.trunk)
+        .replace("{rust}", &entry.rust)
+}
+
+/// Runs `script` with `sh` in a throwaway container of `image`, bypassing
+/// the image's entrypoint.
+pub fn invocation(image: &str, platform: Platform, script: &str) -> Invocation {
+    Invocation::new("docker")
+        .args(["run", "--rm", "--platform"])
+        .arg(platform.to_string())
+        .args(["--entrypoint", "sh", image, "-c", script])
+}
+
+/// The outcome of one test.
+#[derive(Debug)]
+pub struct Outcome {
+    pub test: String,
+    pub result: Result<()>,
+}
+
+/// The outcome of the structure tests against one image.
+#[derive(Debug)]
+pub struct Report {
+    pub image: String,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Fails if any test failed.
+    pub fn check(&self) -> Result<()> {
+        let failed: Vec<_> = self
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .map(|outcome| outcome.test.as_str())
+            .collect();
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Structure(format!(
+            "{} failed {}",
+            self.image,
+            failed.join(", ")
+        )))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "structure tests of {}:", self.image)?;
+        for outcome in &self.outcomes {
+            match &outcome.result {
+                Ok(()) => writeln!(f, "  ok    {}", outcome.test)?,
+                Err(err) => writeln!(f, "  FAIL  {}: {err}", outcome.test)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every test of `spec` against `image`, which holds `entry` built
+/// for `platform`. A failing test doesn't stop the others.
+pub fn run(
+    runner: &mut dyn Runner,
+    spec: &Spec,
+    image: &str,
+    entry: &Entry,
+    platform: Platform,
+) -> Report {
+    let mut outcomes = Vec::new();
+    for test in &spec.command {
+        let script = expand(&test.run, entry);
+        let result = runner
+            .output(&invocation(image, platform, &script))
+            .and_then(|stdout| {
+                match test
+                    .stdout
+                    .iter()
+                    .map(|text| expand(text, entry))
+                    .find(|text| !stdout.contains(text.as_str()))
+                {
+                    Some(missing) => Err(Error::Structure(format!(
+                        "`{script}` printed {:?}, without {missing:?}",
+                        stdout.trim()
+                    ))),
+                    None => Ok(()),
+                }
+            });
+        outcomes.push(Outcome {
+            test: test.name.clone(),
+            result,
+        });
+    }
+    for test in &spec.file {
+        let test = FileTest {
+            path: expand(&test.path, entry),
+            contains: test.contains.as_deref().map(|text| expand(text, entry)),
+            ..test.clone()
+        };
+        // Exit status 1 is the condition failing; anything else is docker
+        // failing to run it, which is reported as is.
+        let script = format!("if {}; then exit 0; else exit 1; fi", test.condition());
+        let result = runner
+            .run(&invocation(image, platform, &script))
+            .map_err(|err| match err {
+                Error::CommandFailed { status, .. } if status.code() == Some(1) => {
+                    Error::Structure(format!("{} should {}", test.path, test.expectation()))
+                }
+                err => err,
+            });
+        outcomes.push(Outcome {
+            test: test.name.clone(),
+            result,
+        });
+    }
+    Report {
+        image: image.to_string(),
+        outcomes,
+    }
+}
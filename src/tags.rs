@@ -0,0 +1,113 @@
+//! Which tags each build is published under.
+//!
+//! Every build gets tags naming its exact trunk and Rust versions. Floating
+//! tags (`0.14`, `0`, `latest`, ...) are owned by exactly one build of the
+//! whole matrix, so building a subset of it never moves a tag to an older
+//! image:
+//!
+//! * `<trunk>` is owned by the build of that trunk version on the newest
+//!   Rust version;
+//! * `<major>.<minor>-rust<rust>` by the newest trunk patch release of that
+//!   line on each Rust version;
+//! * `<major>.<minor>`, `<major>` and `latest` by the newest trunk version
+//!   of the line (or overall) on the newest Rust version.
+//!
+//! Pre-releases such as `0.15.0-alpha.1` own none of the floating tags
+//! naming a line, only `<trunk>` and `<trunk>-rust<rust>`.
+//!
+//! When the base image has a flavour such as `slim`, every tag also gets a
+//! `-slim` twin, and `latest` is paired with plain `slim`.
+//!
+//! Variants of the image get the same tags without twins, suffixed with
+//! the variant's name, which stands in for `latest`: `0.14.0-alpine`,
+//! `0.14-alpine`, ..., `alpine`.
+
+use std::cmp::Ordering;
+
+use semver::Version;
+
+use crate::{
+    matrix::{compare_versions, Entry, Matrix},
+    Error, Result,
+};
+
+/// The tags of `entry`, most specific first.
+pub fn for_entry(matrix: &Matrix, flavor: Option<&str>, entry: &Entry) -> Result<Vec<String>> {
+    let trunk = parse(&entry.trunk)?;
+    let newest_rust = compare_versions(&entry.rust, matrix.newest_rust()).is_eq();
+    // Pre-releases only get their exact tags, and never take a floating
+    // tag from a release.
+    let newest_in = |same_line: &dyn Fn(&Version) -> bool| {
+        trunk.pre.is_empty()
+            && matrix
+                .trunk
+                .iter()
+                .filter_map(|v| Version::parse(v).ok())
+                .filter(|v| v.pre.is_empty() && same_line(v))
+                .all(|other| other.cmp(&trunk) != Ordering::Greater)
+    };
+    let newest_of_minor = newest_in(&|v| v.major == trunk.major && v.minor == trunk.minor);
+    let newest_of_major = newest_in(&|v| v.major == trunk.major);
+    let newest = newest_in(&|_| true);
+
+    let minor = format!("{}.{}", trunk.major, trunk.minor);
+    let mut names = vec![format!("{}-rust{}", entry.trunk, entry.rust)];
+    if newest_of_minor {
+        names.push(format!("{minor}-rust{}", entry.rust));
+    }
+    if newest_rust {
+        names.push(entry.trunk.clone());
+        if newest_of_minor {
+            names.push(minor);
+        }
+        if newest_of_major {
+            names.push(trunk.major.to_string());
+        }
+        if newest {
+            names.push("latest".to_string());
+        }
+    }
+
+    let mut tags = Vec::new();
+    for name in names {
+        if let Some(flavor) = flavor {
+            let twin = if name == "latest" {
+                flavor.to_string()
+            } else {
+                format!("{name}-{flavor}")
+            };
+            tags.push(name);
+            tags.push(twin);
+        } else {
+            tags.push(name);
+        }
+    }
+    Ok(tags)
+}
+
+/// The tags of `entry` in a variant, or in the default image for `None`.
+pub fn for_variant(
+    matrix: &Matrix,
+    flavor: Option<&str>,
+    variant: Option<&str>,
+    entry: &Entry,
+) -> Result<Vec<String>> {
+    let Some(variant) = variant else {
+        return for_entry(matrix, flavor, entry);
+    };
+    Ok(for_entry(matrix, None, entry)?
+        .into_iter()
+        .map(|name| match name.as_str() {
+            "latest" => variant.to_string(),
+            _ => format!("{name}-{variant}"),
+        })
+        .collect())
+}
+
+fn parse(version: &str) -> Result<Version> {
+    Version::parse(version).map_err(|_| {
+        Error::Matrix(format!(
+            "trunk version {version:?} is not a full `major.minor.patch` version"
+        ))
+    })
+}
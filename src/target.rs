@@ -0,0 +1,159 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, Result};
+
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// A repository images are pushed to, e.g. `ghcr.io/our-org/rust-trunk`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Target {
+    /// Registry host, e.g. `docker.io`, `ghcr.io` or `registry.local:5000`.
+    pub registry: String,
+    /// Path between the host and the repository, e.g. the user or org.
+    pub namespace: Option<String>,
+    pub repository: String,
+}
+
+impl Target {
+    /// The repository name as the distribution API sees it.
+    pub fn name(&self) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{namespace}/{}", self.repository),
+            None if self.registry == DOCKER_HUB => format!("library/{}", self.repository),
+            None => self.repository.clone(),
+        }
+    }
+
+    /// Splits an image reference like `rust:1.56-slim` or
+    /// `ghcr.io/org/image@sha256:...` into its repository and its tag or
+    /// digest; a reference without either means `latest`. The digest of a
+    /// reference with both, like `rust:1.56-slim@sha256:...`, wins.
+    pub fn parse_reference(reference: &str) -> Result<(Self, String)> {
+        if let Some((named, digest)) = reference.split_once('@') {
+            let (repository, _) = Self::parse_reference(named)?;
+            return Ok((repository, digest.to_string()));
+        }
+        let name_start = reference.rfind('/').map_or(0, |i| i + 1);
+        match reference[name_start..].rsplit_once(':') {
+            Some((_, tag)) => {
+                let repository = &reference[..reference.len() - tag.len() - 1];
+                Ok((repository.parse()?, tag.to_string()))
+            }
+            None => Ok((reference.parse()?, "latest".to_string())),
+        }
+    }
+
+    /// A full reference to `tag` in this repository.
+    pub fn reference(&self, tag: &str) -> String {
+        format!("{self}:{tag}")
+    }
+
+    /// Base URL of the registry's distribution API. Local registries are
+    /// spoken to over plain HTTP, like docker does.
+    pub fn api_base(&self) -> String {
+        if self.registry == DOCKER_HUB {
+            return "https://registry-1.docker.io".to_string();
+        }
+        let host = self.registry.split(':').next().unwrap_or_default();
+        if host == "localhost" || host == "127.0.0.1" {
+            format!("http://{}", self.registry)
+        } else {
+            format!("https://{}", self.registry)
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.registry != DOCKER_HUB {
+            write!(f, "{}/", self.registry)?;
+        }
+        if let Some(namespace) = &self.namespace {
+            write!(f, "{namespace}/")?;
+        }
+        f.write_str(&self.repository)
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    /// Parses references the way docker does: the first path component is
+    /// a registry host if it contains a `.` or `:` or is `localhost`;
+    /// otherwise the repository lives on Docker Hub.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Target(format!("{s:?} is not a valid repository"));
+        let (registry, path) = match s.split_once('/') {
+            Some((host, path)) if host.contains(['.', ':']) || host == "localhost" => {
+                (host.to_string(), path)
+            }
+            _ => (DOCKER_HUB.to_string(), s),
+        };
+        let (namespace, repository) = match path.rsplit_once('/') {
+            Some((namespace, repository)) => (Some(namespace.to_string()), repository),
+            None => (None, path),
+        };
+        let valid_component = |c: &str| {
+            !c.is_empty()
+                && c.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+        };
+        if !valid_component(repository)
+            || !namespace
+                .as_deref()
+                .is_none_or(|n| n.split('/').all(valid_component))
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            registry,
+            namespace,
+            repository: repository.to_string(),
+        })
+    }
+}
+
+impl Serialize for Target {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Targets are written either as a reference string or as a table with
+/// `registry`, `namespace` and `repository`.
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Spec {
+            Reference(String),
+            Table {
+                #[serde(default = "docker_hub")]
+                registry: String,
+                namespace: Option<String>,
+                repository: String,
+            },
+        }
+
+        fn docker_hub() -> String {
+            DOCKER_HUB.to_string()
+        }
+
+        match Spec::deserialize(deserializer)? {
+            Spec::Reference(reference) => reference.parse().map_err(serde::de::Error::custom),
+            Spec::Table {
+                registry,
+                namespace,
+                repository,
+            } => {
+                let reference = match namespace {
+                    Some(namespace) => format!("{registry}/{namespace}/{repository}"),
+                    None => format!("{registry}/{repository}"),
+                };
+                reference.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+/// The toolchain a project pins with `rust-toolchain.toml` or `rust-toolchain`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ToolchainFile {
+    pub channel: Option<String>,
+    pub path: Option<PathBuf>,
+    pub components: Vec<String>,
+    pub targets: Vec<String>,
+    pub profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Document {
+    toolchain: ToolchainFile,
+}
+
+impl ToolchainFile {
+    /// Finds the toolchain file that applies to `dir`, searching upwards the
+    /// way rustup does. Like rustup, `rust-toolchain` wins over
+    /// `rust-toolchain.toml` when a directory has both.
+    pub fn find(dir: &Path) -> Result<Option<(PathBuf, Self)>> {
+        for dir in dir.ancestors() {
+            for name in ["rust-toolchain", "rust-toolchain.toml"] {
+                let path = dir.join(name);
+                if !path.is_file() {
+                    continue;
+                }
+                let text = fs::read_to_string(&path).map_err(|source| Error::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+                let file = Self::parse(&text).map_err(|source| Error::Config {
+                    path: path.clone(),
+                    source: Box::new(source),
+                })?;
+                return Ok(Some((path, file)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parses either format: a `[toolchain]` table, or the legacy single
+    /// line naming the channel.
+    pub fn parse(text: &str) -> Result<Self> {
+        let trimmed = text.trim();
+        if !trimmed.contains(['\n', '=', '[']) {
+            if trimmed.is_empty() {
+                return Err(Error::Toolchain("toolchain file is empty".into()));
+            }
+            return Ok(Self {
+                channel: Some(trimmed.to_string()),
+                ..Self::default()
+            });
+        }
+        let document: Document = toml::from_str(text)?;
+        Ok(document.toolchain)
+    }
+}
+
+/// Architectures that start the host triple rustup appends to toolchain names.
+const HOST_ARCHES: &[&str] = &[
+    "x86_64",
+    "aarch64",
+    "i686",
+    "armv7",
+    "arm",
+    "powerpc64le",
+    "s390x",
+];
+
+/// A toolchain installed in the image, as reported by rustup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installed {
+    /// Full rustup name, e.g. `1.56.1-x86_64-unknown-linux-gnu`.
+    pub name: String,
+    pub targets: Vec<String>,
+    /// Installed components, with their target suffixes.
+    pub components: Vec<String>,
+}
+
+impl Installed {
+    /// The channel part of the name, e.g. `1.56.1` or `stable`.
+    pub fn channel(&self) -> &str {
+        HOST_ARCHES
+            .iter()
+            .find_map(|arch| self.name.find(&format!("-{arch}-")))
+            .map_or(&self.name, |end| &self.name[..end])
+    }
+
+    fn provides_channel(&self, channel: &str) -> bool {
+        let installed = self.channel();
+        channel == self.name
+            || channel == installed
+            || installed
+                .strip_prefix(channel)
+                .is_some_and(|rest| rest.starts_with('.') && is_numeric_version(channel))
+    }
+
+    fn has_component(&self, component: &str) -> bool {
+        self.components.iter().any(|installed| {
+            installed == component
+                || installed.strip_prefix(component).is_some_and(|rest| {
+                    rest.starts_with('-') && self.targets.iter().any(|t| &rest[1..] == t)
+                })
+        })
+    }
+}
+
+/// Why a toolchain file can't be served by the installed toolchains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    CustomPath(PathBuf),
+    Channel {
+        wanted: String,
+        installed: Vec<String>,
+    },
+    Targets {
+        toolchain: String,
+        missing: Vec<String>,
+    },
+    Components {
+        toolchain: String,
+        missing: Vec<String>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::CustomPath(path) => write!(
+                f,
+                "the project uses the custom toolchain at {}, which this image does not provide",
+                path.display()
+            ),
+            Mismatch::Channel { wanted, installed } => write!(
+                f,
+                "the project pins Rust {wanted}, but this image only has {}; \
+                 use the image tag for that Rust version instead",
+                installed.join(", ")
+            ),
+            Mismatch::Targets { toolchain, missing } => write!(
+                f,
+                "the project needs target(s) {} which are not installed for {toolchain}",
+                missing.join(", ")
+            ),
+            Mismatch::Components { toolchain, missing } => write!(
+                f,
+                "the project needs component(s) {} which are not installed for {toolchain}",
+                missing.join(", ")
+            ),
+        }
+    }
+}
+
+/// Picks the installed toolchain that satisfies `file`, or explains why none
+/// does. Without a channel the first installed (default) toolchain is used.
+pub fn check<'a>(
+    file: &ToolchainFile,
+    installed: &'a [Installed],
+) -> Result<&'a Installed, Mismatch> {
+    if let Some(path) = &file.path {
+        return Err(Mismatch::CustomPath(path.clone()));
+    }
+    let toolchain = match &file.channel {
+        Some(channel) => installed.iter().find(|t| t.provides_channel(channel)),
+        None => installed.first(),
+    }
+    .ok_or_else(|| Mismatch::Channel {
+        wanted: file.channel.clone().unwrap_or_default(),
+        installed: installed.iter().map(|t| t.channel().to_string()).collect(),
+    })?;
+    let missing: Vec<String> = file
+        .targets
+        .iter()
+        .filter(|t| !toolchain.targets.contains(t))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Mismatch::Targets {
+            toolchain: toolchain.name.clone(),
+            missing,
+        });
+    }
+    let missing: Vec<String> = file
+        .components
+        .iter()
+        .filter(|c| !toolchain.has_component(c))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Mismatch::Components {
+            toolchain: toolchain.name.clone(),
+            missing,
+        });
+    }
+    Ok(toolchain)
+}
+
+/// Parses `rustup toolchain list`, default toolchain first.
+pub fn parse_toolchain_list(output: &str) -> Vec<String> {
+    let mut names: Vec<(bool, String)> = output
+        .lines()
+        .filter_map(|line| {
+            let name = line.split_whitespace().next()?;
+            Some((!line.contains("(default)"), name.to_string()))
+        })
+        .collect();
+    names.sort_by_key(|(not_default, _)| *not_default);
+    names.into_iter().map(|(_, name)| name).collect()
+}
+
+fn is_numeric_version(version: &str) -> bool {
+    version
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
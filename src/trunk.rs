@@ -0,0 +1,167 @@
+//! Prebuilt trunk release binaries, pinned by SHA-256 in `trunk.lock`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config, http, manifest::is_sha256, platform::Platform, Error, Result};
+
+/// Checksums of the prebuilt trunk releases, keyed by trunk version and
+/// then by target triple.
+///
+/// A version without an entry for a platform has no prebuilt binary for it
+/// and is installed with `cargo install` instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrunkLock {
+    #[serde(default)]
+    pub release: BTreeMap<String, BTreeMap<String, LockedRelease>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedRelease {
+    pub url: String,
+    pub sha256: String,
+}
+
+impl TrunkLock {
+    pub fn load(path: &Path) -> Result<Self> {
+        config::load(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let body = toml::to_string(self).expect("lockfile serializes");
+        config::write(
+            path,
+            &format!("# SHA-256 of the prebuilt trunk releases; update with `trunk-docker lock-trunk`.\n\n{body}"),
+        )
+    }
+
+    pub fn get(&self, version: &str, platform: Platform) -> Option<&LockedRelease> {
+        self.release.get(version)?.get(&platform.rust_target())
+    }
+
+    /// Looks up the release asset of `version` for `platform` and records
+    /// its checksum. Returns `false` if trunk published no binary for it.
+    pub fn lock(&mut self, url_template: &str, version: &str, platform: Platform) -> Result<bool> {
+        let target = platform.rust_target();
+        let url = asset_url(url_template, version, platform);
+        let Some(asset) = http::get(&url)? else {
+            if let Some(targets) = self.release.get_mut(version) {
+                targets.remove(&target);
+                if targets.is_empty() {
+                    self.release.remove(version);
+                }
+            }
+            return Ok(false);
+        };
+        self.release.entry(version.to_string()).or_default().insert(
+            target,
+            LockedRelease {
+                url,
+                sha256: sha256_hex(&asset),
+            },
+        );
+        Ok(true)
+    }
+}
+
+impl FromStr for TrunkLock {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let lock: TrunkLock = toml::from_str(s)?;
+        let malformed = lock
+            .release
+            .iter()
+            .find(|(_, targets)| targets.values().any(|r| !is_sha256(&r.sha256)));
+        if let Some((version, _)) = malformed {
+            return Err(Error::Checksum(format!(
+                "trunk {version} has a malformed sha256"
+            )));
+        }
+        Ok(lock)
+    }
+}
+
+impl LockedRelease {
+    /// Downloads the release and checks it against the locked checksum.
+    pub fn fetch(&self) -> Result<Vec<u8>> {
+        let asset = http::get(&self.url)?.ok_or_else(|| Error::Http {
+            url: self.url.clone(),
+            message: "release asset no longer exists".into(),
+        })?;
+        let actual = sha256_hex(&asset);
+        if actual != self.sha256 {
+            return Err(Error::Checksum(format!(
+                "{} has sha256 {actual}, but it is locked at {}",
+                self.url, self.sha256
+            )));
+        }
+        Ok(asset)
+    }
+}
+
+/// Expands `{version}` and `{target}` in a release URL template.
+pub fn asset_url(template: &str, version: &str, platform: Platform) -> String {
+    template
+        .replace("{version}", version)
+        .replace("{target}", &platform.rust_target())
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Extracts the `trunk` executable from a release tarball into `dir`.
+pub fn unpack(tarball: &[u8], dir: &Path) -> Result<PathBuf> {
+    let dest = dir.join("trunk");
+    write_executable(&dest, &binary(tarball)?)?;
+    Ok(dest)
+}
+
+/// The `trunk` executable inside a release tarball.
+pub fn binary(tarball: &[u8]) -> Result<Vec<u8>> {
+    let archive_error = |err: std::io::Error| Error::Archive(format!("trunk release: {err}"));
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    for entry in archive.entries().map_err(archive_error)? {
+        let mut entry = entry.map_err(archive_error)?;
+        let path = entry.path().map_err(archive_error)?;
+        if !entry.header().entry_type().is_file() || path.file_name() != Some("trunk".as_ref()) {
+            continue;
+        }
+        let mut binary = Vec::new();
+        entry.read_to_end(&mut binary).map_err(archive_error)?;
+        return Ok(binary);
+    }
+    Err(Error::Archive(
+        "trunk release tarball contains no `trunk` executable".into(),
+    ))
+}
+
+fn write_executable(path: &Path, contents: &[u8]) -> Result<()> {
+    let write = || {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(())
+    };
+    write().map_err(|source| Error::Write {
+        path: path.to_owned(),
+        source,
+    })
+}
@@ -0,0 +1,340 @@
+//! Upstream release checks: trunk releases from the crates.io index and
+//! Rust base images from the registry, proposed as a bump of the matrix,
+//! and of the manifest's binaryen when a new trunk needs a newer one.
+
+use std::{fmt, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    http,
+    manifest::{Base, Manifest},
+    matrix::{compare_versions, Matrix},
+    platform::Platform,
+    registry::Registry,
+    target::Target,
+    wasm_opt, Error, Result,
+};
+
+/// The sparse crates.io index.
+pub const CRATES_INDEX: &str = "https://index.crates.io";
+
+/// Path of a crate's file in the index, e.g. `tr/un/trunk`.
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+#[derive(Deserialize)]
+struct IndexEntry {
+    vers: String,
+    cksum: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
+/// The entries of `name` in the index at `index`: a sparse index URL, or
+/// the directory of a local index mirror.
+fn index_entries(index: &str, name: &str) -> Result<Vec<IndexEntry>> {
+    let path = index_path(name);
+    let text = if Path::new(index).is_dir() {
+        let file = Path::new(index).join(&path);
+        match fs::read(&file) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(source) => return Err(Error::Io { path: file, source }),
+        }
+    } else {
+        http::get(&format!("{}/{path}", index.trim_end_matches('/')))?.unwrap_or_default()
+    };
+    String::from_utf8_lossy(&text)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|err| Error::Json {
+                path: format!("{index}/{path}").into(),
+                message: err.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The released versions of `name`, without yanked ones and pre-releases,
+/// from the index at `index`.
+pub fn crate_versions(index: &str, name: &str) -> Result<Vec<semver::Version>> {
+    let mut versions = Vec::new();
+    for entry in index_entries(index, name)? {
+        match semver::Version::parse(&entry.vers) {
+            Ok(version) if !entry.yanked && version.pre.is_empty() => versions.push(version),
+            _ => {}
+        }
+    }
+    versions.sort();
+    Ok(versions)
+}
+
+/// The SHA-256 of the `.crate` file of `name` at `version`, as the index
+/// at `index` lists it, unless that version is yanked or was never
+/// released.
+pub fn crate_checksum(index: &str, name: &str, version: &str) -> Result<Option<String>> {
+    Ok(index_entries(index, name)?
+        .into_iter()
+        .find(|entry| entry.vers == version && !entry.yanked)
+        .map(|entry| entry.cksum))
+}
+
+/// The Rust versions with a base image among `tags`, e.g. `1.57` for
+/// `1.57-slim`. Only `major.minor` tags count, like the matrix lists.
+pub fn rust_versions(tags: &[String], suffix: Option<&str>) -> Vec<String> {
+    let mut versions: Vec<String> = tags
+        .iter()
+        .filter_map(|tag| match suffix {
+            Some(suffix) => tag.strip_suffix(suffix)?.strip_suffix('-'),
+            None => Some(tag.as_str()),
+        })
+        .filter(|version| {
+            let parts: Vec<&str> = version.split('.').collect();
+            parts.len() == 2 && parts.iter().all(|p| p.parse::<u64>().is_ok())
+        })
+        .map(str::to_string)
+        .collect();
+    versions.sort_by(|a, b| compare_versions(a, b));
+    versions
+}
+
+/// The newest upstream version on one axis of the matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latest {
+    /// The newest version in the matrix.
+    pub current: String,
+    pub latest: String,
+    /// Where it was found, e.g. `rust:1.57-slim@sha256:...`.
+    pub source: String,
+}
+
+impl Latest {
+    pub fn is_newer(&self) -> bool {
+        self.latest != self.current
+    }
+}
+
+/// What upstream has next to the matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub trunk: Latest,
+    pub rust: Latest,
+}
+
+/// Looks up the newest trunk release in the crates.io index at `index`
+/// and the newest Rust base image of `base` published for every platform
+/// of `matrix`.
+pub fn check(matrix: &Matrix, base: &Base, index: &str) -> Result<Check> {
+    let current = matrix.default_entry();
+    let latest_trunk = crate_versions(index, "trunk")?
+        .last()
+        .map(ToString::to_string)
+        .filter(|latest| compare_versions(latest, &current.trunk).is_gt())
+        .unwrap_or_else(|| current.trunk.clone());
+    let trunk = Latest {
+        current: current.trunk,
+        latest: latest_trunk,
+        source: index.to_string(),
+    };
+
+    let (target, _) = Target::parse_reference(&base.reference(&current.rust))?;
+    let mut registry = Registry::for_target(&target);
+    let name = target.name();
+    let candidates = rust_versions(&registry.tags(&name)?, base.suffix.as_deref());
+    let mut rust = Latest {
+        current: current.rust.clone(),
+        latest: current.rust.clone(),
+        source: base.reference(&current.rust),
+    };
+    // A newer tag only counts once it has an image for every platform.
+    for version in candidates.iter().rev() {
+        if compare_versions(version, &current.rust).is_le() {
+            break;
+        }
+        let reference = base.reference(version);
+        let tag = reference.rsplit_once(':').map_or("latest", |(_, tag)| tag);
+        if let Some(digest) = digest_for_platforms(&mut registry, &name, tag, &matrix.platforms)? {
+            rust.latest = version.clone();
+            rust.source = format!("{reference}@{digest}");
+            break;
+        }
+    }
+    Ok(Check { trunk, rust })
+}
+
+/// The digest `tag` points to, if it has an image for every one of
+/// `platforms`, which the matrix never leaves empty.
+pub(crate) fn digest_for_platforms(
+    registry: &mut Registry,
+    name: &str,
+    tag: &str,
+    platforms: &[Platform],
+) -> Result<Option<String>> {
+    let mut digest = None;
+    for &platform in platforms {
+        let Some(image) = registry.try_platform_image(name, tag, platform)? else {
+            return Ok(None);
+        };
+        digest = Some(image.index_digest.unwrap_or(image.digest));
+    }
+    Ok(digest)
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (axis, latest) in [("trunk", &self.trunk), ("rust", &self.rust)] {
+            if latest.is_newer() {
+                writeln!(
+                    f,
+                    "{axis}: {} -> {} ({})",
+                    latest.current, latest.latest, latest.source
+                )?;
+            } else {
+                writeln!(f, "{axis}: {} is up to date", latest.current)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `current` with `latest` in every `axis = [...]` list of the
+/// matrix file `text`, keeping its comments and layout, and checks that
+/// the result is still a valid matrix.
+pub fn bump(text: &str, axis: &str, current: &str, latest: &str) -> Result<String> {
+    rewrite(text, axis, current, &format!("\"{latest}\""))
+}
+
+/// Adds `latest` after `current` in every `axis = [...]` list of the matrix
+/// file `text`, so the images of `current` keep being built next to the new
+/// ones, and checks that the result is still a valid matrix.
+pub fn add(text: &str, axis: &str, current: &str, latest: &str) -> Result<String> {
+    rewrite(text, axis, current, &format!("\"{current}\", \"{latest}\""))
+}
+
+/// Replaces the quoted `current` with `with` in every `axis = [...]` list.
+fn rewrite(text: &str, axis: &str, current: &str, with: &str) -> Result<String> {
+    let quoted = format!("\"{current}\"");
+    let mut out = String::with_capacity(text.len());
+    let mut in_list = false;
+    let mut replaced = false;
+    for line in text.split_inclusive('\n') {
+        let key = line
+            .trim_start()
+            .strip_prefix(axis)
+            .map(str::trim_start)
+            .is_some_and(|rest| rest.starts_with('='));
+        if key {
+            in_list = true;
+        }
+        if in_list {
+            let (code, comment) = line.split_once('#').unwrap_or((line, ""));
+            if code.contains(&quoted) {
+                replaced = true;
+            }
+            out.push_str(&code.replace(&quoted, with));
+            if !comment.is_empty() {
+                out.push('#');
+                out.push_str(comment);
+            }
+            if code.contains(']') {
+                in_list = false;
+            }
+        } else {
+            out.push_str(line);
+        }
+    }
+    if !replaced {
+        return Err(Error::Matrix(format!(
+            "no `{axis}` list in the matrix contains {current}"
+        )));
+    }
+    out.parse::<Matrix>()?;
+    Ok(out)
+}
+
+/// The binaryen release `manifest` has to move to for `trunk`, if the one
+/// it pins is older than [`wasm_opt::minimum_for`] allows. Fails for what
+/// rewriting `[binaryen] version` cannot bump: pinned tarball checksums,
+/// which the new release would not match, and variants with a binaryen of
+/// their own.
+pub fn binaryen_bump(manifest: &Manifest, trunk: &str) -> Result<Option<u32>> {
+    let minimum = wasm_opt::minimum_for(trunk)?;
+    for (variant, image) in manifest.images() {
+        let Some(binaryen) = &image.binaryen else {
+            continue;
+        };
+        if binaryen.version >= minimum {
+            continue;
+        }
+        let inherited = variant.is_some()
+            && manifest.binaryen.as_ref().is_some_and(|own| {
+                own.version == binaryen.version && own.sha256 == binaryen.sha256
+            });
+        if inherited {
+            continue;
+        }
+        let pinned = !binaryen.sha256.is_empty();
+        if variant.is_some() || pinned {
+            let image = variant.map_or("the default image".into(), |v| format!("variant {v}"));
+            let what = if pinned { " and its `sha256` pins" } else { "" };
+            return Err(Error::WasmOpt(format!(
+                "trunk {trunk} needs binaryen version {minimum}, but {image} pins version {}; \
+                 raise it{what} by hand",
+                binaryen.version
+            )));
+        }
+    }
+    Ok(manifest
+        .binaryen
+        .as_ref()
+        .filter(|binaryen| binaryen.version < minimum)
+        .map(|_| minimum))
+}
+
+/// Sets `version` in the `[binaryen]` table of the manifest file `text`,
+/// keeping its comments and layout, and checks that the result is still a
+/// valid manifest.
+pub fn bump_binaryen(text: &str, version: u32) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut in_table = false;
+    let mut replaced = false;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            in_table = trimmed.starts_with("[binaryen]");
+        }
+        let key = trimmed
+            .strip_prefix("version")
+            .map(str::trim_start)
+            .is_some_and(|rest| rest.starts_with('='));
+        if in_table && key && !replaced {
+            let comment = line.find('#').map_or("", |at| &line[at..]);
+            let comment = comment.trim_end_matches('\n');
+            let indent = &line[..line.len() - trimmed.len()];
+            if comment.is_empty() {
+                out.push_str(&format!("{indent}version = {version}\n"));
+            } else {
+                out.push_str(&format!("{indent}version = {version} {comment}\n"));
+            }
+            replaced = true;
+        } else {
+            out.push_str(line);
+        }
+    }
+    if !replaced {
+        return Err(Error::Manifest(
+            "no `version` in a `[binaryen]` table of the manifest".into(),
+        ));
+    }
+    out.parse::<Manifest>()?;
+    Ok(out)
+}
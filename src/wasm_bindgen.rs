@@ -0,0 +1,110 @@
+//! The wasm-bindgen-cli versions bundled in the image, and picking the one a
+//! project's `Cargo.lock` needs.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{matrix::compare_versions, platform::Platform, Error, Result};
+
+/// Each bundled version lives in `<BUNDLE_DIR>/<version>/bin`.
+pub const BUNDLE_DIR: &str = "/usr/local/wasm-bindgen";
+
+/// The target triple of the wasm-bindgen-cli release for `platform`.
+/// wasm-bindgen publishes static musl binaries for x86_64, but only glibc
+/// ones for aarch64.
+pub fn release_target(platform: Platform) -> &'static str {
+    match platform {
+        Platform::Amd64 => "x86_64-unknown-linux-musl",
+        Platform::Arm64 => "aarch64-unknown-linux-gnu",
+    }
+}
+
+/// Expands `{version}` and `{target}` in a release URL template.
+pub fn asset_url(template: &str, version: &str, platform: Platform) -> String {
+    template
+        .replace("{version}", version)
+        .replace("{target}", release_target(platform))
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+}
+
+/// Finds the `Cargo.lock` that applies to `dir`, searching upwards so that
+/// workspace members resolve to the workspace lockfile.
+pub fn find_lockfile(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.is_file())
+}
+
+/// The `wasm-bindgen` version locked in a `Cargo.lock`, if it depends on it.
+pub fn locked_version(lockfile: &str) -> Result<Option<String>> {
+    let lockfile: Lockfile = toml::from_str(lockfile)?;
+    let mut versions: Vec<String> = lockfile
+        .package
+        .into_iter()
+        .filter(|p| p.name == "wasm-bindgen")
+        .map(|p| p.version)
+        .collect();
+    match versions.len() {
+        0 => Ok(None),
+        1 => Ok(versions.pop()),
+        _ => Err(Error::WasmBindgen(format!(
+            "Cargo.lock contains several wasm-bindgen versions ({}); \
+             trunk can only use one, run `cargo update -p wasm-bindgen`",
+            versions.join(", ")
+        ))),
+    }
+}
+
+/// The versions bundled under `bundle_dir`, sorted.
+pub fn bundled_versions(bundle_dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(bundle_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => {
+            return Err(Error::Io {
+                path: bundle_dir.to_owned(),
+                source,
+            })
+        }
+    };
+    let mut versions: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("bin/wasm-bindgen").is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    versions.sort_by(|a, b| compare_versions(a, b));
+    Ok(versions)
+}
+
+/// The `bin` directory of the bundled version matching `locked`.
+pub fn select(bundle_dir: &Path, locked: &str) -> Result<PathBuf> {
+    let bundled = bundled_versions(bundle_dir)?;
+    if bundled.iter().any(|v| v == locked) {
+        return Ok(bundle_dir.join(locked).join("bin"));
+    }
+    let available = if bundled.is_empty() {
+        "none".to_string()
+    } else {
+        bundled.join(", ")
+    };
+    Err(Error::WasmBindgen(format!(
+        "Cargo.lock pins wasm-bindgen {locked}, but this image only bundles \
+         wasm-bindgen-cli {available}; pin one of those with \
+         `cargo update -p wasm-bindgen --precise <version>` or use an image that bundles {locked}"
+    )))
+}
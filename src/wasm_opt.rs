@@ -0,0 +1,70 @@
+//! The pinned binaryen release the image ships `wasm-opt` from, and the
+//! check that it is new enough for the trunk version it is paired with.
+
+use crate::{
+    process::{self, Invocation},
+    Error, Result,
+};
+
+/// Oldest binaryen release each trunk line works with, newest trunk first.
+/// Older releases reject the bulk-memory and sign-ext instructions that
+/// current Rust emits for `data-wasm-opt` builds.
+const MINIMUM_BINARYEN: &[(semver::Version, u32)] = &[
+    (semver::Version::new(0, 17, 0), 116),
+    (semver::Version::new(0, 14, 0), 101),
+];
+
+/// Binaryen release required for trunk versions older than the table covers.
+const FALLBACK_MINIMUM: u32 = 90;
+
+/// Download URL of a binaryen release for an architecture, e.g. `x86_64`.
+pub fn release_url(version: u32, arch: &str) -> String {
+    format!(
+        "https://github.com/WebAssembly/binaryen/releases/download/\
+         version_{version}/binaryen-version_{version}-{arch}-linux.tar.gz"
+    )
+}
+
+/// The oldest binaryen release the given trunk version works with.
+pub fn minimum_for(trunk: &str) -> Result<u32> {
+    let trunk = semver::Version::parse(trunk)
+        .map_err(|err| Error::WasmOpt(format!("invalid trunk version {trunk:?}: {err}")))?;
+    Ok(MINIMUM_BINARYEN
+        .iter()
+        .find(|(since, _)| trunk >= *since)
+        .map_or(FALLBACK_MINIMUM, |(_, minimum)| *minimum))
+}
+
+/// Fails unless binaryen `version` is new enough for `trunk`.
+pub fn check(version: u32, trunk: &str) -> Result<()> {
+    let minimum = minimum_for(trunk)?;
+    if version < minimum {
+        return Err(Error::WasmOpt(format!(
+            "wasm-opt is binaryen version {version}, but trunk {trunk} needs at least version {minimum}"
+        )));
+    }
+    Ok(())
+}
+
+/// Parses the output of `wasm-opt --version`, e.g. `wasm-opt version 105`
+/// or the older `wasm-opt version_90`.
+pub fn parse_version(output: &str) -> Option<u32> {
+    let rest = &output[output.find("version")? + "version".len()..];
+    let digits: String = rest
+        .trim_start_matches([' ', '_'])
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+/// Runs `wasm-opt --version` from `PATH` and returns the binaryen version.
+pub fn installed_version() -> Result<u32> {
+    let output = process::output(&Invocation::new("wasm-opt").arg("--version"))?;
+    parse_version(&output).ok_or_else(|| {
+        Error::WasmOpt(format!(
+            "can't read a version from `wasm-opt --version`: {}",
+            output.trim()
+        ))
+    })
+}
@@ -0,0 +1,702 @@
+mod common;
+
+use std::{collections::HashMap, fs, io::Write, path::Path};
+
+use flate2::{write::GzEncoder, Compression};
+use serde_json::json;
+use trunk_docker::{
+    apt::Archives,
+    assemble::{self, Options},
+    layer::{self, LayerBuilder},
+    lock::ImageLock,
+    manifest::Manifest,
+    matrix::Entry,
+    oci::{self, Descriptor, ImageManifest, Index, Layout},
+    platform::Platform,
+    registry::Registry,
+    sbom,
+    target::Target,
+    trunk::{self, TrunkLock},
+};
+
+const TOOLCHAIN: &str = "/usr/local/rustup/toolchains/1.56.1-x86_64-unknown-linux-gnu";
+
+/// What the stand-in base has installed, some of which the default
+/// manifest's packages depend on.
+const STATUS: &str = "Package: libc6\nStatus: install ok installed\nVersion: 2.31-13+deb11u5\n\n\
+                      Package: perl-base\nStatus: install ok installed\nVersion: 5.32.1-4+deb11u2\n\
+                      Provides: perl5\n\n\
+                      Package: gcc\nStatus: install ok installed\nVersion: 4:10.2.1-1\n\n\
+                      Package: libc6-dev\nStatus: install ok installed\nVersion: 2.31-13+deb11u5\n\n\
+                      Package: libssl1.1\nStatus: install ok installed\nVersion: 1.1.1w-0+deb11u1\n";
+
+fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = LayerBuilder::new();
+    for (path, contents) in files {
+        builder.file(path, 0o755, contents.to_vec());
+    }
+    builder.finish().unwrap().blob
+}
+
+/// Pushes a `rust:1.56-slim` stand-in, behind an image index, to `host`.
+fn push_base(host: &str) {
+    let target: Target = format!("{host}/rust").parse().unwrap();
+    let mut registry = Registry::for_target(&target);
+    let name = target.name();
+    let base_layer = LayerBuilder::new()
+        .file(
+            &format!("{TOOLCHAIN}/lib/rustlib/components"),
+            0o644,
+            b"rustc\ncargo\nrust-std-x86_64-unknown-linux-gnu\n".to_vec(),
+        )
+        .file(
+            "/usr/lib/os-release",
+            0o644,
+            b"ID=debian\nVERSION_CODENAME=bullseye\n".to_vec(),
+        )
+        .file("/var/lib/dpkg/status", 0o644, STATUS.as_bytes().to_vec())
+        .symlink("/bin", "usr/bin")
+        .finish()
+        .unwrap();
+    let config = oci::to_canonical_json(&json!({
+        "architecture": "amd64",
+        "os": "linux",
+        "config": {
+            "Env": ["RUSTUP_HOME=/usr/local/rustup", "RUST_VERSION=1.56.1"],
+            "Cmd": ["bash"],
+        },
+        "rootfs": { "type": "layers", "diff_ids": [base_layer.diff_id] },
+        "history": [{ "created_by": "debian" }],
+    }));
+    let layer = Descriptor::of(oci::LAYER_MEDIA_TYPE, &base_layer.blob);
+    let config_descriptor = Descriptor::of(oci::CONFIG_MEDIA_TYPE, &config);
+    registry
+        .push_blob(&name, &layer.digest, &base_layer.blob)
+        .unwrap();
+    registry
+        .push_blob(&name, &config_descriptor.digest, &config)
+        .unwrap();
+    let manifest = oci::to_canonical_json(&ImageManifest {
+        schema_version: 2,
+        media_type: Some(oci::MANIFEST_MEDIA_TYPE.into()),
+        artifact_type: None,
+        config: config_descriptor,
+        layers: vec![layer],
+        subject: None,
+        annotations: Default::default(),
+    });
+    let mut descriptor = Descriptor::of(oci::MANIFEST_MEDIA_TYPE, &manifest);
+    registry
+        .put_manifest(
+            &name,
+            &descriptor.digest,
+            oci::MANIFEST_MEDIA_TYPE,
+            &manifest,
+        )
+        .unwrap();
+    descriptor.platform = Some(Platform::Amd64.into());
+    let index = oci::to_canonical_json(&Index::new(vec![descriptor]));
+    registry
+        .put_manifest(&name, "1.56-slim", oci::INDEX_MEDIA_TYPE, &index)
+        .unwrap();
+}
+
+/// Serves a trunk release and a Rust channel with a wasm32 rust-std.
+fn serve_downloads() -> (String, TrunkLock) {
+    let trunk_release = tarball(&[("trunk", b"trunk 0.14.0")]);
+    let rust_std = tarball(&[
+        (
+            "rust-std-1.56.1-wasm32-unknown-unknown/rust-std-wasm32-unknown-unknown/lib/rustlib/wasm32-unknown-unknown/lib/libstd.rlib",
+            b"std",
+        ),
+        (
+            "rust-std-1.56.1-wasm32-unknown-unknown/rust-std-wasm32-unknown-unknown/manifest.in",
+            b"file:lib/rustlib/wasm32-unknown-unknown/lib/libstd.rlib\n",
+        ),
+        ("rust-std-1.56.1-wasm32-unknown-unknown/install.sh", b"#!/bin/sh"),
+    ]);
+    let rust_std_hash = trunk::sha256_hex(&rust_std);
+    let lock = format!(
+        "[release.\"0.14.0\".x86_64-unknown-linux-gnu]\n\
+         url = \"{{base}}/trunk.tar.gz\"\nsha256 = \"{}\"",
+        trunk::sha256_hex(&trunk_release)
+    );
+    let trunk_crate = tarball(&[(
+        "trunk-0.14.0/Cargo.lock",
+        b"[[package]]\nname = \"trunk\"\nversion = \"0.14.0\"\n",
+    )]);
+    let binaryen = tarball(&[("binaryen-version_105/bin/wasm-opt", b"wasm-opt 105")]);
+    let binaryen_sha256 = format!(
+        "{}  binaryen-version_105-x86_64-linux.tar.gz\n",
+        trunk::sha256_hex(&binaryen)
+    );
+    let mut files = HashMap::from([
+        ("/trunk.tar.gz".to_string(), trunk_release),
+        ("/rust-std.tar.gz".to_string(), rust_std),
+        ("/crates/trunk/trunk-0.14.0.crate".to_string(), trunk_crate),
+        ("/binaryen-105-x86_64.tar.gz".to_string(), binaryen),
+        (
+            "/binaryen-105-x86_64.tar.gz.sha256".to_string(),
+            binaryen_sha256.into_bytes(),
+        ),
+    ]);
+    for version in ["0.2.78", "0.2.79"] {
+        let dir = format!("wasm-bindgen-{version}-x86_64-unknown-linux-musl");
+        let lockfile =
+            format!("[[package]]\nname = \"wasm-bindgen-cli\"\nversion = \"{version}\"\n");
+        files.insert(
+            format!("/crates/wasm-bindgen-cli/wasm-bindgen-cli-{version}.crate"),
+            tarball(&[(
+                &format!("wasm-bindgen-cli-{version}/Cargo.lock"),
+                lockfile.as_bytes(),
+            )]),
+        );
+        files.insert(
+            format!("/{dir}.tar.gz"),
+            tarball(&[(
+                &format!("{dir}/wasm-bindgen"),
+                format!("wasm-bindgen {version}").as_bytes(),
+            )]),
+        );
+    }
+    let base = common::http::serve_with(move |request| {
+        let host = &request.headers["host"];
+        if request.path == "/dist/channel-rust-1.56.1.toml" {
+            let channel = format!(
+                "[pkg.rust-std.target.wasm32-unknown-unknown]\n\
+                 available = true\nurl = \"http://{host}/rust-std.tar.gz\"\n\
+                 hash = \"{rust_std_hash}\"\n"
+            );
+            return common::http::Response::new(200).body(channel);
+        }
+        match files.get(&request.path) {
+            Some(body) => common::http::Response::new(200).body(body.clone()),
+            None => common::http::Response::new(404),
+        }
+    });
+    let lock = lock.replace("{base}", &base).parse().unwrap();
+    (base, lock)
+}
+
+/// A `.deb` of `control` holding `files`, with its data tarball compressed
+/// with xz, like Debian's, or else with gzip.
+fn deb(control: &str, files: &[(&str, &[u8])], xz: bool) -> Vec<u8> {
+    let tar = |entries: &[(&str, &[u8])]| {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            match path.strip_prefix('@') {
+                Some(path) => {
+                    let target = String::from_utf8_lossy(contents);
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_mode(0o777);
+                    header.set_size(0);
+                    builder.append_link(&mut header, path, &*target).unwrap();
+                }
+                None => {
+                    header.set_mode(0o755);
+                    header.set_size(contents.len() as u64);
+                    header.set_cksum();
+                    builder.append_data(&mut header, path, *contents).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap()
+    };
+    let gzip = |bytes: &[u8]| {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    };
+    let control_tar = gzip(&tar(&[("./control", control.as_bytes())]));
+    let data_tar = tar(files);
+    let data = if xz {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut data_tar.as_slice(), &mut compressed).unwrap();
+        ("data.tar.xz", compressed)
+    } else {
+        ("data.tar.gz", gzip(&data_tar))
+    };
+    let mut ar = b"!<arch>\n".to_vec();
+    for (name, body) in [
+        ("debian-binary", b"2.0\n".to_vec()),
+        ("control.tar.gz", control_tar),
+        (data.0, data.1),
+    ] {
+        ar.extend(
+            format!(
+                "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                0,
+                0,
+                0,
+                100644,
+                body.len()
+            )
+            .bytes(),
+        );
+        ar.extend(&body);
+        if body.len() % 2 == 1 {
+            ar.push(b'\n');
+        }
+    }
+    ar
+}
+
+/// A Debian archive and security archive with the packages of the default
+/// manifest and their dependencies, as `(package, version, fields, files)`.
+fn serve_debian() -> Archives {
+    type Package<'a> = (&'a str, &'a str, &'a str, Vec<(&'a str, &'a [u8])>);
+    let release: Vec<Package> = vec![
+        (
+            "curl",
+            "7.74.0-1.3",
+            "Depends: libc6 (>= 2.17), libcurl4 (= 7.74.0-1.3)\n",
+            vec![("./usr/bin/curl", b"curl")],
+        ),
+        (
+            "libcurl4",
+            "7.74.0-1.3",
+            "Depends: libc6, libssl1.1 (>= 1.1.1)\nMulti-Arch: same\n",
+            vec![
+                ("./usr/lib/x86_64-linux-gnu/libcurl.so.4.7.0", b"libcurl"),
+                (
+                    "@./usr/lib/x86_64-linux-gnu/libcurl.so.4",
+                    b"libcurl.so.4.7.0",
+                ),
+            ],
+        ),
+        (
+            "git-man",
+            "1:2.30.2-1",
+            "",
+            vec![("./usr/share/man/man1/git.1.gz", b"man")],
+        ),
+        (
+            "build-essential",
+            "12.9",
+            "Depends: libc6-dev | libc-dev, gcc (>= 4:10.2), make\n",
+            vec![],
+        ),
+        (
+            "make",
+            "4.3-4.1",
+            "Depends: libc6 (>= 2.27)\n",
+            vec![("./", b""), ("./bin/make", b"make")],
+        ),
+        (
+            "libssl-dev",
+            "1.1.1w-0+deb11u1",
+            "Depends: libssl1.1 (= 1.1.1w-0+deb11u1), debconf (>= 0.5) | debconf-2.0\n",
+            vec![("./usr/include/openssl/ssl.h", b"ssl")],
+        ),
+        (
+            "cdebconf",
+            "0.260",
+            "Provides: debconf-2.0\n",
+            vec![("./usr/bin/debconf", b"debconf")],
+        ),
+        (
+            "pkg-config",
+            "0.29.2-1",
+            "Depends: libc6 (>= 2.14)\n",
+            vec![("./usr/bin/pkg-config", b"pkg-config")],
+        ),
+    ];
+    let security: Vec<Package> = vec![
+        (
+            "curl",
+            "7.74.0-1.3+deb11u7",
+            "Depends: libc6, libcurl4 (= 7.74.0-1.3+deb11u7)\n",
+            vec![("./usr/bin/curl", b"curl, patched")],
+        ),
+        (
+            "git",
+            "1:2.30.2-1+deb11u2",
+            "Depends: libc6, perl5, git-man (>> 1:2.30.2)\n",
+            vec![("./usr/bin/git", b"git")],
+        ),
+    ];
+    let mut files = HashMap::new();
+    for (archive, suite, packages) in [
+        ("debian", "bullseye", &release),
+        ("debian-security", "bullseye-security", &security),
+    ] {
+        let mut index = String::new();
+        for (i, (name, version, fields, contents)) in packages.iter().enumerate() {
+            let control = format!(
+                "Package: {name}\nVersion: {version}\nArchitecture: amd64\n{fields}\
+                 Description: {name}\n"
+            );
+            let filename = format!("pool/main/{name}_{}_amd64.deb", version.replace(':', "%3a"));
+            let deb = deb(&control, contents, i % 2 == 0);
+            index.push_str(&format!(
+                "{control}Filename: {filename}\nSHA256: {}\n\n",
+                trunk::sha256_hex(&deb)
+            ));
+            files.insert(format!("/{archive}/{filename}"), deb);
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(index.as_bytes()).unwrap();
+        files.insert(
+            format!("/{archive}/dists/{suite}/main/binary-amd64/Packages.gz"),
+            encoder.finish().unwrap(),
+        );
+    }
+    let url = common::http::serve(files);
+    Archives {
+        debian: format!("{url}/debian"),
+        security: format!("{url}/debian-security"),
+    }
+}
+
+fn manifest(host: &str, extra: &str) -> Manifest {
+    format!(
+        "[base]\nimage = \"{host}/rust\"\nsuffix = \"slim\"\n\
+         [rust]\ntargets = [\"wasm32-unknown-unknown\"]\n\
+         [entrypoint]\nbuilder = \"rust:1.82-slim-bullseye\"\n{extra}"
+    )
+    .parse()
+    .unwrap()
+}
+
+fn entry() -> Entry {
+    Entry {
+        trunk: "0.14.0".into(),
+        rust: "1.56".into(),
+    }
+}
+
+fn options(output: &Path, dist: &str, entrypoint: &Path) -> Options {
+    let source = output.parent().unwrap().join("source");
+    if !source.exists() {
+        common::git_checkout(&source);
+    }
+    Options {
+        output: output.into(),
+        name: "torhovland/rust-trunk:0.14.0-rust1.56".into(),
+        rust_dist: dist.into(),
+        crates_dl: format!("{dist}/crates"),
+        entrypoint_binary: Some(entrypoint.into()),
+        partial: false,
+        apt_archives: None,
+        source,
+    }
+}
+
+#[test]
+fn assembles_the_base_and_tool_layers_into_a_layout() {
+    let registry = common::registry::start();
+    push_base(&registry.host);
+    let (dist, lock) = serve_downloads();
+    let dir = tempfile::tempdir().unwrap();
+    let entrypoint = dir.path().join("trunk-docker");
+    fs::write(&entrypoint, b"entrypoint").unwrap();
+    let manifest = manifest(&registry.host, "");
+
+    let output = dir.path().join("layout");
+    let assembled = assemble::assemble(
+        &manifest,
+        &lock,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Amd64,
+        &options(&output, &dist, &entrypoint),
+    )
+    .unwrap();
+    assert!(assembled.skipped.is_empty());
+    assert_eq!(assembled.layers.len(), 4);
+    assert_eq!(
+        assembled.sboms[&sbom::Format::Spdx],
+        dir.path().join("layout.spdx.json")
+    );
+
+    let layout = Layout::open(&output).unwrap();
+    let (descriptor, image) = layout.image().unwrap();
+    assert_eq!(descriptor.digest, assembled.manifest.digest);
+    let artifacts: Vec<_> = layout.index.manifests[1..]
+        .iter()
+        .map(|d| d.artifact_type.as_deref().unwrap())
+        .collect();
+    assert_eq!(
+        artifacts,
+        [
+            "application/vnd.cyclonedx+json",
+            "application/spdx+json",
+            "application/vnd.in-toto+json"
+        ]
+    );
+    assert_eq!(assembled.provenance, dir.path().join("layout.intoto.json"));
+    assert_eq!(
+        layout.index.manifests[0].platform,
+        Some(Platform::Amd64.into())
+    );
+    assert_eq!(
+        image.annotations["org.opencontainers.image.base.name"],
+        format!("{}/rust:1.56-slim", registry.host)
+    );
+    let config: serde_json::Value = layout.json(&image.config.digest).unwrap();
+    assert_eq!(config["rootfs"]["diff_ids"].as_array().unwrap().len(), 4);
+    assert_eq!(config["history"].as_array().unwrap().len(), 4);
+    assert_eq!(
+        config["config"]["Entrypoint"],
+        json!(["trunk-docker", "entrypoint"])
+    );
+
+    let blobs: Vec<Vec<u8>> = image
+        .layers
+        .iter()
+        .map(|layer| layout.blob(&layer.digest).unwrap())
+        .collect();
+    let layers: Vec<&[u8]> = blobs.iter().map(Vec::as_slice).collect();
+    let read = |path: &str| layer::read_file(&layers, path).unwrap();
+    assert_eq!(
+        read("/usr/local/cargo/bin/trunk").as_deref(),
+        Some(&b"trunk 0.14.0"[..])
+    );
+    assert_eq!(
+        read("/usr/local/bin/trunk-docker").as_deref(),
+        Some(&b"entrypoint"[..])
+    );
+    assert_eq!(
+        read(&format!(
+            "{TOOLCHAIN}/lib/rustlib/wasm32-unknown-unknown/lib/libstd.rlib"
+        ))
+        .as_deref(),
+        Some(&b"std"[..])
+    );
+    assert_eq!(
+        read(&format!("{TOOLCHAIN}/lib/rustlib/components")).as_deref(),
+        Some(
+            &b"rustc\ncargo\nrust-std-x86_64-unknown-linux-gnu\nrust-std-wasm32-unknown-unknown\n"
+                [..]
+        )
+    );
+
+    let tarball = dir.path().join("image.tar");
+    let again = assemble::assemble(
+        &manifest,
+        &lock,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Amd64,
+        &options(&tarball, &dist, &entrypoint),
+    )
+    .unwrap();
+    assert_eq!(again.manifest, assembled.manifest);
+    assert!(!dir.path().join("image.layout").exists());
+    let mut archive = tar::Archive::new(fs::File::open(&tarball).unwrap());
+    let entries: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().display().to_string())
+        .collect();
+    assert_eq!(entries[..2], ["oci-layout", "index.json"]);
+    let manifest_path = format!("blobs/sha256/{}", &again.manifest.digest[7..]);
+    assert!(entries.contains(&manifest_path));
+}
+
+#[test]
+fn assembles_the_default_manifest_without_partial() {
+    let registry = common::registry::start();
+    push_base(&registry.host);
+    let (dist, lock) = serve_downloads();
+    let archives = serve_debian();
+    let dir = tempfile::tempdir().unwrap();
+    let entrypoint = dir.path().join("trunk-docker");
+    fs::write(&entrypoint, b"entrypoint").unwrap();
+
+    // The committed manifest, with its downloads served locally.
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut manifest = Manifest::load(&root.join("image.toml")).unwrap();
+    manifest.base.image = format!("{}/rust", registry.host);
+    let binaryen = manifest.binaryen.as_mut().unwrap();
+    binaryen.release_url = Some(format!("{dist}/binaryen-{{version}}-{{machine}}.tar.gz"));
+    manifest.wasm_bindgen.release_url =
+        Some(format!("{dist}/wasm-bindgen-{{version}}-{{target}}.tar.gz"));
+    let (_, index) = registry.manifest("rust", "1.56-slim").unwrap();
+    let wasm_bindgen: String = manifest
+        .wasm_bindgen
+        .versions
+        .iter()
+        .map(|version| {
+            let url = format!("{dist}/wasm-bindgen-{version}-x86_64-unknown-linux-musl.tar.gz");
+            let sha256 = trunk::sha256_hex(&trunk_docker::http::get(&url).unwrap().unwrap());
+            format!(
+                "[wasm-bindgen.\"{version}\".x86_64-unknown-linux-musl]\n\
+                 url = \"{url}\"\nsha256 = \"{sha256}\"\n"
+            )
+        })
+        .collect();
+    let image_lock: ImageLock = format!(
+        "[base.\"{}/rust:1.56-slim\"]\ndigest = \"{}\"\nsuite = \"bullseye\"\n\
+         [apt.\"{}\".bullseye.amd64]\n\
+         build-essential = \"12.9\"\ncurl = \"7.74.0-1.3\"\ngit = \"1:2.30.2-1+deb11u2\"\n\
+         libssl-dev = \"1.1.1w-0+deb11u1\"\npkg-config = \"0.29.2-1\"\n{wasm_bindgen}",
+        registry.host,
+        oci::digest(&index),
+        manifest.apt.snapshot.as_ref().unwrap(),
+    )
+    .parse()
+    .unwrap();
+    let mut options = options(&dir.path().join("layout"), &dist, &entrypoint);
+    options.apt_archives = Some(archives);
+
+    let assembled = assemble::assemble(
+        &manifest,
+        &lock,
+        &image_lock,
+        &entry(),
+        Platform::Amd64,
+        &options,
+    )
+    .unwrap();
+    assert!(assembled.skipped.is_empty(), "{:?}", assembled.skipped);
+    // The base, then trunk, the apt packages, binaryen, two wasm-bindgen-cli
+    // versions, rust-std and the entrypoint.
+    assert_eq!(assembled.layers.len(), 8);
+
+    let layout = Layout::open(&options.output).unwrap();
+    let (_, image) = layout.image().unwrap();
+    let blobs: Vec<Vec<u8>> = image
+        .layers
+        .iter()
+        .map(|layer| layout.blob(&layer.digest).unwrap())
+        .collect();
+    let layers: Vec<&[u8]> = blobs.iter().map(Vec::as_slice).collect();
+    let read = |path: &str| {
+        let contents = layer::read_file(&layers, path).unwrap();
+        String::from_utf8(contents.unwrap_or_default()).unwrap()
+    };
+    assert_eq!(read("/usr/local/cargo/bin/trunk"), "trunk 0.14.0");
+    assert_eq!(read("/usr/local/bin/wasm-opt"), "wasm-opt 105");
+    assert_eq!(
+        read("/usr/local/wasm-bindgen/0.2.79/bin/wasm-bindgen"),
+        "wasm-bindgen 0.2.79"
+    );
+    // The locked curl, not the newer one in the security archive.
+    assert_eq!(read("/usr/bin/curl"), "curl");
+    assert_eq!(read("/usr/bin/git"), "git");
+    assert_eq!(read("/usr/bin/debconf"), "debconf");
+    // Merged /usr: /bin stays a link, and what make packages there is in
+    // /usr/bin.
+    assert_eq!(read("/usr/bin/make"), "make");
+    assert_eq!(
+        layer::read_link(&layers, "/bin").unwrap().as_deref(),
+        Some("usr/bin")
+    );
+    assert_eq!(
+        layer::read_link(&layers, "/usr/lib/x86_64-linux-gnu/libcurl.so.4")
+            .unwrap()
+            .as_deref(),
+        Some("libcurl.so.4.7.0")
+    );
+    assert_eq!(
+        read("/var/lib/dpkg/info/libcurl4:amd64.list"),
+        "/usr/lib/x86_64-linux-gnu/libcurl.so.4.7.0\n/usr/lib/x86_64-linux-gnu/libcurl.so.4\n"
+    );
+
+    let status = read("/var/lib/dpkg/status");
+    assert!(status.starts_with(STATUS), "{status}");
+    assert!(status.contains("Package: curl\nStatus: install ok installed\nVersion: 7.74.0-1.3\n"));
+    let installed: Vec<&str> = status
+        .lines()
+        .filter_map(|line| line.strip_prefix("Package: "))
+        .collect();
+    assert_eq!(
+        installed,
+        [
+            "libc6",
+            "perl-base",
+            "gcc",
+            "libc6-dev",
+            "libssl1.1",
+            "build-essential",
+            "cdebconf",
+            "curl",
+            "git",
+            "git-man",
+            "libcurl4",
+            "libssl-dev",
+            "make",
+            "pkg-config"
+        ]
+    );
+
+    let spdx = fs::read_to_string(&assembled.sboms[&sbom::Format::Spdx]).unwrap();
+    assert!(spdx.contains("pkg:deb/debian/curl@7.74.0-1.3?arch=amd64&distro=bullseye"));
+    assert!(spdx.contains("pkg:cargo/wasm-bindgen-cli@0.2.78"));
+    let provenance = fs::read_to_string(&assembled.provenance).unwrap();
+    assert!(provenance.contains("/pool/main/curl_7.74.0-1.3_amd64.deb"));
+}
+
+#[test]
+fn refuses_steps_that_need_docker_unless_partial() {
+    let registry = common::registry::start();
+    push_base(&registry.host);
+    let (dist, lock) = serve_downloads();
+    let dir = tempfile::tempdir().unwrap();
+    let entrypoint = dir.path().join("trunk-docker");
+    fs::write(&entrypoint, b"entrypoint").unwrap();
+    let manifest = manifest(&registry.host, "[apt]\npackages = [\"git\"]");
+    let mut options = options(&dir.path().join("layout"), &dist, &entrypoint);
+
+    let err = assemble::assemble(
+        &manifest,
+        &lock,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Amd64,
+        &options,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("apt-get install git"), "{err}");
+
+    options.partial = true;
+    let assembled = assemble::assemble(
+        &manifest,
+        &lock,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Amd64,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(assembled.skipped, ["apt-get install git"]);
+}
+
+#[test]
+fn fails_when_the_base_has_no_image_for_the_platform() {
+    let registry = common::registry::start();
+    push_base(&registry.host);
+    let dir = tempfile::tempdir().unwrap();
+    common::git_checkout(dir.path());
+    let manifest: Manifest = format!(
+        "[base]\nimage = \"{}/rust\"\nsuffix = \"slim\"",
+        registry.host
+    )
+    .parse()
+    .unwrap();
+    let err = assemble::assemble(
+        &manifest,
+        &TrunkLock::default(),
+        &ImageLock::default(),
+        &entry(),
+        Platform::Arm64,
+        &Options {
+            output: dir.path().join("layout"),
+            name: "rust-trunk".into(),
+            rust_dist: "http://127.0.0.1:1".into(),
+            crates_dl: "http://127.0.0.1:1".into(),
+            entrypoint_binary: None,
+            partial: true,
+            apt_archives: None,
+            source: dir.path().into(),
+        },
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("has no linux/arm64 image"),
+        "{err}"
+    );
+}
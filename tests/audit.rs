@@ -0,0 +1,184 @@
+mod common;
+
+use std::{collections::HashMap, fs, path::Path};
+
+use trunk_docker::{
+    audit::{self, Advisory, Database, Kind, Policy},
+    layer::LayerBuilder,
+    manifest::Manifest,
+    matrix::Entry,
+    sbom,
+};
+
+const TRUNK_LOCK: &[u8] = br#"
+[[package]]
+name = "ansi_term"
+version = "0.12.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "anyhow"
+version = "1.0.44"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "time"
+version = "0.1.43"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "trunk"
+version = "0.14.0"
+dependencies = [
+ "ansi_term",
+ "anyhow",
+ "time",
+]
+"#;
+
+/// Fixture advisories; the IDs are made up so they can't be mistaken for
+/// real ones.
+const ADVISORIES: &[(&str, &str)] = &[
+    (
+        "time/RUSTSEC-2099-0001.md",
+        "```toml\n[advisory]\nid = \"RUSTSEC-2099-0001\"\npackage = \"time\"\n\
+         date = \"2099-01-01\"\nurl = \"https://example.com/time\"\n\n\
+         [versions]\npatched = [\">= 0.2.23\"]\nunaffected = [\"= 0.2.0\"]\n```\n\n\
+         # Segfault in the time crate\n\nDescription.\n",
+    ),
+    (
+        "anyhow/RUSTSEC-2099-0002.md",
+        "```toml\n[advisory]\nid = \"RUSTSEC-2099-0002\"\npackage = \"anyhow\"\n\
+         date = \"2099-01-02\"\n\n[versions]\npatched = [\">= 1.0.0\"]\n```\n\n\
+         # Fixed long ago\n",
+    ),
+    (
+        "anyhow/RUSTSEC-2099-0003.md",
+        "```toml\n[advisory]\nid = \"RUSTSEC-2099-0003\"\npackage = \"anyhow\"\n\
+         date = \"2099-01-03\"\nwithdrawn = \"2099-02-01\"\n\n[versions]\npatched = []\n```\n\n\
+         # Withdrawn\n",
+    ),
+    (
+        "ansi_term/RUSTSEC-2099-0004.md",
+        "```toml\n[advisory]\nid = \"RUSTSEC-2099-0004\"\npackage = \"ansi_term\"\n\
+         date = \"2099-01-04\"\ninformational = \"unmaintained\"\n\n[versions]\npatched = []\n```\n\n\
+         # ansi_term is unmaintained\n",
+    ),
+];
+
+fn write_database(dir: &Path) {
+    for (path, text) in ADVISORIES {
+        let path = dir.join("crates").join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    fs::write(dir.join("README.md"), "# RustSec Advisory Database\n").unwrap();
+}
+
+/// Serves trunk 0.14.0 with its lockfile, and wasm-bindgen-cli 0.2.78
+/// without one.
+fn serve_crates() -> String {
+    let mut trunk = LayerBuilder::new();
+    trunk.file("trunk-0.14.0/Cargo.lock", 0o644, TRUNK_LOCK.to_vec());
+    let mut wasm_bindgen = LayerBuilder::new();
+    wasm_bindgen.file("wasm-bindgen-cli-0.2.78/Cargo.toml", 0o644, Vec::new());
+    common::http::serve(HashMap::from([
+        (
+            "/trunk/trunk-0.14.0.crate".to_string(),
+            trunk.finish().unwrap().blob,
+        ),
+        (
+            "/wasm-bindgen-cli/wasm-bindgen-cli-0.2.78.crate".to_string(),
+            wasm_bindgen.finish().unwrap().blob,
+        ),
+    ]))
+}
+
+fn manifest(audit: &str) -> Manifest {
+    format!("[base]\nimage = \"rust\"\n[wasm-bindgen]\nversions = [\"0.2.78\"]\n{audit}")
+        .parse()
+        .unwrap()
+}
+
+fn run(manifest: &Manifest) -> audit::Report {
+    let dir = tempfile::tempdir().unwrap();
+    write_database(dir.path());
+    let database = Database::open(dir.path()).unwrap();
+    let entries = [
+        Entry {
+            trunk: "0.14.0".into(),
+            rust: "1.56".into(),
+        },
+        Entry {
+            trunk: "0.14.0".into(),
+            rust: "1.55".into(),
+        },
+    ];
+    let tools: Vec<_> = entries
+        .iter()
+        .flat_map(|entry| sbom::tools(manifest, entry))
+        .collect();
+    audit::audit(&database, &tools, &serve_crates(), &manifest.audit).unwrap()
+}
+
+#[test]
+fn parses_advisory_front_matter() {
+    let advisory: Advisory = ADVISORIES[0].1.parse().unwrap();
+    assert_eq!(advisory.id, "RUSTSEC-2099-0001");
+    assert_eq!(advisory.title, "Segfault in the time crate");
+    assert_eq!(advisory.kind, Kind::Vulnerability);
+    assert!(advisory.affects(&"0.1.43".parse().unwrap()));
+    assert!(!advisory.affects(&"0.2.0".parse().unwrap()));
+    assert!(!advisory.affects(&"0.3.5".parse().unwrap()));
+    assert!("# No front matter".parse::<Advisory>().is_err());
+}
+
+#[test]
+fn reports_advisories_against_the_locked_crates() {
+    let report = run(&manifest(""));
+    assert_eq!(report.tools, ["trunk 0.14.0", "wasm-bindgen-cli 0.2.78"]);
+    assert_eq!(report.unlocked, ["wasm-bindgen-cli 0.2.78"]);
+    assert_eq!(report.crates, 5);
+    common::assert_snapshot("audit.txt", &report.to_string());
+
+    let findings: Vec<_> = report
+        .findings
+        .iter()
+        .map(|f| (f.advisory.as_str(), f.kind, f.denied))
+        .collect();
+    assert_eq!(
+        findings,
+        [
+            ("RUSTSEC-2099-0001", Kind::Vulnerability, true),
+            ("RUSTSEC-2099-0004", Kind::Unmaintained, false),
+        ]
+    );
+    let err = report.check().unwrap_err().to_string();
+    assert!(err.contains("RUSTSEC-2099-0001 (time 0.1.43)"), "{err}");
+}
+
+#[test]
+fn policy_decides_what_fails_the_release() {
+    let ignored = run(&manifest("[audit]\nignore = [\"RUSTSEC-2099-0001\"]"));
+    assert!(ignored.findings[0].ignored);
+    ignored.check().unwrap();
+
+    let strict = run(&manifest(
+        "[audit]\ndeny = [\"vulnerability\", \"unmaintained\"]\nignore = [\"RUSTSEC-2099-0001\"]",
+    ));
+    let err = strict.check().unwrap_err().to_string();
+    assert!(
+        err.contains("RUSTSEC-2099-0004 (ansi_term 0.12.1)"),
+        "{err}"
+    );
+    assert!(!err.contains("RUSTSEC-2099-0001"), "{err}");
+}
+
+#[test]
+fn defaults_to_denying_vulnerabilities() {
+    assert_eq!(manifest("").audit, Policy::default());
+    let typo = "[base]\nimage = \"rust\"\n[audit]\nignore = [\"RUSTSEC-2099-01\"]";
+    assert!(typo.parse::<Manifest>().is_err());
+    let unknown = "[base]\nimage = \"rust\"\n[audit]\ndeny = [\"yanked\"]";
+    assert!(unknown.parse::<Manifest>().is_err());
+}
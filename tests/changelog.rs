@@ -0,0 +1,144 @@
+mod common;
+
+use std::{collections::BTreeSet, path::Path};
+
+use trunk_docker::{
+    changelog,
+    oci::Layout,
+    pipeline::BuildPlan,
+    registry::Registry,
+    sbom::{self, Component, Format, Inventory, Kind},
+    target::Target,
+};
+
+fn component(kind: Kind, purl: &str) -> Component {
+    let (name, version) = purl.rsplit_once('/').unwrap().1.split_once('@').unwrap();
+    let version = version.split('?').next().unwrap();
+    Component {
+        kind,
+        name: name.into(),
+        version: version.into(),
+        purl: purl.into(),
+        sha256: None,
+        depends_on: BTreeSet::new(),
+    }
+}
+
+/// A CycloneDX SBOM of an image with `components`.
+fn sbom(components: Vec<Component>) -> String {
+    Inventory {
+        image: "rust-trunk".into(),
+        digest: "sha256:00".into(),
+        created: "2022-01-01T00:00:00Z".into(),
+        components,
+    }
+    .render(Format::CycloneDx)
+}
+
+fn old_components() -> Vec<Component> {
+    vec![
+        component(
+            Kind::Debian,
+            "pkg:deb/debian/curl@7.74.0-1.3+deb11u1?arch=amd64",
+        ),
+        component(Kind::Debian, "pkg:deb/debian/git@1:2.30.2-1?arch=amd64"),
+        component(Kind::Release, "pkg:generic/rust@1.55.0"),
+        component(Kind::Tool, "pkg:cargo/trunk@0.13.1"),
+        component(Kind::Crate, "pkg:cargo/anyhow@1.0.51"),
+        component(Kind::Crate, "pkg:cargo/serde@1.0.130"),
+    ]
+}
+
+fn new_components() -> Vec<Component> {
+    vec![
+        component(
+            Kind::Debian,
+            "pkg:deb/debian/curl@7.74.0-1.3+deb11u2?arch=amd64",
+        ),
+        component(Kind::Debian, "pkg:deb/debian/libssl1.1@1.1.1n-0?arch=amd64"),
+        component(Kind::Release, "pkg:generic/rust@1.56.1"),
+        component(Kind::Release, "pkg:github/WebAssembly/binaryen@version_105"),
+        component(Kind::Tool, "pkg:cargo/trunk@0.14.0"),
+        component(Kind::Crate, "pkg:cargo/anyhow@1.0.51"),
+        component(Kind::Crate, "pkg:cargo/serde@1.0.130"),
+        component(Kind::Crate, "pkg:cargo/serde@1.0.136"),
+    ]
+}
+
+/// Pushes an image under `tag`, with an SBOM of `components` attached
+/// unless there are none.
+fn publish(target: &Target, tag: &str, components: Vec<Component>) {
+    let dir = tempfile::tempdir().unwrap();
+    common::write_image(dir.path(), &[("/etc/hostname", b"old")], &[]);
+    let mut registry = Registry::for_target(target);
+    let descriptor = registry
+        .push_layout(&target.name(), tag, &Layout::open(dir.path()).unwrap())
+        .unwrap();
+    if !components.is_empty() {
+        let artifact = sbom::artifact(Format::CycloneDx, sbom(components).as_bytes(), &descriptor);
+        registry
+            .attach(&target.name(), &descriptor, &artifact)
+            .unwrap();
+    }
+}
+
+/// Plans a pushed build to `host`, with the SBOM of its image written.
+fn plan(host: &str, out_dir: &Path) -> BuildPlan {
+    let mut plan = common::push_plan(host, out_dir);
+    let path = out_dir.join("sbom.cdx.json");
+    std::fs::write(&path, sbom(new_components())).unwrap();
+    plan.images[0].platforms[0]
+        .sboms
+        .insert(Format::CycloneDx, path);
+    plan
+}
+
+#[test]
+fn describes_what_changed_since_the_replaced_image() {
+    let registry = common::registry::start();
+    let target: Target = format!("{}/rust-trunk", registry.host).parse().unwrap();
+    publish(&target, "latest", old_components());
+    let dir = tempfile::tempdir().unwrap();
+    let entries = plan(&registry.host, dir.path()).changelog().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].tag, "0.14.0-rust1.56");
+    assert_eq!(
+        entries[0].previous.as_ref().unwrap().image,
+        target.reference("latest")
+    );
+    common::assert_snapshot(
+        "changelog.md",
+        &entries[0].to_string().replace(&registry.host, "registry"),
+    );
+
+    let paths = changelog::write(&dir.path().join("changelog"), &entries).unwrap();
+    assert_eq!(paths, [dir.path().join("changelog/0.14.0-rust1.56.md")]);
+    assert_eq!(
+        std::fs::read_to_string(&paths[0]).unwrap(),
+        entries[0].to_string()
+    );
+}
+
+#[test]
+fn says_when_there_is_nothing_to_compare_with() {
+    let registry = common::registry::start();
+    let target: Target = format!("{}/rust-trunk", registry.host).parse().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let plan = plan(&registry.host, dir.path());
+
+    let first = plan.changelog().unwrap().remove(0);
+    assert!(first.previous.is_none());
+    let text = first.to_string();
+    assert!(text.contains("- trunk 0.14.0\n- Rust 1.56.1\n"), "{text}");
+    assert!(
+        text.contains("nothing was published under these tags for linux/amd64"),
+        "{text}"
+    );
+
+    publish(&target, "latest", Vec::new());
+    let text = plan.changelog().unwrap()[0].to_string();
+    assert!(
+        text.contains("rust-trunk:latest` has no SBOM attached"),
+        "{text}"
+    );
+}
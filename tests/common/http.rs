@@ -0,0 +1,107 @@
+//! A minimal HTTP server standing in for release download hosts and
+//! registries.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Serves `files` (path → body) on a local port until the test process
+/// exits, and returns the base URL. Unknown paths get a 404.
+pub fn serve(files: HashMap<String, Vec<u8>>) -> String {
+    serve_with(move |request| match files.get(&request.path) {
+        Some(body) => Response::new(200).body(body.clone()),
+        None => Response::new(404),
+    })
+}
+
+/// Answers every request with `handler` on a local port until the test
+/// process exits, and returns the base URL.
+pub fn serve_with(handler: impl Fn(Request) -> Response + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or("GET").to_string();
+            let path = parts.next().unwrap_or("/").to_string();
+            let mut headers = HashMap::new();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+                }
+            }
+            let length = headers
+                .get("content-length")
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let head = method == "HEAD";
+            let response = handler(Request {
+                method,
+                path,
+                headers,
+                body,
+            });
+            write!(
+                stream,
+                "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n",
+                response.status,
+                response.body.len()
+            )
+            .unwrap();
+            for (name, value) in &response.headers {
+                write!(stream, "{name}: {value}\r\n").unwrap();
+            }
+            write!(stream, "\r\n").unwrap();
+            if !head {
+                stream.write_all(&response.body).unwrap();
+            }
+        }
+    });
+    url
+}
@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+
+pub mod http;
+pub mod registry;
+
+use std::{fs, path::Path};
+
+use trunk_docker::{
+    manifest::Manifest,
+    matrix::Matrix,
+    pipeline::{self, BuildPlan, Options},
+    trunk::TrunkLock,
+};
+
+/// Compares `actual` with `tests/snapshots/<name>`, rewriting the snapshot
+/// instead when `UPDATE_SNAPSHOTS` is set.
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}; rerun with UPDATE_SNAPSHOTS=1",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "snapshot {name} is out of date; rerun with UPDATE_SNAPSHOTS=1\n--- expected\n{expected}\n--- actual\n{actual}"
+    );
+}
+
+/// Writes an OCI image layout holding one image with a single `layer`,
+/// like `docker buildx build --output type=oci,tar=false` does, and
+/// returns the manifest's digest.
+pub fn write_layout(dir: &Path, layer: &[u8]) -> String {
+    write_layout_with_config(dir, br#"{"architecture":"amd64","os":"linux"}"#, layer)
+}
+
+/// Writes an OCI image layout of an image with `files` in one layer and
+/// `env` in its config, and returns the manifest's digest.
+pub fn write_image(dir: &Path, files: &[(&str, &[u8])], env: &[&str]) -> String {
+    let mut builder = trunk_docker::layer::LayerBuilder::new();
+    for (path, contents) in files {
+        builder.file(path, 0o644, contents.to_vec());
+    }
+    let layer = builder.finish().unwrap();
+    let config = serde_json::to_vec(&serde_json::json!({
+        "architecture": "amd64",
+        "os": "linux",
+        "created": "2021-11-01T00:00:00Z",
+        "config": { "Env": env },
+        "rootfs": { "type": "layers", "diff_ids": [layer.diff_id] },
+    }))
+    .unwrap();
+    write_layout_with_config(dir, &config, &layer.blob)
+}
+
+fn write_layout_with_config(dir: &Path, config: &[u8], layer: &[u8]) -> String {
+    use trunk_docker::oci::{self, Descriptor, ImageManifest, Index, MANIFEST_MEDIA_TYPE};
+
+    let write_blob = |bytes: &[u8]| {
+        let digest = oci::digest(bytes);
+        let path = dir.join("blobs/sha256").join(&digest["sha256:".len()..]);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+    };
+    write_blob(config);
+    write_blob(layer);
+    let manifest = oci::to_canonical_json(&ImageManifest {
+        schema_version: 2,
+        media_type: Some(MANIFEST_MEDIA_TYPE.into()),
+        artifact_type: None,
+        config: Descriptor::of(oci::CONFIG_MEDIA_TYPE, config),
+        layers: vec![Descriptor::of(oci::LAYER_MEDIA_TYPE, layer)],
+        subject: None,
+        annotations: Default::default(),
+    });
+    write_blob(&manifest);
+    let descriptor = Descriptor::of(MANIFEST_MEDIA_TYPE, &manifest);
+    let index = oci::to_canonical_json(&Index::new(vec![descriptor.clone()]));
+    fs::write(dir.join("index.json"), index).unwrap();
+    fs::write(dir.join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#).unwrap();
+    descriptor.digest
+}
+
+/// Plans a build of every entry of `matrix` on the plain `rust` base,
+/// with nothing in `trunk.lock`.
+pub fn plan(matrix: &Matrix, out_dir: &Path, push: bool) -> BuildPlan {
+    let manifest: Manifest = "[base]\nimage = \"rust\"".parse().unwrap();
+    let options = Options {
+        context: ".".into(),
+        out_dir: out_dir.into(),
+        push,
+        crates_dl: "http://127.0.0.1:1".into(),
+    };
+    pipeline::plan(
+        matrix,
+        &manifest,
+        &TrunkLock::default(),
+        &matrix.entries(),
+        &options,
+    )
+    .unwrap()
+}
+
+/// Plans a pushed build of trunk 0.14.0 on Rust 1.56 to `host/rust-trunk`.
+pub fn push_plan(host: &str, out_dir: &Path) -> BuildPlan {
+    let mut matrix: Matrix = "targets = [\"r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]"
+        .parse()
+        .unwrap();
+    matrix.retarget(vec![format!("{host}/rust-trunk").parse().unwrap()]);
+    plan(&matrix, out_dir, true)
+}
+
+/// Makes `dir` a git checkout with one commit, and returns its revision.
+pub fn git_checkout(dir: &Path) -> String {
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("image.toml"), "[base]\nimage = \"rust\"\n").unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-qm", "Initial commit"]);
+    git(&["rev-parse", "HEAD"])
+}
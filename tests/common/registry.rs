@@ -0,0 +1,201 @@
+//! An in-memory stand-in for a `registry:2` distribution API.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use super::http::{self, Request, Response};
+use trunk_docker::{
+    oci::{self, Index, Layout, INDEX_MEDIA_TYPE},
+    platform::Platform,
+    registry,
+    target::Target,
+};
+
+#[derive(Default)]
+pub struct Contents {
+    pub blobs: HashMap<String, Vec<u8>>,
+    /// `(name, reference)` → `(media type, bytes)`; manifests are stored
+    /// under their tag and their digest.
+    pub manifests: HashMap<(String, String), (String, Vec<u8>)>,
+}
+
+pub struct Registry {
+    /// `host:port`, usable as the registry part of a target.
+    pub host: String,
+    pub contents: Arc<Mutex<Contents>>,
+}
+
+impl Registry {
+    pub fn manifest(&self, name: &str, reference: &str) -> Option<(String, Vec<u8>)> {
+        let contents = self.contents.lock().unwrap();
+        contents
+            .manifests
+            .get(&(name.to_string(), reference.to_string()))
+            .cloned()
+    }
+}
+
+impl Registry {
+    /// Pushes an image index under `tag` in `rust`, with an image holding
+    /// `files` for every one of `platforms`, like the Rust base images.
+    pub fn push_base(&self, tag: &str, platforms: &[Platform], files: &[(&str, &[u8])]) {
+        let target: Target = format!("{}/rust", self.host).parse().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        super::write_image(dir.path(), files, &[]);
+        let mut registry = registry::Registry::for_target(&target);
+        let layout = Layout::open(dir.path()).unwrap();
+        let mut manifests = Vec::new();
+        for &platform in platforms {
+            let mut descriptor = registry
+                .push_layout(&target.name(), &format!("{tag}-{platform}"), &layout)
+                .unwrap()
+                .bare();
+            descriptor.platform = Some(platform.into());
+            manifests.push(descriptor);
+        }
+        let index = oci::to_canonical_json(&Index::new(manifests));
+        registry
+            .put_manifest(&target.name(), tag, INDEX_MEDIA_TYPE, &index)
+            .unwrap();
+    }
+}
+
+/// Starts a registry accepting anonymous pushes.
+pub fn start() -> Registry {
+    start_with_token(None)
+}
+
+/// Starts a registry that, given a token, challenges requests without it
+/// and hands it out at `/token`, like Docker Hub and GHCR do.
+pub fn start_with_token(token: Option<&'static str>) -> Registry {
+    start_with_auth(token.map(|token| (token, None)))
+}
+
+/// Starts a registry that hands out `token` at `/token` only to requests
+/// logged in as `user:password`.
+pub fn start_with_login(token: &'static str, login: &'static str) -> Registry {
+    start_with_auth(Some((token, Some(login))))
+}
+
+fn start_with_auth(auth: Option<(&'static str, Option<&'static str>)>) -> Registry {
+    let contents = Arc::new(Mutex::new(Contents::default()));
+    let shared = contents.clone();
+    let url = http::serve_with(move |request| handle(&shared, auth, request));
+    Registry {
+        host: url.trim_start_matches("http://").to_string(),
+        contents,
+    }
+}
+
+fn handle(
+    contents: &Mutex<Contents>,
+    auth: Option<(&str, Option<&str>)>,
+    request: Request,
+) -> Response {
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    if let Some((token, login)) = auth {
+        if path == "/token" {
+            if let Some(login) = login {
+                let basic = format!("Basic {}", BASE64.encode(login));
+                if request.headers.get("authorization") != Some(&basic) {
+                    return Response::new(401);
+                }
+            }
+            return Response::new(200).body(format!(r#"{{"token":"{token}"}}"#));
+        }
+        if request.headers.get("authorization") != Some(&format!("Bearer {token}")) {
+            let realm = format!("http://{}/token", request.headers["host"]);
+            return Response::new(401).header(
+                "WWW-Authenticate",
+                format!(r#"Bearer realm="{realm}",service="stand-in""#),
+            );
+        }
+    }
+    let Some(path) = path.strip_prefix("/v2/") else {
+        return Response::new(404);
+    };
+    let mut contents = contents.lock().unwrap();
+    if let Some(name) = path.strip_suffix("/blobs/uploads/") {
+        assert_eq!(request.method, "POST");
+        return Response::new(202).header("Location", format!("/v2/{name}/uploads/1"));
+    }
+    if path.contains("/uploads/") {
+        assert_eq!(request.method, "PUT");
+        let digest = query.strip_prefix("digest=").expect("upload has a digest");
+        if oci::digest(&request.body) != digest {
+            return Response::new(400).body("digest mismatch");
+        }
+        contents.blobs.insert(digest.to_string(), request.body);
+        return Response::new(201);
+    }
+    if let Some(name) = path.strip_suffix("/tags/list") {
+        return list_tags(&contents, name, query);
+    }
+    if let Some((_, digest)) = path.split_once("/blobs/") {
+        return match contents.blobs.get(digest) {
+            Some(blob) => Response::new(200).body(blob.clone()),
+            None => Response::new(404),
+        };
+    }
+    if let Some((name, reference)) = path.split_once("/manifests/") {
+        let key = (name.to_string(), reference.to_string());
+        if request.method == "PUT" {
+            let media_type = request.headers["content-type"].clone();
+            let digest = oci::digest(&request.body);
+            let manifest = (media_type, request.body);
+            contents
+                .manifests
+                .insert((name.to_string(), digest.clone()), manifest.clone());
+            contents.manifests.insert(key, manifest);
+            return Response::new(201).header("Docker-Content-Digest", digest);
+        }
+        return match contents.manifests.get(&key) {
+            Some((media_type, bytes)) => Response::new(200)
+                .header("Content-Type", media_type.clone())
+                .body(bytes.clone()),
+            None => Response::new(404),
+        };
+    }
+    Response::new(404)
+}
+
+/// Tags listed per page at most, few enough that clients have to follow
+/// the links to further pages.
+const TAG_PAGE: usize = 2;
+
+/// Lists the tags of `name` sorted, in pages of `n` after `last`, linking
+/// to the next page like Docker Hub does.
+fn list_tags(contents: &Contents, name: &str, query: &str) -> Response {
+    let param = |key: &str| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+    };
+    let mut tags: Vec<&str> = contents
+        .manifests
+        .keys()
+        .filter(|(n, reference)| n == name && !reference.contains(':'))
+        .map(|(_, tag)| tag.as_str())
+        .collect();
+    tags.sort_unstable();
+    if let Some(last) = param("last") {
+        tags.retain(|tag| *tag > last);
+    }
+    let n = param("n").map_or(TAG_PAGE, |n| n.parse::<usize>().unwrap().min(TAG_PAGE));
+    let mut response = Response::new(200);
+    if tags.len() > n {
+        tags.truncate(n);
+        response = response.header(
+            "Link",
+            format!(
+                "</v2/{name}/tags/list?n={n}&last={}>; rel=\"next\"",
+                tags[n - 1]
+            ),
+        );
+    }
+    response.body(serde_json::json!({ "name": name, "tags": tags }).to_string())
+}
@@ -0,0 +1,214 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use trunk_docker::{
+    diff::{Change, Diff, Snapshot},
+    layer::{self, LayerBuilder},
+    oci::Layout,
+    platform::Platform,
+    registry::Registry,
+    target::Target,
+};
+
+const REGISTRY: &str = "(registry+https://github.com/rust-lang/crates.io-index)";
+
+fn dpkg_status(packages: &[(&str, &str)]) -> Vec<u8> {
+    packages
+        .iter()
+        .map(|(name, version)| {
+            format!(
+                "Package: {name}\nStatus: install ok installed\n\
+                 Architecture: amd64\nVersion: {version}\n"
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .into_bytes()
+}
+
+fn crates_toml(crates: &[(&str, &str, &str)]) -> Vec<u8> {
+    let mut text = "[v1]\n".to_string();
+    for (name, version, bin) in crates {
+        text.push_str(&format!("\"{name} {version} {REGISTRY}\" = [\"{bin}\"]\n"));
+    }
+    text.into_bytes()
+}
+
+/// An image with trunk 0.13.1 and wasm-bindgen 0.2.78 on bullseye.
+fn write_old(dir: &std::path::Path) {
+    common::write_image(
+        dir,
+        &[
+            (
+                "/var/lib/dpkg/status",
+                &dpkg_status(&[("curl", "7.74.0-1.3+deb11u1"), ("git", "1:2.30.2-1")]),
+            ),
+            (
+                "/usr/local/cargo/.crates.toml",
+                &crates_toml(&[("trunk", "0.13.1", "trunk")]),
+            ),
+            ("/usr/local/cargo/bin/trunk", &[0; 300]),
+            ("/usr/local/cargo/bin/cargo", &[0; 100]),
+            (
+                "/usr/local/wasm-bindgen/0.2.78/.crates.toml",
+                &crates_toml(&[("wasm-bindgen-cli", "0.2.78", "wasm-bindgen")]),
+            ),
+        ],
+        &["RUST_VERSION=1.56.1", "TRUNK_VERSION=0.13.1"],
+    );
+}
+
+/// The next release: trunk 0.14.0, a second wasm-bindgen, curl patched,
+/// git dropped and wasm-opt added.
+fn write_new(dir: &std::path::Path) {
+    common::write_image(
+        dir,
+        &[
+            (
+                "/var/lib/dpkg/status",
+                &dpkg_status(&[("curl", "7.74.0-1.3+deb11u2"), ("libssl1.1", "1.1.1n-0")]),
+            ),
+            (
+                "/usr/local/cargo/.crates.toml",
+                &crates_toml(&[("trunk", "0.14.0", "trunk")]),
+            ),
+            ("/usr/local/cargo/bin/trunk", &[0; 320]),
+            ("/usr/local/cargo/bin/cargo", &[0; 100]),
+            ("/usr/local/cargo/bin/wasm-opt", &[0; 50]),
+            (
+                "/usr/local/wasm-bindgen/0.2.78/.crates.toml",
+                &crates_toml(&[("wasm-bindgen-cli", "0.2.78", "wasm-bindgen")]),
+            ),
+            (
+                "/usr/local/wasm-bindgen/0.2.79/.crates.toml",
+                &crates_toml(&[("wasm-bindgen-cli", "0.2.79", "wasm-bindgen")]),
+            ),
+        ],
+        &[
+            "RUST_VERSION=1.56.1",
+            "TRUNK_VERSION=0.14.0",
+            "WASM_OPT=/usr/local/cargo/bin/wasm-opt",
+        ],
+    );
+}
+
+#[test]
+fn lists_files_as_the_container_sees_them() {
+    let base = LayerBuilder::new()
+        .file("/usr/local/cargo/bin/cargo", 0o755, vec![0; 10])
+        .file("/usr/local/cargo/bin/rustc", 0o755, vec![0; 20])
+        .file("/usr/local/cargo/bin/tools/a", 0o755, vec![0; 1])
+        .finish()
+        .unwrap();
+    let top = LayerBuilder::new()
+        .file("/usr/local/cargo/bin/.wh.rustc", 0o644, Vec::new())
+        .file("/usr/local/cargo/bin/tools/.wh..wh..opq", 0o644, Vec::new())
+        .file("/usr/local/cargo/bin/tools/b", 0o755, vec![0; 2])
+        .file("/usr/local/cargo/bin/cargo", 0o755, vec![0; 30])
+        .file("/usr/local/bin/other", 0o755, vec![0; 1])
+        .finish()
+        .unwrap();
+    let files = layer::list_files(&[&base.blob, &top.blob], "/usr/local/cargo/bin").unwrap();
+    assert_eq!(
+        files,
+        BTreeMap::from([
+            ("/usr/local/cargo/bin/cargo".to_string(), 30),
+            ("/usr/local/cargo/bin/tools/b".to_string(), 2),
+        ])
+    );
+}
+
+#[test]
+fn reports_what_changed_between_two_layouts() {
+    let (old, new) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    write_old(old.path());
+    write_new(new.path());
+    let old = Snapshot::of_layout(old.path()).unwrap();
+    let new = Snapshot::of_layout(new.path()).unwrap();
+    assert_eq!(new.crates["wasm-bindgen-cli"], "0.2.78, 0.2.79");
+
+    let diff = Diff::new(
+        &Snapshot {
+            image: "old".into(),
+            ..old.clone()
+        },
+        &Snapshot {
+            image: "new".into(),
+            ..new
+        },
+    );
+    common::assert_snapshot("diff.txt", &diff.to_string());
+    let json: serde_json::Value = serde_json::from_str(&diff.to_json()).unwrap();
+    assert_eq!(
+        json["packages"]["git"],
+        serde_json::json!({ "change": "removed", "old": "1:2.30.2-1" })
+    );
+    assert_eq!(
+        json["cargo_bin"]["trunk"],
+        serde_json::json!({ "change": "changed", "old": 300, "new": 320 })
+    );
+
+    let same = Diff::new(&old, &old);
+    assert!(same.is_empty());
+    assert!(same.to_string().contains("OS packages: unchanged"));
+}
+
+#[test]
+fn compares_alpine_packages() {
+    let apk = |curl: &str| format!("P:curl\nV:{curl}\nA:x86_64\n\nP:musl\nV:1.2.4-r2\nA:x86_64\n");
+    let (old, new) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    common::write_image(
+        old.path(),
+        &[("/lib/apk/db/installed", apk("8.5.0-r0").as_bytes())],
+        &[],
+    );
+    common::write_image(
+        new.path(),
+        &[("/lib/apk/db/installed", apk("8.9.1-r1").as_bytes())],
+        &[],
+    );
+    let old = Snapshot::of_layout(old.path()).unwrap();
+    assert_eq!(old.packages["musl"], "1.2.4-r2");
+    let diff = Diff::new(&old, &Snapshot::of_layout(new.path()).unwrap());
+    assert_eq!(
+        diff.packages,
+        BTreeMap::from([(
+            "curl".to_string(),
+            Change::Changed {
+                old: "8.5.0-r0".into(),
+                new: "8.9.1-r1".into()
+            }
+        )])
+    );
+}
+
+#[test]
+fn compares_a_layout_with_a_published_image() {
+    let registry = common::registry::start();
+    let target: Target = format!("{}/rust-trunk", registry.host).parse().unwrap();
+    let old = tempfile::tempdir().unwrap();
+    write_old(old.path());
+    Registry::for_target(&target)
+        .push_layout(&target.name(), "0.13.1", &Layout::open(old.path()).unwrap())
+        .unwrap();
+    let new = tempfile::tempdir().unwrap();
+    write_new(new.path());
+
+    let reference = target.reference("0.13.1");
+    let old = Snapshot::load(&reference, Platform::Amd64).unwrap();
+    let new = Snapshot::load(new.path().to_str().unwrap(), Platform::Amd64).unwrap();
+    assert_eq!(old.image, reference);
+    let diff = Diff::new(&old, &new);
+    assert_eq!(
+        diff.crates["trunk"],
+        Change::Changed {
+            old: "0.13.1".into(),
+            new: "0.14.0".into()
+        }
+    );
+    assert_eq!(diff.env.len(), 2);
+
+    let missing = Snapshot::load(&target.reference("0.12.0"), Platform::Amd64).unwrap_err();
+    assert!(missing.to_string().contains("does not exist"), "{missing}");
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::assert_snapshot;
+use trunk_docker::{
+    dockerfile::{self, TrunkSource},
+    lock::ImageLock,
+    manifest::Manifest,
+    matrix::{Entry, Matrix},
+    platform::Platform,
+    trunk::TrunkLock,
+};
+
+fn entry() -> Entry {
+    Entry {
+        trunk: "0.14.0".into(),
+        rust: "1.56".into(),
+    }
+}
+
+#[test]
+fn renders_the_default_image() {
+    let manifest: Manifest = r#"
+        [base]
+        image = "rust"
+        suffix = "slim"
+
+        [rust]
+        targets = ["wasm32-unknown-unknown"]
+
+        [apt]
+        packages = ["pkg-config", "curl", "git", "curl"]
+
+        [binaryen]
+        version = 105
+        sha256 = { x86_64 = "0000000000000000000000000000000000000000000000000000000000000000" }
+
+        [wasm-bindgen]
+        versions = ["0.2.78", "0.2.79"]
+
+        [entrypoint]
+        builder = "rust:1.82-slim-bullseye"
+    "#
+    .parse()
+    .unwrap();
+    assert_snapshot(
+        "default.Dockerfile",
+        &dockerfile::render(
+            &manifest,
+            &ImageLock::default(),
+            &entry(),
+            Platform::Amd64,
+            TrunkSource::Cargo,
+        ),
+    );
+    assert_snapshot(
+        "prebuilt-trunk.Dockerfile",
+        &dockerfile::render(
+            &manifest,
+            &ImageLock::default(),
+            &entry(),
+            Platform::Amd64,
+            TrunkSource::Prebuilt,
+        ),
+    );
+}
+
+#[test]
+fn renders_extra_cargo_tools_without_apt() {
+    let manifest: Manifest = r#"
+        [base]
+        image = "rust"
+
+        [[cargo]]
+        name = "wasm-bindgen-cli"
+        version = "0.2.78"
+
+        [[cargo]]
+        name = "cargo-watch"
+        version = "8.1.1"
+    "#
+    .parse()
+    .unwrap();
+    assert_snapshot(
+        "cargo-tools.Dockerfile",
+        &dockerfile::render(
+            &manifest,
+            &ImageLock::default(),
+            &entry(),
+            Platform::Amd64,
+            TrunkSource::Cargo,
+        ),
+    );
+}
+
+#[test]
+fn downloads_binaryen_for_the_platform() {
+    let manifest: Manifest = r#"
+        [base]
+        image = "rust"
+
+        [apt]
+        packages = ["curl"]
+
+        [binaryen]
+        version = 117
+    "#
+    .parse()
+    .unwrap();
+    let rendered = dockerfile::render(
+        &manifest,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Arm64,
+        TrunkSource::Cargo,
+    );
+    assert!(rendered.contains("binaryen-version_117-aarch64-linux.tar.gz.sha256"));
+}
+
+#[test]
+fn rejects_invalid_manifests() {
+    let bad_package = "[base]\nimage = \"rust\"\n[apt]\npackages = [\"git; rm -rf /\"]";
+    assert!(bad_package.parse::<Manifest>().is_err());
+    let trunk = "[base]\nimage = \"rust\"\n[[cargo]]\nname = \"trunk\"\nversion = \"0.14.0\"";
+    assert!(trunk.parse::<Manifest>().is_err());
+}
+
+#[test]
+fn binaryen_conflicts_with_the_apt_package() {
+    let both = "[base]\nimage = \"rust\"\n[apt]\npackages = [\"binaryen\", \"curl\"]\n[binaryen]\nversion = 105";
+    assert!(both.parse::<Manifest>().is_err());
+    let no_curl = "[base]\nimage = \"rust\"\n[binaryen]\nversion = 105";
+    assert!(no_curl.parse::<Manifest>().is_err());
+}
+
+#[test]
+fn checked_in_dockerfile_is_up_to_date() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let matrix = Matrix::load(&root.join("matrix.toml")).unwrap();
+    let manifest = Manifest::load(&root.join("image.toml")).unwrap();
+    let lock = TrunkLock::load(&root.join("trunk.lock")).unwrap();
+    let image_lock = ImageLock::load(&root.join("image.lock")).unwrap();
+    let entry = matrix.default_entry();
+    let platform = matrix.platforms[0];
+    let source = TrunkSource::for_entry(&manifest, &lock, &entry, platform);
+    let generated = dockerfile::render(&manifest, &image_lock, &entry, platform, source);
+    assert_eq!(
+        fs::read_to_string(root.join("Dockerfile")).unwrap(),
+        generated,
+        "Dockerfile is stale; run `cargo run -- generate`"
+    );
+}
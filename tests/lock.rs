@@ -0,0 +1,660 @@
+mod common;
+
+use std::{cmp::Ordering, collections::HashMap, fs, io::Write};
+
+use flate2::{write::GzEncoder, Compression};
+use trunk_docker::{
+    apt::{self, Archives},
+    assemble::{self, Options},
+    dockerfile::{self, TrunkSource},
+    layer::LayerBuilder,
+    lock::ImageLock,
+    manifest::Manifest,
+    matrix::{Entry, Matrix},
+    oci::{self, Layout},
+    platform::Platform,
+    provenance::BaseImage,
+    target::Target,
+    trunk::TrunkLock,
+};
+
+const SHA256: &str = "5e1bb4d5c6e8e0f4b0a8b7b0f4d5a3f3c2a2e1d0c9b8a7f6e5d4c3b2a1f0e9d8";
+
+const OS_RELEASE: &[u8] =
+    b"PRETTY_NAME=\"Debian GNU/Linux 11 (bullseye)\"\nVERSION_CODENAME=bullseye\nID=debian\n";
+
+fn packages_gz(packages: &[(&str, &str)]) -> Vec<u8> {
+    let text: Vec<String> = packages
+        .iter()
+        .map(|(name, version)| {
+            format!(
+                "Package: {name}\nVersion: {version}\nArchitecture: amd64\nDescription: {name}\n"
+            )
+        })
+        .collect();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.join("\n").as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Debian and security archives serving bullseye for amd64 and arm64, with
+/// the security fixes in bullseye-security and a stable update in
+/// bullseye-updates.
+fn mirror() -> Archives {
+    let release = packages_gz(&[
+        ("curl", "7.74.0-1.3"),
+        ("git", "1:2.30.2-1"),
+        ("pkg-config", "0.29.2-1"),
+    ]);
+    let updates = packages_gz(&[("pkg-config", "0.29.2-1+deb11u1")]);
+    let security = packages_gz(&[
+        ("curl", "7.74.0-1.3+deb11u1"),
+        ("git", "1:2.30.2-1+deb11u2"),
+    ]);
+    let mut files = HashMap::new();
+    for arch in ["amd64", "arm64"] {
+        for (path, packages) in [
+            ("debian/dists/bullseye", &release),
+            ("debian/dists/bullseye-updates", &updates),
+            ("debian-security/dists/bullseye-security", &security),
+        ] {
+            files.insert(
+                format!("/{path}/main/binary-{arch}/Packages.gz"),
+                packages.clone(),
+            );
+        }
+    }
+    let url = common::http::serve(files);
+    Archives {
+        debian: format!("{url}/debian"),
+        security: format!("{url}/debian-security"),
+    }
+}
+
+/// A crates.io index mirror listing trunk.
+fn index() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("tr/un")).unwrap();
+    fs::write(
+        dir.path().join("tr/un/trunk"),
+        format!(
+            "{{\"name\":\"trunk\",\"vers\":\"0.14.0\",\"deps\":[],\"cksum\":\"{SHA256}\",\"features\":{{}},\"yanked\":false}}\n\
+             {{\"name\":\"trunk\",\"vers\":\"0.15.0\",\"deps\":[],\"cksum\":\"{SHA256}\",\"features\":{{}},\"yanked\":true}}\n"
+        ),
+    )
+    .unwrap();
+    dir
+}
+
+fn inputs(host: &str, packages: &str) -> (Matrix, Manifest) {
+    let matrix = "targets = [\"r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]\n\
+                  platforms = [\"linux/amd64\", \"linux/arm64\"]"
+        .parse()
+        .unwrap();
+    let manifest = format!(
+        "[base]\nimage = \"{host}/rust\"\nsuffix = \"slim\"\n[apt]\npackages = [{packages}]\n\
+         snapshot = \"20220301T000000Z\""
+    )
+    .parse()
+    .unwrap();
+    (matrix, manifest)
+}
+
+fn entry() -> Entry {
+    Entry {
+        trunk: "0.14.0".into(),
+        rust: "1.56".into(),
+    }
+}
+
+#[test]
+fn reads_package_versions_from_a_mirror() {
+    let mirror = mirror();
+    let packages = apt::packages(&mirror.debian, "bullseye", Platform::Arm64).unwrap();
+    assert_eq!(packages["git"], ["1:2.30.2-1"]);
+    assert_eq!(packages["curl"], ["7.74.0-1.3"]);
+    let err = apt::packages(&mirror.debian, "bookworm", Platform::Amd64).unwrap_err();
+    assert!(err.to_string().contains("no package index"), "{err}");
+
+    let available = apt::available(&mirror, "bullseye", Platform::Arm64).unwrap();
+    assert_eq!(available["git"], ["1:2.30.2-1", "1:2.30.2-1+deb11u2"]);
+    assert_eq!(available["pkg-config"], ["0.29.2-1", "0.29.2-1+deb11u1"]);
+    let err = apt::available(&mirror, "bookworm", Platform::Amd64).unwrap_err();
+    assert!(err.to_string().contains("no package index"), "{err}");
+}
+
+#[test]
+fn orders_debian_versions_like_dpkg() {
+    for (older, newer) in [
+        ("7.74.0-1.3", "7.74.0-1.3+deb11u1"),
+        ("1:2.30.2-1", "1:2.30.2-1+deb11u2"),
+        ("2.30.2-9", "1:2.0-1"),
+        ("1.0~rc1-1", "1.0-1"),
+        ("1.9-1", "1.10-1"),
+        ("1.0-1", "1.0a-1"),
+        ("1.0", "1.0-1"),
+    ] {
+        assert_eq!(apt::compare_versions(older, newer), Ordering::Less);
+        assert_eq!(apt::compare_versions(newer, older), Ordering::Greater);
+    }
+    assert_eq!(apt::compare_versions("0:1.01-1", "1.1-1"), Ordering::Equal);
+}
+
+#[test]
+fn builds_from_the_locked_inputs() {
+    let registry = common::registry::start();
+    let both = [Platform::Amd64, Platform::Arm64];
+    registry.push_base("1.56-slim", &both, &[("/usr/lib/os-release", OS_RELEASE)]);
+    let (matrix, manifest) = inputs(&registry.host, "\"git\", \"curl\"");
+    let index = index();
+
+    let lock = ImageLock::resolve(
+        &matrix,
+        &manifest,
+        index.path().to_str().unwrap(),
+        Some(&mirror()),
+        None,
+    )
+    .unwrap();
+    let reference = format!("{}/rust:1.56-slim", registry.host);
+    let (_, bytes) = registry.manifest("rust", "1.56-slim").unwrap();
+    let digest = oci::digest(&bytes);
+    assert_eq!(lock.base[&reference].digest, digest);
+    assert_eq!(lock.base[&reference].suite.as_deref(), Some("bullseye"));
+    assert_eq!(
+        lock.apt["20220301T000000Z"]["bullseye"]["arm64"]["git"],
+        "1:2.30.2-1+deb11u2"
+    );
+    assert_eq!(lock.trunk_sha256("0.14.0"), Some(SHA256));
+    assert!(lock
+        .unlocked(&manifest, &entry(), Platform::Arm64)
+        .is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("image.lock");
+    lock.save(&path).unwrap();
+    assert_eq!(ImageLock::load(&path).unwrap(), lock);
+
+    let rendered = dockerfile::render(
+        &manifest,
+        &lock,
+        &entry(),
+        Platform::Amd64,
+        TrunkSource::Cargo,
+    );
+    assert!(
+        rendered.contains(&format!("FROM {reference}@{digest}\n")),
+        "{rendered}"
+    );
+    assert!(rendered.contains("    curl=7.74.0-1.3+deb11u1 \\\n    git=1:2.30.2-1+deb11u2 && \\\n"));
+    assert!(rendered.contains(&format!(
+        "echo '{SHA256}  /tmp/trunk.crate' | sha256sum -c -"
+    )));
+    assert!(rendered.contains("cargo install --locked --path /tmp/trunk-0.14.0"));
+    assert!(!rendered.contains("--version 0.14.0"));
+
+    let mut unsnapshotted = manifest.clone();
+    unsnapshotted.apt.snapshot = None;
+    assert_eq!(
+        lock.unlocked(&unsnapshotted, &entry(), Platform::Amd64),
+        ["apt package git", "apt package curl"]
+    );
+
+    let unpinned = dockerfile::render(
+        &manifest,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Amd64,
+        TrunkSource::Cargo,
+    );
+    assert!(unpinned.contains(&format!("FROM {reference}\n")));
+    assert_eq!(
+        ImageLock::default().unlocked(&manifest, &entry(), Platform::Amd64),
+        [
+            reference,
+            "apt package git".into(),
+            "apt package curl".into(),
+            "trunk crate 0.14.0".into()
+        ]
+    );
+}
+
+#[test]
+fn assembles_and_attests_the_locked_base() {
+    let registry = common::registry::start();
+    let both = [Platform::Amd64, Platform::Arm64];
+    registry.push_base("1.56-slim", &both, &[("/usr/lib/os-release", OS_RELEASE)]);
+    let (matrix, manifest) = inputs(&registry.host, "");
+    let index = index();
+    let lock = ImageLock::resolve(
+        &matrix,
+        &manifest,
+        index.path().to_str().unwrap(),
+        Some(&Archives {
+            debian: "http://127.0.0.1:1/debian".into(),
+            security: "http://127.0.0.1:1/debian-security".into(),
+        }),
+        None,
+    )
+    .unwrap();
+    let (_, bytes) = registry.manifest("rust", "1.56-slim").unwrap();
+    let digest = oci::digest(&bytes);
+    let reference = lock.base_reference(&manifest.base, "1.56");
+    assert_eq!(
+        reference,
+        format!("{}/rust:1.56-slim@{digest}", registry.host)
+    );
+
+    let (target, tag) = Target::parse_reference(&reference).unwrap();
+    assert_eq!(target.to_string(), format!("{}/rust", registry.host));
+    assert_eq!(tag, digest);
+    let base = BaseImage::resolve(&reference, Platform::Arm64).unwrap();
+    assert_eq!(base.index_digest.as_deref(), Some(digest.as_str()));
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    common::git_checkout(&source);
+    let options = Options {
+        output: dir.path().join("layout"),
+        name: "rust-trunk:0.14.0-rust1.56".into(),
+        rust_dist: "http://127.0.0.1:1".into(),
+        crates_dl: "http://127.0.0.1:1".into(),
+        entrypoint_binary: None,
+        partial: true,
+        apt_archives: None,
+        source,
+    };
+    let assembled = assemble::assemble(
+        &manifest,
+        &TrunkLock::default(),
+        &lock,
+        &entry(),
+        Platform::Amd64,
+        &options,
+    )
+    .unwrap();
+    let layout = Layout::open(&options.output).unwrap();
+    let (_, image) = layout.image().unwrap();
+    assert_eq!(
+        image.annotations["org.opencontainers.image.base.name"],
+        reference
+    );
+    let provenance = fs::read_to_string(&assembled.provenance).unwrap();
+    assert!(provenance.contains(&digest), "{provenance}");
+}
+
+#[test]
+fn locks_apt_versions_per_snapshot() {
+    let registry = common::registry::start();
+    registry.push_base(
+        "1.56-slim",
+        &[Platform::Amd64],
+        &[("/usr/lib/os-release", OS_RELEASE)],
+    );
+    let index = index();
+    let (mut matrix, _) = inputs(&registry.host, "\"git\"");
+    matrix.platforms = vec![Platform::Amd64];
+    let manifest: Manifest = format!(
+        "[base]\nimage = \"{0}/rust\"\nsuffix = \"slim\"\n[apt]\n\
+         packages = [\"git\", \"curl\"]\nversions = {{ git = \"1:2.30.2-1\" }}\n\
+         snapshot = \"20220301T000000Z\"\n\
+         [variants.newer]\nbase = {{ image = \"{0}/rust\", suffix = \"slim\" }}\n\
+         apt = {{ packages = [\"git\", \"curl\"], snapshot = \"20230101T000000Z\" }}",
+        registry.host
+    )
+    .parse()
+    .unwrap();
+    let newer = manifest.variant(Some("newer")).unwrap();
+
+    let mut lock = ImageLock::resolve(
+        &matrix,
+        &manifest,
+        index.path().to_str().unwrap(),
+        Some(&mirror()),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        lock.apt.keys().collect::<Vec<_>>(),
+        ["20220301T000000Z", "20230101T000000Z"]
+    );
+    let render = |manifest: &Manifest, lock: &ImageLock| {
+        dockerfile::render(
+            manifest,
+            lock,
+            &entry(),
+            Platform::Amd64,
+            TrunkSource::Cargo,
+        )
+    };
+    assert!(render(&manifest, &lock).contains("    git=1:2.30.2-1 && \\\n"));
+    assert!(render(newer, &lock).contains("    git=1:2.30.2-1+deb11u2 && \\\n"));
+
+    // Each image only reads the versions locked for its own snapshot.
+    lock.apt.remove("20230101T000000Z");
+    assert!(lock
+        .unlocked(&manifest, &entry(), Platform::Amd64)
+        .is_empty());
+    assert_eq!(
+        lock.unlocked(newer, &entry(), Platform::Amd64),
+        ["apt package git", "apt package curl"]
+    );
+}
+
+#[test]
+fn locks_apk_packages_per_alpine_branch() {
+    let registry = common::registry::start();
+    registry.push_base(
+        "1.56-alpine",
+        &[Platform::Amd64],
+        &[("/etc/os-release", b"ID=alpine\nVERSION_ID=3.19.1\n")],
+    );
+    // An APKINDEX.tar.gz is the gzipped signature followed by the gzipped
+    // index.
+    let mut apkindex = LayerBuilder::new()
+        .file("/.SIGN.RSA.alpine.rsa.pub", 0o644, b"signature".to_vec())
+        .finish()
+        .unwrap()
+        .blob;
+    apkindex.extend(
+        LayerBuilder::new()
+            .file(
+                "/APKINDEX",
+                0o644,
+                b"P:curl\nV:8.5.0-r0\nA:x86_64\n\nP:git\nV:2.43.0-r0\nA:x86_64\n".to_vec(),
+            )
+            .finish()
+            .unwrap()
+            .blob,
+    );
+    let mirror = common::http::serve(HashMap::from([(
+        "/alpine/v3.19/main/x86_64/APKINDEX.tar.gz".to_string(),
+        apkindex,
+    )]));
+    let mirror = format!("{mirror}/alpine");
+    let index = index();
+    let index = index.path().to_str().unwrap();
+    let (mut matrix, _) = inputs(&registry.host, "");
+    matrix.platforms = vec![Platform::Amd64];
+    let manifest = |versions: &str| -> Manifest {
+        format!(
+            "[base]\nimage = \"{}/rust\"\nsuffix = \"alpine\"\n\
+             [apk]\npackages = [\"git\", \"curl\"]\nversions = {{ {versions} }}",
+            registry.host
+        )
+        .parse()
+        .unwrap()
+    };
+
+    let unpinned = manifest("");
+    let lock = ImageLock::resolve(&matrix, &unpinned, index, None, Some(&mirror)).unwrap();
+    let reference = format!("{}/rust:1.56-alpine", registry.host);
+    assert_eq!(lock.base[&reference].branch.as_deref(), Some("v3.19"));
+    assert_eq!(lock.base[&reference].suite, None);
+    assert_eq!(lock.apk["v3.19"]["amd64"]["curl"], "8.5.0-r0");
+    assert!(lock
+        .unlocked(&unpinned, &entry(), Platform::Amd64)
+        .is_empty());
+    let rendered = dockerfile::render(
+        &unpinned,
+        &lock,
+        &entry(),
+        Platform::Amd64,
+        TrunkSource::Cargo,
+    );
+    assert!(
+        rendered.contains("apk add --no-cache \\\n    curl=8.5.0-r0 \\\n    git=2.43.0-r0"),
+        "{rendered}"
+    );
+
+    let err = ImageLock::resolve(
+        &matrix,
+        &manifest("git = \"2.40.1-r0\""),
+        index,
+        None,
+        Some(&mirror),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "apk: git=2.40.1-r0 is not available from {mirror} v3.19 on linux/amd64, \
+             which has only 2.43.0-r0"
+        )
+    );
+
+    for (versions, message) in [
+        (
+            "vim = \"9.0.2127-r0\"",
+            "pins vim, which is not in `apk.packages`",
+        ),
+        ("git = \"latest\"", "not a valid Alpine version"),
+    ] {
+        let manifest = format!(
+            "[base]\nimage = \"rust\"\n[apk]\npackages = [\"git\"]\nversions = {{ {versions} }}"
+        );
+        let err = manifest.parse::<Manifest>().unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+}
+
+#[test]
+fn locks_wasm_bindgen_releases() {
+    let registry = common::registry::start();
+    let both = [Platform::Amd64, Platform::Arm64];
+    registry.push_base("1.56-slim", &both, &[("/usr/lib/os-release", OS_RELEASE)]);
+    let (matrix, _) = inputs(&registry.host, "");
+    let index = index();
+    // Only x86_64 has a release, like wasm-bindgen-cli's older versions.
+    let release = LayerBuilder::new()
+        .file(
+            "/wasm-bindgen-0.2.78-x86_64-unknown-linux-musl/wasm-bindgen",
+            0o755,
+            b"wasm-bindgen 0.2.78".to_vec(),
+        )
+        .finish()
+        .unwrap()
+        .blob;
+    let sha256 = trunk_docker::trunk::sha256_hex(&release);
+    let url = common::http::serve(HashMap::from([(
+        "/0.2.78/wasm-bindgen-0.2.78-x86_64-unknown-linux-musl.tar.gz".to_string(),
+        release,
+    )]));
+    let manifest: Manifest = format!(
+        "[base]\nimage = \"{}/rust\"\nsuffix = \"slim\"\n\
+         [apt]\npackages = [\"curl\"]\nsnapshot = \"20220301T000000Z\"\n\
+         [wasm-bindgen]\nversions = [\"0.2.78\"]\n\
+         release-url = \"{url}/{{version}}/wasm-bindgen-{{version}}-{{target}}.tar.gz\"",
+        registry.host
+    )
+    .parse()
+    .unwrap();
+
+    let lock = ImageLock::resolve(
+        &matrix,
+        &manifest,
+        index.path().to_str().unwrap(),
+        Some(&mirror()),
+        None,
+    )
+    .unwrap();
+    let locked = &lock.wasm_bindgen["0.2.78"];
+    assert_eq!(
+        locked.keys().collect::<Vec<_>>(),
+        ["x86_64-unknown-linux-musl"]
+    );
+    assert_eq!(locked["x86_64-unknown-linux-musl"].sha256, sha256);
+
+    let render =
+        |platform| dockerfile::render(&manifest, &lock, &entry(), platform, TrunkSource::Cargo);
+    let amd64 = render(Platform::Amd64);
+    assert!(amd64.contains(&format!(
+        "echo '{sha256}  /tmp/wasm-bindgen.tar.gz' | sha256sum -c -"
+    )));
+    assert!(amd64.contains(
+        "tar -xzf /tmp/wasm-bindgen.tar.gz -C /usr/local/wasm-bindgen/0.2.78/bin --strip-components=1"
+    ));
+    assert!(!amd64.contains("cargo install --locked wasm-bindgen-cli"));
+    assert!(render(Platform::Arm64).contains(
+        "cargo install --locked wasm-bindgen-cli --version 0.2.78 --root /usr/local/wasm-bindgen/0.2.78"
+    ));
+
+    // Without a release URL the manifest compiles every version.
+    let mut compiled = manifest.clone();
+    compiled.wasm_bindgen.release_url = None;
+    assert!(lock
+        .wasm_bindgen_release(&compiled, &entry(), Platform::Amd64, "0.2.78")
+        .is_none());
+}
+
+#[test]
+fn refuses_what_upstream_cannot_provide() {
+    let registry = common::registry::start();
+    registry.push_base(
+        "1.56-slim",
+        &[Platform::Amd64],
+        &[("/usr/lib/os-release", OS_RELEASE)],
+    );
+    let index = index();
+    let index = index.path().to_str().unwrap();
+
+    let (matrix, manifest) = inputs(&registry.host, "\"git\"");
+    let err = ImageLock::resolve(&matrix, &manifest, index, Some(&mirror()), None).unwrap_err();
+    assert!(
+        err.to_string().contains("has no image for every platform"),
+        "{err}"
+    );
+
+    let (mut matrix, manifest) = inputs(&registry.host, "\"git\", \"libssl-dev\"");
+    matrix.platforms = vec![Platform::Amd64];
+    let err = ImageLock::resolve(&matrix, &manifest, index, Some(&mirror()), None).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("bullseye has no package libssl-dev for linux/amd64"),
+        "{err}"
+    );
+
+    let (mut matrix, manifest) = inputs(&registry.host, "\"git\"");
+    matrix.platforms = vec![Platform::Amd64];
+    matrix.trunk = vec!["0.15.0".into()];
+    let err = ImageLock::resolve(&matrix, &manifest, index, Some(&mirror()), None).unwrap_err();
+    assert!(
+        err.to_string().contains("trunk 0.15.0 is not released"),
+        "{err}"
+    );
+
+    let (_, mut manifest) = inputs(&registry.host, "\"git\"");
+    manifest.apt.snapshot = None;
+    let err = ImageLock::resolve(&matrix, &manifest, index, Some(&mirror()), None).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("the default image installs apt packages without `apt.snapshot`"),
+        "{err}"
+    );
+
+    let malformed = "[base.\"rust:1.56-slim\"]\ndigest = \"sha256:00\"\nsuite = \"bullseye\"";
+    assert!(malformed.parse::<ImageLock>().is_err());
+}
+
+#[test]
+fn installs_the_pinned_apt_versions() {
+    let registry = common::registry::start();
+    registry.push_base(
+        "1.56-slim",
+        &[Platform::Amd64],
+        &[("/usr/lib/os-release", OS_RELEASE)],
+    );
+    let index = index();
+    let index = index.path().to_str().unwrap();
+    let mirror = mirror();
+    let (mut matrix, _) = inputs(&registry.host, "\"git\"");
+    matrix.platforms = vec![Platform::Amd64];
+    let pinned = |versions: &str| -> Manifest {
+        format!(
+            "[base]\nimage = \"{}/rust\"\nsuffix = \"slim\"\n[apt]\n\
+             packages = [\"git\", \"curl\"]\nversions = {{ {versions} }}\n\
+             snapshot = \"20220301T000000Z\"",
+            registry.host
+        )
+        .parse()
+        .unwrap()
+    };
+
+    let manifest = pinned("git = \"1:2.30.2-1\"");
+    assert_eq!(
+        manifest.apt.archives(),
+        Archives {
+            debian: "http://snapshot.debian.org/archive/debian/20220301T000000Z".into(),
+            security: "http://snapshot.debian.org/archive/debian-security/20220301T000000Z".into(),
+        }
+    );
+    let lock = ImageLock::resolve(&matrix, &manifest, index, Some(&mirror), None).unwrap();
+    let locked = &lock.apt["20220301T000000Z"]["bullseye"]["amd64"];
+    assert_eq!(locked["git"], "1:2.30.2-1+deb11u2");
+    assert_eq!(locked["curl"], "7.74.0-1.3+deb11u1");
+    lock.check_apt_pins(&manifest, &entry(), Platform::Amd64, &mirror)
+        .unwrap();
+
+    // The manifest's pin wins over a lock resolved before it was added.
+    let unpinned_lock = {
+        let (_, manifest) = inputs(&registry.host, "\"git\", \"curl\"");
+        ImageLock::resolve(&matrix, &manifest, index, Some(&mirror), None).unwrap()
+    };
+    let rendered = dockerfile::render(
+        &manifest,
+        &unpinned_lock,
+        &entry(),
+        Platform::Amd64,
+        TrunkSource::Cargo,
+    );
+    assert!(
+        rendered.contains("    curl=7.74.0-1.3+deb11u1 \\\n    git=1:2.30.2-1 && \\\n"),
+        "{rendered}"
+    );
+    let sources = rendered.find("> /etc/apt/sources.list").unwrap();
+    assert!(sources < rendered.find("apt-get -y update").unwrap());
+    assert!(rendered.contains(
+        "http://snapshot.debian.org/archive/debian-security/20220301T000000Z \
+         \"$VERSION_CODENAME-security\""
+    ));
+
+    let manifest = pinned("git = \"1:2.30.2-2\"");
+    let err = unpinned_lock
+        .check_apt_pins(&manifest, &entry(), Platform::Amd64, &mirror)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "apt: git=1:2.30.2-2 is not available from {0} bullseye, {0} bullseye-updates, \
+             {1} bullseye-security on linux/amd64, which has only 1:2.30.2-1, \
+             1:2.30.2-1+deb11u2",
+            mirror.debian, mirror.security
+        )
+    );
+    let err = ImageLock::resolve(&matrix, &manifest, index, Some(&mirror), None).unwrap_err();
+    assert!(err.to_string().contains("git=1:2.30.2-2 is not available"));
+
+    for (versions, message) in [
+        (
+            "vim = \"2:8.2.2434-3\"",
+            "pins vim, which is not in `apt.packages`",
+        ),
+        ("git = \"latest\"", "not a valid Debian version"),
+        (
+            "git = \"1:2.30.2-1\"",
+            "`apt.versions` needs `apt.snapshot`",
+        ),
+    ] {
+        let manifest = format!(
+            "[base]\nimage = \"rust\"\n[apt]\npackages = [\"git\"]\nversions = {{ {versions} }}"
+        );
+        let err = manifest.parse::<Manifest>().unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+    let err = "[base]\nimage = \"rust\"\n[apt]\npackages = []\nsnapshot = \"2022-03-01\""
+        .parse::<Manifest>()
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("not a snapshot.debian.org timestamp"));
+}
@@ -0,0 +1,123 @@
+use trunk_docker::{
+    matrix::{Entry, Matrix},
+    platform::Platform,
+};
+
+fn matrix() -> Matrix {
+    r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.14.0", "0.15.0"]
+        rust = ["1.9", "1.56", "1.10"]
+    "#
+    .parse()
+    .unwrap()
+}
+
+fn entry(trunk: &str, rust: &str) -> Entry {
+    Entry {
+        trunk: trunk.into(),
+        rust: rust.into(),
+    }
+}
+
+#[test]
+fn entries_are_the_cross_product() {
+    let entries = matrix().entries();
+    assert_eq!(entries.len(), 6);
+    assert_eq!(entries[0], entry("0.14.0", "1.9"));
+    assert_eq!(entries[5], entry("0.15.0", "1.10"));
+}
+
+#[test]
+fn newest_rust_compares_numerically() {
+    assert_eq!(matrix().newest_rust(), "1.56");
+}
+
+#[test]
+fn select_filters_each_axis() {
+    let selected = matrix()
+        .select(&["0.15.0".into()], &["1.56".into()])
+        .unwrap();
+    assert_eq!(selected, vec![entry("0.15.0", "1.56")]);
+    assert_eq!(matrix().select(&[], &["1.10".into()]).unwrap().len(), 2);
+}
+
+#[test]
+fn select_rejects_unknown_versions() {
+    let err = matrix().select(&["0.13.0".into()], &[]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid matrix: trunk version 0.13.0 is not in the matrix"
+    );
+}
+
+#[test]
+fn rejects_invalid_matrices() {
+    let empty = "targets = [\"r\"]\ntrunk = []\nrust = [\"1.56\"]";
+    assert!(empty.parse::<Matrix>().is_err());
+    let duplicate = "targets = [\"r\"]\ntrunk = [\"0.14.0\", \"0.14.0\"]\nrust = [\"1.56\"]";
+    assert!(duplicate.parse::<Matrix>().is_err());
+    let malformed = "targets = [\"r\"]\ntrunk = [\"latest\"]\nrust = [\"1.56\"]";
+    assert!(malformed.parse::<Matrix>().is_err());
+}
+
+#[test]
+fn platforms_default_to_amd64() {
+    assert_eq!(matrix().platforms, [Platform::Amd64]);
+    let both = "targets = [\"r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]\n\
+                platforms = [\"linux/amd64\", \"linux/arm64\"]";
+    assert_eq!(
+        both.parse::<Matrix>().unwrap().platforms,
+        [Platform::Amd64, Platform::Arm64]
+    );
+    let unsupported = "targets = [\"r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]\n\
+                       platforms = [\"linux/s390x\"]";
+    assert!(unsupported.parse::<Matrix>().is_err());
+}
+
+#[test]
+fn publish_tables_override_targets_per_entry() {
+    let mut matrix: Matrix = r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.14.0", "0.15.0"]
+        rust = ["1.56"]
+
+        [[publish]]
+        trunk = ["0.15.0"]
+        targets = [
+            "ghcr.io/our-org/rust-trunk",
+            { registry = "registry.local:5000", repository = "rust-trunk" },
+        ]
+    "#
+    .parse()
+    .unwrap();
+    let targets = |matrix: &Matrix, trunk| {
+        matrix
+            .targets_for(&entry(trunk, "1.56"))
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(targets(&matrix, "0.14.0"), ["torhovland/rust-trunk"]);
+    assert_eq!(
+        targets(&matrix, "0.15.0"),
+        [
+            "ghcr.io/our-org/rust-trunk",
+            "registry.local:5000/rust-trunk"
+        ]
+    );
+
+    matrix.retarget(vec!["localhost:5000/rust-trunk".parse().unwrap()]);
+    assert_eq!(targets(&matrix, "0.15.0"), ["localhost:5000/rust-trunk"]);
+}
+
+#[test]
+fn rejects_invalid_targets() {
+    let none = "targets = []\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]";
+    assert!(none.parse::<Matrix>().is_err());
+    let twice = "targets = [\"r\", \"docker.io/r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]";
+    assert!(twice.parse::<Matrix>().is_err());
+    let unknown = "targets = [\"r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]\n\
+                   [[publish]]\ntrunk = [\"0.13.0\"]\ntargets = [\"s\"]";
+    assert!(unknown.parse::<Matrix>().is_err());
+}
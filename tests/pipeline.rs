@@ -0,0 +1,207 @@
+mod common;
+
+use std::fs;
+
+use trunk_docker::{
+    dockerfile::TrunkSource,
+    manifest::Manifest,
+    matrix::Matrix,
+    pipeline::{self, Options},
+    platform::Platform,
+    process::{Invocation, Runner},
+    trunk::TrunkLock,
+    Result,
+};
+
+#[derive(Default)]
+struct Recorder(Vec<String>);
+
+impl Runner for Recorder {
+    fn run(&mut self, invocation: &Invocation) -> Result<()> {
+        self.0.push(invocation.to_string());
+        Ok(())
+    }
+}
+
+fn matrix() -> Matrix {
+    r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.14.0"]
+        rust = ["1.55", "1.56"]
+        platforms = ["linux/amd64", "linux/arm64"]
+    "#
+    .parse()
+    .unwrap()
+}
+
+fn manifest() -> Manifest {
+    "[base]\nimage = \"rust\"\nsuffix = \"slim\""
+        .parse()
+        .unwrap()
+}
+
+fn options(push: bool) -> Options {
+    Options {
+        context: ".".into(),
+        out_dir: "out".into(),
+        push,
+        crates_dl: "http://127.0.0.1:1".into(),
+    }
+}
+
+fn locked_amd64() -> TrunkLock {
+    format!(
+        "[release.\"0.14.0\".x86_64-unknown-linux-gnu]\n\
+         url = \"https://example.com/trunk.tar.gz\"\nsha256 = \"{}\"",
+        "a".repeat(64)
+    )
+    .parse()
+    .unwrap()
+}
+
+#[test]
+fn exports_per_platform_layouts_for_pushing() {
+    let matrix = matrix();
+    let entries = matrix.select(&[], &["1.56".into()]).unwrap();
+    let plan = pipeline::plan(
+        &matrix,
+        &manifest(),
+        &locked_amd64(),
+        &entries,
+        &options(true),
+    )
+    .unwrap();
+    let mut recorder = Recorder::default();
+    pipeline::run(&mut recorder, &plan.invocations(&options(true))).unwrap();
+    assert_eq!(
+        recorder.0,
+        [
+            "docker buildx build --platform linux/amd64 -f out/0.14.0-rust1.56/amd64/Dockerfile \
+             --build-context trunk=out/0.14.0-rust1.56/amd64/trunk \
+             --metadata-file out/0.14.0-rust1.56/amd64/metadata.json --provenance=false \
+             --output type=oci,dest=out/0.14.0-rust1.56/amd64/oci,tar=false .",
+            "docker buildx build --platform linux/arm64 -f out/0.14.0-rust1.56/arm64/Dockerfile \
+             --metadata-file out/0.14.0-rust1.56/arm64/metadata.json --provenance=false \
+             --output type=oci,dest=out/0.14.0-rust1.56/arm64/oci,tar=false .",
+        ]
+    );
+}
+
+#[test]
+fn local_builds_tag_the_first_platform() {
+    let matrix = matrix();
+    let entries = matrix.select(&[], &["1.55".into()]).unwrap();
+    let plan = pipeline::plan(
+        &matrix,
+        &manifest(),
+        &TrunkLock::default(),
+        &entries,
+        &options(false),
+    )
+    .unwrap();
+    let invocations = plan.invocations(&options(false));
+    assert_eq!(invocations.len(), 2);
+    assert_eq!(
+        invocations[0].to_string(),
+        "docker buildx build --platform linux/amd64 -f out/0.14.0-rust1.55/amd64/Dockerfile \
+         --metadata-file out/0.14.0-rust1.55/amd64/metadata.json \
+         -t torhovland/rust-trunk:0.14.0-rust1.55-amd64 \
+         -t torhovland/rust-trunk:0.14.0-rust1.55 -t torhovland/rust-trunk:0.14.0-rust1.55-slim \
+         -t torhovland/rust-trunk:0.14-rust1.55 -t torhovland/rust-trunk:0.14-rust1.55-slim \
+         --load ."
+    );
+    assert!(!invocations[1]
+        .args
+        .contains(&"torhovland/rust-trunk:0.14.0-rust1.55".to_string()));
+}
+
+#[test]
+fn plan_json_records_platforms_tags_and_digests() {
+    let matrix = matrix();
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        context: ".".into(),
+        out_dir: dir.path().into(),
+        push: true,
+        crates_dl: "http://127.0.0.1:1".into(),
+    };
+    let entries = matrix.select(&[], &["1.56".into()]).unwrap();
+    let mut plan =
+        pipeline::plan(&matrix, &manifest(), &locked_amd64(), &entries, &options).unwrap();
+    for (build, digest) in plan.images[0]
+        .platforms
+        .iter()
+        .zip(["sha256:aaa", "sha256:bbb"])
+    {
+        fs::create_dir_all(build.metadata_file.parent().unwrap()).unwrap();
+        fs::write(
+            &build.metadata_file,
+            format!(r#"{{"containerimage.digest": "{digest}", "image.name": "x"}}"#),
+        )
+        .unwrap();
+    }
+    plan.record_digests().unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&plan.to_json()).unwrap();
+    let image = &json["images"][0];
+    assert_eq!(
+        image["targets"],
+        serde_json::json!(["torhovland/rust-trunk"])
+    );
+    assert_eq!(image["tags"][0], "0.14.0-rust1.56");
+    assert_eq!(image["tags"].as_array().unwrap().len(), 12);
+    assert_eq!(image["platforms"][0]["platform"], "linux/amd64");
+    assert_eq!(image["platforms"][0]["trunk"], "prebuilt");
+    assert_eq!(image["platforms"][0]["digest"], "sha256:aaa");
+    assert_eq!(image["platforms"][1]["platform"], "linux/arm64");
+    assert_eq!(image["platforms"][1]["trunk"], "cargo");
+    assert_eq!(image["platforms"][1]["digest"], "sha256:bbb");
+    assert_eq!(plan.images[0].platforms[1].platform, Platform::Arm64);
+    assert_eq!(plan.images[0].platforms[1].trunk, TrunkSource::Cargo);
+}
+
+#[test]
+fn preview_lists_commands_downloads_and_pushes() {
+    let matrix = matrix();
+    let lock = locked_amd64();
+    let entries = matrix.select(&[], &["1.56".into()]).unwrap();
+    let plan = pipeline::plan(&matrix, &manifest(), &lock, &entries, &options(true)).unwrap();
+    let preview = plan.preview(&lock, &options(true));
+    common::assert_snapshot("plan.txt", &preview.to_string());
+
+    let json: serde_json::Value = serde_json::from_str(&preview.to_json()).unwrap();
+    let image = &json["images"][0];
+    assert_eq!(json["push"], true);
+    assert_eq!(image["trunk"], "0.14.0");
+    assert_eq!(
+        image["downloads"],
+        serde_json::json!(["https://example.com/trunk.tar.gz"])
+    );
+    assert_eq!(image["pushes"].as_array().unwrap().len(), 14);
+    assert_eq!(image["commands"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn preview_without_push_pushes_nothing() {
+    let matrix = matrix();
+    let entries = matrix.select(&[], &["1.55".into()]).unwrap();
+    let plan = pipeline::plan(
+        &matrix,
+        &manifest(),
+        &TrunkLock::default(),
+        &entries,
+        &options(false),
+    )
+    .unwrap();
+    let preview = plan.preview(&TrunkLock::default(), &options(false));
+    assert!(preview.images[0].pushes.is_empty());
+    assert!(preview
+        .to_string()
+        .contains("pushes: nothing (run with --push to publish)"));
+}
+
+#[test]
+fn quotes_arguments_for_display() {
+    let invocation = Invocation::new("docker").arg("build").arg("my context");
+    assert_eq!(invocation.to_string(), "docker build 'my context'");
+}
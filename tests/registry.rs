@@ -0,0 +1,169 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use trunk_docker::{
+    matrix::Matrix,
+    oci::{self, Index, INDEX_MEDIA_TYPE},
+    registry::Registry,
+    target::Target,
+};
+
+fn matrix() -> Matrix {
+    r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.14.0"]
+        rust = ["1.56"]
+        platforms = ["linux/amd64", "linux/arm64"]
+    "#
+    .parse()
+    .unwrap()
+}
+
+#[test]
+fn pushes_an_index_of_every_platform_to_every_target() {
+    let hub = common::registry::start();
+    let ghcr = common::registry::start();
+    let targets: Vec<Target> = [
+        format!("{}/team/rust-trunk", hub.host),
+        format!("{}/rust-trunk", ghcr.host),
+    ]
+    .iter()
+    .map(|t| t.parse().unwrap())
+    .collect();
+    let mut matrix = matrix();
+    matrix.retarget(targets.clone());
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut plan = common::plan(&matrix, dir.path(), true);
+    let digests: Vec<String> = plan.images[0]
+        .platforms
+        .iter()
+        .map(|build| common::write_layout(&build.layout, build.tag.as_bytes()))
+        .collect();
+    plan.push().unwrap();
+    for (registry, name) in [(&hub, "team/rust-trunk"), (&ghcr, "rust-trunk")] {
+        assert!(registry.manifest(name, "0.14.0-rust1.56").is_none());
+        assert!(registry.manifest(name, "0.14.0-rust1.56-arm64").is_none());
+    }
+    plan.tag().unwrap();
+
+    let image = &plan.images[0];
+    for (registry, name) in [(&hub, "team/rust-trunk"), (&ghcr, "rust-trunk")] {
+        let (media_type, bytes) = registry.manifest(name, "0.14.0-rust1.56").unwrap();
+        assert_eq!(media_type, INDEX_MEDIA_TYPE);
+        assert_eq!(image.digest.as_deref(), Some(oci::digest(&bytes).as_str()));
+        let index: Index = serde_json::from_slice(&bytes).unwrap();
+        let pushed: Vec<_> = index.manifests.iter().map(|m| m.digest.clone()).collect();
+        assert_eq!(pushed, digests);
+        let platform = index.manifests[1].platform.as_ref().unwrap();
+        assert_eq!(
+            (platform.architecture.as_str(), platform.variant.as_deref()),
+            ("arm64", Some("v8"))
+        );
+        assert!(registry.manifest(name, "latest").is_some());
+        assert!(registry.manifest(name, "0.14.0-rust1.56-arm64").is_some());
+        assert!(registry
+            .contents
+            .lock()
+            .unwrap()
+            .blobs
+            .contains_key(&oci::digest(b"0.14.0-rust1.56-amd64")));
+    }
+}
+
+#[test]
+fn fetches_a_token_when_challenged() {
+    let stand_in = common::registry::start_with_token(Some("s3cret"));
+    let target: Target = format!("{}/team/rust-trunk", stand_in.host)
+        .parse()
+        .unwrap();
+    let mut registry = Registry::for_target(&target);
+    let blob = b"layer";
+    let digest = oci::digest(blob);
+    registry.push_blob(&target.name(), &digest, blob).unwrap();
+    assert_eq!(registry.blob(&target.name(), &digest).unwrap(), blob);
+    assert!(registry
+        .manifest(&target.name(), "missing")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn logs_in_with_the_credential_helper_of_the_docker_config() {
+    let stand_in = common::registry::start_with_login("s3cret", "ci:hunter2");
+    let unknown = common::registry::start_with_login("s3cret", "ci:hunter2");
+    let broken = common::registry::start_with_login("s3cret", "ci:hunter2");
+    let dir = tempfile::tempdir().unwrap();
+    let helper = dir.path().join("docker-credential-stand-in");
+    fs::write(
+        &helper,
+        format!(
+            "#!/bin/sh\n\
+             [ \"$1\" = get ] || exit 2\n\
+             case \"$(cat)\" in\n\
+             {}) echo '{{\"ServerURL\":\"x\",\"Username\":\"ci\",\"Secret\":\"hunter2\"}}' ;;\n\
+             *) echo 'credentials not found in native keychain'; exit 1 ;;\n\
+             esac\n",
+            stand_in.host
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        dir.path().join("config.json"),
+        format!(
+            r#"{{"auths": {{"{}": {{}}}}, "credsStore": "stand-in", "credHelpers": {{"{}": "missing"}}}}"#,
+            stand_in.host, broken.host
+        ),
+    )
+    .unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{path}", dir.path().display()));
+    std::env::set_var("DOCKER_CONFIG", dir.path());
+
+    let blob = b"layer";
+    let digest = oci::digest(blob);
+    let target: Target = format!("{}/rust-trunk", stand_in.host).parse().unwrap();
+    let mut registry = Registry::for_target(&target);
+    registry.push_blob(&target.name(), &digest, blob).unwrap();
+    assert_eq!(registry.blob(&target.name(), &digest).unwrap(), blob);
+
+    let target: Target = format!("{}/rust-trunk", unknown.host).parse().unwrap();
+    let err = Registry::for_target(&target)
+        .push_blob(&target.name(), &digest, blob)
+        .unwrap_err();
+    assert!(err.to_string().contains("refused a token"), "{err}");
+
+    let target: Target = format!("{}/rust-trunk", broken.host).parse().unwrap();
+    let err = Registry::for_target(&target)
+        .push_blob(&target.name(), &digest, blob)
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("docker-credential-missing"),
+        "{err}"
+    );
+}
+
+#[test]
+fn parses_targets_like_docker() {
+    let hub: Target = "torhovland/rust-trunk".parse().unwrap();
+    assert_eq!(hub.registry, "docker.io");
+    assert_eq!(hub.name(), "torhovland/rust-trunk");
+    assert_eq!(hub.api_base(), "https://registry-1.docker.io");
+    assert_eq!(hub.reference("latest"), "torhovland/rust-trunk:latest");
+
+    let official: Target = "rust".parse().unwrap();
+    assert_eq!(official.name(), "library/rust");
+
+    let ghcr: Target = "ghcr.io/our-org/tools/rust-trunk".parse().unwrap();
+    assert_eq!(ghcr.registry, "ghcr.io");
+    assert_eq!(ghcr.namespace.as_deref(), Some("our-org/tools"));
+    assert_eq!(ghcr.to_string(), "ghcr.io/our-org/tools/rust-trunk");
+
+    let local: Target = "localhost:5000/rust-trunk".parse().unwrap();
+    assert_eq!(local.api_base(), "http://localhost:5000");
+
+    assert!("Upper/Case".parse::<Target>().is_err());
+    assert!("ghcr.io/".parse::<Target>().is_err());
+}
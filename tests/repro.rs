@@ -0,0 +1,200 @@
+mod common;
+
+use std::path::Path;
+
+use flate2::{write::GzEncoder, Compression};
+use trunk_docker::{
+    matrix::Matrix,
+    oci::{self, Descriptor, ImageManifest, Index, Layout, MANIFEST_MEDIA_TYPE},
+    pipeline::PlatformBuild,
+    process::{Invocation, Runner},
+    repro::{self, Outcome},
+    Result,
+};
+
+const EPOCH: u64 = 1_640_995_200;
+
+/// A layer entry: path, mode, modification time and contents.
+type File = (&'static str, u32, u64, &'static [u8]);
+
+fn layer(files: &[File]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for &(path, mode, mtime, contents) in files {
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(mode);
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(contents.len() as u64);
+        builder.append_data(&mut header, path, contents).unwrap();
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    std::io::Write::write_all(&mut encoder, &builder.into_inner().unwrap()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Writes a layout of an image with `layers`, like buildx exports it.
+fn write_build(dir: &Path, layers: &[Vec<u8>]) {
+    let mut layout = Layout::create(dir).unwrap();
+    let config = layout
+        .add_blob(
+            oci::CONFIG_MEDIA_TYPE,
+            br#"{"architecture":"amd64","os":"linux"}"#,
+        )
+        .unwrap();
+    let layers = layers
+        .iter()
+        .map(|blob| layout.add_blob(oci::LAYER_MEDIA_TYPE, blob).unwrap())
+        .collect();
+    let manifest = oci::to_canonical_json(&ImageManifest {
+        schema_version: 2,
+        media_type: Some(MANIFEST_MEDIA_TYPE.into()),
+        artifact_type: None,
+        config,
+        layers,
+        subject: None,
+        annotations: Default::default(),
+    });
+    let descriptor: Descriptor = layout.add_blob(MANIFEST_MEDIA_TYPE, &manifest).unwrap();
+    layout.index = Index::new(vec![descriptor]);
+    layout.save().unwrap();
+}
+
+/// The base image's layer, the same in every build.
+fn base_layer() -> Vec<u8> {
+    layer(&[("etc/debian_version", 0o644, 1_600_000_000, b"11.2\n")])
+}
+
+/// Plays buildx: writes each build's image to the layout in `--output`.
+/// Files written by the build carry the time of the build, the `n`th one
+/// being `EPOCH + n` minutes, unless `sloppy` makes the second build differ.
+struct FakeBuildx {
+    invocations: Vec<Invocation>,
+    builds: u64,
+    sloppy: bool,
+}
+
+impl FakeBuildx {
+    fn new(sloppy: bool) -> Self {
+        Self {
+            invocations: Vec::new(),
+            builds: 0,
+            sloppy,
+        }
+    }
+}
+
+impl Runner for FakeBuildx {
+    fn run(&mut self, invocation: &Invocation) -> Result<()> {
+        self.invocations.push(invocation.clone());
+        let output = &invocation.args[invocation
+            .args
+            .iter()
+            .position(|a| a == "--output")
+            .unwrap()
+            + 1];
+        let dest = output
+            .split(',')
+            .find_map(|option| option.strip_prefix("dest="))
+            .unwrap();
+        self.builds += 1;
+        let n = self.builds;
+        let now = EPOCH + n * 60;
+        let built = if self.sloppy && n == 2 {
+            layer(&[
+                (
+                    "usr/local/cargo/bin/trunk",
+                    0o755,
+                    now,
+                    b"trunk, built differently",
+                ),
+                ("usr/local/cargo/.crates.toml", 0o600, now, b"[v1]\n"),
+                ("usr/local/cargo/.package-cache", 0o644, EPOCH - 5, b""),
+                ("usr/local/cargo/build-id", 0o644, now, b"2"),
+            ])
+        } else {
+            layer(&[
+                ("usr/local/cargo/bin/trunk", 0o755, now, b"trunk"),
+                ("usr/local/cargo/.crates.toml", 0o644, now, b"[v1]\n"),
+                ("usr/local/cargo/.package-cache", 0o644, EPOCH - 10, b""),
+            ])
+        };
+        write_build(Path::new(dest), &[base_layer(), built]);
+        Ok(())
+    }
+}
+
+fn build(out_dir: &Path) -> PlatformBuild {
+    let matrix: Matrix = "targets = [\"r\"]\ntrunk = [\"0.14.0\"]\nrust = [\"1.56\"]"
+        .parse()
+        .unwrap();
+    let mut plan = common::plan(&matrix, out_dir, false);
+    plan.images.remove(0).platforms.remove(0)
+}
+
+#[test]
+fn builds_twice_without_the_cache_under_the_epoch() {
+    let dir = tempfile::tempdir().unwrap();
+    let build = build(dir.path());
+    let mut buildx = FakeBuildx::new(false);
+    let report = repro::verify(
+        &mut buildx,
+        &build,
+        Path::new("."),
+        EPOCH,
+        &dir.path().join("repro"),
+    )
+    .unwrap();
+
+    let commands: Vec<String> = buildx.invocations.iter().map(ToString::to_string).collect();
+    let dockerfile = build.dockerfile.display();
+    let repro_dir = dir.path().join("repro");
+    let repro_dir = repro_dir.display();
+    assert_eq!(
+        commands,
+        ["first", "second"].map(|name| format!(
+            "docker buildx build --platform linux/amd64 -f {dockerfile} --no-cache \
+             --provenance=false --build-arg SOURCE_DATE_EPOCH={EPOCH} --output \
+             type=oci,dest={repro_dir}/{name},tar=false,rewrite-timestamp=true ."
+        ))
+    );
+    let outcomes: Vec<&Outcome> = report.layers.iter().map(|l| &l.outcome).collect();
+    assert_eq!(
+        outcomes,
+        [&Outcome::Identical, &Outcome::IdenticalOnceNormalized]
+    );
+    let text = report.to_string();
+    assert!(
+        text.contains("    only in timestamps after the epoch, which buildx did not rewrite\n"),
+        "{text}"
+    );
+    assert!(text.ends_with("\nnot reproducible: 1 of 2 layers differ\n"));
+    let err = report.check().unwrap_err();
+    assert!(err.to_string().ends_with("layer 2 differs between builds"));
+}
+
+#[test]
+fn reports_the_files_that_differ() {
+    let dir = tempfile::tempdir().unwrap();
+    let build = build(dir.path());
+    let report = repro::verify(
+        &mut FakeBuildx::new(true),
+        &build,
+        Path::new("."),
+        EPOCH,
+        &dir.path().join("repro"),
+    )
+    .unwrap();
+    let Outcome::Differs(files) = &report.layers[1].outcome else {
+        panic!("{report}");
+    };
+    assert_eq!(files["/usr/local/cargo/.crates.toml"], ["mode 644 vs 600"]);
+    common::assert_snapshot("repro.txt", &report.to_string());
+    let err = report.check().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "reproducibility: 0.14.0-rust1.56-amd64 on linux/amd64 is not reproducible: \
+         layer 2 differs between builds"
+    );
+}
@@ -0,0 +1,243 @@
+mod common;
+
+use std::collections::HashMap;
+
+use trunk_docker::{
+    layer::LayerBuilder,
+    manifest::Manifest,
+    matrix::Entry,
+    oci::{Index, Layout},
+    pipeline::Options,
+    sbom::{self, Format, Inventory, Kind},
+};
+
+const OS_RELEASE: &[u8] = b"ID=debian\nVERSION_CODENAME=bullseye\n";
+
+const DPKG_STATUS: &[u8] = b"\
+Package: libc6
+Status: install ok installed
+Architecture: amd64
+Version: 2.31-13+deb11u2
+
+Package: curl
+Status: install ok installed
+Architecture: amd64
+Version: 7.74.0-1.3+deb11u1
+Depends: libc6 (>= 2.17),
+ libcurl4 (= 7.74.0-1.3+deb11u1) | libc6
+
+Package: removed
+Status: deinstall ok config-files
+Architecture: amd64
+Version: 1.0
+";
+
+const TRUNK_LOCK: &[u8] = br#"
+[[package]]
+name = "anyhow"
+version = "1.0.44"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "61604a8f862e1d5c3229fdd78f8b02c68dcf73a4c4b05fd636d12240aaa242c1"
+
+[[package]]
+name = "trunk"
+version = "0.14.0"
+dependencies = [
+ "anyhow",
+]
+"#;
+
+/// Serves trunk 0.14.0 as published on crates.io, with its lockfile.
+fn serve_crates() -> String {
+    let mut builder = LayerBuilder::new();
+    builder.file("trunk-0.14.0/Cargo.lock", 0o644, TRUNK_LOCK.to_vec());
+    common::http::serve(HashMap::from([(
+        "/trunk/trunk-0.14.0.crate".to_string(),
+        builder.finish().unwrap().blob,
+    )]))
+}
+
+fn manifest() -> Manifest {
+    "[base]\nimage = \"rust\"\nsuffix = \"slim\"\n\
+     [apt]\npackages = [\"curl\"]\n\
+     [binaryen]\nversion = 105"
+        .parse()
+        .unwrap()
+}
+
+fn entry() -> Entry {
+    Entry {
+        trunk: "0.14.0".into(),
+        rust: "1.56".into(),
+    }
+}
+
+fn write_image(dir: &std::path::Path) -> String {
+    common::write_image(
+        dir,
+        &[
+            ("/etc/os-release", OS_RELEASE),
+            ("/var/lib/dpkg/status", DPKG_STATUS),
+        ],
+        &["RUST_VERSION=1.56.1"],
+    )
+}
+
+#[test]
+fn lists_debian_packages_releases_and_locked_crates() {
+    let crates_dl = serve_crates();
+    let dir = tempfile::tempdir().unwrap();
+    let digest = write_image(dir.path());
+    let layout = Layout::open(dir.path()).unwrap();
+    let tools = sbom::tools(&manifest(), &entry());
+    let inventory = Inventory::of_image(
+        "torhovland/rust-trunk:0.14.0-rust1.56",
+        &layout,
+        &tools,
+        &crates_dl,
+    )
+    .unwrap();
+    assert_eq!(inventory.digest, digest);
+
+    let purls: Vec<_> = inventory
+        .components
+        .iter()
+        .map(|c| (c.purl.as_str(), c.kind))
+        .collect();
+    assert_eq!(
+        purls,
+        [
+            ("pkg:cargo/anyhow@1.0.44", Kind::Crate),
+            ("pkg:cargo/trunk@0.14.0", Kind::Tool),
+            (
+                "pkg:deb/debian/curl@7.74.0-1.3+deb11u1?arch=amd64&distro=bullseye",
+                Kind::Debian
+            ),
+            (
+                "pkg:deb/debian/libc6@2.31-13+deb11u2?arch=amd64&distro=bullseye",
+                Kind::Debian
+            ),
+            ("pkg:generic/rust@1.56.1", Kind::Release),
+            ("pkg:github/WebAssembly/binaryen@version_105", Kind::Release),
+        ]
+    );
+    let curl = &inventory.components[2];
+    assert_eq!(
+        curl.depends_on.iter().collect::<Vec<_>>(),
+        ["pkg:deb/debian/libc6@2.31-13+deb11u2?arch=amd64&distro=bullseye"]
+    );
+
+    // The digest changes with every layer tarball, so the snapshots use a
+    // fixed one.
+    let inventory = Inventory {
+        digest: "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef".into(),
+        ..inventory
+    };
+    common::assert_snapshot("sbom.cdx.json", &inventory.render(Format::CycloneDx));
+    common::assert_snapshot("sbom.spdx.json", &inventory.render(Format::Spdx));
+}
+
+#[test]
+fn lists_alpine_packages_from_the_apk_database() {
+    const APK_INSTALLED: &[u8] = b"\
+C:Q1abc=
+P:musl
+V:1.2.4-r2
+A:x86_64
+p:so:libc.musl-x86_64.so.1=1
+
+P:libcurl
+V:8.5.0-r0
+A:x86_64
+D:so:libc.musl-x86_64.so.1
+p:so:libcurl.so.4=4.8.0
+
+P:curl
+V:8.5.0-r0
+A:x86_64
+D:libcurl=8.5.0-r0 so:libc.musl-x86_64.so.1 !curl-doc
+p:cmd:curl=8.5.0-r0
+";
+    let dir = tempfile::tempdir().unwrap();
+    common::write_image(
+        dir.path(),
+        &[
+            ("/etc/os-release", b"ID=alpine\nVERSION_ID=3.19.0\n"),
+            ("/lib/apk/db/installed", APK_INSTALLED),
+        ],
+        &[],
+    );
+    let inventory = Inventory::of_image(
+        "torhovland/rust-trunk:alpine",
+        &Layout::open(dir.path()).unwrap(),
+        &[],
+        "http://127.0.0.1:1",
+    )
+    .unwrap();
+
+    let purls: Vec<_> = inventory
+        .components
+        .iter()
+        .map(|c| (c.purl.as_str(), c.kind))
+        .collect();
+    assert_eq!(
+        purls,
+        [
+            ("pkg:apk/alpine/curl@8.5.0-r0?arch=x86_64", Kind::Alpine),
+            ("pkg:apk/alpine/libcurl@8.5.0-r0?arch=x86_64", Kind::Alpine),
+            ("pkg:apk/alpine/musl@1.2.4-r2?arch=x86_64", Kind::Alpine),
+        ]
+    );
+    assert_eq!(
+        inventory.components[0]
+            .depends_on
+            .iter()
+            .collect::<Vec<_>>(),
+        [
+            "pkg:apk/alpine/libcurl@8.5.0-r0?arch=x86_64",
+            "pkg:apk/alpine/musl@1.2.4-r2?arch=x86_64"
+        ]
+    );
+    assert_eq!(inventory.components[1].version, "8.5.0-r0");
+}
+
+#[test]
+fn attaches_sboms_to_pushed_images() {
+    let crates_dl = serve_crates();
+    let registry = common::registry::start();
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        context: ".".into(),
+        out_dir: dir.path().into(),
+        push: true,
+        crates_dl,
+    };
+    let mut plan = common::push_plan(&registry.host, dir.path());
+    let digest = write_image(&plan.images[0].platforms[0].layout);
+    plan.write_sboms(&manifest(), &options).unwrap();
+    let build = &plan.images[0].platforms[0];
+    assert_eq!(
+        build.sboms[&Format::CycloneDx],
+        dir.path().join("0.14.0-rust1.56/amd64/sbom.cdx.json")
+    );
+    assert!(build.sboms[&Format::Spdx].exists());
+    plan.push().unwrap();
+
+    let referrers_tag = digest.replace(':', "-");
+    let (_, bytes) = registry.manifest("rust-trunk", &referrers_tag).unwrap();
+    let referrers: Index = serde_json::from_slice(&bytes).unwrap();
+    let artifact_types: Vec<_> = referrers
+        .manifests
+        .iter()
+        .map(|d| d.artifact_type.as_deref().unwrap())
+        .collect();
+    assert_eq!(
+        artifact_types,
+        ["application/vnd.cyclonedx+json", "application/spdx+json"]
+    );
+    let (_, artifact) = registry
+        .manifest("rust-trunk", &referrers.manifests[1].digest)
+        .unwrap();
+    let artifact: serde_json::Value = serde_json::from_slice(&artifact).unwrap();
+    assert_eq!(artifact["subject"]["digest"], digest.as_str());
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use std::path::Path;
+
+use trunk_docker::{
+    manifest::Manifest,
+    oci::Layout,
+    pipeline::BuildPlan,
+    registry::Registry,
+    size::{self, Budget, ImageSize},
+    target::Target,
+};
+
+/// `len` bytes that don't compress, so sizes grow with them.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Pushes an image with `len` bytes of noise to `target` under `tag`.
+fn publish(target: &Target, tag: &str, len: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    common::write_image(dir.path(), &[("/usr/local/bin/trunk", &noise(len))], &[]);
+    Registry::for_target(target)
+        .push_layout(&target.name(), tag, &Layout::open(dir.path()).unwrap())
+        .unwrap();
+}
+
+/// Plans a pushed build to `host`, with its image exported with `len`
+/// bytes of noise.
+fn plan(host: &str, out_dir: &Path, len: usize) -> BuildPlan {
+    let plan = common::push_plan(host, out_dir);
+    common::write_image(
+        &plan.images[0].platforms[0].layout,
+        &[("/usr/local/bin/trunk", &noise(len))],
+        &[],
+    );
+    plan
+}
+
+fn budget(percent: f64) -> Budget {
+    Budget {
+        max_growth_percent: Some(percent),
+    }
+}
+
+#[test]
+fn measures_the_layers_of_a_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    common::write_image(dir.path(), &[("/etc/hostname", &noise(5000))], &[]);
+    let size = ImageSize::of_layout(dir.path()).unwrap();
+    let (_, manifest) = Layout::open(dir.path()).unwrap().image().unwrap();
+    assert_eq!(size.layers.len(), 1);
+    assert_eq!(size.layers[0].digest, manifest.layers[0].digest);
+    assert_eq!(size.compressed(), manifest.layers[0].size);
+    // The file, padded to 512-byte tar blocks, plus headers.
+    assert_eq!(size.uncompressed() % 512, 0);
+    assert!(size.uncompressed() > 5120, "{}", size.uncompressed());
+
+    assert_eq!(size::human(512), "512 B");
+    assert_eq!(size::human(1536), "1.5 KiB");
+    assert_eq!(size::human(300 * 1024 * 1024), "300.0 MiB");
+}
+
+#[test]
+fn compares_with_the_image_the_push_replaces() {
+    let registry = common::registry::start();
+    let target: Target = format!("{}/rust-trunk", registry.host).parse().unwrap();
+    publish(&target, "latest", 10_000);
+    let dir = tempfile::tempdir().unwrap();
+    let plan = plan(&registry.host, dir.path(), 12_000);
+
+    let sizes = plan.measure().unwrap();
+    assert_eq!(sizes.len(), 1);
+    let comparison = &sizes[0];
+    let previous = comparison.previous.as_ref().unwrap();
+    assert_eq!(previous.image, target.reference("latest"));
+    let (compressed, uncompressed) = comparison.growth().unwrap();
+    assert!((15.0..25.0).contains(&compressed), "{compressed}");
+    assert!((15.0..25.0).contains(&uncompressed), "{uncompressed}");
+    common::assert_snapshot(
+        "size.txt",
+        &comparison.to_string().replace(&registry.host, "registry"),
+    );
+
+    comparison.check(&Budget::default()).unwrap();
+    comparison.check(&budget(25.0)).unwrap();
+    let err = comparison.check(&budget(5.0)).unwrap_err().to_string();
+    assert!(
+        err.contains("-amd64 grew by ") && err.contains("compressed over"),
+        "{err}"
+    );
+    assert!(err.contains("more than the 5% budget"), "{err}");
+
+    // The exact tag wins over the floating ones once it exists.
+    publish(&target, "0.14.0-rust1.56-amd64", 12_000);
+    let sizes = plan.measure().unwrap();
+    assert_eq!(
+        sizes[0].previous.as_ref().unwrap().image,
+        target.reference("0.14.0-rust1.56-amd64")
+    );
+    assert_eq!(sizes[0].growth(), Some((0.0, 0.0)));
+}
+
+#[test]
+fn reports_new_images_without_a_comparison() {
+    let registry = common::registry::start();
+    let dir = tempfile::tempdir().unwrap();
+    let sizes = plan(&registry.host, dir.path(), 1_000).measure().unwrap();
+    assert!(sizes[0].previous.is_none());
+    assert!(sizes[0].to_string().contains(", not published before:"));
+    sizes[0].check(&budget(0.0)).unwrap();
+
+    let path = dir.path().join("sizes.json");
+    size::write_json(&path, &sizes).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert!(json[0]["current"]["layers"][0]["uncompressed"].is_u64());
+    assert!(json[0]["previous"].is_null());
+}
+
+#[test]
+fn budget_must_be_a_positive_percentage() {
+    let manifest: Manifest = "[base]\nimage = \"rust\"\n[size]\nmax-growth-percent = 5"
+        .parse()
+        .unwrap();
+    assert_eq!(manifest.size, budget(5.0));
+    let negative = "[base]\nimage = \"rust\"\n[size]\nmax-growth-percent = -1";
+    assert!(negative.parse::<Manifest>().is_err());
+    let unknown = "[base]\nimage = \"rust\"\n[size]\nmax-growth = 1";
+    assert!(unknown.parse::<Manifest>().is_err());
+}
@@ -0,0 +1,197 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use trunk_docker::{
+    process::{Invocation, Runner, SystemRunner},
+    smoke::{self, Dist, Fixture},
+    Error, Result,
+};
+
+const HASH: &str = "3f9a1c0e7b2d4a65";
+
+fn fixtures() -> Vec<Fixture> {
+    smoke::fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join(smoke::FIXTURES)).unwrap()
+}
+
+/// Writes what `trunk build --release` writes for crate `name` to `dist`.
+fn write_dist(dist: &Path, name: &str, stylesheet: bool) {
+    fs::create_dir_all(dist).unwrap();
+    let wasm = format!("{name}-{HASH}_bg.wasm");
+    let js = format!("{name}-{HASH}.js");
+    let css = format!("style-{HASH}.css");
+    let link = if stylesheet {
+        format!("<link rel=\"stylesheet\" href=\"/{css}\"/>")
+    } else {
+        String::new()
+    };
+    let html = format!(
+        "<!DOCTYPE html><html><head>{link}\
+         <link rel=\"preload\" href=\"/{wasm}\" as=\"fetch\" type=\"application/wasm\" crossorigin=\"\">\
+         <link rel=\"modulepreload\" href=\"/{js}\"></head><body>\
+         <script type=\"module\">import init from '/{js}';init('/{wasm}');</script>\
+         </body></html>"
+    );
+    fs::write(dist.join("index.html"), html).unwrap();
+    fs::write(dist.join(wasm), b"\0asm").unwrap();
+    fs::write(dist.join(js), b"export default function init() {}").unwrap();
+    if stylesheet {
+        fs::write(dist.join(css), b"body{}").unwrap();
+    }
+}
+
+/// Plays trunk: records the invocations and writes a dist for each
+/// mounted fixture, except the ones named in `broken`. For the ones named
+/// in `downloading`, it lists a downloaded wasm-bindgen like the container
+/// does.
+#[derive(Default)]
+struct FakeTrunk {
+    invocations: Vec<Invocation>,
+    broken: Vec<&'static str>,
+    downloading: Vec<&'static str>,
+}
+
+impl Runner for FakeTrunk {
+    fn run(&mut self, invocation: &Invocation) -> Result<()> {
+        self.invocations.push(invocation.clone());
+        let volume = &invocation.args[3];
+        let dir = PathBuf::from(volume.strip_suffix(":/app").unwrap());
+        let name = dir.file_name().unwrap().to_str().unwrap();
+        if self.broken.contains(&name) {
+            return Err(Error::Smoke("trunk failed".into()));
+        }
+        let stylesheet = dir.join("style.scss").exists();
+        write_dist(&dir.join("dist"), &format!("smoke-{name}"), stylesheet);
+        let downloads = if self.downloading.contains(&name) {
+            "wasm-bindgen-0.2.78\n"
+        } else {
+            ""
+        };
+        fs::write(dir.join(".trunk-downloads"), downloads).unwrap();
+        Ok(())
+    }
+}
+
+#[test]
+fn lists_the_fixture_apps() {
+    let fixtures = fixtures();
+    let names: Vec<_> = fixtures.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["scss", "wasm-bindgen", "wasm-opt", "yew"]);
+    let styled: Vec<_> = fixtures
+        .iter()
+        .filter(|f| f.stylesheet)
+        .map(|f| f.name.as_str())
+        .collect();
+    assert_eq!(styled, ["scss"]);
+    let wasm_opt = fs::read_to_string(fixtures[2].dir.join("index.html")).unwrap();
+    assert!(wasm_opt.contains("data-wasm-opt"));
+}
+
+#[test]
+fn checks_the_dist_trunk_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let dist = dir.path().join("dist");
+    write_dist(&dist, "app", true);
+    assert_eq!(
+        smoke::check_dist(&dist).unwrap(),
+        Dist {
+            wasm: vec![format!("app-{HASH}_bg.wasm")],
+            js: vec![format!("app-{HASH}.js")],
+            css: vec![format!("style-{HASH}.css")],
+        }
+    );
+
+    fs::remove_file(dist.join(format!("app-{HASH}.js"))).unwrap();
+    let err = smoke::check_dist(&dist).unwrap_err().to_string();
+    assert!(err.contains(&format!("references /app-{HASH}.js")), "{err}");
+
+    let unhashed = dir.path().join("unhashed");
+    fs::create_dir(&unhashed).unwrap();
+    fs::write(
+        unhashed.join("index.html"),
+        "<script type=\"module\">import init from '/app.js';init('/app_bg.wasm');</script>",
+    )
+    .unwrap();
+    fs::write(unhashed.join("app.js"), "").unwrap();
+    fs::write(unhashed.join("app_bg.wasm"), "").unwrap();
+    let err = smoke::check_dist(&unhashed).unwrap_err().to_string();
+    assert!(err.contains("app_bg.wasm has no content hash"), "{err}");
+
+    fs::write(unhashed.join("index.html"), "<html></html>").unwrap();
+    let err = smoke::check_dist(&unhashed).unwrap_err().to_string();
+    assert!(err.contains("references no .wasm file"), "{err}");
+}
+
+#[test]
+fn builds_every_fixture_in_the_image() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut trunk = FakeTrunk {
+        broken: vec!["yew"],
+        ..FakeTrunk::default()
+    };
+    let report = smoke::run(&mut trunk, "rust-trunk:0.14.0", &fixtures(), dir.path()).unwrap();
+
+    let work_dir = fs::canonicalize(dir.path()).unwrap();
+    assert_eq!(
+        trunk.invocations[0].to_string(),
+        format!(
+            "docker run --rm --volume {}/scss:/app --workdir /app rust-trunk:0.14.0 sh -c \
+             'trunk build --release; status=$?; \
+             ls \"${{XDG_CACHE_HOME:-$HOME/.cache}}/trunk\" > .trunk-downloads 2>/dev/null; \
+             chown -R \"$(stat -c %u:%g .)\" .; exit $status'",
+            work_dir.display()
+        )
+    );
+    assert_eq!(trunk.invocations.len(), 4);
+    assert!(work_dir.join("scss/style.scss").exists());
+    assert!(work_dir.join("yew/src/main.rs").exists());
+    assert!(work_dir.join("yew/Cargo.lock").exists());
+
+    let text = report.to_string();
+    assert!(
+        text.contains(&format!(
+            "  ok    scss (smoke-scss-{HASH}_bg.wasm, smoke-scss-{HASH}.js, style-{HASH}.css)"
+        )),
+        "{text}"
+    );
+    assert!(
+        text.contains("  FAIL  yew: smoke test: trunk failed"),
+        "{text}"
+    );
+    let err = report.check().unwrap_err().to_string();
+    assert!(
+        err.contains("rust-trunk:0.14.0 failed to build yew"),
+        "{err}"
+    );
+}
+
+#[test]
+fn fails_fixtures_that_make_trunk_download_tools() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut trunk = FakeTrunk {
+        downloading: vec!["wasm-bindgen"],
+        ..FakeTrunk::default()
+    };
+    let report = smoke::run(&mut trunk, "rust-trunk:0.14.0", &fixtures(), dir.path()).unwrap();
+
+    let text = report.to_string();
+    assert!(text.contains("  ok    yew ("), "{text}");
+    assert!(
+        text.contains(
+            "  FAIL  wasm-bindgen: smoke test: trunk downloaded wasm-bindgen-0.2.78 \
+             instead of using the image's"
+        ),
+        "{text}"
+    );
+}
+
+#[test]
+#[ignore = "needs docker; set SMOKE_IMAGE to the image to test"]
+fn builds_the_fixtures_with_docker() {
+    let image = env::var("SMOKE_IMAGE").unwrap_or_else(|_| "torhovland/rust-trunk".into());
+    let dir = tempfile::tempdir().unwrap();
+    let report = smoke::run(&mut SystemRunner, &image, &fixtures(), dir.path()).unwrap();
+    print!("{report}");
+    report.check().unwrap();
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use std::{os::unix::process::ExitStatusExt, path::Path, process::ExitStatus};
+
+use trunk_docker::{
+    matrix::{Entry, Matrix},
+    platform::Platform,
+    process::{Invocation, Runner},
+    structure::{self, Spec},
+    Error, Result,
+};
+
+/// Plays docker running the scripts of the tests: prints `stdout` for
+/// scripts starting with its key, and exits with 1 for scripts containing
+/// one of `failing`, or with `status` for every script.
+#[derive(Default)]
+struct FakeDocker {
+    invocations: Vec<Invocation>,
+    stdout: Vec<(&'static str, &'static str)>,
+    failing: Vec<&'static str>,
+    status: Option<i32>,
+}
+
+impl Runner for FakeDocker {
+    fn run(&mut self, invocation: &Invocation) -> Result<()> {
+        self.output(invocation).map(drop)
+    }
+
+    fn output(&mut self, invocation: &Invocation) -> Result<String> {
+        self.invocations.push(invocation.clone());
+        let script = invocation.args.last().unwrap();
+        let code = match self.status {
+            Some(code) => Some(code),
+            None if self.failing.iter().any(|s| script.contains(s)) => Some(1),
+            None => None,
+        };
+        if let Some(code) = code {
+            return Err(Error::CommandFailed {
+                command: invocation.to_string(),
+                status: ExitStatus::from_raw(code << 8),
+            });
+        }
+        Ok(self
+            .stdout
+            .iter()
+            .find(|(prefix, _)| script.starts_with(prefix))
+            .map_or("", |(_, stdout)| stdout)
+            .to_string())
+    }
+}
+
+fn spec() -> Spec {
+    Spec::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join(structure::SPEC)).unwrap()
+}
+
+fn entry() -> Entry {
+    Entry {
+        trunk: "0.14.0".into(),
+        rust: "1.56".into(),
+    }
+}
+
+#[test]
+fn parses_the_repository_spec() {
+    let spec = spec();
+    let names: Vec<_> = spec
+        .command
+        .iter()
+        .map(|t| t.name.as_str())
+        .chain(spec.file.iter().map(|t| t.name.as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "trunk version",
+            "rustc version",
+            "wasm-opt on PATH",
+            "wasm32 target",
+            "no apt cache",
+            "entrypoint",
+            "no cargo registry",
+            "no apt lists"
+        ]
+    );
+}
+
+#[test]
+fn rejects_tests_that_check_nothing_or_contradict_themselves() {
+    let duplicate = "[[command]]\nname = \"a\"\nrun = \"true\"\n\
+                     [[file]]\nname = \"a\"\npath = \"/\"\nexists = true";
+    let err = duplicate.parse::<Spec>().unwrap_err().to_string();
+    assert!(err.contains("two tests are named \"a\""), "{err}");
+
+    let nothing = "[[file]]\nname = \"a\"\npath = \"/\"";
+    let err = nothing.parse::<Spec>().unwrap_err().to_string();
+    assert!(err.contains("checks nothing"), "{err}");
+
+    let missing = "[[file]]\nname = \"a\"\npath = \"/a\"\nexists = false\ncontains = \"x\"";
+    let err = missing.parse::<Spec>().unwrap_err().to_string();
+    assert!(err.contains("can't check anything else"), "{err}");
+
+    assert!("[[file]]\nname = \"a\"\npath = \"/\"\nsize = 1"
+        .parse::<Spec>()
+        .is_err());
+}
+
+#[test]
+fn runs_the_tests_in_a_container_of_the_image() {
+    let mut docker = FakeDocker {
+        stdout: vec![
+            ("trunk --version", "trunk 0.13.1\n"),
+            ("rustc --version", "rustc 1.56.1 (59eed8a2a 2021-11-01)\n"),
+            ("rustup target list", "wasm32-unknown-unknown\n"),
+        ],
+        failing: vec!["/usr/local/cargo/registry"],
+        ..FakeDocker::default()
+    };
+    let report = structure::run(
+        &mut docker,
+        &spec(),
+        "rust-trunk:0.14.0",
+        &entry(),
+        Platform::Amd64,
+    );
+    assert_eq!(
+        docker.invocations[0].to_string(),
+        "docker run --rm --platform linux/amd64 --entrypoint sh rust-trunk:0.14.0 \
+         -c 'trunk --version'"
+    );
+    assert_eq!(
+        docker.invocations[6].args.last().unwrap(),
+        "if if test -d /usr/local/cargo/registry; then \
+         test -z \"$(ls -A /usr/local/cargo/registry)\"; \
+         else ! test -s /usr/local/cargo/registry; fi; then exit 0; else exit 1; fi"
+    );
+    common::assert_snapshot("structure.txt", &report.to_string());
+    let err = report.check().unwrap_err().to_string();
+    assert!(
+        err.contains("rust-trunk:0.14.0 failed trunk version, no cargo registry"),
+        "{err}"
+    );
+}
+
+#[test]
+fn reports_docker_failures_as_they_are() {
+    let mut docker = FakeDocker {
+        status: Some(125),
+        ..FakeDocker::default()
+    };
+    let report = structure::run(
+        &mut docker,
+        &spec(),
+        "missing:latest",
+        &entry(),
+        Platform::Amd64,
+    );
+    let entrypoint = report
+        .outcomes
+        .iter()
+        .find(|outcome| outcome.test == "entrypoint")
+        .unwrap();
+    let err = entrypoint.result.as_ref().unwrap_err();
+    assert!(matches!(err, Error::CommandFailed { .. }), "{err}");
+}
+
+#[test]
+fn tests_every_platform_image_of_a_local_build() {
+    let matrix: Matrix = "targets = [\"torhovland/rust-trunk\"]\ntrunk = [\"0.14.0\"]\n\
+                          rust = [\"1.56\"]\nplatforms = [\"linux/amd64\", \"linux/arm64\"]"
+        .parse()
+        .unwrap();
+    let plan = common::plan(&matrix, "out".as_ref(), false);
+    let spec: Spec = "[[command]]\nname = \"trunk\"\nrun = \"trunk --version\""
+        .parse()
+        .unwrap();
+    let mut docker = FakeDocker::default();
+    let reports = plan.structure_test(&mut docker, &spec);
+    let images: Vec<_> = reports.iter().map(|r| r.image.as_str()).collect();
+    assert_eq!(
+        images,
+        [
+            "torhovland/rust-trunk:0.14.0-rust1.56-amd64",
+            "torhovland/rust-trunk:0.14.0-rust1.56-arm64"
+        ]
+    );
+    assert_eq!(docker.invocations[1].args[3], "linux/arm64");
+    for report in reports {
+        report.check().unwrap();
+    }
+}
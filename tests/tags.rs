@@ -0,0 +1,159 @@
+use trunk_docker::{
+    matrix::{Entry, Matrix},
+    tags,
+};
+
+fn matrix() -> Matrix {
+    r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.13.1", "0.14.0", "0.14.1", "1.0.0"]
+        rust = ["1.55", "1.56"]
+    "#
+    .parse()
+    .unwrap()
+}
+
+fn tags_of(trunk: &str, rust: &str, flavor: Option<&str>) -> Vec<String> {
+    let entry = Entry {
+        trunk: trunk.into(),
+        rust: rust.into(),
+    };
+    tags::for_entry(&matrix(), flavor, &entry).unwrap()
+}
+
+#[test]
+fn newest_build_owns_every_floating_tag() {
+    assert_eq!(
+        tags_of("1.0.0", "1.56", None),
+        [
+            "1.0.0-rust1.56",
+            "1.0-rust1.56",
+            "1.0.0",
+            "1.0",
+            "1",
+            "latest"
+        ]
+    );
+}
+
+#[test]
+fn newest_patch_owns_the_minor_and_major_line() {
+    assert_eq!(
+        tags_of("0.14.1", "1.56", None),
+        ["0.14.1-rust1.56", "0.14-rust1.56", "0.14.1", "0.14", "0"]
+    );
+    assert_eq!(
+        tags_of("0.14.0", "1.56", None),
+        ["0.14.0-rust1.56", "0.14.0"]
+    );
+    assert_eq!(
+        tags_of("0.13.1", "1.56", None),
+        ["0.13.1-rust1.56", "0.13-rust1.56", "0.13.1", "0.13"]
+    );
+}
+
+#[test]
+fn older_rust_only_gets_toolchain_qualified_tags() {
+    assert_eq!(
+        tags_of("0.14.1", "1.55", None),
+        ["0.14.1-rust1.55", "0.14-rust1.55"]
+    );
+    assert_eq!(tags_of("0.14.0", "1.55", None), ["0.14.0-rust1.55"]);
+}
+
+#[test]
+fn pre_releases_only_get_their_exact_tags() {
+    let matrix: Matrix = r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.14.0", "0.15.0-alpha.1", "1.0.0-rc.1"]
+        rust = ["1.56"]
+    "#
+    .parse()
+    .unwrap();
+    let tags_of = |trunk: &str| {
+        let entry = Entry {
+            trunk: trunk.into(),
+            rust: "1.56".into(),
+        };
+        tags::for_entry(&matrix, None, &entry).unwrap()
+    };
+    assert_eq!(tags_of("1.0.0-rc.1"), ["1.0.0-rc.1-rust1.56", "1.0.0-rc.1"]);
+    assert_eq!(
+        tags_of("0.15.0-alpha.1"),
+        ["0.15.0-alpha.1-rust1.56", "0.15.0-alpha.1"]
+    );
+    assert_eq!(
+        tags_of("0.14.0"),
+        [
+            "0.14.0-rust1.56",
+            "0.14-rust1.56",
+            "0.14.0",
+            "0.14",
+            "0",
+            "latest"
+        ]
+    );
+}
+
+#[test]
+fn flavor_twins_every_tag() {
+    assert_eq!(
+        tags_of("1.0.0", "1.56", Some("slim")),
+        [
+            "1.0.0-rust1.56",
+            "1.0.0-rust1.56-slim",
+            "1.0-rust1.56",
+            "1.0-rust1.56-slim",
+            "1.0.0",
+            "1.0.0-slim",
+            "1.0",
+            "1.0-slim",
+            "1",
+            "1-slim",
+            "latest",
+            "slim",
+        ]
+    );
+}
+
+#[test]
+fn variants_suffix_every_tag_and_stand_in_for_latest() {
+    let entry = Entry {
+        trunk: "1.0.0".into(),
+        rust: "1.56".into(),
+    };
+    assert_eq!(
+        tags::for_variant(&matrix(), Some("slim"), Some("alpine"), &entry).unwrap(),
+        [
+            "1.0.0-rust1.56-alpine",
+            "1.0-rust1.56-alpine",
+            "1.0.0-alpine",
+            "1.0-alpine",
+            "1-alpine",
+            "alpine",
+        ]
+    );
+    assert_eq!(
+        tags::for_variant(&matrix(), Some("slim"), None, &entry).unwrap(),
+        tags_of("1.0.0", "1.56", Some("slim"))
+    );
+}
+
+#[test]
+fn trunk_versions_must_be_full_semver() {
+    let partial = "targets = [\"r\"]\ntrunk = [\"0.14\"]\nrust = [\"1.56\"]";
+    assert!(partial.parse::<Matrix>().is_err());
+}
+
+#[test]
+fn rejects_entries_that_are_not_versions() {
+    let entry = Entry {
+        trunk: "foo".into(),
+        rust: "1.56".into(),
+    };
+    let err = tags::for_entry(&matrix(), None, &entry).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid matrix: trunk version \"foo\" is not a full `major.minor.patch` version"
+    );
+}
@@ -0,0 +1,103 @@
+use std::fs;
+
+use trunk_docker::toolchain::{self, Installed, Mismatch, ToolchainFile};
+
+fn installed() -> Vec<Installed> {
+    vec![Installed {
+        name: "1.56.1-x86_64-unknown-linux-gnu".into(),
+        targets: vec![
+            "wasm32-unknown-unknown".into(),
+            "x86_64-unknown-linux-gnu".into(),
+        ],
+        components: vec![
+            "cargo-x86_64-unknown-linux-gnu".into(),
+            "rust-std-wasm32-unknown-unknown".into(),
+            "rust-std-x86_64-unknown-linux-gnu".into(),
+            "rustc-x86_64-unknown-linux-gnu".into(),
+        ],
+    }]
+}
+
+fn file(text: &str) -> ToolchainFile {
+    ToolchainFile::parse(text).unwrap()
+}
+
+#[test]
+fn parses_both_formats() {
+    assert_eq!(file("1.56.1\n").channel.as_deref(), Some("1.56.1"));
+    let toml = file(
+        r#"
+        [toolchain]
+        channel = "1.56"
+        targets = ["wasm32-unknown-unknown"]
+        components = ["rustfmt"]
+        "#,
+    );
+    assert_eq!(toml.channel.as_deref(), Some("1.56"));
+    assert_eq!(toml.targets, ["wasm32-unknown-unknown"]);
+    assert_eq!(toml.components, ["rustfmt"]);
+}
+
+#[test]
+fn minor_channel_matches_installed_patch() {
+    let installed = installed();
+    let toolchain = toolchain::check(&file("1.56"), &installed).unwrap();
+    assert_eq!(toolchain.name, "1.56.1-x86_64-unknown-linux-gnu");
+    assert!(toolchain::check(&file("1.56.1"), &installed).is_ok());
+    assert!(toolchain::check(&file("1.5"), &installed).is_err());
+}
+
+#[test]
+fn reports_missing_channel_targets_and_components() {
+    let installed = installed();
+    let err = toolchain::check(&file("nightly-2021-11-01"), &installed).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the project pins Rust nightly-2021-11-01, but this image only has 1.56.1; \
+         use the image tag for that Rust version instead"
+    );
+    let targets = file("[toolchain]\nchannel = \"1.56\"\ntargets = [\"wasm32-wasi\"]");
+    assert_eq!(
+        toolchain::check(&targets, &installed).unwrap_err(),
+        Mismatch::Targets {
+            toolchain: "1.56.1-x86_64-unknown-linux-gnu".into(),
+            missing: vec!["wasm32-wasi".into()],
+        }
+    );
+    let components = file("[toolchain]\ncomponents = [\"rust-std\", \"clippy\"]");
+    assert_eq!(
+        toolchain::check(&components, &installed).unwrap_err(),
+        Mismatch::Components {
+            toolchain: "1.56.1-x86_64-unknown-linux-gnu".into(),
+            missing: vec!["clippy".into()],
+        }
+    );
+}
+
+#[test]
+fn default_toolchain_is_listed_first() {
+    let list = "stable-x86_64-unknown-linux-gnu\n1.56.1-x86_64-unknown-linux-gnu (default)\n";
+    assert_eq!(
+        toolchain::parse_toolchain_list(list),
+        [
+            "1.56.1-x86_64-unknown-linux-gnu",
+            "stable-x86_64-unknown-linux-gnu"
+        ]
+    );
+}
+
+#[test]
+fn finds_legacy_file_before_toml_in_parent_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("rust-toolchain.toml"),
+        "[toolchain]\nchannel = \"1.55\"",
+    )
+    .unwrap();
+    fs::write(dir.path().join("rust-toolchain"), "1.56").unwrap();
+    let nested = dir.path().join("crates/app");
+    fs::create_dir_all(&nested).unwrap();
+    let (path, file) = ToolchainFile::find(&nested).unwrap().unwrap();
+    assert_eq!(path, dir.path().join("rust-toolchain"));
+    assert_eq!(file.channel.as_deref(), Some("1.56"));
+}
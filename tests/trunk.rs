@@ -0,0 +1,81 @@
+mod common;
+
+use std::{collections::HashMap, fs};
+
+use flate2::{write::GzEncoder, Compression};
+use trunk_docker::{
+    platform::Platform,
+    trunk::{self, TrunkLock},
+};
+
+/// A release tarball laid out like trunk's, with the binary at the root.
+fn release_tarball(binary: &[u8]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(binary.len() as u64);
+    header.set_mode(0o755);
+    header.set_cksum();
+    builder.append_data(&mut header, "trunk", binary).unwrap();
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+fn template(base: &str) -> String {
+    format!("{base}/releases/download/v{{version}}/trunk-{{target}}.tar.gz")
+}
+
+#[test]
+fn locks_fetches_and_unpacks_a_release() {
+    let tarball = release_tarball(b"#!/bin/sh\necho trunk 0.14.0\n");
+    let base = common::http::serve(HashMap::from([(
+        "/releases/download/v0.14.0/trunk-x86_64-unknown-linux-gnu.tar.gz".to_string(),
+        tarball.clone(),
+    )]));
+    let mut lock = TrunkLock::default();
+    assert!(lock
+        .lock(&template(&base), "0.14.0", Platform::Amd64)
+        .unwrap());
+    assert!(!lock
+        .lock(&template(&base), "0.14.0", Platform::Arm64)
+        .unwrap());
+    assert!(!lock
+        .lock(&template(&base), "0.13.1", Platform::Amd64)
+        .unwrap());
+    let release = lock.get("0.14.0", Platform::Amd64).unwrap();
+    assert_eq!(release.sha256, trunk::sha256_hex(&tarball));
+    assert!(lock.get("0.14.0", Platform::Arm64).is_none());
+    assert!(!lock.release.contains_key("0.13.1"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let binary = trunk::unpack(&release.fetch().unwrap(), dir.path()).unwrap();
+    assert_eq!(fs::read(binary).unwrap(), b"#!/bin/sh\necho trunk 0.14.0\n");
+}
+
+#[test]
+fn rejects_a_release_that_does_not_match_the_lock() {
+    let base = common::http::serve(HashMap::from([(
+        "/releases/download/v0.14.0/trunk-x86_64-unknown-linux-gnu.tar.gz".to_string(),
+        release_tarball(b"tampered"),
+    )]));
+    let release = trunk::LockedRelease {
+        url: trunk::asset_url(&template(&base), "0.14.0", Platform::Amd64),
+        sha256: trunk::sha256_hex(b"original"),
+    };
+    let err = release.fetch().unwrap_err();
+    assert!(err.to_string().starts_with("checksum mismatch:"), "{err}");
+}
+
+#[test]
+fn lockfile_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trunk.lock");
+    let mut lock = TrunkLock::default();
+    lock.release.entry("0.14.0".into()).or_default().insert(
+        "x86_64-unknown-linux-gnu".into(),
+        trunk::LockedRelease {
+            url: "https://example.com/trunk.tar.gz".into(),
+            sha256: "b".repeat(64),
+        },
+    );
+    lock.save(&path).unwrap();
+    assert_eq!(TrunkLock::load(&path).unwrap(), lock);
+}
@@ -0,0 +1,230 @@
+mod common;
+
+use std::{collections::HashMap, fs};
+
+use trunk_docker::{
+    manifest::Manifest, matrix::Matrix, oci, platform::Platform, registry::Registry,
+    target::Target, updates,
+};
+
+/// The index file of trunk, as the sparse index and its mirrors serve it.
+const TRUNK_INDEX: &str = r#"{"name":"trunk","vers":"0.13.1","deps":[],"cksum":"00","features":{},"yanked":false}
+{"name":"trunk","vers":"0.14.0","deps":[],"cksum":"00","features":{},"yanked":false}
+{"name":"trunk","vers":"0.15.0","deps":[],"cksum":"00","features":{},"yanked":false}
+{"name":"trunk","vers":"0.16.0-alpha.1","deps":[],"cksum":"00","features":{},"yanked":false}
+{"name":"trunk","vers":"0.16.0","deps":[],"cksum":"00","features":{},"yanked":true}
+"#;
+
+const MATRIX: &str = r#"targets = ["torhovland/rust-trunk"]
+
+# Every trunk version is built on top of every Rust base image listed here.
+trunk = ["0.13.1", "0.14.0"]
+rust = [
+    "1.56", # the newest
+]
+platforms = ["linux/amd64", "linux/arm64"]
+
+[[publish]]
+trunk = ["0.14.0"]
+targets = ["ghcr.io/our-org/rust-trunk"]
+"#;
+
+#[test]
+fn reads_released_versions_from_the_index() {
+    assert_eq!(updates::index_path("trunk"), "tr/un/trunk");
+    assert_eq!(updates::index_path("cc"), "2/cc");
+    assert_eq!(updates::index_path("syn"), "3/s/syn");
+    assert_eq!(updates::index_path("Inflector"), "in/fl/inflector");
+
+    let mirror = tempfile::tempdir().unwrap();
+    fs::create_dir_all(mirror.path().join("tr/un")).unwrap();
+    fs::write(mirror.path().join("tr/un/trunk"), TRUNK_INDEX).unwrap();
+    let versions = updates::crate_versions(mirror.path().to_str().unwrap(), "trunk").unwrap();
+    let versions: Vec<String> = versions.iter().map(ToString::to_string).collect();
+    assert_eq!(versions, ["0.13.1", "0.14.0", "0.15.0"]);
+
+    let sparse = common::http::serve(HashMap::from([(
+        "/tr/un/trunk".to_string(),
+        TRUNK_INDEX.as_bytes().to_vec(),
+    )]));
+    let versions = updates::crate_versions(&sparse, "trunk").unwrap();
+    assert_eq!(versions.last().unwrap().to_string(), "0.15.0");
+    assert!(updates::crate_versions(&sparse, "missing")
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn finds_the_newest_base_image_for_every_platform() {
+    let registry = common::registry::start();
+    let both = [Platform::Amd64, Platform::Arm64];
+    registry.push_base("1.56-slim", &both, &[("/etc/hostname", b"1.56-slim")]);
+    registry.push_base("1.57-slim", &both, &[("/etc/hostname", b"1.57-slim")]);
+    registry.push_base("1.57.0-slim", &both, &[("/etc/hostname", b"1.57.0-slim")]);
+    registry.push_base(
+        "1.58-bullseye",
+        &both,
+        &[("/etc/hostname", b"1.58-bullseye")],
+    );
+    registry.push_base(
+        "1.58-slim",
+        &[Platform::Amd64],
+        &[("/etc/hostname", b"1.58-slim")],
+    );
+    let target: Target = format!("{}/rust", registry.host).parse().unwrap();
+    let tags = Registry::for_target(&target).tags(&target.name()).unwrap();
+    assert_eq!(tags.len(), 5 + 9, "every page is followed: {tags:?}");
+    assert_eq!(
+        updates::rust_versions(&tags, Some("slim")),
+        ["1.56", "1.57", "1.58"]
+    );
+
+    let mirror = tempfile::tempdir().unwrap();
+    fs::create_dir_all(mirror.path().join("tr/un")).unwrap();
+    fs::write(mirror.path().join("tr/un/trunk"), TRUNK_INDEX).unwrap();
+    let index = mirror.path().to_str().unwrap();
+    let matrix: Matrix = MATRIX.parse().unwrap();
+    let manifest: Manifest = format!(
+        "[base]\nimage = \"{}/rust\"\nsuffix = \"slim\"",
+        registry.host
+    )
+    .parse()
+    .unwrap();
+    let check = updates::check(&matrix, &manifest.base, index).unwrap();
+    assert_eq!(
+        (check.trunk.latest.as_str(), check.rust.latest.as_str()),
+        ("0.15.0", "1.57")
+    );
+    let (_, index_bytes) = registry.manifest("rust", "1.57-slim").unwrap();
+    assert_eq!(
+        check.rust.source,
+        format!(
+            "{}/rust:1.57-slim@{}",
+            registry.host,
+            oci::digest(&index_bytes)
+        )
+    );
+    assert_eq!(
+        check.to_string().replace(&registry.host, "registry"),
+        format!(
+            "trunk: 0.14.0 -> 0.15.0 ({index})\n\
+             rust: 1.56 -> 1.57 (registry/rust:1.57-slim@{})\n",
+            oci::digest(&index_bytes)
+        )
+    );
+
+    let manifest: Manifest = format!(
+        "[base]\nimage = \"{}/rust\"\nsuffix = \"alpine\"",
+        registry.host
+    )
+    .parse()
+    .unwrap();
+    let check = updates::check(&matrix, &manifest.base, index).unwrap();
+    assert!(!check.rust.is_newer());
+    assert!(check.to_string().contains("rust: 1.56 is up to date"));
+}
+
+#[test]
+fn bumps_the_matrix_keeping_its_layout() {
+    let bumped = updates::bump(MATRIX, "trunk", "0.14.0", "0.15.0").unwrap();
+    let bumped = updates::bump(&bumped, "rust", "1.56", "1.57").unwrap();
+    assert_eq!(
+        bumped,
+        MATRIX
+            .replace("\"0.14.0\"", "\"0.15.0\"")
+            .replace("\"1.56\"", "\"1.57\"")
+    );
+    let matrix: Matrix = bumped.parse().unwrap();
+    assert_eq!(matrix.trunk, ["0.13.1", "0.15.0"]);
+    assert_eq!(matrix.publish[0].trunk, ["0.15.0"]);
+
+    let repository =
+        fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/matrix.toml")).unwrap();
+    let current = repository.parse::<Matrix>().unwrap().default_entry();
+    updates::bump(&repository, "trunk", &current.trunk, "99.0.0").unwrap();
+
+    let err = updates::bump(MATRIX, "trunk", "0.12.0", "0.15.0").unwrap_err();
+    assert!(err.to_string().contains("no `trunk` list"), "{err}");
+    let err = updates::bump(MATRIX, "trunk", "0.13.1", "0.14.0").unwrap_err();
+    assert!(err.to_string().contains("more than once"), "{err}");
+}
+
+#[test]
+fn adds_a_trunk_release_next_to_the_current_one() {
+    let added = updates::add(MATRIX, "trunk", "0.14.0", "0.15.0").unwrap();
+    assert_eq!(
+        added,
+        MATRIX.replace("\"0.14.0\"", "\"0.14.0\", \"0.15.0\"")
+    );
+    let matrix: Matrix = added.parse().unwrap();
+    assert_eq!(matrix.trunk, ["0.13.1", "0.14.0", "0.15.0"]);
+    assert_eq!(matrix.publish[0].trunk, ["0.14.0", "0.15.0"]);
+    assert_eq!(matrix.default_entry().trunk, "0.15.0");
+
+    let err = updates::add(MATRIX, "trunk", "0.13.1", "0.14.0").unwrap_err();
+    assert!(err.to_string().contains("more than once"), "{err}");
+}
+
+#[test]
+fn raises_binaryen_when_a_new_trunk_needs_it() {
+    const MANIFEST: &str = "[base]\nimage = \"rust\"\n\n[apt]\npackages = [\"curl\"]\n\n\
+                            # wasm-opt's release.\n[binaryen]\nversion = 105 # for trunk 0.14\n\n\
+                            [wasm-bindgen]\nversions = []\n";
+    let manifest: Manifest = MANIFEST.parse().unwrap();
+    assert_eq!(updates::binaryen_bump(&manifest, "0.16.0").unwrap(), None);
+    assert_eq!(
+        updates::binaryen_bump(&manifest, "0.17.0").unwrap(),
+        Some(116)
+    );
+    let bumped = updates::bump_binaryen(MANIFEST, 116).unwrap();
+    assert_eq!(
+        bumped,
+        MANIFEST.replace("version = 105 #", "version = 116 #")
+    );
+    assert_eq!(
+        bumped
+            .parse::<Manifest>()
+            .unwrap()
+            .binaryen
+            .unwrap()
+            .version,
+        116
+    );
+
+    let repository =
+        fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/image.toml")).unwrap();
+    let bumped = updates::bump_binaryen(&repository, 116).unwrap();
+    let manifest: Manifest = bumped.parse().unwrap();
+    assert_eq!(manifest.binaryen.unwrap().version, 116);
+
+    // Pinned tarballs and a variant's own binaryen are left to a person.
+    let pinned: Manifest = MANIFEST
+        .replace(
+            "version = 105 # for trunk 0.14\n",
+            &format!(
+                "version = 105\nsha256 = {{ x86_64 = \"{}\" }}\n",
+                "0".repeat(64)
+            ),
+        )
+        .parse()
+        .unwrap();
+    let err = updates::binaryen_bump(&pinned, "0.17.0").unwrap_err();
+    assert!(
+        err.to_string().contains(
+            "trunk 0.17.0 needs binaryen version 116, but the default image pins version 105; \
+             raise it and its `sha256` pins by hand"
+        ),
+        "{err}"
+    );
+    let variant: Manifest = format!(
+        "{}[variants.old]\nbinaryen = {{ version = 101 }}\n",
+        MANIFEST.replace("version = 105", "version = 116")
+    )
+    .parse()
+    .unwrap();
+    let err = updates::binaryen_bump(&variant, "0.17.0").unwrap_err();
+    assert!(
+        err.to_string().contains("but variant old pins version 101"),
+        "{err}"
+    );
+}
@@ -0,0 +1,312 @@
+mod common;
+
+use std::path::Path;
+
+use trunk_docker::{
+    dockerfile::{self, TrunkSource},
+    lock::ImageLock,
+    manifest::Manifest,
+    matrix::{Entry, Matrix},
+    pipeline::{self, Options},
+    platform::Platform,
+    process::{Invocation, Runner},
+    smoke::Fixture,
+    trunk::TrunkLock,
+    Error, Result,
+};
+
+const MANIFEST: &str = r#"
+[base]
+image = "rust"
+suffix = "slim"
+
+[apt]
+packages = ["build-essential", "curl", "git", "pkg-config"]
+
+[binaryen]
+version = 105
+
+[variants.alpine]
+base = { image = "rust", suffix = "alpine" }
+trunk = { from-source = true }
+apt = { packages = [] }
+apk = { packages = ["bash", "curl", "git", "musl-dev"] }
+
+[variants.full]
+cargo = [{ name = "cargo-watch", version = "8.1.1" }]
+
+[variants.minimal]
+apt = { packages = ["curl", "gcc"] }
+"#;
+
+fn matrix() -> Matrix {
+    r#"
+        targets = ["torhovland/rust-trunk"]
+        trunk = ["0.14.0"]
+        rust = ["1.56"]
+    "#
+    .parse()
+    .unwrap()
+}
+
+fn entry() -> Entry {
+    Entry {
+        trunk: "0.14.0".into(),
+        rust: "1.56".into(),
+    }
+}
+
+fn options() -> Options {
+    Options {
+        context: ".".into(),
+        out_dir: "out".into(),
+        push: false,
+        crates_dl: "http://127.0.0.1:1".into(),
+    }
+}
+
+fn locked_amd64() -> TrunkLock {
+    format!(
+        "[release.\"0.14.0\".x86_64-unknown-linux-gnu]\n\
+         url = \"https://example.com/trunk.tar.gz\"\nsha256 = \"{}\"",
+        "a".repeat(64)
+    )
+    .parse()
+    .unwrap()
+}
+
+/// Plays docker: records the invocations and fails every build, which the
+/// smoke reports record.
+#[derive(Default)]
+struct Recorder(Vec<Invocation>);
+
+impl Runner for Recorder {
+    fn run(&mut self, invocation: &Invocation) -> Result<()> {
+        self.0.push(invocation.clone());
+        Err(Error::Smoke("trunk failed".into()))
+    }
+}
+
+#[test]
+fn variants_replace_the_tables_they_set() {
+    let manifest: Manifest = MANIFEST.parse().unwrap();
+    let names: Vec<_> = manifest.images().map(|(name, _)| name).collect();
+    assert_eq!(names, [None, Some("alpine"), Some("full"), Some("minimal")]);
+
+    let alpine = manifest.variant(Some("alpine")).unwrap();
+    assert_eq!(alpine.base.reference("1.56"), "rust:1.56-alpine");
+    assert!(alpine.apt.packages.is_empty());
+    assert_eq!(alpine.binaryen.as_ref().unwrap().version, 105);
+    let full = manifest.variant(Some("full")).unwrap();
+    assert_eq!(full.apt.packages, manifest.apt.packages);
+    assert_eq!(full.cargo[0].name, "cargo-watch");
+    let minimal = manifest.variant(Some("minimal")).unwrap();
+    assert_eq!(minimal.apt.packages, ["curl", "gcc"]);
+    assert!(manifest.cargo.is_empty());
+
+    let err = manifest.variant(Some("musl")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid image manifest: there is no variant named musl"
+    );
+
+    let rendered = dockerfile::render(
+        alpine,
+        &ImageLock::default(),
+        &entry(),
+        Platform::Amd64,
+        TrunkSource::Cargo,
+    );
+    common::assert_snapshot("alpine.Dockerfile", &rendered);
+}
+
+#[test]
+fn rejects_invalid_variants() {
+    for (variant, message) in [
+        (
+            "[variants.Alpine]\nbase = { image = \"rust\" }",
+            "\"Alpine\" is not a valid variant name",
+        ),
+        (
+            "[variants.latest]\nbase = { image = \"rust\" }",
+            "\"latest\" is not a valid variant name",
+        ),
+        (
+            "[variants.slim]\nbase = { image = \"rust\" }",
+            "would share its tags",
+        ),
+        (
+            "[variants.small]\nsize = { max-growth-percent = 5 }",
+            "variant small: `[size]` applies to every image",
+        ),
+        (
+            "[variants.alpine]\napk = { packages = [\"bash\"] }",
+            "variant alpine: a base image has either apt or apk",
+        ),
+        (
+            "[variants.minimal]\napt = { packages = [\"git\"] }",
+            "variant minimal: downloading binaryen needs `curl`",
+        ),
+        (
+            "[variants.full]\ncargo = { name = \"cargo-watch\" }",
+            "variant full: invalid type",
+        ),
+        (
+            "[variants.alpine]\nbase = { image = \"rust\", suffix = \"alpine\" }\n\
+             trunk = { from-source = true }\napt = { packages = [] }\n\
+             apk = { packages = [\"curl\"] }",
+            "variant alpine: `cargo install` of trunk needs a C toolchain",
+        ),
+        (
+            "[variants.minimal]\nwasm-bindgen = { versions = [\"0.2.78\"] }",
+            "variant minimal: `cargo install` of wasm-bindgen-cli 0.2.78 needs a C toolchain",
+        ),
+        (
+            "[variants.distroless]\nbase = { image = \"gcr.io/distroless/cc\" }",
+            "variant distroless: distroless base images are not supported",
+        ),
+        (
+            "variants = [\"alpine\"]",
+            "must be a table of named variants",
+        ),
+    ] {
+        let manifest = format!(
+            "{variant}\n[base]\nimage = \"rust\"\nsuffix = \"slim\"\n\
+             [apt]\npackages = [\"curl\"]\n[binaryen]\nversion = 105"
+        );
+        let err = manifest.parse::<Manifest>().unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+}
+
+#[test]
+fn plans_every_variant_with_its_own_tags() {
+    let manifest: Manifest = MANIFEST.parse().unwrap();
+    let plan = pipeline::plan(
+        &matrix(),
+        &manifest,
+        &locked_amd64(),
+        &[entry()],
+        &options(),
+    )
+    .unwrap();
+    let images: Vec<_> = plan
+        .images
+        .iter()
+        .map(|image| {
+            (
+                image.variant.as_deref(),
+                image.tags.join(" "),
+                image.platforms[0].dockerfile.clone(),
+                image.platforms[0].trunk,
+            )
+        })
+        .collect();
+    assert_eq!(
+        images,
+        [
+            (
+                None,
+                "0.14.0-rust1.56 0.14.0-rust1.56-slim 0.14-rust1.56 0.14-rust1.56-slim \
+                 0.14.0 0.14.0-slim 0.14 0.14-slim 0 0-slim latest slim"
+                    .to_string(),
+                Path::new("out/0.14.0-rust1.56/amd64/Dockerfile").to_path_buf(),
+                TrunkSource::Prebuilt,
+            ),
+            (
+                Some("alpine"),
+                "0.14.0-rust1.56-alpine 0.14-rust1.56-alpine 0.14.0-alpine 0.14-alpine \
+                 0-alpine alpine"
+                    .to_string(),
+                Path::new("out/0.14.0-rust1.56-alpine/amd64/Dockerfile").to_path_buf(),
+                TrunkSource::Cargo,
+            ),
+            (
+                Some("full"),
+                "0.14.0-rust1.56-full 0.14-rust1.56-full 0.14.0-full 0.14-full 0-full full"
+                    .to_string(),
+                Path::new("out/0.14.0-rust1.56-full/amd64/Dockerfile").to_path_buf(),
+                TrunkSource::Prebuilt,
+            ),
+            (
+                Some("minimal"),
+                "0.14.0-rust1.56-minimal 0.14-rust1.56-minimal 0.14.0-minimal 0.14-minimal \
+                 0-minimal minimal"
+                    .to_string(),
+                Path::new("out/0.14.0-rust1.56-minimal/amd64/Dockerfile").to_path_buf(),
+                TrunkSource::Prebuilt,
+            ),
+        ]
+    );
+    assert_eq!(
+        plan.images[1].platforms[0].tag,
+        "0.14.0-rust1.56-alpine-amd64"
+    );
+    assert_eq!(
+        plan.images[3].manifest(&manifest).apt.packages,
+        ["curl", "gcc"]
+    );
+}
+
+#[test]
+fn variants_without_a_c_toolchain_need_a_prebuilt_trunk() {
+    let manifest: Manifest = "[base]\nimage = \"rust\"\n[apt]\npackages = [\"curl\", \"git\"]\n\
+                              [variants.minimal]\napt = { packages = [\"curl\"] }"
+        .parse()
+        .unwrap();
+    let plan =
+        |lock: &TrunkLock| pipeline::plan(&matrix(), &manifest, lock, &[entry()], &options());
+
+    let err = plan(&TrunkLock::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid image manifest: variant minimal: `cargo install` of trunk 0.14.0 without a \
+         prebuilt release for linux/amd64 in trunk.lock needs a C toolchain; add one of \
+         build-essential, gcc, build-base, musl-dev to the packages"
+    );
+    let plan = plan(&locked_amd64()).unwrap();
+    assert_eq!(plan.images[1].platforms[0].trunk, TrunkSource::Prebuilt);
+}
+
+#[test]
+fn smoke_tests_every_variant() {
+    let manifest: Manifest = MANIFEST.parse().unwrap();
+    let plan = pipeline::plan(
+        &matrix(),
+        &manifest,
+        &TrunkLock::default(),
+        &[entry()],
+        &options(),
+    )
+    .unwrap();
+    let work_dir = tempfile::tempdir().unwrap();
+    let fixture_dir = tempfile::tempdir().unwrap();
+    std::fs::write(fixture_dir.path().join("index.html"), "<html></html>").unwrap();
+    let fixtures = [Fixture {
+        name: "app".into(),
+        dir: fixture_dir.path().into(),
+        stylesheet: false,
+    }];
+
+    let mut docker = Recorder::default();
+    let reports = plan
+        .smoke_test(&mut docker, &fixtures, work_dir.path())
+        .unwrap();
+    let images: Vec<_> = reports.iter().map(|r| r.image.as_str()).collect();
+    assert_eq!(
+        images,
+        [
+            "torhovland/rust-trunk:0.14.0-rust1.56",
+            "torhovland/rust-trunk:0.14.0-rust1.56-alpine",
+            "torhovland/rust-trunk:0.14.0-rust1.56-full",
+            "torhovland/rust-trunk:0.14.0-rust1.56-minimal",
+        ]
+    );
+    let mounts: Vec<_> = docker.0.iter().map(|i| i.args[3].clone()).collect();
+    let work_dir = work_dir.path().canonicalize().unwrap();
+    assert_eq!(
+        mounts[1],
+        format!("{}/0.14.0-rust1.56-alpine/app:/app", work_dir.display())
+    );
+    assert!(reports.iter().all(|report| report.check().is_err()));
+}
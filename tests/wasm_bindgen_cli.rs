@@ -0,0 +1,82 @@
+use std::fs;
+
+use trunk_docker::wasm_bindgen;
+
+const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["wasm-bindgen"]
+
+[[package]]
+name = "wasm-bindgen"
+version = "0.2.78"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+
+fn bundle(versions: &[&str]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for version in versions {
+        let bin = dir.path().join(version).join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("wasm-bindgen"), "").unwrap();
+    }
+    dir
+}
+
+#[test]
+fn reads_the_locked_version() {
+    assert_eq!(
+        wasm_bindgen::locked_version(LOCKFILE).unwrap().as_deref(),
+        Some("0.2.78")
+    );
+    assert_eq!(wasm_bindgen::locked_version("version = 3").unwrap(), None);
+}
+
+#[test]
+fn rejects_several_locked_versions() {
+    let twice = format!("{LOCKFILE}\n[[package]]\nname = \"wasm-bindgen\"\nversion = \"0.2.79\"\n");
+    let err = wasm_bindgen::locked_version(&twice).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("several wasm-bindgen versions (0.2.78, 0.2.79)"));
+}
+
+#[test]
+fn selects_the_bundled_version() {
+    let dir = bundle(&["0.2.79", "0.2.78"]);
+    assert_eq!(
+        wasm_bindgen::bundled_versions(dir.path()).unwrap(),
+        ["0.2.78", "0.2.79"]
+    );
+    assert_eq!(
+        wasm_bindgen::select(dir.path(), "0.2.79").unwrap(),
+        dir.path().join("0.2.79/bin")
+    );
+}
+
+#[test]
+fn names_the_bundled_versions_when_missing() {
+    let dir = bundle(&["0.2.78", "0.2.79"]);
+    let err = wasm_bindgen::select(dir.path(), "0.2.80").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Cargo.lock pins wasm-bindgen 0.2.80, but this image only bundles \
+         wasm-bindgen-cli 0.2.78, 0.2.79; pin one of those with \
+         `cargo update -p wasm-bindgen --precise <version>` or use an image that bundles 0.2.80"
+    );
+}
+
+#[test]
+fn finds_the_workspace_lockfile() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("Cargo.lock"), LOCKFILE).unwrap();
+    let member = dir.path().join("crates/frontend");
+    fs::create_dir_all(&member).unwrap();
+    assert_eq!(
+        wasm_bindgen::find_lockfile(&member),
+        Some(dir.path().join("Cargo.lock"))
+    );
+}
@@ -0,0 +1,29 @@
+use trunk_docker::wasm_opt;
+
+#[test]
+fn parses_version_output() {
+    assert_eq!(wasm_opt::parse_version("wasm-opt version 105\n"), Some(105));
+    assert_eq!(
+        wasm_opt::parse_version("wasm-opt version 116 (version_116)"),
+        Some(116)
+    );
+    assert_eq!(wasm_opt::parse_version("wasm-opt version_90"), Some(90));
+    assert_eq!(wasm_opt::parse_version("wasm-opt"), None);
+}
+
+#[test]
+fn minimum_depends_on_the_trunk_line() {
+    assert_eq!(wasm_opt::minimum_for("0.14.0").unwrap(), 101);
+    assert_eq!(wasm_opt::minimum_for("0.17.5").unwrap(), 116);
+    assert_eq!(wasm_opt::minimum_for("0.13.1").unwrap(), 90);
+}
+
+#[test]
+fn rejects_old_wasm_opt() {
+    assert!(wasm_opt::check(105, "0.14.0").is_ok());
+    let err = wasm_opt::check(100, "0.14.0").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "wasm-opt is binaryen version 100, but trunk 0.14.0 needs at least version 101"
+    );
+}